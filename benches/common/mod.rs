@@ -0,0 +1,77 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+//! Fixtures for the benchmarks: the programs, and interpreters to run them
+
+use rfunge::{
+    bfvec, BefungeVec, Interpreter, InterpreterBuilder, PagedFungeSpace, ProgramResult, RunMode,
+    SilentEnv,
+};
+
+pub type Befunge = Interpreter<BefungeVec<i64>, PagedFungeSpace<BefungeVec<i64>, i64>, SilentEnv>;
+
+/// Counts down from 1000, calling `16y` (the least point) each time
+pub const Y_LOOP: &str = "a:*a*>1-:!#@_88+y$v\n     ^            <";
+/// Counts down from 100, calling `16y` and overwriting (9, 9) with a space
+/// and then an `x` each time, which forces the bounds to be recomputed
+pub const Y_LOOP_ERASING: &str = "a:*>1-:!#@_88+y$v\n   ^  p99x'p99 '<";
+
+/// Forks 1000 times. The children go west from `t`, pop the counter with
+/// `_` and hit `@`, so there are never more than a few IPs around, but each
+/// of them has to be copied from its parent.
+pub const FORK_STORM: &str = "a:*a*v\n     >1-:!#@_t";
+/// The same, with a few more values on the stack to copy each time
+pub const FORK_STORM_DEEP: &str = "12345a:*a*v\n          >1-:!#@_t";
+
+/// Pushes a 43 character string 1000 times, clearing the stack each time.
+/// The loop counter lives in (0, 0).
+pub const STRING_MODE: &str = concat!(
+    "a:*a*00pv\n",
+    "        >\"The quick brown fox jumps over the lazy dog\"n00g1-:00p!#@_",
+);
+/// The same, also round-tripping the string through JSTR's `W` and `R` (pop
+/// a 0gnirts, write it to funge-space, read it back as a 0gnirts) and
+/// popping it once more with `W`
+pub const ZERO_GNIRTS: &str = concat!(
+    "\"RTSJ\"4($$a:*a*00pv\n",
+    "                  >050\"The quick brown fox jumps over the lazy dog\"",
+    "05WR05W$$n00g1-:00p!#@_",
+);
+
+/// A Befunge-98 interpreter with no input, that discards its output
+pub fn interpreter() -> Befunge {
+    InterpreterBuilder::befunge().env(SilentEnv::new()).build()
+}
+
+/// An interpreter with `src` loaded at the origin
+pub fn with_program(src: &str) -> Befunge {
+    let mut interpreter = interpreter();
+    interpreter.load_program_at(bfvec(0, 0), src);
+    interpreter
+}
+
+/// Scatter some data over a few hundred pages
+pub fn scatter_pages(interpreter: &mut Befunge) {
+    for i in 0..300 {
+        interpreter.space[bfvec(i * 97 - 200_000, i * 31 - 60_000)] = 'x' as i64;
+    }
+}
+
+/// Run the program to the end; it must exit with code 0
+pub fn run_to_end(interpreter: &mut Befunge) {
+    assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+}
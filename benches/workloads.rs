@@ -0,0 +1,130 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+//! A fixed set of workloads, measured with criterion, for comparing changes
+//! to the interpreter's performance:
+//!
+//!  * moving across a long distance, wrapping around funge-space
+//!  * scanning through allocated but mostly blank pages
+//!  * sysinfo (`y`) in a loop, with the funge-space bounds cached, and with
+//!    the bounds recomputed every time
+//!  * lots of short-lived IPs from `t`
+//!  * string mode, and instructions that pop and push 0gnirtses
+//!
+//! Run with `cargo bench --features benchmarks --bench workloads`.
+
+mod common;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+use rfunge::{bfvec, FungeSpace};
+
+use common::*;
+
+fn run_program(c: &mut Criterion, name: &str, setup: impl Fn() -> Befunge) {
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            &setup,
+            |mut interpreter| run_to_end(&mut interpreter),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn move_by_wrap(c: &mut Criterion) {
+    let mut interpreter = interpreter();
+    // A few far-flung cells, so that wrapping has to skip a lot of nothing
+    for i in 0..10 {
+        interpreter.space[bfvec(i * 100_000, i * 30_000)] = 'x' as i64;
+    }
+    let space = &interpreter.space;
+    c.bench_function("move_by long-range wrap", |b| {
+        b.iter(|| {
+            // east off the edge, and diagonally from one cell to the next
+            black_box(space.move_by(black_box(bfvec(900_000, 270_000)), bfvec(1, 0)));
+            black_box(space.move_by(black_box(bfvec(0, 0)), bfvec(1, 1)));
+        })
+    });
+}
+
+fn dense_page_scan(c: &mut Criterion) {
+    let mut interpreter = interpreter();
+    // Only every 100th cell along the first row is set, but every page it
+    // passes through is allocated (for a cell on the next row)
+    for i in 0..100 {
+        interpreter.space[bfvec(i * 100, 0)] = 'x' as i64;
+    }
+    for i in 0..250 {
+        interpreter.space[bfvec(i * 40, 1)] = 'y' as i64;
+    }
+    let space = &interpreter.space;
+    for (name, delta) in [("east", bfvec(1, 0)), ("west", bfvec(-1, 0))] {
+        c.bench_function(&format!("scan mostly blank pages {}", name), |b| {
+            b.iter(|| {
+                let mut location = bfvec(0, 0);
+                loop {
+                    location = space.move_by(location, delta).0;
+                    if location == bfvec(0, 0) {
+                        break;
+                    }
+                }
+            })
+        });
+    }
+}
+
+fn sysinfo(c: &mut Criterion) {
+    let with_pages = |src| {
+        let mut interpreter = with_program(src);
+        scatter_pages(&mut interpreter);
+        interpreter
+    };
+    run_program(c, "sysinfo loop", || with_pages(Y_LOOP));
+    // Recomputing the bounds is slow, so take fewer samples
+    let mut group = c.benchmark_group("uncached");
+    group.sample_size(10);
+    group.bench_function("sysinfo loop, erasing", |b| {
+        b.iter_batched(
+            || with_pages(Y_LOOP_ERASING),
+            |mut interpreter| run_to_end(&mut interpreter),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn forks(c: &mut Criterion) {
+    run_program(c, "fork storm", || with_program(FORK_STORM));
+    run_program(c, "fork storm, 6 cell stack", || {
+        with_program(FORK_STORM_DEEP)
+    });
+}
+
+fn strings(c: &mut Criterion) {
+    run_program(c, "string mode", || with_program(STRING_MODE));
+    run_program(c, "0gnirts (JSTR)", || with_program(ZERO_GNIRTS));
+}
+
+criterion_group!(
+    benches,
+    move_by_wrap,
+    dense_page_scan,
+    sysinfo,
+    forks,
+    strings
+);
+criterion_main!(benches);
@@ -0,0 +1,123 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Benchmarks (`rfunge bench`)
+
+use std::time::{Duration, Instant};
+
+use rfunge::{
+    read_funge_src, IOMode, InstructionBudget, InterpreterBuilder, ProgramResult, RunMode,
+};
+
+use super::env::{CmdLineEnv, OutputBuffer};
+
+/// A program that runs forever, exercising one part of the interpreter
+pub struct Workload {
+    pub name: &'static str,
+    pub description: &'static str,
+    src: &'static str,
+}
+
+pub const WORKLOADS: &[Workload] = &[
+    Workload {
+        name: "tight-loop",
+        description: "one IP going round a small loop",
+        src: ">1+v\n^  <\n",
+    },
+    Workload {
+        name: "wrapping",
+        description: "one IP wrapping around a row 100000 cells wide",
+        src: "\"x\"a:*:*a*1p1+\n",
+    },
+    Workload {
+        name: "stack-churn",
+        description: "pushing, clearing and moving values between stacks",
+        src: ">\"abcdefghijklmnopqrstuvwxyz\"a{a}n v\n^                                 <\n",
+    },
+    Workload {
+        name: "concurrent",
+        description: "17 IPs going round the same loop",
+        src: "fkt>1+ v\n   ^   <\n",
+    },
+];
+
+/// Result of running a workload
+#[derive(Debug, Clone, Copy)]
+pub struct BenchResult {
+    pub instructions: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Print the names and descriptions of the workloads
+pub fn list_workloads() {
+    for workload in WORKLOADS {
+        println!("{:<12} {}", workload.name, workload.description);
+    }
+}
+
+/// Run a workload until it has executed (at least) `instructions`
+/// instructions
+pub fn run_workload(workload: &Workload, instructions: u64) -> Result<BenchResult, String> {
+    let mut env = CmdLineEnv::new(IOMode::Text, false, true, vec![workload.name.to_owned()]);
+    env.capture_output(OutputBuffer::new());
+    let mut interpreter = InterpreterBuilder::befunge().env(env).build();
+    read_funge_src(&mut interpreter.space, workload.src);
+    interpreter.settings.instruction_budget = Some(InstructionBudget::new(instructions));
+
+    let start = Instant::now();
+    let result = interpreter.run(RunMode::Run);
+    let elapsed = start.elapsed();
+
+    match result {
+        ProgramResult::TimedOut => Ok(BenchResult {
+            instructions: interpreter.settings.instruction_budget.unwrap().used,
+            elapsed,
+        }),
+        result => Err(format!("{} stopped early: {:?}", workload.name, result)),
+    }
+}
+
+/// Run the workloads called `names` (or all of them), and print a report
+/// to stdout
+pub fn run_benchmarks(names: &[&str], instructions: u64) -> Result<(), String> {
+    for name in names {
+        if !WORKLOADS.iter().any(|w| w.name == *name) {
+            return Err(format!("Unknown workload {}", name));
+        }
+    }
+    for workload in WORKLOADS {
+        if !names.is_empty() && !names.contains(&workload.name) {
+            continue;
+        }
+        let result = run_workload(workload, instructions)?;
+        println!(
+            "{:<12} {:>12} instructions  {:>8.3} s  {:>8.2} M instructions/s",
+            workload.name,
+            result.instructions,
+            result.elapsed.as_secs_f64(),
+            result.instructions_per_second() / 1e6
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,624 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Static analysis (`rfunge check`)
+//!
+//! Starting from the origin, follow every path the IP could take, assuming
+//! that any branch can go any way. This finds instructions that would
+//! reflect, strings that never end, fingerprints that can't be loaded, and
+//! code that is never executed. Programs that modify themselves (`p`) or
+//! move in computed directions (`x`) can't be fully analysed this way.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+use rfunge::fungespace::SrcIO;
+use rfunge::{
+    all_fingerprints, bfvec, fingerprint_to_string, read_funge_src, read_funge_src_bin,
+    safe_fingerprints, BefungeVec, FungeSpace, FungeValue, PagedFungeSpace,
+};
+
+use super::debugger::DebugIdx;
+
+/// Maximum number of known values kept on the simulated stack
+const MAX_STACK: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckOptions {
+    /// Befunge (2) or Unefunge (1)
+    pub dimensions: u32,
+    pub unicode: bool,
+    /// Only allow the fingerprints that are available in sandbox mode
+    pub sandbox: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Diagnostic {
+    /// Column and row
+    pub location: (i64, i64),
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{}: {}: {}",
+            self.location.0,
+            self.location.1,
+            match self.severity {
+                Severity::Note => "note",
+                Severity::Warning => "warning",
+            },
+            self.message
+        )
+    }
+}
+
+/// Analyse a program; the diagnostics are sorted by location
+pub fn check_src(src: &[u8], options: &CheckOptions) -> io::Result<Vec<Diagnostic>> {
+    if options.dimensions == 1 {
+        let space = PagedFungeSpace::<i64, i64>::new_with_page_size(1000);
+        check_space(space, src, options)
+    } else {
+        let space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(80, 25));
+        check_space(space, src, options)
+    }
+}
+
+type Xy = (i64, i64);
+
+/// Keep track of the values that are pushed (for `(`)
+fn push_known(stack: &mut Vec<Option<i64>>, v: Option<i64>) {
+    if stack.len() >= MAX_STACK {
+        stack.remove(0);
+    }
+    stack.push(v);
+}
+
+/// A place the IP can be, about to execute an instruction
+struct State {
+    location: Xy,
+    delta: Xy,
+    /// What we know about the stack (`None`: some value)
+    stack: Vec<Option<i64>>,
+}
+
+struct Checker<'a, Idx, Space> {
+    space: &'a Space,
+    options: &'a CheckOptions,
+    _idx: std::marker::PhantomData<Idx>,
+    /// States that have been (or will be) explored
+    seen: HashSet<(Xy, Xy)>,
+    todo: Vec<State>,
+    /// Cells that are executed, or used by the instructions that are
+    reached: HashSet<Xy>,
+    diagnostics: BTreeSet<Diagnostic>,
+    /// Fingerprint instructions (A-Z) that are executed
+    fingerprint_instructions: BTreeSet<Xy>,
+    /// Did the program load any fingerprints?
+    loads_fingerprints: bool,
+    /// Are we following an instruction executed by k?
+    iterating: bool,
+}
+
+fn check_space<Idx, Space>(
+    mut space: Space,
+    src: &[u8],
+    options: &CheckOptions,
+) -> io::Result<Vec<Diagnostic>>
+where
+    Idx: SrcIO<Space> + DebugIdx,
+    Space: FungeSpace<Idx>,
+    Space::Output: FungeValue,
+{
+    if options.unicode {
+        let src =
+            std::str::from_utf8(src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        read_funge_src(&mut space, src);
+    } else {
+        read_funge_src_bin(&mut space, src);
+    }
+    let (min, max) = match (space.min_idx(), space.max_idx()) {
+        (Some(min), Some(max)) => (min.to_xy(), max.to_xy()),
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut checker = Checker::<Idx, Space> {
+        space: &space,
+        options,
+        _idx: std::marker::PhantomData,
+        seen: HashSet::new(),
+        todo: Vec::new(),
+        reached: HashSet::new(),
+        diagnostics: BTreeSet::new(),
+        fingerprint_instructions: BTreeSet::new(),
+        loads_fingerprints: false,
+        iterating: false,
+    };
+    // The IP starts at the origin, going east
+    let start = checker.next((-1, 0), (1, 0));
+    checker.push(start, (1, 0), Vec::new());
+    while let Some(state) = checker.todo.pop() {
+        checker.step(state);
+    }
+    checker.finish(min, max);
+    Ok(checker.diagnostics.into_iter().collect())
+}
+
+impl<'a, Idx, Space> Checker<'a, Idx, Space>
+where
+    Idx: SrcIO<Space> + DebugIdx,
+    Space: FungeSpace<Idx>,
+    Space::Output: FungeValue,
+{
+    fn cell(&self, location: Xy) -> char {
+        match Idx::from_xy(location.0, location.1) {
+            Some(idx) => self.space[idx].to_char(),
+            None => ' ',
+        }
+    }
+
+    /// The next cell the IP executes after `location`
+    fn next(&self, location: Xy, delta: Xy) -> Xy {
+        match (
+            Idx::from_xy(location.0, location.1),
+            Idx::from_xy(delta.0, delta.1),
+        ) {
+            (Some(location), Some(delta)) => self.space.move_by(location, delta).0.to_xy(),
+            _ => location,
+        }
+    }
+
+    /// Walk from `location` to the next cell containing `c`. Returns its
+    /// location, and whether we had to come all the way around.
+    fn find(&mut self, location: Xy, delta: Xy, c: char) -> (Xy, bool) {
+        let mut pos = location;
+        loop {
+            pos = self.next(pos, delta);
+            self.reached.insert(pos);
+            if pos == location {
+                return (pos, true);
+            } else if self.cell(pos) == c {
+                return (pos, false);
+            }
+        }
+    }
+
+    fn push(&mut self, location: Xy, delta: Xy, stack: Vec<Option<i64>>) {
+        if self.seen.insert((location, delta)) {
+            self.todo.push(State {
+                location,
+                delta,
+                stack,
+            });
+        }
+    }
+
+    fn warn(&mut self, location: Xy, severity: Severity, message: String) {
+        if self.iterating {
+            // Reported where the instruction is
+            return;
+        }
+        self.diagnostics.insert(Diagnostic {
+            location,
+            severity,
+            message,
+        });
+    }
+
+    fn step(&mut self, state: State) {
+        self.reached.insert(state.location);
+        let c = self.cell(state.location);
+        self.exec(state, c);
+    }
+
+    /// Follow instruction `c` (normally the one at `state.location`)
+    fn exec(&mut self, state: State, c: char) {
+        let State {
+            location,
+            delta,
+            mut stack,
+        } = state;
+        let two_d = Idx::DIMENSIONS > 1;
+        let reverse = (-delta.0, -delta.1);
+        let left = (delta.1, -delta.0);
+        let right = (-delta.1, delta.0);
+        // Where the IP can go next: (cell, delta)
+        let mut next = Vec::new();
+        match c {
+            '"' => {
+                let (end, wrapped) = self.find(location, delta, '"');
+                if wrapped {
+                    self.warn(
+                        location,
+                        Severity::Warning,
+                        "string has no end: it wraps around to its own start".to_owned(),
+                    );
+                }
+                // Push the string (in the order the IP sees it)
+                let mut pos = location;
+                while !wrapped && pos != end {
+                    pos = self.next(pos, delta);
+                    if pos != end {
+                        push_known(&mut stack, Some(self.cell(pos) as i64));
+                    }
+                }
+                next.push((self.next(end, delta), delta));
+            }
+            ';' => {
+                let (end, wrapped) = self.find(location, delta, ';');
+                if wrapped {
+                    self.warn(
+                        location,
+                        Severity::Warning,
+                        "comment has no end: it wraps around to its own start".to_owned(),
+                    );
+                }
+                next.push((self.next(end, delta), delta));
+            }
+            '#' | '\'' | 's' => {
+                let skipped = (location.0 + delta.0, location.1 + delta.1);
+                self.reached.insert(skipped);
+                if c == '\'' {
+                    push_known(&mut stack, Some(self.cell(skipped) as i64));
+                } else {
+                    stack.clear();
+                }
+                next.push((self.next(skipped, delta), delta));
+            }
+            '0'..='9' | 'a'..='f' => {
+                push_known(&mut stack, c.to_digit(16).map(i64::from));
+                next.push((self.next(location, delta), delta));
+            }
+            '@' | 'q' => {}
+            'x' => self.warn(
+                location,
+                Severity::Note,
+                "the IP moves in a computed direction here; the analysis stops".to_owned(),
+            ),
+            '>' => next.push((self.next(location, (1, 0)), (1, 0))),
+            '<' => next.push((self.next(location, (-1, 0)), (-1, 0))),
+            '^' if two_d => next.push((self.next(location, (0, -1)), (0, -1))),
+            'v' if two_d => next.push((self.next(location, (0, 1)), (0, 1))),
+            '[' if two_d => next.push((self.next(location, left), left)),
+            ']' if two_d => next.push((self.next(location, right), right)),
+            'r' => next.push((self.next(location, reverse), reverse)),
+            '_' => {
+                stack.pop();
+                for d in [(1, 0), (-1, 0)] {
+                    next.push((self.next(location, d), d));
+                }
+            }
+            '|' if two_d => {
+                stack.pop();
+                for d in [(0, 1), (0, -1)] {
+                    next.push((self.next(location, d), d));
+                }
+            }
+            'w' if two_d => {
+                stack.clear();
+                for d in [delta, left, right] {
+                    next.push((self.next(location, d), d));
+                }
+            }
+            '?' => {
+                let dirs: &[Xy] = if two_d {
+                    &[(1, 0), (0, 1), (-1, 0), (0, -1)]
+                } else {
+                    &[(1, 0), (-1, 0)]
+                };
+                for &d in dirs {
+                    next.push((self.next(location, d), d));
+                }
+            }
+            't' => {
+                // The new IP goes the other way
+                stack.clear();
+                next.push((self.next(location, delta), delta));
+                next.push((self.next(location, reverse), reverse));
+            }
+            'j' => {
+                // We don't know how far, but it's somewhere on this line
+                stack.clear();
+                let mut pos = location;
+                loop {
+                    pos = self.next(pos, delta);
+                    next.push((pos, delta));
+                    if pos == location {
+                        break;
+                    }
+                }
+            }
+            'k' => {
+                stack.clear();
+                // k looks for its operand past any ; comments
+                let mut operand = self.next(location, delta);
+                while self.cell(operand) == ';' {
+                    let (end, wrapped) = self.find(operand, delta, ';');
+                    if wrapped {
+                        break;
+                    }
+                    operand = self.next(end, delta);
+                }
+                self.reached.insert(operand);
+                // 0k skips the operand
+                next.push((self.next(operand, delta), delta));
+                // Otherwise, the operand is executed in place of k, and then
+                // the IP moves on as usual (and executes it once more)
+                match self.cell(operand) {
+                    'k' | '"' | ';' => next.push((self.next(location, delta), delta)),
+                    op => {
+                        self.iterating = true;
+                        self.exec(
+                            State {
+                                location,
+                                delta,
+                                stack: Vec::new(),
+                            },
+                            op,
+                        );
+                        self.iterating = false;
+                    }
+                }
+            }
+            '(' | ')' => {
+                if c == '(' {
+                    self.loads_fingerprints = true;
+                    self.check_fingerprint(location, &stack);
+                }
+                stack.clear();
+                // Reflects if the fingerprint can't be (un)loaded
+                next.push((self.next(location, delta), delta));
+                next.push((self.next(location, reverse), reverse));
+            }
+            'A'..='Z' => {
+                self.fingerprint_instructions.insert(location);
+                stack.clear();
+                next.push((self.next(location, delta), delta));
+                next.push((self.next(location, reverse), reverse));
+            }
+            // Instructions that reflect on failure
+            '~' | '&' | 'i' | 'o' | '=' | '{' | '}' | 'u' => {
+                stack.clear();
+                next.push((self.next(location, delta), delta));
+                next.push((self.next(location, reverse), reverse));
+            }
+            ' ' | '!' | '$' | '%' | '*' | '+' | ',' | '-' | '.' | '/' | ':' | '\\' | '`' | 'g'
+            | 'n' | 'p' | 'y' | 'z' => {
+                if c == 'p' {
+                    self.warn(
+                        location,
+                        Severity::Note,
+                        "the program modifies itself here; the analysis may be incomplete"
+                            .to_owned(),
+                    );
+                }
+                stack.clear();
+                next.push((self.next(location, delta), delta));
+            }
+            _ => {
+                self.warn(
+                    location,
+                    Severity::Warning,
+                    format!("unknown instruction {:?} reflects", c),
+                );
+                stack.clear();
+                next.push((self.next(location, reverse), reverse));
+            }
+        }
+        let n = next.len();
+        for (i, (location, delta)) in next.into_iter().enumerate() {
+            if i + 1 == n {
+                self.push(location, delta, std::mem::take(&mut stack));
+            } else {
+                self.push(location, delta, stack.clone());
+            }
+        }
+    }
+
+    /// Check the fingerprint loaded by `(`, if we know which one it is
+    fn check_fingerprint(&mut self, location: Xy, stack: &[Option<i64>]) {
+        let mut values = stack.iter().rev();
+        let count = match values.next() {
+            Some(Some(count)) if (1..=4).contains(count) => *count,
+            _ => return,
+        };
+        let mut fpr: i32 = 0;
+        for _ in 0..count {
+            match values.next() {
+                Some(Some(v)) => fpr = (fpr << 8).wrapping_add(*v as i32),
+                _ => return,
+            }
+        }
+        let name = fingerprint_to_string(fpr);
+        if !all_fingerprints().contains(&fpr) {
+            self.warn(
+                location,
+                Severity::Warning,
+                format!("fingerprint {:?} is not available", name),
+            );
+        } else if self.options.sandbox && !safe_fingerprints().contains(&fpr) {
+            self.warn(
+                location,
+                Severity::Warning,
+                format!("fingerprint {:?} is not allowed in sandbox mode", name),
+            );
+        }
+    }
+
+    fn finish(&mut self, min: Xy, max: Xy) {
+        // Fingerprint instructions are unknown if nothing is ever loaded
+        if !self.loads_fingerprints {
+            for location in std::mem::take(&mut self.fingerprint_instructions) {
+                let c = self.cell(location);
+                self.warn(
+                    location,
+                    Severity::Warning,
+                    format!(
+                        "unknown instruction {:?} reflects (no fingerprint is loaded)",
+                        c
+                    ),
+                );
+            }
+        }
+
+        // Group the cells that are never reached into regions
+        let mut unreached = HashSet::new();
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                if !self.reached.contains(&(x, y)) && self.cell((x, y)) != ' ' {
+                    unreached.insert((x, y));
+                }
+            }
+        }
+        let mut cells: Vec<Xy> = unreached.iter().copied().collect();
+        cells.sort_by_key(|&(x, y)| (y, x));
+        for first in cells {
+            if !unreached.remove(&first) {
+                continue;
+            }
+            let (mut lo, mut hi, mut count) = (first, first, 0);
+            let mut region = vec![first];
+            while let Some((x, y)) = region.pop() {
+                count += 1;
+                lo = (lo.0.min(x), lo.1.min(y));
+                hi = (hi.0.max(x), hi.1.max(y));
+                for neighbour in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                    if unreached.remove(&neighbour) {
+                        region.push(neighbour);
+                    }
+                }
+            }
+            let message = if count == 1 {
+                "this cell is never executed".to_owned()
+            } else if Idx::DIMENSIONS == 1 {
+                format!("{} cells up to {} are never executed", count, hi.0)
+            } else {
+                format!("{} cells up to {},{} are never executed", count, hi.0, hi.1)
+            };
+            self.warn(lo, Severity::Note, message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFUNGE: CheckOptions = CheckOptions {
+        dimensions: 2,
+        unicode: false,
+        sandbox: false,
+    };
+
+    fn check(src: &str, options: &CheckOptions) -> Vec<String> {
+        check_src(src.as_bytes(), options)
+            .unwrap()
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_program() {
+        assert!(check("\"olleh\">:#,_@", &BEFUNGE).is_empty());
+        // a known fingerprint makes A-Z valid
+        assert!(check("\"AMOR\"4(A@", &BEFUNGE).is_empty());
+    }
+
+    #[test]
+    fn test_unterminated() {
+        assert_eq!(
+            check("\"abc", &BEFUNGE),
+            ["0,0: warning: string has no end: it wraps around to its own start"]
+        );
+        assert_eq!(
+            check(";abc@", &BEFUNGE),
+            ["0,0: warning: comment has no end: it wraps around to its own start"]
+        );
+    }
+
+    #[test]
+    fn test_reflecting_instructions() {
+        assert_eq!(
+            check("h@", &BEFUNGE),
+            ["0,0: warning: unknown instruction 'h' reflects"]
+        );
+        assert_eq!(
+            check("A@", &BEFUNGE),
+            ["0,0: warning: unknown instruction 'A' reflects (no fingerprint is loaded)"]
+        );
+    }
+
+    #[test]
+    fn test_incomplete_analysis() {
+        assert_eq!(
+            check("x@", &BEFUNGE),
+            [
+                "0,0: note: the IP moves in a computed direction here; the analysis stops",
+                "1,0: note: this cell is never executed",
+            ]
+        );
+        assert_eq!(
+            check("00p@", &BEFUNGE),
+            ["2,0: note: the program modifies itself here; the analysis may be incomplete"]
+        );
+    }
+
+    #[test]
+    fn test_fingerprints() {
+        assert_eq!(
+            check("\"XXXX\"4(@", &BEFUNGE),
+            ["7,0: warning: fingerprint \"XXXX\" is not available"]
+        );
+        assert!(check("\"KCOS\"4(@", &BEFUNGE).is_empty());
+        let sandbox = CheckOptions {
+            sandbox: true,
+            ..BEFUNGE
+        };
+        assert_eq!(
+            check("\"KCOS\"4(@", &sandbox),
+            ["7,0: warning: fingerprint \"SOCK\" is not allowed in sandbox mode"]
+        );
+    }
+
+    #[test]
+    fn test_unreached() {
+        assert_eq!(
+            check("@\nab\ncd", &BEFUNGE),
+            ["0,1: note: 4 cells up to 1,2 are never executed"]
+        );
+        let unefunge = CheckOptions {
+            dimensions: 1,
+            ..BEFUNGE
+        };
+        assert_eq!(
+            check("@ abc", &unefunge),
+            ["2,0: note: 3 cells up to 4 are never executed"]
+        );
+    }
+}
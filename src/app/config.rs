@@ -0,0 +1,233 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Default settings from `rfunge.toml`
+//!
+//! The file is looked for in the current directory, and then in
+//! `$XDG_CONFIG_HOME/rfunge/` (or `~/.config/rfunge/`). For example:
+//!
+//! ```toml
+//! cell-size = 32                  # or 64
+//! io = "unicode"                  # or "binary"
+//! sandbox = true
+//! fingerprints = ["ROMA", "MODU"] # the fingerprints programs may load
+//! turt-output-dir = "drawings"    # relative to the file
+//! turt-format = "pdf"             # or "svg", "png"
+//! ```
+//!
+//! Command line flags take precedence.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+use super::turt_image::TurtImageFormat;
+
+pub const CONFIG_FILE_NAME: &str = "rfunge.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// 32 or 64
+    pub cell_size: Option<u32>,
+    /// Unicode (rather than binary) mode
+    pub unicode: Option<bool>,
+    pub sandbox: Option<bool>,
+    /// Fingerprint names
+    pub fingerprints: Option<Vec<String>>,
+    /// Where TURT writes its images
+    pub turt_output_dir: Option<PathBuf>,
+    pub turt_format: Option<TurtImageFormat>,
+}
+
+impl Config {
+    /// Find and read the config file, if there is one
+    pub fn load() -> Result<Option<Self>, String> {
+        match Self::find() {
+            Some(path) => {
+                let src = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                let config = Self::parse(&src, path.parent().unwrap_or_else(|| Path::new("")))
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                Ok(Some(config))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn find() -> Option<PathBuf> {
+        let local = PathBuf::from(CONFIG_FILE_NAME);
+        if local.is_file() {
+            return Some(local);
+        }
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        let global = config_home.join("rfunge").join(CONFIG_FILE_NAME);
+        if global.is_file() {
+            Some(global)
+        } else {
+            None
+        }
+    }
+
+    /// Parse a config file; relative paths are relative to `base_dir`
+    pub fn parse(src: &str, base_dir: &Path) -> Result<Self, String> {
+        let value = src.parse::<Value>().map_err(|e| e.to_string())?;
+        let table = value.as_table().ok_or("expected a table")?;
+        let mut config = Self::default();
+        for (key, value) in table {
+            let invalid = || format!("invalid value for {}: {}", key, value);
+            match key.as_str() {
+                "cell-size" => {
+                    config.cell_size = match value.as_integer() {
+                        Some(32) => Some(32),
+                        Some(64) => Some(64),
+                        _ => return Err(invalid()),
+                    }
+                }
+                "io" => {
+                    config.unicode = match value.as_str() {
+                        Some("unicode") => Some(true),
+                        Some("binary") => Some(false),
+                        _ => return Err(invalid()),
+                    }
+                }
+                "sandbox" => config.sandbox = Some(value.as_bool().ok_or_else(invalid)?),
+                "fingerprints" => {
+                    config.fingerprints = Some(
+                        value
+                            .as_array()
+                            .ok_or_else(invalid)?
+                            .iter()
+                            .map(|name| name.as_str().map(str::to_owned).ok_or_else(invalid))
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+                "turt-output-dir" => {
+                    config.turt_output_dir =
+                        Some(base_dir.join(value.as_str().ok_or_else(invalid)?))
+                }
+                "turt-format" => {
+                    config.turt_format = Some(
+                        value
+                            .as_str()
+                            .and_then(TurtImageFormat::from_name)
+                            .ok_or_else(invalid)?,
+                    )
+                }
+                _ => return Err(format!("unknown setting {}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Resolve an on/off setting: the `--on` and `--off` flags take precedence
+/// over `setting`, which comes from the config file (or the program's header)
+pub fn choose_flag(on: bool, off: bool, setting: Option<bool>) -> bool {
+    on || (!off && setting == Some(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+cell-size = 32                  # or 64
+io = "unicode"                  # or "binary"
+sandbox = true
+fingerprints = ["ROMA", "MODU"] # the fingerprints programs may load
+turt-output-dir = "drawings"    # relative to the file
+turt-format = "pdf"             # or "svg", "png"
+"#;
+
+    #[test]
+    fn test_parse() {
+        let base_dir = Path::new("/home/user/.config/rfunge");
+        assert_eq!(
+            Config::parse(SAMPLE, base_dir).unwrap(),
+            Config {
+                cell_size: Some(32),
+                unicode: Some(true),
+                sandbox: Some(true),
+                fingerprints: Some(vec!["ROMA".to_owned(), "MODU".to_owned()]),
+                turt_output_dir: Some(base_dir.join("drawings")),
+                turt_format: Some(TurtImageFormat::Pdf),
+            }
+        );
+        assert_eq!(Config::parse("", base_dir).unwrap(), Config::default());
+        let config = Config::parse(
+            "cell-size = 64\nio = \"binary\"\nturt-output-dir = \"/tmp\"",
+            base_dir,
+        )
+        .unwrap();
+        assert_eq!(config.cell_size, Some(64));
+        assert_eq!(config.unicode, Some(false));
+        assert_eq!(config.turt_output_dir, Some(PathBuf::from("/tmp")));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |src| Config::parse(src, Path::new("")).unwrap_err();
+        assert_eq!(parse("cellsize = 32"), "unknown setting cellsize");
+        assert_eq!(
+            parse("[sandbox]\nenabled = true"),
+            "invalid value for sandbox: enabled = true\n"
+        );
+        assert_eq!(parse("cell-size = 16"), "invalid value for cell-size: 16");
+        assert_eq!(
+            parse("cell-size = \"32\""),
+            "invalid value for cell-size: \"32\""
+        );
+        assert_eq!(parse("io = \"utf-8\""), "invalid value for io: \"utf-8\"");
+        assert_eq!(
+            parse("sandbox = \"yes\""),
+            "invalid value for sandbox: \"yes\""
+        );
+        assert_eq!(
+            parse("fingerprints = \"ROMA\""),
+            "invalid value for fingerprints: \"ROMA\""
+        );
+        assert_eq!(
+            parse("fingerprints = [\"ROMA\", 3]"),
+            "invalid value for fingerprints: [\"ROMA\", 3]"
+        );
+        assert_eq!(
+            parse("turt-format = \"gif\""),
+            "invalid value for turt-format: \"gif\""
+        );
+        // Not TOML at all
+        parse("sandbox");
+        parse("sandbox = true\nsandbox = false");
+    }
+
+    #[test]
+    fn test_precedence() {
+        // No flags: the setting applies
+        assert!(!choose_flag(false, false, None));
+        assert!(!choose_flag(false, false, Some(false)));
+        assert!(choose_flag(false, false, Some(true)));
+        // Either flag overrides it
+        assert!(choose_flag(true, false, None));
+        assert!(choose_flag(true, false, Some(false)));
+        assert!(!choose_flag(false, true, Some(true)));
+        assert!(!choose_flag(false, true, None));
+    }
+}
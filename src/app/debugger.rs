@@ -0,0 +1,435 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Interactive step debugger for the command line (`--debug`)
+
+use std::fmt::Display;
+use std::io::{stderr, stdin, BufRead, IsTerminal, Write};
+
+use futures_lite::future::block_on;
+use futures_lite::io::AsyncWriteExt;
+
+use rfunge::fungespace::SrcIO;
+use rfunge::interpreter::MotionCmds;
+use rfunge::{
+    bfvec, BefungeVec, Breakpoint, BreakpointId, FungeSpace, FungeValue, InstructionPointer,
+    Interpreter, InterpreterEnv, ProgramResult, RunMode,
+};
+
+use super::panic::print_history;
+
+/// Default number of cells to show on each side of the IP in `list`
+const LIST_RADIUS: i64 = 5;
+
+const HELP: &str = "\
+commands:
+  step [N]          run N ticks (default 1)          (alias: s)
+  stepip            move only the next IP            (alias: i)
+  continue          run until a breakpoint is hit    (alias: c)
+  break X[,Y]       break when an IP reaches a cell  (alias: b)
+  watch X[,Y]       break when a cell is changed     (alias: w)
+  depth N           break when a stack holds N values
+  delete N          delete breakpoint N              (alias: d)
+  breakpoints       list breakpoints
+  ips               show all IPs
+  stack [IP]        show the stack stack of an IP
+  history [IP]      show the last steps of an IP (see --history)
+  list [R]          show the space around the first IP (alias: l)
+                    (IPs are shown on the cell they execute next)
+  quit              stop the program                 (alias: q)
+An empty line repeats the previous command.";
+
+/// Coordinate types that can be shown on a (two-dimensional) screen, by the
+/// debugger and the visualizer
+pub trait DebugIdx: Sized {
+    /// Number of dimensions (1 or 2)
+    const DIMENSIONS: u32;
+
+    /// Screen position (column, row) of this cell
+    fn to_xy(&self) -> (i64, i64);
+
+    /// The cell at a screen position, if there is one
+    fn from_xy(x: i64, y: i64) -> Option<Self>;
+
+    /// The rows of cells within `radius` of `center` (wider than high, since
+    /// terminal cells are tall)
+    fn window(center: &Self, radius: i64) -> Vec<Vec<Self>> {
+        let (x, y) = center.to_xy();
+        let (dx, dy) = if Self::DIMENSIONS == 1 {
+            (4 * radius, 0)
+        } else {
+            (2 * radius, radius)
+        };
+        (y.saturating_sub(dy)..=y.saturating_add(dy))
+            .map(|y| {
+                (x.saturating_sub(dx)..=x.saturating_add(dx))
+                    .filter_map(|x| Self::from_xy(x, y))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|row| !row.is_empty())
+            .collect()
+    }
+}
+
+impl DebugIdx for i32 {
+    const DIMENSIONS: u32 = 1;
+
+    fn to_xy(&self) -> (i64, i64) {
+        (*self as i64, 0)
+    }
+
+    fn from_xy(x: i64, y: i64) -> Option<Self> {
+        if y == 0 {
+            x.try_into().ok()
+        } else {
+            None
+        }
+    }
+}
+
+impl DebugIdx for i64 {
+    const DIMENSIONS: u32 = 1;
+
+    fn to_xy(&self) -> (i64, i64) {
+        (*self, 0)
+    }
+
+    fn from_xy(x: i64, y: i64) -> Option<Self> {
+        if y == 0 {
+            Some(x)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> DebugIdx for BefungeVec<T>
+where
+    T: FungeValue + Copy,
+{
+    const DIMENSIONS: u32 = 2;
+
+    fn to_xy(&self) -> (i64, i64) {
+        (
+            self.x.to_i64().unwrap_or_default(),
+            self.y.to_i64().unwrap_or_default(),
+        )
+    }
+
+    fn from_xy(x: i64, y: i64) -> Option<Self> {
+        Some(bfvec(T::from_i64(x)?, T::from_i64(y)?))
+    }
+}
+
+/// Run the program under the interactive debugger
+///
+/// Commands are read line by line from stdin, and the debugger's output goes
+/// to stderr so that it stays out of the program's output. Note that stdin is
+/// shared with the program: input instructions consume the lines that follow
+/// the current command.
+pub fn run_debugger<Idx, Space, Env>(
+    interpreter: &mut Interpreter<Idx, Space, Env>,
+) -> ProgramResult
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + DebugIdx + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+    Env: InterpreterEnv + 'static,
+{
+    eprintln!("rfunge debugger - type \"help\" for a list of commands");
+    show_ips(interpreter);
+    let stdin = stdin();
+    let mut last_line = String::new();
+    loop {
+        eprint!("(rfunge) ");
+        stderr().flush().ok();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                eprintln!();
+                return ProgramResult::Paused;
+            }
+            Ok(_) => {}
+        }
+        if line.trim().is_empty() {
+            line = last_line.clone();
+        } else {
+            last_line = line.clone();
+        }
+        let mut words = line.split_whitespace();
+        let cmd = words.next().unwrap_or_default();
+        let arg = words.next();
+        let result = match cmd {
+            "" => continue,
+            "s" | "step" => {
+                let n = match arg.map(str::parse::<u64>) {
+                    None => 1,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => {
+                        eprintln!("expected a number of ticks");
+                        continue;
+                    }
+                };
+                let mut result = ProgramResult::Paused;
+                for _ in 0..n {
+                    result = interpreter.run(RunMode::Step);
+                    if result != ProgramResult::Paused {
+                        break;
+                    }
+                }
+                result
+            }
+            "i" | "stepip" => interpreter.run(RunMode::StepIp),
+            "c" | "continue" => interpreter.run(RunMode::Run),
+            "b" | "break" | "w" | "watch" => {
+                match arg.and_then(|s| <Idx as SrcIO<Space>>::parse_idx(s)) {
+                    Some(idx) => {
+                        let bp = if cmd.starts_with('b') {
+                            Breakpoint::Location(idx)
+                        } else {
+                            Breakpoint::Watch(idx)
+                        };
+                        let id = interpreter.debugger.add(bp);
+                        eprintln!("breakpoint {} set", id.0);
+                    }
+                    None => eprintln!("expected coordinates"),
+                }
+                continue;
+            }
+            "depth" => {
+                match arg.and_then(|s| s.parse().ok()) {
+                    Some(depth) => {
+                        let id = interpreter.debugger.add(Breakpoint::StackDepth(depth));
+                        eprintln!("breakpoint {} set", id.0);
+                    }
+                    None => eprintln!("expected a stack depth"),
+                }
+                continue;
+            }
+            "d" | "delete" => {
+                match arg.and_then(|s| s.parse().ok()) {
+                    Some(id) if interpreter.debugger.remove(BreakpointId(id)) => {}
+                    _ => eprintln!("no such breakpoint"),
+                }
+                continue;
+            }
+            "breakpoints" => {
+                for (id, bp) in interpreter.debugger.breakpoints() {
+                    match bp {
+                        Breakpoint::Location(idx) => eprintln!("{:>4}  break at {}", id.0, idx),
+                        Breakpoint::Watch(idx) => eprintln!("{:>4}  watch {}", id.0, idx),
+                        Breakpoint::StackDepth(depth) => {
+                            eprintln!("{:>4}  stack depth {}", id.0, depth)
+                        }
+                    }
+                }
+                continue;
+            }
+            "ips" => {
+                show_ips(interpreter);
+                continue;
+            }
+            "stack" => {
+                show_stack(interpreter, arg);
+                continue;
+            }
+            "history" => {
+                match find_ip(interpreter, arg) {
+                    Some(ip) if ip.history.is_empty() => {
+                        eprintln!("no history (run with --history N to keep one)")
+                    }
+                    Some(ip) => print_history(ip),
+                    None => eprintln!("no such IP"),
+                }
+                continue;
+            }
+            "l" | "list" => {
+                match arg.map(str::parse::<i64>) {
+                    None => list(interpreter, LIST_RADIUS),
+                    Some(Ok(radius)) if radius >= 0 => list(interpreter, radius),
+                    _ => eprintln!("expected a radius"),
+                }
+                continue;
+            }
+            "h" | "help" => {
+                eprintln!("{}", HELP);
+                continue;
+            }
+            "q" | "quit" => return ProgramResult::Paused,
+            _ => {
+                eprintln!(
+                    "unknown command {:?} - type \"help\" for a list of commands",
+                    cmd
+                );
+                continue;
+            }
+        };
+        // Show the program's output so far before the debugger's
+        block_on(interpreter.env.output_writer().flush()).ok();
+        match result {
+            ProgramResult::Paused => show_ips(interpreter),
+            ProgramResult::Hit(id) => {
+                eprintln!("breakpoint {} hit", id.0);
+                show_ips(interpreter);
+            }
+            ProgramResult::AwaitingInput => {
+                eprintln!("waiting for input");
+            }
+            ProgramResult::Done(code) => {
+                eprintln!("program finished with exit code {}", code);
+                return result;
+            }
+            ProgramResult::Panic | ProgramResult::TimedOut | ProgramResult::LoopDetected => {
+                eprintln!("program aborted");
+                return result;
+            }
+        }
+    }
+}
+
+/// The cell an IP will execute next, and its instruction
+fn next_cell<'a, Idx, Space, Env>(
+    interpreter: &'a Interpreter<Idx, Space, Env>,
+    ip: &InstructionPointer<Interpreter<Idx, Space, Env>>,
+) -> (Idx, &'a Space::Output)
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + DebugIdx + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+    Env: InterpreterEnv + 'static,
+{
+    interpreter.space.move_by(ip.location, ip.delta)
+}
+
+fn show_ips<Idx, Space, Env>(interpreter: &Interpreter<Idx, Space, Env>)
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + DebugIdx + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+    Env: InterpreterEnv + 'static,
+{
+    for ip in &interpreter.ips {
+        let stack = ip.stack();
+        let top = stack
+            .iter()
+            .rev()
+            .take(5)
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (next, instruction) = next_cell(interpreter, ip);
+        eprintln!(
+            "IP {}: at {}, next {} {:?}, delta {}  stack[{}]: {}",
+            ip.id,
+            ip.location,
+            next,
+            instruction.to_char(),
+            ip.delta,
+            stack.len(),
+            top
+        );
+    }
+}
+
+/// The IP with the given ID, or the first IP
+fn find_ip<'a, Idx, Space, Env>(
+    interpreter: &'a Interpreter<Idx, Space, Env>,
+    ip_id: Option<&str>,
+) -> Option<&'a InstructionPointer<Interpreter<Idx, Space, Env>>>
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + DebugIdx + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+    Env: InterpreterEnv + 'static,
+{
+    match ip_id {
+        None => interpreter.ips.first(),
+        Some(s) => interpreter
+            .ips
+            .iter()
+            .find(|ip| ip.id.to_string() == s.trim()),
+    }
+}
+
+fn show_stack<Idx, Space, Env>(interpreter: &Interpreter<Idx, Space, Env>, ip_id: Option<&str>)
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + DebugIdx + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+    Env: InterpreterEnv + 'static,
+{
+    let ip = match find_ip(interpreter, ip_id) {
+        Some(ip) => ip,
+        None => {
+            eprintln!("no such IP");
+            return;
+        }
+    };
+    eprintln!("IP {}: storage offset {}", ip.id, ip.storage_offset);
+    for (i, stack) in ip.stack_stack.iter().rev().enumerate() {
+        let values = stack
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let label = if i == 0 {
+            "TOSS".to_owned()
+        } else {
+            format!("SOSS{}", i)
+        };
+        eprintln!("{:>6}: [{}] {}", label, stack.len(), values);
+    }
+}
+
+fn list<Idx, Space, Env>(interpreter: &Interpreter<Idx, Space, Env>, radius: i64)
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + DebugIdx + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+    Env: InterpreterEnv + 'static,
+{
+    let next: Vec<Idx> = interpreter
+        .ips
+        .iter()
+        .map(|ip| next_cell(interpreter, ip).0)
+        .collect();
+    let center = match next.first() {
+        Some(idx) => *idx,
+        None => return,
+    };
+    // Highlight IPs in reverse video if we can, otherwise in brackets
+    let ansi = stderr().is_terminal();
+    for row in Idx::window(&center, radius) {
+        let mut line = String::new();
+        let start = match row.first() {
+            Some(idx) => idx.to_string(),
+            None => continue,
+        };
+        for idx in &row {
+            let c = interpreter.space[*idx].to_char();
+            let c = if c.is_control() { '·' } else { c };
+            let has_ip = next.contains(idx);
+            match (has_ip, ansi) {
+                (false, _) => line.push(c),
+                (true, true) => line.push_str(&format!("\x1b[7m{}\x1b[0m", c)),
+                (true, false) => line.push_str(&format!("[{}]", c)),
+            }
+        }
+        eprintln!("{:>16} {}", start, line);
+    }
+}
@@ -0,0 +1,189 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Snapshots of the interpreter state for the command line (`--dump-state`)
+//!
+//! The IPs are written with the same fields as [rfunge::IpState], followed
+//! by the bounds of the populated part of funge-space (but not its
+//! contents).
+
+use std::fmt::Write as _;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use rfunge::fungespace::SrcIO;
+use rfunge::interpreter::{IpMode, MotionCmds};
+use rfunge::{
+    fingerprint_to_string, FungeSpace, FungeValue, Interpreter, InterpreterEnv, ProgramResult,
+};
+
+use super::limits::{run_with_limits, RunLimits};
+use super::trace::json_escape;
+
+/// Writes the state of the interpreter to a JSON file when the program ends,
+/// and (on Unix) whenever the process receives SIGUSR1
+#[derive(Debug, Clone)]
+pub struct StateDumper {
+    path: PathBuf,
+    requested: Arc<AtomicBool>,
+}
+
+impl StateDumper {
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        let requested = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGUSR1, requested.clone())?;
+        Ok(Self { path, requested })
+    }
+
+    /// Write the state if a signal asked for it since the last call
+    pub fn poll<Idx, Space, Env>(&self, interpreter: &Interpreter<Idx, Space, Env>)
+    where
+        Idx: MotionCmds<Space, Env> + SrcIO<Space> + 'static,
+        Space: FungeSpace<Idx> + 'static,
+        Space::Output: FungeValue + 'static,
+        Env: InterpreterEnv + 'static,
+    {
+        if self.requested.swap(false, Ordering::AcqRel) && self.dump(interpreter, "signal") {
+            eprintln!("State written to {}", self.path.display());
+        }
+    }
+
+    /// Write the state, giving `reason` (`exit` or `signal`) for the dump.
+    /// Returns false (after printing an error) if the file can't be written.
+    pub fn dump<Idx, Space, Env>(
+        &self,
+        interpreter: &Interpreter<Idx, Space, Env>,
+        reason: &str,
+    ) -> bool
+    where
+        Idx: MotionCmds<Space, Env> + SrcIO<Space> + 'static,
+        Space: FungeSpace<Idx> + 'static,
+        Space::Output: FungeValue + 'static,
+        Env: InterpreterEnv + 'static,
+    {
+        match std::fs::write(&self.path, state_json(interpreter, reason)) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("ERROR: {}: {}", self.path.display(), e);
+                false
+            }
+        }
+    }
+}
+
+/// Run the program (see [run_with_limits]), dumping the state on
+/// request and when it ends
+pub fn run_with_dumps<Idx, Space, Env>(
+    interpreter: &mut Interpreter<Idx, Space, Env>,
+    limits: RunLimits,
+    dumper: Option<&StateDumper>,
+) -> ProgramResult
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    match dumper {
+        Some(dumper) => {
+            let result = run_with_limits(
+                interpreter,
+                limits,
+                Some(&mut |interpreter: &Interpreter<Idx, Space, Env>| dumper.poll(interpreter)),
+            );
+            dumper.dump(interpreter, "exit");
+            result
+        }
+        None => run_with_limits(interpreter, limits, None),
+    }
+}
+
+fn state_json<Idx, Space, Env>(interpreter: &Interpreter<Idx, Space, Env>, reason: &str) -> String
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    let vector = |idx: Idx| {
+        let mut components = Vec::new();
+        Idx::push_vector_onto(&mut components, idx);
+        let components: Vec<_> = components.iter().map(|c| c.to_string()).collect();
+        format!("[{}]", components.join(","))
+    };
+
+    let mut out = String::new();
+    write!(
+        out,
+        "{{\n  \"reason\": \"{}\",\n  \"ticks\": {},\n  \"bounds\": ",
+        reason,
+        interpreter.ticks()
+    )
+    .ok();
+    match (interpreter.space.min_idx(), interpreter.space.max_idx()) {
+        (Some(min), Some(max)) => write!(
+            out,
+            "{{\"min\": {}, \"max\": {}}}",
+            vector(min),
+            vector(max)
+        )
+        .ok(),
+        _ => write!(out, "null").ok(),
+    };
+    out.push_str(",\n  \"ips\": [");
+    for (i, ip) in interpreter.ips.iter().enumerate() {
+        let stacks: Vec<String> = ip
+            .stack_stack
+            .iter()
+            .rev()
+            .map(|stack| {
+                let values: Vec<_> = stack.iter().map(|v| v.to_string()).collect();
+                format!("[{}]", values.join(","))
+            })
+            .collect();
+        write!(
+            out,
+            "{}\n    {{\"id\": {}, \"location\": {}, \"delta\": {}, \"storage_offset\": {}, \
+             \"stack_stack\": [{}], \"string_mode\": {}, \"fingerprints\": [",
+            if i == 0 { "" } else { "," },
+            ip.id,
+            vector(ip.location),
+            vector(ip.delta),
+            vector(ip.storage_offset),
+            stacks.join(","),
+            ip.mode.contains(IpMode::STRING),
+        )
+        .ok();
+        for (j, fpr) in ip.instructions.loaded_fingerprints().iter().enumerate() {
+            if j > 0 {
+                out.push(',');
+            }
+            out.push('"');
+            for c in fingerprint_to_string(*fpr).chars() {
+                json_escape(&mut out, c);
+            }
+            out.push('"');
+        }
+        out.push_str("]}");
+    }
+    out.push_str("\n  ]\n}\n");
+    out
+}
@@ -18,29 +18,123 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use std::any::Any;
 use std::fs::File;
-use std::io::{stderr, Error, ErrorKind, Read, Result, Write};
-use std::process::Command;
+use std::io::{stderr, Result, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
 
-use async_std::io::{stdin, stdout, Stdin, Stdout};
+use async_std::io::{stdin, stdout};
 use futures_lite::io::{AsyncRead, AsyncWrite};
+use futures_lite::ready;
 
 use rfunge::interpreter::fingerprints::{
     string_to_fingerprint,
     TURT::{SimpleRobot, TurtleRobotBox},
 };
-use rfunge::{all_fingerprints, safe_fingerprints, ExecMode, IOMode, InterpreterEnv};
+use rfunge::{
+    all_fingerprints, safe_fingerprints, ExecMode, Funge, FungeFilesystem, IOMode, InterpreterEnv,
+    RealFilesystem, ResourceLimits, TraceEvent,
+};
 
+use super::file_access::AllowedDirs;
+use super::sound::TonePlayer;
+use super::trace::Tracer;
 use super::turt::LocalTurtDisplay;
+use super::turt_image::TurtOutput;
+use super::turt_record::TurtRecorder;
+
+/// Program output kept in memory (see [CmdLineEnv::capture_output])
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far
+    pub fn contents(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.0.lock().unwrap()
+    }
+
+    fn push(&self, data: &[u8]) {
+        self.contents().extend_from_slice(data);
+    }
+}
+
+impl AsyncWrite for OutputBuffer {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.push(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Flushes after every write (see [CmdLineEnv::set_flush_every_write])
+struct FlushEveryWrite<W> {
+    inner: W,
+    /// Bytes written but not yet flushed
+    written: Option<usize>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for FlushEveryWrite<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        if this.written.is_none() {
+            this.written = Some(ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?);
+        }
+        let flushed = ready!(Pin::new(&mut this.inner).poll_flush(cx));
+        let written = this.written.take().unwrap();
+        Poll::Ready(flushed.map(|_| written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
 
 pub struct CmdLineEnv {
     io_mode: IOMode,
     warnings: bool,
     sandbox: bool,
-    stdout: Stdout,
-    stdin: Stdin,
+    /// Does `=` capture the output of commands?
+    capture_exec: bool,
+    resource_limits: ResourceLimits,
+    /// If set, file access is restricted to these directories
+    allowed_dirs: Option<AllowedDirs>,
+    stdout: Box<dyn AsyncWrite + Unpin>,
+    /// Is stdout flushed after every write?
+    flush_every_write: bool,
+    stdin: Box<dyn AsyncRead + Unpin>,
+    /// Has the program's input been redirected (see [CmdLineEnv::set_input])?
+    input_redirected: bool,
+    /// Program output, if it is not written to stdout (see
+    /// [CmdLineEnv::capture_output])
+    captured_output: Option<OutputBuffer>,
+    tracer: Option<Tracer>,
     argv: Vec<String>,
     allowed_fingerprints: Vec<i32>,
     turt_helper: Option<TurtleRobotBox>,
+    turt_output: TurtOutput,
+    /// Where to save an animation of the TURT drawing
+    turt_recording: Option<PathBuf>,
+    /// The TURT recorder's clock, if there is one
+    turt_clock: Option<Arc<AtomicU64>>,
+    tone_player: TonePlayer,
 }
 
 impl CmdLineEnv {
@@ -48,9 +142,16 @@ impl CmdLineEnv {
         Self {
             io_mode,
             warnings,
-            stdout: stdout(),
-            stdin: stdin(),
+            stdout: Box::new(stdout()),
+            flush_every_write: false,
+            stdin: Box::new(stdin()),
+            input_redirected: false,
+            captured_output: None,
+            tracer: None,
             sandbox,
+            capture_exec: false,
+            resource_limits: ResourceLimits::default(),
+            allowed_dirs: None,
             argv,
             allowed_fingerprints: if sandbox {
                 safe_fingerprints()
@@ -58,85 +159,177 @@ impl CmdLineEnv {
                 all_fingerprints()
             },
             turt_helper: None,
+            turt_output: TurtOutput::default(),
+            turt_recording: None,
+            turt_clock: None,
+            tone_player: TonePlayer::new(),
         }
     }
 
-    pub fn init_turt(&mut self, disp: LocalTurtDisplay) {
+    /// Write the program's output (and warnings) to `buffer` instead of
+    /// stdout, for when the terminal is needed for something else
+    pub fn capture_output(&mut self, buffer: OutputBuffer) {
+        self.captured_output = Some(buffer);
+    }
+
+    /// Flush stdout after every `,` and `.`, so that output reaches the
+    /// reader of a pipe right away
+    pub fn set_flush_every_write(&mut self) {
+        self.stdout = Box::new(FlushEveryWrite {
+            inner: stdout(),
+            written: None,
+        });
+        self.flush_every_write = true;
+    }
+
+    /// Read the program's input from somewhere other than stdin
+    pub fn set_input(&mut self, input: Box<dyn AsyncRead + Unpin>) {
+        self.stdin = input;
+        self.input_redirected = true;
+    }
+
+    /// Read the program's input from a file instead of stdin
+    pub fn set_input_file(&mut self, file: File) {
+        self.set_input(Box::new(async_std::fs::File::from(file)));
+    }
+
+    /// Does the program read its input from stdin?
+    pub fn reads_stdin(&self) -> bool {
+        !self.input_redirected
+    }
+
+    /// Only allow these fingerprints to be loaded (instead of all of them, or
+    /// the safe ones in sandbox mode)
+    pub fn set_allowed_fingerprints(&mut self, allowed: Vec<i32>) {
+        self.allowed_fingerprints = allowed;
+    }
+
+    /// Only allow `i` and `o` to access files in these directories (in or
+    /// out of sandbox mode)
+    pub fn set_allowed_dirs(&mut self, dirs: AllowedDirs) {
+        self.allowed_dirs = Some(dirs);
+    }
+
+    /// Limit the resources the program may use
+    pub fn set_resource_limits(&mut self, limits: ResourceLimits) {
+        self.resource_limits = limits;
+    }
+
+    /// Let `=` capture the output of commands (see [ExecMode::Capture])
+    pub fn set_capture_exec(&mut self) {
+        self.capture_exec = true;
+    }
+
+    /// Log every instruction (see [Tracer])
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Choose where and how TURT images are saved
+    pub fn set_turt_output(&mut self, output: TurtOutput) {
+        self.turt_output = output;
+    }
+
+    pub fn turt_output(&self) -> &TurtOutput {
+        &self.turt_output
+    }
+
+    /// Record the TURT drawing, and save it as an animation at `path`
+    pub fn set_turt_recording(&mut self, path: PathBuf) {
+        self.turt_recording = Some(path);
+    }
+
+    pub fn init_turt(&mut self, mut disp: LocalTurtDisplay) {
+        disp.set_output(self.turt_output.clone());
+        if let Some(path) = self.turt_recording.clone() {
+            let recorder = TurtRecorder::new(path);
+            self.turt_clock = Some(recorder.clock());
+            disp.set_recorder(recorder);
+        }
         self.turt_helper = Some(SimpleRobot::new_in_box(disp));
     }
 }
 
+/// A command to run `command` in the system shell
+fn shell_command(command: &str) -> Option<Command> {
+    if cfg!(unix) {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        Some(cmd)
+    } else if cfg!(windows) {
+        let mut cmd = Command::new("CMD");
+        cmd.arg("/C").arg(command);
+        Some(cmd)
+    } else {
+        eprintln!("WARNING: Attempted to execute command, but I don't know how on this system!");
+        None
+    }
+}
+
 impl InterpreterEnv for CmdLineEnv {
     fn get_iomode(&self) -> IOMode {
         self.io_mode
     }
     fn is_io_buffered(&self) -> bool {
-        true
+        !self.flush_every_write
     }
     fn output_writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
-        &mut self.stdout
+        match self.captured_output {
+            Some(ref mut output) => output,
+            None => &mut self.stdout,
+        }
     }
     fn input_reader(&mut self) -> &mut (dyn AsyncRead + Unpin) {
         &mut self.stdin
     }
     fn warn(&mut self, msg: &str) {
         if self.warnings {
-            writeln!(stderr(), "{}", msg).ok();
+            match self.captured_output {
+                Some(ref output) => output.push(format!("{}\n", msg).as_bytes()),
+                None => {
+                    writeln!(stderr(), "{}", msg).ok();
+                }
+            }
         }
     }
-    fn have_file_input(&self) -> bool {
-        !self.sandbox
+    fn filesystem(&self) -> Option<&dyn FungeFilesystem> {
+        if let Some(dirs) = &self.allowed_dirs {
+            Some(dirs)
+        } else if self.sandbox {
+            None
+        } else {
+            Some(&RealFilesystem)
+        }
     }
-    fn have_file_output(&self) -> bool {
-        !self.sandbox
+    fn limits(&self) -> ResourceLimits {
+        self.resource_limits
     }
     fn have_execute(&self) -> ExecMode {
         if self.sandbox {
             ExecMode::Disabled
+        } else if self.capture_exec {
+            ExecMode::Capture
         } else {
             ExecMode::System
         }
     }
-    fn read_file(&mut self, filename: &str) -> Result<Vec<u8>> {
+    fn execute_command(&mut self, command: &str) -> i32 {
         if self.sandbox {
-            Err(Error::from(ErrorKind::PermissionDenied))
-        } else {
-            let mut buf = Vec::new();
-            File::open(filename).and_then(|mut f| f.read_to_end(&mut buf))?;
-            Ok(buf)
+            return -1;
         }
+        shell_command(command)
+            .and_then(|mut cmd| cmd.status().ok())
+            .and_then(|s| s.code())
+            .unwrap_or(-1)
     }
-    fn write_file(&mut self, filename: &str, content: &[u8]) -> Result<()> {
+    fn execute_command_capture(&mut self, command: &str) -> (i32, Vec<u8>) {
         if self.sandbox {
-            Err(Error::from(ErrorKind::PermissionDenied))
-        } else {
-            File::create(filename).and_then(|mut f| f.write_all(content))
+            return (-1, Vec::new());
         }
-    }
-    fn execute_command(&mut self, command: &str) -> i32 {
-        if self.sandbox {
-            -1
-        } else if cfg!(unix) {
-            Command::new("sh")
-                .arg("-c")
-                .arg(command)
-                .status()
-                .ok()
-                .and_then(|s| s.code())
-                .unwrap_or(-1)
-        } else if cfg!(windows) {
-            Command::new("CMD")
-                .arg("/C")
-                .arg(command)
-                .status()
-                .ok()
-                .and_then(|s| s.code())
-                .unwrap_or(-1)
-        } else {
-            eprintln!(
-                "WARNING: Attempted to execute command, but I don't know how on this system!"
-            );
-            -1
+        match shell_command(command).and_then(|mut cmd| cmd.stderr(Stdio::inherit()).output().ok())
+        {
+            Some(output) => (output.status.code().unwrap_or(-1), output.stdout),
+            None => (-1, Vec::new()),
         }
     }
     fn env_vars(&mut self) -> Vec<(String, String)> {
@@ -144,7 +337,6 @@ impl InterpreterEnv for CmdLineEnv {
             Vec::new()
         } else {
             std::env::vars_os()
-                .into_iter()
                 .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
                 .collect()
         }
@@ -153,13 +345,26 @@ impl InterpreterEnv for CmdLineEnv {
         self.argv.clone()
     }
     fn is_fingerprint_enabled(&self, fpr: i32) -> bool {
-        self.allowed_fingerprints.iter().any(|f| *f == fpr)
+        self.allowed_fingerprints.contains(&fpr)
+    }
+
+    fn trace<F: Funge>(&mut self, event: &TraceEvent<F>) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(event);
+        }
+        if let Some(clock) = &self.turt_clock {
+            clock.store(event.tick, Ordering::Relaxed);
+        }
+    }
+
+    fn play_tone(&mut self, frequency: f32, duration_ms: u32) -> bool {
+        self.tone_player.play(frequency, duration_ms)
     }
 
     fn fingerprint_support_library(&mut self, fpr: i32) -> Option<&mut dyn Any> {
         if fpr == string_to_fingerprint("TURT") {
             if self.turt_helper.is_none() {
-                self.turt_helper = Some(SimpleRobot::new_in_box(LocalTurtDisplay::new()));
+                self.init_turt(LocalTurtDisplay::new());
             }
             self.turt_helper.as_mut().map(|x| x as &mut dyn Any)
         } else {
@@ -0,0 +1,216 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Compare a program's output to the expected output (`--expect-io`)
+
+use std::fmt::Write;
+
+/// Number of unchanged lines shown around each change
+const CONTEXT: usize = 3;
+
+/// Above this many (expected × actual) lines, don't look for the smallest
+/// diff, just show everything from the first difference onwards
+const MAX_DIFF_CELLS: usize = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Get a unified diff of the expected and actual output, or `None` if they
+/// are the same
+pub fn diff_output(expected: &[u8], actual: &[u8]) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let expected = String::from_utf8_lossy(expected);
+    let actual = String::from_utf8_lossy(actual);
+    let a: Vec<&str> = expected.split_inclusive('\n').collect();
+    let b: Vec<&str> = actual.split_inclusive('\n').collect();
+    let lines = diff_lines(&a, &b);
+
+    let mut out = String::new();
+    writeln!(out, "--- expected").ok();
+    writeln!(out, "+++ actual").ok();
+    // Group the changes into hunks with some context around them
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|&i| !matches!(lines[i], Line::Same(_)))
+        .collect();
+    let mut i = 0;
+    while i < changed.len() {
+        let start = changed[i].saturating_sub(CONTEXT);
+        let mut end = changed[i] + 1;
+        while i < changed.len() && changed[i] <= end + 2 * CONTEXT {
+            end = changed[i] + 1;
+            i += 1;
+        }
+        let end = (end + CONTEXT).min(lines.len());
+        // Line numbers (1-based) of the first lines in the hunk
+        let count =
+            |keep: fn(&Line) -> bool, upto: usize| lines[..upto].iter().filter(|l| keep(l)).count();
+        writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            count(in_a, start) + 1,
+            count(in_a, end) - count(in_a, start),
+            count(in_b, start) + 1,
+            count(in_b, end) - count(in_b, start),
+        )
+        .ok();
+        for line in &lines[start..end] {
+            let (prefix, text) = match line {
+                Line::Same(text) => (' ', text),
+                Line::Removed(text) => ('-', text),
+                Line::Added(text) => ('+', text),
+            };
+            match text.strip_suffix('\n') {
+                Some(text) => writeln!(out, "{}{}", prefix, text).ok(),
+                None => writeln!(out, "{}{}\n\\ No newline at end of output", prefix, text).ok(),
+            };
+        }
+    }
+    if changed.is_empty() {
+        // Same text, different bytes (invalid UTF-8)
+        writeln!(
+            out,
+            "(the output differs in bytes that are not valid UTF-8)"
+        )
+        .ok();
+    }
+    Some(out)
+}
+
+fn in_a(line: &Line) -> bool {
+    !matches!(line, Line::Added(_))
+}
+
+fn in_b(line: &Line) -> bool {
+    !matches!(line, Line::Removed(_))
+}
+
+/// The shortest edit from `a` to `b` (longest common subsequence)
+fn diff_lines<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<Line<'a>> {
+    // Common prefix and suffix don't need the expensive part
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut lines: Vec<Line> = a[..prefix].iter().map(|l| Line::Same(l)).collect();
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n.saturating_mul(m) > MAX_DIFF_CELLS {
+        lines.extend(a_mid.iter().map(|l| Line::Removed(l)));
+        lines.extend(b_mid.iter().map(|l| Line::Added(l)));
+    } else {
+        // lcs[i][j]: length of the LCS of a_mid[i..] and b_mid[j..]
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        let at = |i: usize, j: usize| i * (m + 1) + j;
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[at(i, j)] = if a_mid[i] == b_mid[j] {
+                    lcs[at(i + 1, j + 1)] + 1
+                } else {
+                    lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && a_mid[i] == b_mid[j] {
+                lines.push(Line::Same(a_mid[i]));
+                i += 1;
+                j += 1;
+            } else if j == m || (i < n && lcs[at(i + 1, j)] >= lcs[at(i, j + 1)]) {
+                lines.push(Line::Removed(a_mid[i]));
+                i += 1;
+            } else {
+                lines.push(Line::Added(b_mid[j]));
+                j += 1;
+            }
+        }
+    }
+    lines.extend(a[a.len() - suffix..].iter().map(|l| Line::Same(l)));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(expected: &str, actual: &str) -> Option<String> {
+        diff_output(expected.as_bytes(), actual.as_bytes())
+    }
+
+    #[test]
+    fn test_same_output() {
+        assert_eq!(diff("", ""), None);
+        assert_eq!(diff("a\nb\n", "a\nb\n"), None);
+    }
+
+    #[test]
+    fn test_changed_line() {
+        assert_eq!(
+            diff("a\nb\nc\n", "a\nX\nc\n").unwrap(),
+            "--- expected\n+++ actual\n@@ -1,3 +1,3 @@\n a\n-b\n+X\n c\n"
+        );
+    }
+
+    #[test]
+    fn test_trailing_newline() {
+        assert_eq!(
+            diff("a\n", "a").unwrap(),
+            "--- expected\n+++ actual\n@@ -1,1 +1,1 @@\n-a\n+a\n\\ No newline at end of output\n"
+        );
+    }
+
+    #[test]
+    fn test_extra_and_missing_lines() {
+        assert_eq!(
+            diff("a\n", "a\nb\n").unwrap(),
+            "--- expected\n+++ actual\n@@ -1,1 +1,2 @@\n a\n+b\n"
+        );
+        assert_eq!(
+            diff("a\nb\n", "a\n").unwrap(),
+            "--- expected\n+++ actual\n@@ -1,2 +1,1 @@\n a\n-b\n"
+        );
+    }
+
+    #[test]
+    fn test_separate_hunks() {
+        let lines: Vec<String> = (1..=20).map(|i| format!("{}\n", i)).collect();
+        let expected = lines.concat();
+        let mut changed = lines;
+        changed[1] = "two\n".to_owned();
+        changed[18] = "nineteen\n".to_owned();
+        let diff = diff(&expected, &changed.concat()).unwrap();
+        assert!(diff.contains("@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n@@"));
+        assert!(diff.ends_with("@@ -16,5 +16,5 @@\n 16\n 17\n 18\n-19\n+nineteen\n 20\n"));
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let diff = diff_output(b"\xff\n", b"\xfe\n").unwrap();
+        assert!(diff.ends_with("(the output differs in bytes that are not valid UTF-8)\n"));
+    }
+}
@@ -0,0 +1,223 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! File access restricted to certain directories (`--allow-read`,
+//! `--allow-write`)
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use rfunge::FungeFilesystem;
+
+/// The directories a program may read from and write to. Paths are
+/// resolved relative to the working directory, and symbolic links are
+/// followed before checking that a path is inside one of the directories.
+#[derive(Debug, Clone, Default)]
+pub struct AllowedDirs {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+}
+
+impl AllowedDirs {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read.is_empty() && self.write.is_empty()
+    }
+
+    /// Allow reading the files in `dir` and its subdirectories
+    pub fn allow_read(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        self.read.push(canonical_dir(dir.as_ref())?);
+        Ok(())
+    }
+
+    /// Allow reading and writing the files in `dir` and its subdirectories
+    pub fn allow_write(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        self.write.push(canonical_dir(dir.as_ref())?);
+        Ok(())
+    }
+
+    /// The real path of an existing file that may be read
+    fn check_read(&self, path: &str) -> io::Result<PathBuf> {
+        let real_path = Path::new(path).canonicalize()?;
+        if self
+            .read
+            .iter()
+            .chain(self.write.iter())
+            .any(|dir| real_path.starts_with(dir))
+        {
+            Ok(real_path)
+        } else {
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        }
+    }
+
+    /// The real path of a file, which need not exist yet, that may be
+    /// written
+    fn check_write(&self, path: &str) -> io::Result<PathBuf> {
+        let path = Path::new(path);
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut real_path = parent.canonicalize()?.join(file_name);
+        // An existing symbolic link could point anywhere
+        if fs::symlink_metadata(&real_path).is_ok_and(|m| m.file_type().is_symlink()) {
+            real_path = real_path.canonicalize()?;
+        }
+        if self.write.iter().any(|dir| real_path.starts_with(dir)) {
+            Ok(real_path)
+        } else {
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        }
+    }
+}
+
+fn canonical_dir(dir: &Path) -> io::Result<PathBuf> {
+    let dir = dir.canonicalize()?;
+    if dir.is_dir() {
+        Ok(dir)
+    } else {
+        Err(io::Error::other("not a directory"))
+    }
+}
+
+impl FungeFilesystem for AllowedDirs {
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(File::open(self.check_read(path)?)?))
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(BufWriter::new(File::create(
+            self.check_write(path)?,
+        )?)))
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(self.check_read(path)?)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn is_writable(&self) -> bool {
+        !self.write.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path_str(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    #[test]
+    fn test_allowed_dirs() {
+        let root = std::env::temp_dir().join(format!("rfunge-allow-{}", std::process::id()));
+        fs::create_dir_all(root.join("ro")).unwrap();
+        fs::create_dir_all(root.join("rw")).unwrap();
+        fs::write(root.join("ro/a.txt"), "A").unwrap();
+        fs::write(root.join("rw/b.txt"), "B").unwrap();
+        fs::write(root.join("secret.txt"), "outside").unwrap();
+
+        let mut dirs = AllowedDirs::new();
+        assert!(dirs.is_empty());
+        dirs.allow_read(root.join("ro")).unwrap();
+        dirs.allow_write(root.join("rw")).unwrap();
+        assert!(!dirs.is_empty());
+        assert!(dirs.is_writable());
+        assert!(dirs.allow_read(root.join("ro/a.txt")).is_err());
+
+        // reading: the read dir, the write dir, and nothing else
+        assert_eq!(dirs.read(path_str(&root.join("ro/a.txt"))).unwrap(), b"A");
+        assert_eq!(dirs.read(path_str(&root.join("rw/b.txt"))).unwrap(), b"B");
+        assert_eq!(
+            dirs.read(path_str(&root.join("secret.txt")))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            dirs.read(path_str(&root.join("ro/../secret.txt")))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            dirs.list(path_str(&root.join("ro"))).unwrap(),
+            vec!["a.txt"]
+        );
+
+        // writing: only the write dir
+        dirs.write(path_str(&root.join("rw/new.txt")), b"new")
+            .unwrap();
+        assert_eq!(fs::read(root.join("rw/new.txt")).unwrap(), b"new");
+        assert_eq!(
+            dirs.write(path_str(&root.join("ro/new.txt")), b"")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            dirs.write(path_str(&root.join("rw/../escaped.txt")), b"")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(!root.join("escaped.txt").exists());
+        // a missing parent directory is an error, not a panic
+        assert_eq!(
+            dirs.write(path_str(&root.join("rw/missing/new.txt")), b"")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+
+        #[cfg(unix)]
+        {
+            // links inside an allowed dir don't lead out of it
+            std::os::unix::fs::symlink(root.join("secret.txt"), root.join("ro/link")).unwrap();
+            std::os::unix::fs::symlink(root.join("secret.txt"), root.join("rw/link")).unwrap();
+            for link in ["ro/link", "rw/link"] {
+                assert_eq!(
+                    dirs.read(path_str(&root.join(link))).unwrap_err().kind(),
+                    io::ErrorKind::PermissionDenied
+                );
+            }
+            assert_eq!(
+                dirs.write(path_str(&root.join("rw/link")), b"overwritten")
+                    .unwrap_err()
+                    .kind(),
+                io::ErrorKind::PermissionDenied
+            );
+            assert_eq!(fs::read(root.join("secret.txt")).unwrap(), b"outside");
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
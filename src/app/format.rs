@@ -0,0 +1,195 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Source formatter (`rfunge fmt`)
+
+use std::io;
+
+use rfunge::fungespace::SrcIO;
+use rfunge::{
+    bfvec, read_funge_src, read_funge_src_bin, BefungeVec, FungeSpace, FungeValue, PagedFungeSpace,
+};
+
+use super::debugger::DebugIdx;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FormatOptions {
+    /// Befunge (2) or Unefunge (1)
+    pub dimensions: u32,
+    /// Read and write UTF-8 rather than bytes
+    pub unicode: bool,
+    /// Remove blank space before the program, not just after it
+    pub trim: bool,
+    /// Use CR LF line endings instead of LF
+    pub crlf: bool,
+}
+
+/// Load a program into funge-space and write it out again: without trailing
+/// whitespace, with consistent line endings and a final newline
+pub fn format_src(src: &[u8], options: &FormatOptions) -> io::Result<Vec<u8>> {
+    if options.dimensions == 1 {
+        let space = PagedFungeSpace::<i64, i64>::new_with_page_size(1000);
+        format_space(space, src, options)
+    } else {
+        let space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(80, 25));
+        format_space(space, src, options)
+    }
+}
+
+/// Format the program body, keeping the header (shebang and directives) that
+/// precedes it exactly as it was
+pub fn format_file(header: &[u8], body: &[u8], options: &FormatOptions) -> io::Result<Vec<u8>> {
+    format_src(body, options).map(|formatted| [header, &formatted].concat())
+}
+
+fn format_space<Idx, Space>(
+    mut space: Space,
+    src: &[u8],
+    options: &FormatOptions,
+) -> io::Result<Vec<u8>>
+where
+    Idx: SrcIO<Space> + DebugIdx,
+    Space: FungeSpace<Idx>,
+    Space::Output: FungeValue,
+{
+    if options.unicode {
+        let src = String::from_utf8(src.to_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        read_funge_src(&mut space, &src);
+    } else {
+        read_funge_src_bin(&mut space, src);
+    }
+    let (min, max) = match (space.min_idx(), space.max_idx()) {
+        (Some(min), Some(max)) => (min.to_xy(), max.to_xy()),
+        _ => return Ok(Vec::new()),
+    };
+    let (x0, y0) = if options.trim { min } else { (0, 0) };
+    let height = if Idx::DIMENSIONS == 1 {
+        0
+    } else {
+        max.1 - y0 + 1
+    };
+    // The program was loaded at the origin, so this can't fail
+    let start = Idx::from_xy(x0, y0).unwrap();
+    let size = Idx::from_xy(max.0 - x0 + 1, height).unwrap();
+
+    let mut out = Vec::new();
+    if options.unicode {
+        Idx::write_src_to(&space, &start, &size, true, &mut out)?;
+    } else {
+        Idx::write_src_bin_to(&space, &start, &size, true, &mut out)?;
+    }
+    if !out.is_empty() {
+        out.push(b'\n');
+    }
+    if options.crlf {
+        let mut crlf = Vec::with_capacity(out.len() + out.len() / 16);
+        for b in out {
+            if b == b'\n' {
+                crlf.push(b'\r');
+            }
+            crlf.push(b);
+        }
+        out = crlf;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::app::header::parse_header;
+
+    const SRC: &[u8] = b"\n  >  v  \r\n\n   @ <   \n\n\n";
+
+    fn all_options() -> Vec<FormatOptions> {
+        let mut all = Vec::new();
+        for &dimensions in &[1, 2] {
+            for &trim in &[false, true] {
+                for &crlf in &[false, true] {
+                    all.push(FormatOptions {
+                        dimensions,
+                        unicode: false,
+                        trim,
+                        crlf,
+                    });
+                }
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn test_format() {
+        let options = FormatOptions {
+            dimensions: 2,
+            ..Default::default()
+        };
+        assert_eq!(format_src(SRC, &options).unwrap(), b"\n  >  v\n\n   @ <\n");
+        let options = FormatOptions {
+            dimensions: 2,
+            trim: true,
+            crlf: true,
+            ..Default::default()
+        };
+        assert_eq!(format_src(SRC, &options).unwrap(), b">  v\r\n\r\n @ <\r\n");
+        assert_eq!(format_src(b" \n\n", &options).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_idempotent() {
+        for options in all_options() {
+            let once = format_src(SRC, &options).unwrap();
+            let twice = format_src(&once, &options).unwrap();
+            assert_eq!(once, twice, "{:?}", options);
+        }
+        let options = FormatOptions {
+            dimensions: 2,
+            unicode: true,
+            ..Default::default()
+        };
+        let once = format_src("\"λ\"  ,@  \n".as_bytes(), &options).unwrap();
+        assert_eq!(once, "\"λ\"  ,@\n".as_bytes());
+        assert_eq!(format_src(&once, &options).unwrap(), once);
+    }
+
+    #[test]
+    fn test_header() {
+        let src = b"#!/usr/bin/env rfunge\n#rfunge: dialect=befunge   \n  1.@   \n\n";
+        let (_, body) = parse_header(src).unwrap();
+        let header = &src[..src.len() - body.len()];
+        assert_eq!(
+            header,
+            b"#!/usr/bin/env rfunge\n#rfunge: dialect=befunge   \n"
+        );
+        for options in all_options() {
+            let formatted = format_file(header, body, &options).unwrap();
+            assert!(formatted.starts_with(header), "{:?}", options);
+            assert_eq!(
+                &formatted[header.len()..],
+                &format_src(body, &options).unwrap()[..]
+            );
+            // Formatting the result again leaves it alone
+            let (_, body2) = parse_header(&formatted).unwrap();
+            let header2 = &formatted[..formatted.len() - body2.len()];
+            assert_eq!(header2, header);
+            assert_eq!(format_file(header2, body2, &options).unwrap(), formatted);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Shebang lines and `#rfunge:` directives at the top of a program
+//!
+//! A program can start with a `#!` line, so it can be run as a script, and
+//! with any number of directive lines like
+//!
+//! ```text
+//! #rfunge: dialect=befunge cell=32 unicode
+//! ```
+//!
+//! These lines are not part of the program: they are skipped when loading
+//! funge-space, which starts with the first line after them.
+
+/// Settings given by the directives. Command line options override these.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    /// `dialect=unefunge` (1) or `dialect=befunge` (2)
+    pub dimensions: Option<usize>,
+    /// `cell=32` or `cell=64`
+    pub cell_size: Option<u32>,
+    /// `unicode` or `binary`
+    pub unicode: Option<bool>,
+}
+
+const DIRECTIVE_PREFIX: &[u8] = b"#rfunge:";
+
+/// Split the header lines off the source. Returns the settings and the rest
+/// of the source, or an error message if a directive doesn't make sense.
+pub fn parse_header(src: &[u8]) -> Result<(Header, &[u8]), String> {
+    let mut header = Header::default();
+    let mut rest = src;
+    let mut line_no = 0;
+    while !rest.is_empty() {
+        let line_len = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(rest.len(), |i| i + 1);
+        let line = &rest[..line_len];
+        if line_no == 0 && line.starts_with(b"#!") {
+            // shebang
+        } else if let Some(directives) = line.strip_prefix(DIRECTIVE_PREFIX) {
+            let directives = String::from_utf8_lossy(directives);
+            for directive in directives.split_whitespace() {
+                parse_directive(directive, &mut header)
+                    .map_err(|e| format!("line {}: {}", line_no + 1, e))?;
+            }
+        } else {
+            break;
+        }
+        rest = &rest[line_len..];
+        line_no += 1;
+    }
+    Ok((header, rest))
+}
+
+fn parse_directive(directive: &str, header: &mut Header) -> Result<(), String> {
+    match directive.split_once('=') {
+        Some(("dialect", "unefunge")) => header.dimensions = Some(1),
+        Some(("dialect", "befunge")) => header.dimensions = Some(2),
+        Some(("dialect", value)) => {
+            return Err(format!(
+                "dialect must be unefunge or befunge, not {:?}",
+                value
+            ))
+        }
+        Some(("cell", "32")) => header.cell_size = Some(32),
+        Some(("cell", "64")) => header.cell_size = Some(64),
+        Some(("cell", value)) => return Err(format!("cell must be 32 or 64, not {:?}", value)),
+        None if directive == "unicode" => header.unicode = Some(true),
+        None if directive == "binary" => header.unicode = Some(false),
+        _ => return Err(format!("unknown directive {:?}", directive)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_header() {
+        let src = b"#v\n@<\n";
+        assert_eq!(parse_header(src).unwrap(), (Header::default(), &src[..]));
+    }
+
+    #[test]
+    fn test_shebang() {
+        let (header, rest) = parse_header(b"#!/usr/bin/env rfunge\n1.@\n").unwrap();
+        assert_eq!(header, Header::default());
+        assert_eq!(rest, b"1.@\n");
+        // only on the first line
+        let src = b"#rfunge: unicode\n#!x\n";
+        assert_eq!(parse_header(src).unwrap().1, b"#!x\n");
+    }
+
+    #[test]
+    fn test_directives() {
+        let src = b"#!rfunge\n#rfunge: dialect=unefunge cell=32\n#rfunge: unicode\n@";
+        let (header, rest) = parse_header(src).unwrap();
+        assert_eq!(
+            header,
+            Header {
+                dimensions: Some(1),
+                cell_size: Some(32),
+                unicode: Some(true),
+            }
+        );
+        assert_eq!(rest, b"@");
+
+        let (header, _) = parse_header(b"#rfunge: binary cell=64 dialect=befunge\n").unwrap();
+        assert_eq!(
+            header,
+            Header {
+                dimensions: Some(2),
+                cell_size: Some(64),
+                unicode: Some(false),
+            }
+        );
+        // later directives win
+        let (header, _) = parse_header(b"#rfunge: cell=64 cell=32\n").unwrap();
+        assert_eq!(header.cell_size, Some(32));
+    }
+
+    #[test]
+    fn test_bad_directives() {
+        assert_eq!(
+            parse_header(b"#rfunge: dialect=trefunge\n").unwrap_err(),
+            "line 1: dialect must be unefunge or befunge, not \"trefunge\""
+        );
+        assert_eq!(
+            parse_header(b"#!x\n#rfunge: cell=16\n").unwrap_err(),
+            "line 2: cell must be 32 or 64, not \"16\""
+        );
+        assert_eq!(
+            parse_header(b"#rfunge: fast\n").unwrap_err(),
+            "line 1: unknown directive \"fast\""
+        );
+        assert_eq!(
+            parse_header(b"#rfunge: unicode=yes\n").unwrap_err(),
+            "line 1: unknown directive \"unicode=yes\""
+        );
+    }
+
+    #[test]
+    fn test_header_only() {
+        let (header, rest) = parse_header(b"#!/usr/bin/env rfunge\n#rfunge: unicode").unwrap();
+        assert_eq!(header.unicode, Some(true));
+        assert!(rest.is_empty());
+        assert_eq!(parse_header(b"").unwrap(), (Header::default(), &b""[..]));
+    }
+}
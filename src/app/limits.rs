@@ -0,0 +1,89 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Bounds on how long a program may run (`--time-limit`, `--max-ticks`)
+
+use std::time::{Duration, Instant};
+
+use rfunge::fungespace::SrcIO;
+use rfunge::interpreter::MotionCmds;
+use rfunge::{FungeSpace, FungeValue, Interpreter, InterpreterEnv, ProgramResult, RunMode};
+
+/// Exit code when a program is stopped because it hit a limit (the same as
+/// that of timeout(1))
+pub const EXIT_LIMIT_REACHED: i32 = 124;
+
+/// Number of ticks to run between checks of the time limit (if there is also
+/// a tick limit) and calls to the polling function
+const TICKS_PER_CHECK: u64 = 1000;
+
+/// Called while the program runs (see [run_with_limits])
+pub type PollFn<'a, Idx, Space, Env> = &'a mut dyn FnMut(&Interpreter<Idx, Space, Env>);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunLimits {
+    /// Wall clock time the program may take
+    pub time_limit: Option<Duration>,
+    /// Number of ticks the program may run for
+    pub max_ticks: Option<u64>,
+}
+
+/// Run the program until it finishes or hits one of the limits, in which case
+/// this returns [ProgramResult::TimedOut]. If given, `poll` is called every
+/// [TICKS_PER_CHECK] ticks.
+pub fn run_with_limits<Idx, Space, Env>(
+    interpreter: &mut Interpreter<Idx, Space, Env>,
+    limits: RunLimits,
+    mut poll: Option<PollFn<Idx, Space, Env>>,
+) -> ProgramResult
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    if limits.max_ticks.is_none() && poll.is_none() {
+        return interpreter.run(match limits.time_limit {
+            Some(time_limit) => RunMode::Timeout(time_limit),
+            None => RunMode::Run,
+        });
+    }
+    let deadline = limits.time_limit.map(|t| Instant::now() + t);
+    let last_tick = limits
+        .max_ticks
+        .map(|max_ticks| interpreter.ticks().saturating_add(max_ticks));
+    loop {
+        let remaining = last_tick.map_or(u64::MAX, |last| last - interpreter.ticks());
+        if remaining == 0 || deadline.is_some_and(|d| Instant::now() >= d) {
+            return ProgramResult::TimedOut;
+        }
+        let chunk = if deadline.is_some() || poll.is_some() {
+            remaining.min(TICKS_PER_CHECK)
+        } else {
+            remaining.min(u32::MAX as u64)
+        };
+        match interpreter.run(RunMode::Limited(chunk as u32)) {
+            // Either the chunk is done or an instruction is waiting for input
+            ProgramResult::Paused | ProgramResult::AwaitingInput => {}
+            result => return result,
+        }
+        if let Some(poll) = poll.as_mut() {
+            poll(interpreter);
+        }
+    }
+}
@@ -16,5 +16,24 @@ You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+pub mod bench;
+pub mod check;
+pub mod config;
+pub mod debugger;
+pub mod dump;
 pub mod env;
+pub mod expect;
+pub mod file_access;
+pub mod format;
+pub mod header;
+pub mod limits;
+pub mod panic;
+pub mod profile;
+pub mod sound;
+pub mod testsuite;
+pub mod trace;
 pub mod turt;
+pub mod turt_image;
+pub mod turt_record;
+pub mod visual;
+pub mod watch;
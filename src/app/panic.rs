@@ -0,0 +1,68 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Reporting programs that panic
+
+use std::fmt::Display;
+
+use rfunge::fungespace::SrcIO;
+use rfunge::interpreter::MotionCmds;
+use rfunge::{Funge, FungeSpace, FungeValue, InstructionPointer, Interpreter, InterpreterEnv};
+
+/// Exit code when the interpreter panics (EX_SOFTWARE from sysexits.h)
+pub const EXIT_PANIC: i32 = 70;
+
+/// Say which IP made the program panic, and where (and how it got there, if
+/// the history is enabled with `--history`)
+pub fn print_panic<Idx, Space, Env>(interpreter: &Interpreter<Idx, Space, Env>)
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    if let Some(ip) = interpreter.panicked_ip() {
+        eprintln!(
+            "Program panicked: IP {} at {} executing {:?}",
+            ip.id,
+            ip.location,
+            interpreter.space[ip.location].to_char()
+        );
+        print_history(ip);
+    }
+}
+
+/// Print the steps in an IP's history, oldest first
+pub fn print_history<F>(ip: &InstructionPointer<F>)
+where
+    F: Funge,
+    F::Idx: Display,
+{
+    if ip.history.is_empty() {
+        return;
+    }
+    eprintln!("Last {} steps of IP {}:", ip.history.len(), ip.id);
+    for entry in &ip.history {
+        eprintln!(
+            "  {} delta {} {:?}",
+            entry.location,
+            entry.delta,
+            entry.instruction.to_char()
+        );
+    }
+}
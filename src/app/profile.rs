@@ -0,0 +1,71 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::fmt::Display;
+
+use rfunge::ProfileReport;
+
+/// Number of entries to show in each section of the profile
+const TOP_N: usize = 10;
+
+/// Print a summary of a profile to stderr (for `--profile`)
+pub fn print_profile<Idx, Value>(report: &ProfileReport<Idx, Value>)
+where
+    Idx: Display,
+    Value: Display,
+{
+    eprintln!();
+    eprintln!("--- profile: {} instructions executed ---", report.total);
+    eprintln!("hottest instructions:");
+    for (instruction, count) in report.by_instruction.iter().take(TOP_N) {
+        eprintln!(
+            "{:>12}  {:>5.1}%  {:?}",
+            count,
+            percentage(*count, report.total),
+            instruction
+        );
+    }
+    eprintln!("hottest cells:");
+    for (cell, count) in report.by_cell.iter().take(TOP_N) {
+        eprintln!(
+            "{:>12}  {:>5.1}%  {}",
+            count,
+            percentage(*count, report.total),
+            cell
+        );
+    }
+    if report.by_ip.len() > 1 {
+        eprintln!("busiest IPs:");
+        for (ip_id, count) in report.by_ip.iter().take(TOP_N) {
+            eprintln!(
+                "{:>12}  {:>5.1}%  IP {}",
+                count,
+                percentage(*count, report.total),
+                ip_id
+            );
+        }
+    }
+}
+
+fn percentage(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * count as f64 / total as f64
+    }
+}
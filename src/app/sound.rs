@@ -0,0 +1,188 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::io::{stderr, Write};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::Duration;
+
+#[cfg(feature = "sqnt-rodio")]
+use rodio::{source::SineWave, OutputStream, OutputStreamHandle, Sink, Source};
+
+/// How many tones can be waiting to be played before [TonePlayer::play]
+/// blocks
+const QUEUE_LENGTH: usize = 32;
+
+/// Sound output for the SQNT fingerprint.
+///
+/// Tones are played one after the other on a background thread, so playing
+/// a tone doesn't hold up the interpreter (and the other IPs). Dropping the
+/// player waits for the queued tones to finish.
+///
+/// With the `sqnt-rodio` feature, tones are played on the default audio
+/// device. Otherwise (or if no audio device is available), every note rings
+/// the terminal bell.
+#[derive(Default)]
+pub struct TonePlayer {
+    queue: Option<SyncSender<(f32, Duration)>>,
+    thread: Option<JoinHandle<()>>,
+    /// Plays one tone on the player's thread, instead of [AudioOutput]
+    output: Option<Box<dyn FnMut(f32, Duration) + Send>>,
+}
+
+impl TonePlayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A player that hands the tones to `output` rather than the sound card
+    #[cfg(test)]
+    fn with_output(output: impl FnMut(f32, Duration) + Send + 'static) -> Self {
+        let mut player = Self::new();
+        player.output = Some(Box::new(output));
+        player
+    }
+
+    /// Queue a tone (or a rest if `frequency` is 0). Only blocks if the
+    /// queue is full.
+    pub fn play(&mut self, frequency: f32, duration_ms: u32) -> bool {
+        let duration = Duration::from_millis(duration_ms as u64);
+        let queue = self.queue.get_or_insert_with(|| {
+            let (sender, receiver) = sync_channel(QUEUE_LENGTH);
+            let output = self.output.take();
+            self.thread = Some(thread::spawn(move || {
+                let mut output: Box<dyn FnMut(f32, Duration)> = match output {
+                    Some(output) => output,
+                    None => {
+                        let mut audio = AudioOutput::default();
+                        Box::new(move |frequency, duration| audio.play(frequency, duration))
+                    }
+                };
+                for (frequency, duration) in receiver {
+                    output(frequency, duration);
+                }
+            }));
+            sender
+        });
+        queue.send((frequency, duration)).is_ok()
+    }
+
+    /// Wait for all queued tones to finish
+    pub fn finish(&mut self) {
+        // Closing the queue stops the thread once it's empty
+        self.queue = None;
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for TonePlayer {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Plays the tones, on the player's thread
+#[derive(Default)]
+struct AudioOutput {
+    #[cfg(feature = "sqnt-rodio")]
+    stream: Option<(OutputStream, OutputStreamHandle)>,
+    #[cfg(feature = "sqnt-rodio")]
+    no_audio_device: bool,
+}
+
+impl AudioOutput {
+    /// Play a tone (or rest if `frequency` is 0), blocking until it is done
+    fn play(&mut self, frequency: f32, duration: Duration) {
+        if frequency > 0.0 && !self.play_audio(frequency, duration) {
+            ring_bell();
+        }
+        sleep(duration);
+    }
+
+    #[cfg(feature = "sqnt-rodio")]
+    fn play_audio(&mut self, frequency: f32, duration: Duration) -> bool {
+        if self.stream.is_none() && !self.no_audio_device {
+            // Open the audio device on first use
+            self.stream = OutputStream::try_default().ok();
+            self.no_audio_device = self.stream.is_none();
+        }
+        if let Some((_, handle)) = self.stream.as_ref() {
+            if let Ok(sink) = Sink::try_new(handle) {
+                sink.append(
+                    SineWave::new(frequency.round() as u32)
+                        .take_duration(duration)
+                        .amplify(0.2),
+                );
+                // The caller waits for the note to finish
+                sink.detach();
+                return true;
+            }
+        }
+        false
+    }
+
+    #[cfg(not(feature = "sqnt-rodio"))]
+    fn play_audio(&mut self, _frequency: f32, _duration: Duration) -> bool {
+        false
+    }
+}
+
+fn ring_bell() {
+    // stdout belongs to the program, so ring the bell on stderr
+    let mut err = stderr();
+    err.write_all(b"\x07").ok();
+    err.flush().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_play_doesnt_block() {
+        let played = Arc::new(Mutex::new(Vec::new()));
+        // Each tone only finishes once the test says so
+        let (finish_tone, tone_finished) = channel();
+        let mut player = TonePlayer::with_output({
+            let played = played.clone();
+            move |frequency, duration| {
+                tone_finished.recv().unwrap();
+                played.lock().unwrap().push((frequency, duration));
+            }
+        });
+        for i in 0..5 {
+            assert!(player.play(100.0 * i as f32, 60));
+        }
+        assert!(played.lock().unwrap().is_empty());
+
+        // ... but they are played one after the other
+        for _ in 0..5 {
+            finish_tone.send(()).unwrap();
+        }
+        player.finish();
+        let expected: Vec<_> = (0..5)
+            .map(|i| (100.0 * i as f32, Duration::from_millis(60)))
+            .collect();
+        assert_eq!(*played.lock().unwrap(), expected);
+    }
+}
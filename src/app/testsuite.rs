@@ -0,0 +1,372 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Test suite runner (`rfunge test DIR`)
+
+use std::env::{current_dir, set_current_dir};
+use std::fmt::Display;
+use std::fs::{read, read_dir};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use futures_lite::io::Cursor;
+
+use rfunge::fungespace::SrcIO;
+use rfunge::interpreter::MotionCmds;
+use rfunge::{
+    read_funge_src_bin, FungeSpace, FungeValue, IOMode, Interpreter, InterpreterBuilder,
+    ProgramResult, RunMode,
+};
+
+use super::env::{CmdLineEnv, OutputBuffer};
+
+/// How the results are reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Test Anything Protocol
+    Tap,
+    /// JUnit-style XML
+    JUnit,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestSuiteOptions {
+    pub format: ReportFormat,
+    /// Time limit for each program
+    pub timeout: Duration,
+    pub is_32bit: bool,
+    pub sandbox: bool,
+}
+
+/// A program, and the files describing what it should do
+#[derive(Debug, Clone)]
+struct TestCase {
+    name: String,
+    program: PathBuf,
+    /// Expected output (`NAME.b98.expected` or `NAME.expected`)
+    expected: Option<PathBuf>,
+    /// Input for the program (`NAME.b98.input` or `NAME.input`)
+    input: Option<PathBuf>,
+}
+
+struct TestResult {
+    name: String,
+    /// Why the test failed, if it did
+    failure: Option<String>,
+    duration: Duration,
+}
+
+/// Run all the Unefunge (`.u98`) and Befunge (`.b98`) programs in `dir` and
+/// write a report to `out`. Returns `true` if all of them passed.
+///
+/// A program passes if it exits with code 0 and its output matches the
+/// expected output. If there is no file with the expected output, the
+/// program checks itself, like Mycology does: it fails if a line of its
+/// output starts with "BAD".
+///
+/// The programs run in `dir`, so they can find the files they need.
+pub fn run_test_suite(
+    dir: &Path,
+    options: &TestSuiteOptions,
+    out: &mut impl Write,
+) -> io::Result<bool> {
+    let cases = find_test_cases(&dir.canonicalize()?)?;
+    let prev_dir = current_dir()?;
+    set_current_dir(dir)?;
+    let mut results = Vec::new();
+    if options.format == ReportFormat::Tap {
+        writeln!(out, "TAP version 13")?;
+        writeln!(out, "1..{}", cases.len())?;
+    }
+    for (i, case) in cases.iter().enumerate() {
+        let start = Instant::now();
+        let failure = match run_test_case(case, options) {
+            Ok(failure) => failure,
+            Err(e) => Some(format!("error: {}", e)),
+        };
+        let result = TestResult {
+            name: case.name.clone(),
+            failure,
+            duration: start.elapsed(),
+        };
+        if options.format == ReportFormat::Tap {
+            write_tap_result(out, i + 1, &result)?;
+        }
+        results.push(result);
+    }
+    set_current_dir(prev_dir)?;
+    if options.format == ReportFormat::JUnit {
+        write_junit_report(out, &dir.display().to_string(), &results)?;
+    }
+    Ok(results.iter().all(|r| r.failure.is_none()))
+}
+
+fn find_test_cases(dir: &Path) -> io::Result<Vec<TestCase>> {
+    let mut files = Vec::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            files.push(name.to_owned());
+        }
+    }
+    files.sort();
+    let find = |name: &str, stem: &str, ext: &str| {
+        [format!("{}.{}", name, ext), format!("{}.{}", stem, ext)]
+            .into_iter()
+            .find(|f| files.contains(f))
+            .map(|f| dir.join(f))
+    };
+    Ok(files
+        .iter()
+        .filter_map(|name| {
+            let lower = name.to_lowercase();
+            let stem = lower
+                .strip_suffix(".b98")
+                .or_else(|| lower.strip_suffix(".u98"))?;
+            let stem = &name[..stem.len()];
+            Some(TestCase {
+                name: name.clone(),
+                program: dir.join(name),
+                expected: find(name, stem, "expected"),
+                input: find(name, stem, "input"),
+            })
+        })
+        .collect())
+}
+
+/// Run a test case. Returns the reason it failed, if it did.
+fn run_test_case(case: &TestCase, options: &TestSuiteOptions) -> io::Result<Option<String>> {
+    let src = read(&case.program)?;
+    let input = match &case.input {
+        Some(path) => read(path)?,
+        None => Vec::new(),
+    };
+    let output = OutputBuffer::new();
+    let mut env = CmdLineEnv::new(
+        IOMode::Binary,
+        false,
+        options.sandbox,
+        vec![case.name.clone()],
+    );
+    env.capture_output(output.clone());
+    env.set_input(Box::new(Cursor::new(input)));
+
+    let timeout = options.timeout;
+    let result = if case.name.to_lowercase().ends_with(".u98") {
+        if options.is_32bit {
+            run_program(
+                InterpreterBuilder::unefunge()
+                    .cell_size_32()
+                    .env(env)
+                    .build(),
+                &src,
+                timeout,
+            )
+        } else {
+            run_program(
+                InterpreterBuilder::unefunge().env(env).build(),
+                &src,
+                timeout,
+            )
+        }
+    } else if options.is_32bit {
+        run_program(
+            InterpreterBuilder::befunge()
+                .cell_size_32()
+                .env(env)
+                .build(),
+            &src,
+            timeout,
+        )
+    } else {
+        run_program(
+            InterpreterBuilder::befunge().env(env).build(),
+            &src,
+            timeout,
+        )
+    };
+
+    let output = output.contents();
+    match result {
+        ProgramResult::Done(0) => {}
+        ProgramResult::Done(code) => return Ok(Some(format!("exited with code {}", code))),
+        ProgramResult::TimedOut => {
+            return Ok(Some(format!("timed out after {:?}", timeout)));
+        }
+        _ => return Ok(Some("program aborted".to_owned())),
+    }
+    Ok(match &case.expected {
+        Some(path) => compare_output(&output, &read(path)?),
+        None => String::from_utf8_lossy(&output)
+            .lines()
+            .find(|line| line.starts_with("BAD"))
+            .map(|line| line.to_owned()),
+    })
+}
+
+fn run_program<Idx, Space>(
+    mut interpreter: Interpreter<Idx, Space, CmdLineEnv>,
+    src: &[u8],
+    timeout: Duration,
+) -> ProgramResult
+where
+    Idx: MotionCmds<Space, CmdLineEnv> + SrcIO<Space> + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+{
+    read_funge_src_bin(&mut interpreter.space, src);
+    interpreter.run(RunMode::Timeout(timeout))
+}
+
+/// Describe the first difference between the output and the expected output
+fn compare_output(output: &[u8], expected: &[u8]) -> Option<String> {
+    if output == expected {
+        return None;
+    }
+    let output = String::from_utf8_lossy(output);
+    let expected = String::from_utf8_lossy(expected);
+    let mut output_lines = output.split('\n');
+    let mut expected_lines = expected.split('\n');
+    for line_no in 1.. {
+        match (output_lines.next(), expected_lines.next()) {
+            (Some(a), Some(b)) if a == b => {}
+            (Some(a), Some(b)) => {
+                return Some(format!("line {}: expected {:?}, got {:?}", line_no, b, a))
+            }
+            (Some(a), None) => return Some(format!("line {}: unexpected {:?}", line_no, a)),
+            (None, Some(b)) => return Some(format!("line {}: missing {:?}", line_no, b)),
+            (None, None) => break,
+        }
+    }
+    // Same text, different bytes (invalid UTF-8)
+    Some("output differs".to_owned())
+}
+
+fn write_tap_result(out: &mut impl Write, number: usize, result: &TestResult) -> io::Result<()> {
+    match &result.failure {
+        None => writeln!(out, "ok {} - {}", number, result.name)?,
+        Some(reason) => {
+            writeln!(out, "not ok {} - {}", number, result.name)?;
+            writeln!(out, "  ---")?;
+            writeln!(out, "  message: {:?}", reason)?;
+            writeln!(out, "  ...")?;
+        }
+    }
+    Ok(())
+}
+
+fn write_junit_report(
+    out: &mut impl Write,
+    suite_name: &str,
+    results: &[TestResult],
+) -> io::Result<()> {
+    let failures = results.iter().filter(|r| r.failure.is_some()).count();
+    let total_time: Duration = results.iter().map(|r| r.duration).sum();
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        out,
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        xml_escape(suite_name),
+        results.len(),
+        failures,
+        total_time.as_secs_f64()
+    )?;
+    for result in results {
+        let name = xml_escape(&result.name);
+        let time = result.duration.as_secs_f64();
+        match &result.failure {
+            None => writeln!(out, "  <testcase name=\"{}\" time=\"{:.3}\"/>", name, time)?,
+            Some(reason) => {
+                writeln!(out, "  <testcase name=\"{}\" time=\"{:.3}\">", name, time)?;
+                writeln!(out, "    <failure message=\"{}\"/>", xml_escape(reason))?;
+                writeln!(out, "  </testcase>")?;
+            }
+        }
+    }
+    writeln!(out, "</testsuite>")
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Not allowed in XML 1.0 at all
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn test_run_test_suite() {
+        let dir = std::env::temp_dir().join(format!("rfunge-testsuite-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("pass.b98"), "\"ko\",,a,@").unwrap();
+        fs::write(dir.join("pass.expected"), "ok\n").unwrap();
+        fs::write(dir.join("fail.b98"), "\"DAB\",,,a,@").unwrap();
+        fs::write(dir.join("notes.txt"), "not a test").unwrap();
+
+        let mut options = TestSuiteOptions {
+            format: ReportFormat::Tap,
+            timeout: Duration::from_secs(10),
+            is_32bit: false,
+            sandbox: true,
+        };
+        let mut out = Vec::new();
+        assert!(!run_test_suite(&dir, &options, &mut out).unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "TAP version 13\n\
+             1..2\n\
+             not ok 1 - fail.b98\n  ---\n  message: \"BAD\"\n  ...\n\
+             ok 2 - pass.b98\n"
+        );
+
+        options.format = ReportFormat::JUnit;
+        let mut out = Vec::new();
+        assert!(!run_test_suite(&dir, &options, &mut out).unwrap());
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("tests=\"2\" failures=\"1\""), "{}", report);
+        assert!(report.contains("<failure message=\"BAD\"/>"), "{}", report);
+
+        // Without the failing program, the suite passes
+        fs::remove_file(dir.join("fail.b98")).unwrap();
+        options.format = ReportFormat::Tap;
+        let mut out = Vec::new();
+        assert!(run_test_suite(&dir, &options, &mut out).unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "TAP version 13\n1..1\nok 1 - pass.b98\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
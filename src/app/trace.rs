@@ -0,0 +1,287 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Per-instruction trace log for the command line (`--trace`)
+
+use std::fmt::Write as _;
+use std::io::{stderr, BufWriter, Stderr, Write};
+
+use num::ToPrimitive;
+
+use rfunge::interpreter::MotionCmds;
+use rfunge::{Funge, TraceEvent};
+
+/// How each instruction is logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// `key=value` pairs, one instruction per line
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Which instructions are logged
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only log this IP
+    pub ip: Option<i64>,
+    /// Only log instructions in this region (least and greatest corner,
+    /// both inclusive)
+    pub region: Option<(Vec<i64>, Vec<i64>)>,
+}
+
+impl TraceFilter {
+    /// Parse an IP ID
+    pub fn parse_ip(s: &str) -> Option<i64> {
+        s.trim().parse().ok()
+    }
+
+    /// Parse a region written as `X0,Y0:X1,Y1` (or `X0:X1` in Unefunge)
+    pub fn parse_region(s: &str) -> Option<(Vec<i64>, Vec<i64>)> {
+        let parse = |corner: &str| {
+            corner
+                .split(',')
+                .map(|c| c.trim().parse().ok())
+                .collect::<Option<Vec<i64>>>()
+        };
+        let (from, to) = s.split_once(':')?;
+        let (from, to) = (parse(from)?, parse(to)?);
+        if from.len() != to.len() {
+            return None;
+        }
+        let least = from.iter().zip(&to).map(|(a, b)| *a.min(b)).collect();
+        let greatest = from.iter().zip(&to).map(|(a, b)| *a.max(b)).collect();
+        Some((least, greatest))
+    }
+
+    fn matches(&self, ip_id: Option<i64>, location: &[Option<i64>]) -> bool {
+        if self.ip.is_some() && ip_id != self.ip {
+            return false;
+        }
+        match &self.region {
+            None => true,
+            Some((least, greatest)) => {
+                location.len() == least.len()
+                    && location
+                        .iter()
+                        .zip(least.iter().zip(greatest))
+                        .all(|(c, (lo, hi))| c.is_some_and(|c| *lo <= c && c <= *hi))
+            }
+        }
+    }
+}
+
+/// Writes the trace log to stderr
+pub struct Tracer {
+    format: TraceFormat,
+    filter: TraceFilter,
+    out: BufWriter<Stderr>,
+    line: String,
+}
+
+impl Tracer {
+    pub fn new(format: TraceFormat, filter: TraceFilter) -> Self {
+        Self {
+            format,
+            filter,
+            out: BufWriter::new(stderr()),
+            line: String::new(),
+        }
+    }
+
+    pub fn trace<F: Funge>(&mut self, event: &TraceEvent<F>) {
+        if self.format_event(event) {
+            writeln!(self.out, "{}", self.line).ok();
+        }
+    }
+
+    /// Write the log line for `event` to `self.line`. Returns `false` if the
+    /// event is filtered out.
+    fn format_event<F: Funge>(&mut self, event: &TraceEvent<F>) -> bool {
+        let mut location = Vec::new();
+        F::Idx::push_vector_onto(&mut location, event.location);
+        let coords: Vec<Option<i64>> = location.iter().map(|c| c.to_i64()).collect();
+        if !self.filter.matches(event.ip_id.to_i64(), &coords) {
+            return false;
+        }
+        let location = location
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let line = &mut self.line;
+        line.clear();
+        match self.format {
+            TraceFormat::Text => {
+                write!(
+                    line,
+                    "tick={} ip={} at={} instr={:?} depth={} top=",
+                    event.tick, event.ip_id, location, event.instruction, event.stack_depth
+                )
+                .ok();
+                match event.stack_top {
+                    Some(top) => write!(line, "{}", top).ok(),
+                    None => write!(line, "-").ok(),
+                };
+            }
+            TraceFormat::Json => {
+                write!(
+                    line,
+                    "{{\"tick\":{},\"ip\":{},\"location\":[{}],\"instruction\":\"",
+                    event.tick, event.ip_id, location
+                )
+                .ok();
+                json_escape(line, event.instruction);
+                write!(line, "\",\"stack_depth\":{},\"top\":", event.stack_depth).ok();
+                match event.stack_top {
+                    Some(top) => write!(line, "{}}}", top).ok(),
+                    None => write!(line, "null}}").ok(),
+                };
+            }
+        }
+        true
+    }
+}
+
+pub fn json_escape(out: &mut String, c: char) {
+    match c {
+        '"' => out.push_str("\\\""),
+        '\\' => out.push_str("\\\\"),
+        c if (c as u32) < 0x20 => {
+            write!(out, "\\u{:04x}", c as u32).ok();
+        }
+        c => out.push(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rfunge::{bfvec, BefungeVec, Interpreter, PagedFungeSpace};
+
+    use crate::app::env::CmdLineEnv;
+
+    type Befunge = Interpreter<BefungeVec<i64>, PagedFungeSpace<BefungeVec<i64>, i64>, CmdLineEnv>;
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(TraceFilter::parse_ip("3"), Some(3));
+        assert_eq!(TraceFilter::parse_ip(" -1 "), Some(-1));
+        assert_eq!(TraceFilter::parse_ip(""), None);
+        assert_eq!(TraceFilter::parse_ip("ip1"), None);
+        assert_eq!(TraceFilter::parse_ip("1.5"), None);
+
+        assert_eq!(
+            TraceFilter::parse_region("0,0:79,24"),
+            Some((vec![0, 0], vec![79, 24]))
+        );
+        // The corners can be given in any order
+        assert_eq!(
+            TraceFilter::parse_region("10, -5 : 2, 3"),
+            Some((vec![2, -5], vec![10, 3]))
+        );
+        assert_eq!(TraceFilter::parse_region("7:3"), Some((vec![3], vec![7])));
+        for bad in &[
+            "",
+            "0,0",
+            "0,0:",
+            ":1,1",
+            "0,0:1",
+            "0,0:1,1,1",
+            "0,0-1,1",
+            "a,0:1,1",
+            "0,,0:1,1",
+        ] {
+            assert_eq!(TraceFilter::parse_region(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let all = TraceFilter::default();
+        assert!(all.matches(Some(0), &[Some(100), Some(-100)]));
+        let filter = TraceFilter {
+            ip: Some(1),
+            region: TraceFilter::parse_region("0,0:9,4"),
+        };
+        assert!(filter.matches(Some(1), &[Some(0), Some(4)]));
+        assert!(filter.matches(Some(1), &[Some(9), Some(0)]));
+        assert!(!filter.matches(Some(0), &[Some(0), Some(0)]));
+        assert!(!filter.matches(None, &[Some(0), Some(0)]));
+        assert!(!filter.matches(Some(1), &[Some(10), Some(0)]));
+        assert!(!filter.matches(Some(1), &[Some(0), Some(-1)]));
+        assert!(!filter.matches(Some(1), &[None, Some(0)]));
+        assert!(!filter.matches(Some(1), &[Some(0)]));
+    }
+
+    #[test]
+    fn test_json_line() {
+        let mut tracer = Tracer::new(TraceFormat::Json, TraceFilter::default());
+        let event = TraceEvent::<Befunge> {
+            tick: 12,
+            ip_id: &2,
+            location: bfvec(3, -1),
+            instruction: '"',
+            stack_depth: 1,
+            stack_top: Some(&-7),
+        };
+        assert!(tracer.format_event(&event));
+        let line: serde_json::Value = serde_json::from_str(&tracer.line).unwrap();
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "tick": 12,
+                "ip": 2,
+                "location": [3, -1],
+                "instruction": "\"",
+                "stack_depth": 1,
+                "top": -7,
+            })
+        );
+
+        let event = TraceEvent::<Befunge> {
+            instruction: '\n',
+            stack_depth: 0,
+            stack_top: None,
+            ..event
+        };
+        assert!(tracer.format_event(&event));
+        let line: serde_json::Value = serde_json::from_str(&tracer.line).unwrap();
+        assert_eq!(line["instruction"], "\n");
+        assert_eq!(line["top"], serde_json::Value::Null);
+
+        tracer.filter.ip = Some(0);
+        assert!(!tracer.format_event(&event));
+    }
+
+    #[test]
+    fn test_text_line() {
+        let mut tracer = Tracer::new(TraceFormat::Text, TraceFilter::default());
+        let event = TraceEvent::<Befunge> {
+            tick: 0,
+            ip_id: &0,
+            location: bfvec(1, 2),
+            instruction: '+',
+            stack_depth: 0,
+            stack_top: None,
+        };
+        assert!(tracer.format_event(&event));
+        assert_eq!(tracer.line, "tick=0 ip=0 at=1,2 instr='+' depth=0 top=-");
+    }
+}
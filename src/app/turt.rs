@@ -16,9 +16,6 @@ You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::fs::OpenOptions;
-use std::io::{ErrorKind, Write};
-
 #[cfg(feature = "turt-gui")]
 use std::sync::{
     atomic::{AtomicBool, Ordering},
@@ -28,7 +25,7 @@ use std::sync::{
 #[cfg(feature = "turt-gui")]
 use glutin::{
     dpi::LogicalSize,
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
     window::WindowBuilder,
     ContextBuilder, WindowedContext,
@@ -37,21 +34,30 @@ use glutin::{
 // #[cfg(feature = "turt-gui")]
 // use shader_version::OpenGL;
 
-use rfunge::interpreter::fingerprints::TURT::{calc_bounds, Colour, Dot, Line, TurtleDisplay};
+use rfunge::interpreter::fingerprints::TURT::{Colour, Dot, Fill, Line, TurtleDisplay};
+
+#[cfg(feature = "turt-gui")]
+use super::turt_image::TurtImageFormat;
+use super::turt_image::TurtOutput;
+use super::turt_record::TurtRecorder;
+
+#[cfg(feature = "turt-gui")]
+use std::fmt::Display;
 
+#[cfg(feature = "turt-gui")]
+use super::dump::{run_with_dumps, StateDumper};
 #[cfg(feature = "turt-gui")]
 use super::env::CmdLineEnv;
 #[cfg(feature = "turt-gui")]
-use rfunge::interpreter::fingerprints::TURT::Point;
+use super::limits::RunLimits;
 #[cfg(feature = "turt-gui")]
-use rfunge::{Funge, Interpreter, ProgramResult, RunMode};
-
-#[derive(Debug, Default)]
-struct TurtImage {
-    background: Option<Colour>,
-    lines: Vec<Line>,
-    dots: Vec<Dot>,
-}
+use super::panic::print_panic;
+#[cfg(feature = "turt-gui")]
+use super::profile::print_profile;
+#[cfg(feature = "turt-gui")]
+use rfunge::interpreter::fingerprints::TURT::{calc_drawing_bounds, Point, VecDisplay};
+#[cfg(feature = "turt-gui")]
+use rfunge::{Funge, Interpreter, ProgramResult};
 
 #[cfg(feature = "turt-gui")]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -65,32 +71,113 @@ enum TurtGuiMsg {
 #[cfg(feature = "turt-gui")]
 #[derive(Debug, Default)]
 pub struct LocalTurtDisplay {
-    state: Arc<Mutex<TurtImage>>,
+    state: Arc<Mutex<VecDisplay>>,
     msg_channel: Option<mpsc::Sender<TurtGuiMsg>>,
     display_active: Arc<AtomicBool>,
+    output: TurtOutput,
+    recorder: Option<TurtRecorder>,
 }
 
 #[cfg(not(feature = "turt-gui"))]
 #[derive(Debug, Default)]
-pub struct LocalTurtDisplay;
+pub struct LocalTurtDisplay {
+    output: TurtOutput,
+    recorder: Option<TurtRecorder>,
+}
 
 #[cfg(feature = "turt-gui")]
 struct TurtWindowState {
     pub wnd_ctx: WindowedContext<glutin::PossiblyCurrent>,
     pub canvas: femtovg::Canvas<femtovg::renderer::OpenGl>,
+    pub view: TurtView,
+}
+
+/// How the user has zoomed and panned the drawing
+#[cfg(feature = "turt-gui")]
+#[derive(Debug, Clone, Copy)]
+struct TurtView {
+    /// Magnification on top of fitting the drawing into the window
+    zoom: f32,
+    /// Offset in (physical) pixels
+    pan: (f32, f32),
+    cursor: (f32, f32),
+    /// Where the mouse was when the left button was pressed (or last moved
+    /// while dragging)
+    drag_from: Option<(f32, f32)>,
+}
+
+#[cfg(feature = "turt-gui")]
+impl Default for TurtView {
+    fn default() -> Self {
+        Self {
+            zoom: 1.,
+            pan: (0., 0.),
+            cursor: (0., 0.),
+            drag_from: None,
+        }
+    }
+}
+
+#[cfg(feature = "turt-gui")]
+impl TurtView {
+    /// Zoom in (factor > 1) or out, keeping the point under the cursor in
+    /// place
+    fn zoom_at_cursor(&mut self, factor: f32) {
+        let new_zoom = (self.zoom * factor).clamp(0.1, 100.);
+        let factor = new_zoom / self.zoom;
+        let (cx, cy) = self.cursor;
+        self.pan = (
+            cx - (cx - self.pan.0) * factor,
+            cy - (cy - self.pan.1) * factor,
+        );
+        self.zoom = new_zoom;
+    }
+
+    /// The cursor has moved: pan if dragging
+    fn move_cursor(&mut self, x: f32, y: f32) {
+        if let Some((x0, y0)) = self.drag_from {
+            self.pan = (self.pan.0 + x - x0, self.pan.1 + y - y0);
+            self.drag_from = Some((x, y));
+        }
+        self.cursor = (x, y);
+    }
 }
 
 impl LocalTurtDisplay {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Choose where and how images are saved
+    pub fn set_output(&mut self, output: TurtOutput) {
+        self.output = output;
+    }
+
+    /// Record the drawing as it progresses. The animation is saved when the
+    /// display is dropped, at the end of the program.
+    pub fn set_recorder(&mut self, recorder: TurtRecorder) {
+        self.recorder = Some(recorder);
+    }
+}
+
+impl Drop for LocalTurtDisplay {
+    fn drop(&mut self) {
+        if let Some(recorder) = &self.recorder {
+            recorder.save();
+        }
+    }
 }
 
 #[cfg(feature = "turt-gui")]
-pub fn run_with_turt<InitFn, Interp>(make_interpreter: InitFn) -> ProgramResult
+pub fn run_with_turt<InitFn, Interp>(
+    make_interpreter: InitFn,
+    limits: RunLimits,
+    dump_state: Option<StateDumper>,
+) -> ProgramResult
 where
     InitFn: FnOnce() -> Interpreter<Interp::Idx, Interp::Space, Interp::Env> + Send + 'static,
     Interp: Funge<Env = CmdLineEnv> + 'static,
+    Interp::Idx: Display,
 {
     let mut disp = LocalTurtDisplay::new();
     let disp_state = disp.state.clone();
@@ -98,11 +185,24 @@ where
     let (tx, rx) = mpsc::channel();
     let turt_tx = tx.clone();
     disp.msg_channel.replace(turt_tx);
+    // The settings for saving images are only known once the interpreter
+    // exists, in the worker thread
+    let export_output = Arc::new(Mutex::new(TurtOutput::default()));
+    let worker_export_output = export_output.clone();
 
     let worker_handle = std::thread::spawn(move || {
         let mut interpreter = make_interpreter();
+        if let Ok(mut output) = worker_export_output.lock() {
+            *output = interpreter.env.turt_output().clone();
+        }
         interpreter.env.init_turt(disp);
-        let result = interpreter.run(RunMode::Run);
+        let result = run_with_dumps(&mut interpreter, limits, dump_state.as_ref());
+        if matches!(result, ProgramResult::Panic | ProgramResult::LoopDetected) {
+            print_panic(&interpreter);
+        }
+        if let Some(report) = interpreter.profile() {
+            print_profile(&report);
+        }
         tx.send(TurtGuiMsg::Finished).ok();
         result
     });
@@ -200,9 +300,9 @@ where
                     let size = ws.wnd_ctx.window().inner_size();
                     // println!("dpi {:?}", dpi_factor);
                     ws.canvas
-                        .set_size(size.width as u32, size.height as u32, dpi_factor as f32);
+                        .set_size(size.width, size.height, dpi_factor as f32);
                     if let Ok(img) = disp_state.lock() {
-                        draw_turt(&mut ws.canvas, &img);
+                        draw_turt(&mut ws.canvas, &img, &ws.view);
                     }
                     ws.canvas.flush();
                     ws.wnd_ctx.swap_buffers().unwrap();
@@ -219,6 +319,55 @@ where
                         .send_event(TurtGuiMsg::CloseDisplay)
                         .unwrap();
                 }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    if let Some(ws) = window_state.as_mut() {
+                        let steps = match delta {
+                            MouseScrollDelta::LineDelta(_, y) => *y,
+                            MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 50.,
+                        };
+                        ws.view.zoom_at_cursor(1.2_f32.powf(steps));
+                        ws.wnd_ctx.window().request_redraw();
+                    }
+                }
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Left,
+                    ..
+                } => {
+                    if let Some(ws) = window_state.as_mut() {
+                        ws.view.drag_from = match state {
+                            ElementState::Pressed => Some(ws.view.cursor),
+                            ElementState::Released => None,
+                        };
+                    }
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    if let Some(ws) = window_state.as_mut() {
+                        let dragging = ws.view.drag_from.is_some();
+                        ws.view.move_cursor(position.x as f32, position.y as f32);
+                        if dragging {
+                            ws.wnd_ctx.window().request_redraw();
+                        }
+                    }
+                }
+                WindowEvent::ReceivedCharacter(c) => {
+                    let format = match c {
+                        's' | 'S' => Some(TurtImageFormat::Svg),
+                        'p' | 'P' => Some(TurtImageFormat::Png),
+                        '0' => {
+                            // Back to the whole drawing
+                            if let Some(ws) = window_state.as_mut() {
+                                ws.view = TurtView::default();
+                                ws.wnd_ctx.window().request_redraw();
+                            }
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(format) = format {
+                        export_image(&export_output, &disp_state, format);
+                    }
+                }
                 _ => {}
             },
             _ => {}
@@ -237,14 +386,20 @@ fn create_turt_window<E>(el: &EventLoopWindowTarget<E>) -> Option<TurtWindowStat
     // Create the FemtoVG renderer and canvas
     use femtovg::renderer::OpenGl;
     // let renderer = OpenGl::new_from_glutin_context(&wnd_ctx).unwrap();
-    let renderer = OpenGl::new(|s| wnd_ctx.get_proc_address(s) as *const _).ok()?;
+    // The GL function pointers are only valid while wnd_ctx is current.
+    let renderer =
+        unsafe { OpenGl::new_from_function(|s| wnd_ctx.get_proc_address(s) as *const _) }.ok()?;
     let canvas = femtovg::Canvas::new(renderer).unwrap();
     // Store the window-related stuff in the state variable
-    Some(TurtWindowState { wnd_ctx, canvas })
+    Some(TurtWindowState {
+        wnd_ctx,
+        canvas,
+        view: TurtView::default(),
+    })
 }
 
 #[cfg(feature = "turt-gui")]
-fn draw_turt<R: femtovg::Renderer>(c: &mut femtovg::Canvas<R>, img: &TurtImage) {
+fn draw_turt<R: femtovg::Renderer>(c: &mut femtovg::Canvas<R>, img: &VecDisplay, view: &TurtView) {
     use femtovg::{Color, LineCap, Paint, Path};
 
     let width = c.width();
@@ -263,10 +418,14 @@ fn draw_turt<R: femtovg::Renderer>(c: &mut femtovg::Canvas<R>, img: &TurtImage)
 
     // println!("Cleared w {} h {}", width, height);
 
+    // Apply the user's zoom and pan on top of fitting the drawing in
+    c.translate(view.pan.0, view.pan.1);
+    c.scale(view.zoom, view.zoom);
+
     // Figure out the right transformation
     const PADDING: i32 = 10;
     let (Point { x: x0, y: y0 }, Point { x: x1, y: y1 }) =
-        calc_bounds(img.lines.iter(), img.dots.iter());
+        calc_drawing_bounds(&img.fills, &img.lines, &img.dots);
     let img_width = (x1 - x0 + PADDING) as f32;
     let img_height = (y1 - y0 + PADDING) as f32;
 
@@ -287,10 +446,24 @@ fn draw_turt<R: femtovg::Renderer>(c: &mut femtovg::Canvas<R>, img: &TurtImage)
     let dy = height / scale / 2.0 - (y0 + y1) as f32 / 2.0;
     c.translate(dx, dy);
 
+    for fill in &img.fills {
+        let paint = Paint::color(fvg_colour(fill.colour));
+        let mut path = Path::new();
+        for (i, p) in fill.points.iter().enumerate() {
+            if i == 0 {
+                path.move_to(p.x as f32, p.y as f32);
+            } else {
+                path.line_to(p.x as f32, p.y as f32);
+            }
+        }
+        path.close();
+        c.fill_path(&mut path, paint);
+    }
+
     for line in &img.lines {
         let mut paint = Paint::color(fvg_colour(line.colour));
         paint.set_line_cap(LineCap::Round);
-        paint.set_line_width(1.0);
+        paint.set_line_width(line.width as f32);
 
         let mut path = Path::new();
         path.move_to(line.from.x as f32, line.from.y as f32);
@@ -301,13 +474,33 @@ fn draw_turt<R: femtovg::Renderer>(c: &mut femtovg::Canvas<R>, img: &TurtImage)
     for dot in &img.dots {
         let paint = Paint::color(fvg_colour(dot.colour));
         let mut path = Path::new();
-        path.circle(dot.pos.x as f32, dot.pos.y as f32, 0.5);
+        path.circle(dot.pos.x as f32, dot.pos.y as f32, dot.width as f32 / 2.);
         c.fill_path(&mut path, paint);
     }
 }
 
-fn css_colour(clr: Colour) -> String {
-    format!("rgb({}, {}, {})", clr.r, clr.g, clr.b)
+/// Save the drawing currently on screen (keyboard shortcut)
+#[cfg(feature = "turt-gui")]
+fn export_image(output: &Mutex<TurtOutput>, img: &Mutex<VecDisplay>, format: TurtImageFormat) {
+    if !format.is_supported() {
+        eprintln!(
+            "This build can't write {} images",
+            format.extension().to_uppercase()
+        );
+        return;
+    }
+    let mut output = match output.lock() {
+        Ok(output) => output.clone(),
+        Err(_) => return,
+    };
+    output.format = format;
+    // Keep the configured name, but not its extension
+    output.file_name = output
+        .file_name
+        .map(|name| name.with_extension(format.extension()));
+    if let Ok(img) = img.lock() {
+        output.save(img.background, &img.fills, &img.lines, &img.dots);
+    }
 }
 
 #[cfg(feature = "turt-gui")]
@@ -320,14 +513,26 @@ impl TurtleDisplay for LocalTurtDisplay {
     fn display(&mut self, _show: bool) {}
     #[cfg(not(feature = "turt-gui"))]
     fn display_visible(&self) -> bool {
-        false
+        // The recorder needs to see every change
+        self.recorder.is_some()
     }
     #[cfg(not(feature = "turt-gui"))]
-    fn draw(&mut self, _background: Option<Colour>, _lines: &[Line], _dots: &[Dot]) {}
+    fn draw(
+        &mut self,
+        _turtle: i32,
+        background: Option<Colour>,
+        fills: &[Fill],
+        lines: &[Line],
+        dots: &[Dot],
+    ) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(background, fills, lines, dots);
+        }
+    }
 
     #[cfg(feature = "turt-gui")]
     fn display(&mut self, show: bool) {
-        let currently_visible = self.display_visible();
+        let currently_visible = self.display_active.load(Ordering::Acquire);
         if show && !currently_visible {
             // Tell the main thread to show the GUI
             self.msg_channel
@@ -343,93 +548,27 @@ impl TurtleDisplay for LocalTurtDisplay {
     }
     #[cfg(feature = "turt-gui")]
     fn display_visible(&self) -> bool {
-        self.display_active.load(Ordering::Acquire)
+        // The recorder needs to see every change
+        self.recorder.is_some() || self.display_active.load(Ordering::Acquire)
     }
     #[cfg(feature = "turt-gui")]
-    fn draw(&mut self, background: Option<Colour>, lines: &[Line], dots: &[Dot]) {
+    fn draw(
+        &mut self,
+        turtle: i32,
+        background: Option<Colour>,
+        fills: &[Fill],
+        lines: &[Line],
+        dots: &[Dot],
+    ) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(background, fills, lines, dots);
+        }
         if let Ok(mut img_state) = self.state.lock() {
-            img_state.background = background;
-            img_state.lines.clear();
-            img_state.dots.clear();
-            img_state.lines.extend_from_slice(lines);
-            img_state.dots.extend_from_slice(dots);
+            img_state.draw(turtle, background, fills, lines, dots);
         }
     }
 
-    fn print(&mut self, background: Option<Colour>, lines: &[Line], dots: &[Dot]) {
-        // craft an SVG
-        // figure out the bounding box
-        let (topleft, bottomright) = calc_bounds(lines.iter(), dots.iter());
-        let x0 = topleft.x as f64 - 0.5;
-        let y0 = topleft.y as f64 - 0.5;
-        let width = bottomright.x - topleft.x + 1;
-        let height = bottomright.y - topleft.y + 1;
-        let mut svg = r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_owned();
-        svg.push_str(&format!(
-            r#"<svg viewBox="{} {} {} {}" xmlns="http://www.w3.org/2000/svg" stroke-linecap="round" stroke-width="1">"#,
-            x0, y0, width, height));
-        // Add the background
-        if let Some(clr) = background {
-            svg.push_str(&format!(
-                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
-                x0,
-                y0,
-                width,
-                height,
-                css_colour(clr)
-            ))
-        }
-        // Add the lines
-        for line in lines {
-            svg.push_str(&format!(
-                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}"/>"#,
-                line.from.x,
-                line.from.y,
-                line.to.x,
-                line.to.y,
-                css_colour(line.colour)
-            ));
-        }
-        // Add the dots
-        for dot in dots {
-            svg.push_str(&format!(
-                r#"<circle cx="{}" cy="{}" r="0.5" fill="{}"/>"#,
-                dot.pos.x,
-                dot.pos.y,
-                css_colour(dot.colour)
-            ));
-        }
-        // Close tag
-        svg.push_str("</svg>\n");
-
-        // Write to file
-        let mut fn_idx = 1;
-        let mut fname = "rfunge_TURT_image.svg".to_owned();
-        loop {
-            // Create a new file!
-            match OpenOptions::new().write(true).create_new(true).open(&fname) {
-                Ok(mut out_f) => {
-                    eprintln!("Writing TURT image to {}", fname);
-                    out_f.write_all(svg.as_bytes()).unwrap_or_else(|e| {
-                        eprintln!("Error writing to file {} ({:?})", fname, e);
-                    });
-                    break;
-                }
-                Err(e) => {
-                    match e.kind() {
-                        ErrorKind::AlreadyExists => {
-                            // Try another filename
-                            fn_idx += 1;
-                            fname = format!("rfunge_TURT_image-{}.svg", fn_idx);
-                            continue;
-                        }
-                        _ => {
-                            eprintln!("Error opening file {} ({:?})", fname, e);
-                            break;
-                        }
-                    }
-                }
-            }
-        }
+    fn print(&mut self, background: Option<Colour>, fills: &[Fill], lines: &[Line], dots: &[Dot]) {
+        self.output.save(background, fills, lines, dots);
     }
 }
@@ -0,0 +1,329 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Saving TURT drawings (the `I` instruction) as SVG, PNG or PDF
+
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use rfunge::interpreter::fingerprints::TURT::{
+    calc_image_box, render_turt_to_svg, Colour, Dot, Fill, Line,
+};
+
+/// File format for TURT drawings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TurtImageFormat {
+    #[default]
+    Svg,
+    /// Only available with the `turt-png` feature
+    Png,
+    Pdf,
+}
+
+impl TurtImageFormat {
+    /// Look up a format by name (which is also its file extension)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "svg" => Some(Self::Svg),
+            "png" => Some(Self::Png),
+            "pdf" => Some(Self::Pdf),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Svg => "svg",
+            Self::Png => "png",
+            Self::Pdf => "pdf",
+        }
+    }
+
+    /// Can this build write images in this format?
+    pub fn is_supported(self) -> bool {
+        self != Self::Png || cfg!(feature = "turt-png")
+    }
+}
+
+/// Where TURT drawings are saved, and how
+#[derive(Debug, Clone, Default)]
+pub struct TurtOutput {
+    /// Directory for the images (default: the current directory)
+    pub dir: Option<PathBuf>,
+    /// File name (default: `rfunge_TURT_image`). Existing files are never
+    /// overwritten: if the file exists, a number is added to the name.
+    pub file_name: Option<PathBuf>,
+    pub format: TurtImageFormat,
+}
+
+impl TurtOutput {
+    /// Render a drawing and write it to a new file
+    pub fn save(&self, background: Option<Colour>, fills: &[Fill], lines: &[Line], dots: &[Dot]) {
+        let image = match self.format {
+            TurtImageFormat::Svg => render_turt_to_svg(fills, lines, dots, background).into_bytes(),
+            TurtImageFormat::Pdf => render_pdf(background, fills, lines, dots),
+            #[cfg(feature = "turt-png")]
+            TurtImageFormat::Png => match render_png(background, fills, lines, dots) {
+                Some(image) => image,
+                None => {
+                    eprintln!("Error rendering TURT image");
+                    return;
+                }
+            },
+            #[cfg(not(feature = "turt-png"))]
+            TurtImageFormat::Png => {
+                eprintln!("This build can't write PNG images");
+                return;
+            }
+        };
+        self.write_new_file(&image);
+    }
+
+    fn write_new_file(&self, data: &[u8]) {
+        let out_dir = self.dir.clone().unwrap_or_default();
+        let file_name = self
+            .file_name
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("rfunge_TURT_image"));
+        let stem = file_name.with_extension("");
+        let extension = match file_name.extension() {
+            Some(ext) => ext.to_string_lossy().into_owned(),
+            None => self.format.extension().to_owned(),
+        };
+        let mut fn_idx = 1;
+        let mut fname = out_dir.join(stem.with_extension(&extension));
+        loop {
+            // Create a new file!
+            match OpenOptions::new().write(true).create_new(true).open(&fname) {
+                Ok(mut out_f) => {
+                    eprintln!("Writing TURT image to {}", fname.display());
+                    out_f.write_all(data).unwrap_or_else(|e| {
+                        eprintln!("Error writing to file {} ({:?})", fname.display(), e);
+                    });
+                    break;
+                }
+                Err(e) => {
+                    match e.kind() {
+                        ErrorKind::AlreadyExists => {
+                            // Try another filename
+                            fn_idx += 1;
+                            let mut numbered = stem.clone().into_os_string();
+                            numbered.push(format!("-{}.{}", fn_idx, extension));
+                            fname = out_dir.join(numbered);
+                            continue;
+                        }
+                        _ => {
+                            eprintln!("Error opening file {} ({:?})", fname.display(), e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A PDF colour operand: "r g b"
+fn pdf_colour(clr: Colour) -> String {
+    format!(
+        "{:.3} {:.3} {:.3}",
+        clr.r as f64 / 255.,
+        clr.g as f64 / 255.,
+        clr.b as f64 / 255.
+    )
+}
+
+/// Render a drawing as a one-page PDF, one point per pixel
+pub fn render_pdf(
+    background: Option<Colour>,
+    fills: &[Fill],
+    lines: &[Line],
+    dots: &[Dot],
+) -> Vec<u8> {
+    let (x0, y0, width, height) = calc_image_box(fills, lines, dots);
+
+    // The page contents. PDF's y axis points up, TURT's points down.
+    let mut content = format!("1 0 0 -1 {} {} cm\n1 J\n", -x0, y0 + height);
+    if let Some(clr) = background {
+        content.push_str(&format!(
+            "{} rg {} {} {} {} re f\n",
+            pdf_colour(clr),
+            x0,
+            y0,
+            width,
+            height
+        ));
+    }
+    for fill in fills {
+        content.push_str(&format!("{} rg", pdf_colour(fill.colour)));
+        for (i, p) in fill.points.iter().enumerate() {
+            content.push_str(&format!(
+                " {} {} {}",
+                p.x,
+                p.y,
+                if i == 0 { "m" } else { "l" }
+            ));
+        }
+        content.push_str(" h f\n");
+    }
+    for line in lines {
+        content.push_str(&format!(
+            "{} RG {} w {} {} m {} {} l S\n",
+            pdf_colour(line.colour),
+            line.width,
+            line.from.x,
+            line.from.y,
+            line.to.x,
+            line.to.y
+        ));
+    }
+    // A line of length zero with round caps is a dot
+    for dot in dots {
+        content.push_str(&format!(
+            "{} RG {} w {} {} m {} {} l S\n",
+            pdf_colour(dot.colour),
+            dot.width,
+            dot.pos.x,
+            dot.pos.y,
+            dot.pos.x,
+            dot.pos.y
+        ));
+    }
+
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_owned(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_owned(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents 4 0 R >>",
+            width, height
+        ),
+        format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ),
+    ];
+    let mut pdf = "%PDF-1.4\n".to_owned();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+    }
+    let xref_offset = pdf.len();
+    pdf.push_str(&format!(
+        "xref\n0 {}\n0000000000 65535 f \n",
+        objects.len() + 1
+    ));
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    pdf.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref_offset
+    ));
+    pdf.into_bytes()
+}
+
+/// Small drawings are scaled up so that PNG images are at least this many
+/// pixels across
+#[cfg(feature = "turt-png")]
+const MIN_PNG_SIZE: f64 = 512.;
+
+/// Render a drawing as a PNG image
+#[cfg(feature = "turt-png")]
+pub fn render_png(
+    background: Option<Colour>,
+    fills: &[Fill],
+    lines: &[Line],
+    dots: &[Dot],
+) -> Option<Vec<u8>> {
+    use tiny_skia::{Color, FillRule, LineCap, Paint, PathBuilder, Pixmap, Stroke, Transform};
+
+    let (x0, y0, width, height) = calc_image_box(fills, lines, dots);
+    let scale = (MIN_PNG_SIZE / width.max(height)).max(1.);
+    let mut pixmap = Pixmap::new(
+        (width * scale).ceil() as u32,
+        (height * scale).ceil() as u32,
+    )?;
+    if let Some(clr) = background {
+        pixmap.fill(Color::from_rgba8(clr.r, clr.g, clr.b, 0xff));
+    }
+    let transform = Transform::from_row(
+        scale as f32,
+        0.,
+        0.,
+        scale as f32,
+        (-x0 * scale) as f32,
+        (-y0 * scale) as f32,
+    );
+    let paint = |clr: Colour| {
+        let mut paint = Paint::default();
+        paint.set_color_rgba8(clr.r, clr.g, clr.b, 0xff);
+        paint.anti_alias = true;
+        paint
+    };
+
+    for fill in fills {
+        let mut path = PathBuilder::new();
+        for (i, p) in fill.points.iter().enumerate() {
+            if i == 0 {
+                path.move_to(p.x as f32, p.y as f32);
+            } else {
+                path.line_to(p.x as f32, p.y as f32);
+            }
+        }
+        path.close();
+        if let Some(path) = path.finish() {
+            pixmap.fill_path(
+                &path,
+                &paint(fill.colour),
+                FillRule::Winding,
+                transform,
+                None,
+            );
+        }
+    }
+    for line in lines {
+        let stroke = Stroke {
+            width: line.width as f32,
+            line_cap: LineCap::Round,
+            ..Stroke::default()
+        };
+        let mut path = PathBuilder::new();
+        path.move_to(line.from.x as f32, line.from.y as f32);
+        path.line_to(line.to.x as f32, line.to.y as f32);
+        if let Some(path) = path.finish() {
+            pixmap.stroke_path(&path, &paint(line.colour), &stroke, transform, None);
+        }
+    }
+    for dot in dots {
+        let radius = dot.width as f32 / 2.;
+        if let Some(path) = PathBuilder::from_circle(dot.pos.x as f32, dot.pos.y as f32, radius) {
+            pixmap.fill_path(
+                &path,
+                &paint(dot.colour),
+                FillRule::Winding,
+                transform,
+                None,
+            );
+        }
+    }
+    pixmap.encode_png().ok()
+}
@@ -0,0 +1,252 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Recording TURT drawings as they happen (`--turt-record`), and saving
+//! them as animated SVG images
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rfunge::interpreter::fingerprints::TURT::{
+    calc_image_box, render_turt_elements, Colour, Dot, Element, Fill, Line, SvgExtra,
+};
+
+/// How long the animation lasts, however long the program ran for
+const ANIMATION_SECONDS: f64 = 10.;
+
+#[derive(Debug, Clone)]
+enum ShapeKind {
+    Background(Colour),
+    Fill(Fill),
+    Line(Line),
+    Dot(Dot),
+}
+
+impl ShapeKind {
+    fn element(&self) -> Element<'_> {
+        match self {
+            ShapeKind::Background(clr) => Element::Background(*clr),
+            ShapeKind::Fill(fill) => Element::Fill(fill),
+            ShapeKind::Line(line) => Element::Line(line),
+            ShapeKind::Dot(dot) => Element::Dot(dot),
+        }
+    }
+}
+
+/// Something that is visible for part of the animation
+#[derive(Debug, Clone)]
+struct Shape {
+    kind: ShapeKind,
+    /// Tick at which the shape appeared
+    shown: u64,
+    /// Tick at which the shape disappeared
+    hidden: Option<u64>,
+}
+
+/// Keeps track of everything the turtle drew, and when
+#[derive(Debug)]
+pub struct TurtRecorder {
+    path: PathBuf,
+    /// The interpreter's current tick, kept up to date by the environment
+    clock: Arc<AtomicU64>,
+    shapes: Vec<Shape>,
+    /// Indices into shapes of what is visible right now
+    background: Option<usize>,
+    fills: Vec<usize>,
+    lines: Vec<usize>,
+    dots: Vec<usize>,
+}
+
+impl TurtRecorder {
+    /// Record an animation, to be saved to `path`
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            clock: Arc::new(AtomicU64::new(0)),
+            shapes: Vec::new(),
+            background: None,
+            fills: Vec::new(),
+            lines: Vec::new(),
+            dots: Vec::new(),
+        }
+    }
+
+    /// The clock to keep up to date with the interpreter's tick
+    pub fn clock(&self) -> Arc<AtomicU64> {
+        self.clock.clone()
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.load(Ordering::Relaxed)
+    }
+
+    /// Record what the display looks like now
+    pub fn record(
+        &mut self,
+        background: Option<Colour>,
+        fills: &[Fill],
+        lines: &[Line],
+        dots: &[Dot],
+    ) {
+        let now = self.now();
+
+        let current_background = self.background.map(|idx| match self.shapes[idx].kind {
+            ShapeKind::Background(clr) => clr,
+            _ => unreachable!(),
+        });
+        if background != current_background {
+            if let Some(idx) = self.background.take() {
+                self.shapes[idx].hidden = Some(now);
+            }
+            if let Some(clr) = background {
+                self.background = Some(self.push(ShapeKind::Background(clr), now));
+            }
+        }
+
+        // Usually, the turtle has just drawn one more thing, so only look at
+        // the end of the lists
+        let kept_fills = self.kept(&self.fills, fills, |kind| match kind {
+            ShapeKind::Fill(fill) => Some(fill),
+            _ => None,
+        });
+        self.hide_from(kept_fills, now, |rec| &mut rec.fills);
+        for fill in &fills[kept_fills..] {
+            let idx = self.push(ShapeKind::Fill(fill.clone()), now);
+            self.fills.push(idx);
+        }
+
+        let kept_lines = self.kept(&self.lines, lines, |kind| match kind {
+            ShapeKind::Line(line) => Some(line),
+            _ => None,
+        });
+        self.hide_from(kept_lines, now, |rec| &mut rec.lines);
+        for line in &lines[kept_lines..] {
+            let idx = self.push(ShapeKind::Line(*line), now);
+            self.lines.push(idx);
+        }
+
+        let kept_dots = self.kept(&self.dots, dots, |kind| match kind {
+            ShapeKind::Dot(dot) => Some(dot),
+            _ => None,
+        });
+        self.hide_from(kept_dots, now, |rec| &mut rec.dots);
+        for dot in &dots[kept_dots..] {
+            let idx = self.push(ShapeKind::Dot(*dot), now);
+            self.dots.push(idx);
+        }
+    }
+
+    fn push(&mut self, kind: ShapeKind, now: u64) -> usize {
+        self.shapes.push(Shape {
+            kind,
+            shown: now,
+            hidden: None,
+        });
+        self.shapes.len() - 1
+    }
+
+    /// How many of the visible shapes (`visible`) are still there in `new`?
+    fn kept<T: PartialEq>(
+        &self,
+        visible: &[usize],
+        new: &[T],
+        get: impl Fn(&ShapeKind) -> Option<&T>,
+    ) -> usize {
+        match visible.last() {
+            Some(&last)
+                if new.len() >= visible.len()
+                    && get(&self.shapes[last].kind) == Some(&new[visible.len() - 1]) =>
+            {
+                visible.len()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Hide all visible shapes in a list, except the first `keep`
+    fn hide_from(&mut self, keep: usize, now: u64, list: fn(&mut Self) -> &mut Vec<usize>) {
+        let hidden: Vec<_> = list(self).drain(keep..).collect();
+        for idx in hidden {
+            self.shapes[idx].hidden = Some(now);
+        }
+    }
+
+    /// Render the animation as an SVG image
+    pub fn render_svg(&self) -> String {
+        let end = self.now();
+        let start = self.shapes.iter().map(|s| s.shown).min().unwrap_or(end);
+        let seconds = |tick: u64| {
+            if end > start {
+                (tick - start) as f64 / (end - start) as f64 * ANIMATION_SECONDS
+            } else {
+                0.
+            }
+        };
+
+        // Everything that was ever drawn has to fit
+        let mut fills = Vec::new();
+        let mut lines = Vec::new();
+        let mut dots = Vec::new();
+        for shape in &self.shapes {
+            match &shape.kind {
+                ShapeKind::Background(_) => {}
+                ShapeKind::Fill(fill) => fills.push(fill.clone()),
+                ShapeKind::Line(line) => lines.push(*line),
+                ShapeKind::Dot(dot) => dots.push(*dot),
+            }
+        }
+        let image_box = calc_image_box(&fills, &lines, &dots);
+
+        // Every shape starts out hidden, and is shown (and maybe hidden
+        // again) when it was on the display
+        let elements = self.shapes.iter().map(|shape| shape.kind.element());
+        render_turt_elements(image_box, elements, |idx, _| {
+            let shape = &self.shapes[idx];
+            let mut children = format!(
+                r#"<set attributeName="visibility" to="visible" begin="{:.3}s" fill="freeze"/>"#,
+                seconds(shape.shown)
+            );
+            if let Some(hidden) = shape.hidden {
+                children.push_str(&format!(
+                    r#"<set attributeName="visibility" to="hidden" begin="{:.3}s" fill="freeze"/>"#,
+                    seconds(hidden)
+                ));
+            }
+            SvgExtra {
+                attributes: r#" visibility="hidden""#.to_owned(),
+                children,
+            }
+        })
+    }
+
+    /// Write the animation to the file given to [TurtRecorder::new]
+    pub fn save(&self) {
+        if self.shapes.is_empty() {
+            eprintln!(
+                "Nothing was drawn with TURT; not writing {}",
+                self.path.display()
+            );
+            return;
+        }
+        eprintln!("Writing TURT animation to {}", self.path.display());
+        if let Err(e) = std::fs::write(&self.path, self.render_svg()) {
+            eprintln!("Error writing to file {} ({:?})", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,417 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Terminal visualizer (`--visual`): watch the IPs move through funge-space
+
+#![cfg(feature = "visual")]
+
+use std::fmt::Display;
+use std::io::{self, stdin, stdout, BufRead, IsTerminal, Stdout, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, size, Clear, ClearType, EnterAlternateScreen,
+    LeaveAlternateScreen,
+};
+use crossterm::{execute, queue};
+use futures_lite::io::AsyncRead;
+
+use rfunge::fungespace::SrcIO;
+use rfunge::interpreter::MotionCmds;
+use rfunge::{FungeSpace, FungeValue, Interpreter, ProgramResult, RunMode};
+
+use super::debugger::DebugIdx;
+use super::env::{CmdLineEnv, OutputBuffer};
+
+/// Speeds (in ticks per second) to choose from with `+` and `-`
+const SPEEDS: &[u32] = &[
+    1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000, 100000,
+];
+
+/// Maximum number of frames drawn per second
+const MAX_FPS: u32 = 30;
+
+/// Number of lines of program output shown below funge-space
+const OUTPUT_LINES: u16 = 5;
+
+const HELP: &str = "q quit  space pause  s step  +/- speed  arrows scroll  f follow IP";
+
+/// Restores the terminal when dropped, even if we panic
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new() -> io::Result<Self> {
+        enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        execute!(stdout(), Show, LeaveAlternateScreen).ok();
+        disable_raw_mode().ok();
+    }
+}
+
+/// Program input read from the terminal a line at a time, with a prompt on
+/// the bottom line of the screen (the terminal is in raw mode otherwise)
+struct PromptInput {
+    output: OutputBuffer,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl PromptInput {
+    fn read_line(&mut self) -> io::Result<()> {
+        // The last (unfinished) line of output is most likely a question
+        let prompt = {
+            let output = self.output.contents();
+            let start = output
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |i| i + 1);
+            printable(&String::from_utf8_lossy(&output[start..]))
+        };
+        let (_, rows) = size()?;
+        execute!(
+            stdout(),
+            MoveTo(0, rows.saturating_sub(1)),
+            Clear(ClearType::CurrentLine),
+            SetAttribute(Attribute::Bold),
+            Print("input> "),
+            SetAttribute(Attribute::Reset),
+            Print(prompt),
+            Show
+        )?;
+        disable_raw_mode()?;
+        let mut line = String::new();
+        let result = stdin().lock().read_line(&mut line);
+        enable_raw_mode()?;
+        execute!(stdout(), Hide)?;
+        result?;
+        self.line = line.into_bytes();
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl AsyncRead for PromptInput {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.pos >= self.line.len() {
+            if let Err(e) = self.read_line() {
+                return Poll::Ready(Err(e));
+            }
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// Replace control characters, which would mess up the screen
+fn printable(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_control() { '·' } else { c })
+        .collect()
+}
+
+/// What's on the screen, apart from funge-space
+struct View {
+    /// Funge-space coordinates of the top left corner
+    origin: (i64, i64),
+    /// Scroll to keep the first IP in view
+    follow: bool,
+    paused: bool,
+    speed: usize,
+    ticks: u64,
+    status: String,
+}
+
+/// Run the program in the terminal visualizer, starting at `speed` ticks per
+/// second
+///
+/// The program's output is shown below funge-space while it runs, and
+/// written to stdout at the end. If stdin is a terminal, the program's input
+/// is read with a prompt; otherwise it is read from stdin as usual.
+pub fn run_visual<Idx, Space>(
+    interpreter: &mut Interpreter<Idx, Space, CmdLineEnv>,
+    speed: u32,
+) -> ProgramResult
+where
+    Idx: MotionCmds<Space, CmdLineEnv> + SrcIO<Space> + DebugIdx + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+{
+    let output = OutputBuffer::new();
+    interpreter.env.capture_output(output.clone());
+    if interpreter.env.reads_stdin() && stdin().is_terminal() {
+        interpreter.env.set_input(Box::new(PromptInput {
+            output: output.clone(),
+            line: Vec::new(),
+            pos: 0,
+        }));
+    }
+    let result = match TerminalGuard::new() {
+        Ok(_guard) => visual_loop(interpreter, speed, &output),
+        Err(e) => Err(e),
+    };
+    // Back on the normal screen: show what the program wrote
+    stdout().write_all(&output.contents()).ok();
+    stdout().flush().ok();
+    result.unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        ProgramResult::Panic
+    })
+}
+
+fn visual_loop<Idx, Space>(
+    interpreter: &mut Interpreter<Idx, Space, CmdLineEnv>,
+    speed: u32,
+    output: &OutputBuffer,
+) -> io::Result<ProgramResult>
+where
+    Idx: MotionCmds<Space, CmdLineEnv> + SrcIO<Space> + DebugIdx + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+{
+    let mut out = stdout();
+    let mut view = View {
+        origin: (0, 0),
+        follow: true,
+        paused: false,
+        speed: SPEEDS
+            .iter()
+            .position(|&s| s >= speed)
+            .unwrap_or(SPEEDS.len() - 1),
+        ticks: 0,
+        status: String::new(),
+    };
+    let mut next_frame = Instant::now();
+    loop {
+        draw(&mut out, interpreter, &mut view, output)?;
+
+        // Wait for the next frame, handling key presses in the meantime
+        let mut step = false;
+        loop {
+            let timeout = if view.paused {
+                Duration::from_secs(3600)
+            } else {
+                next_frame.saturating_duration_since(Instant::now())
+            };
+            if !poll(timeout)? {
+                break;
+            }
+            if let Event::Key(KeyEvent { code, modifiers }) = read()? {
+                match code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(ProgramResult::Paused),
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(ProgramResult::Paused)
+                    }
+                    KeyCode::Char(' ') => {
+                        view.paused = !view.paused;
+                        next_frame = Instant::now();
+                    }
+                    KeyCode::Char('s') => {
+                        view.paused = true;
+                        step = true;
+                    }
+                    KeyCode::Char('+') | KeyCode::Char('=') => {
+                        view.speed = (view.speed + 1).min(SPEEDS.len() - 1)
+                    }
+                    KeyCode::Char('-') => view.speed = view.speed.saturating_sub(1),
+                    KeyCode::Char('f') => view.follow = true,
+                    KeyCode::Left => scroll(&mut view, -1, 0),
+                    KeyCode::Right => scroll(&mut view, 1, 0),
+                    KeyCode::Up if Idx::DIMENSIONS > 1 => scroll(&mut view, 0, -1),
+                    KeyCode::Down if Idx::DIMENSIONS > 1 => scroll(&mut view, 0, 1),
+                    _ => {}
+                }
+            }
+            if step {
+                break;
+            }
+            draw(&mut out, interpreter, &mut view, output)?;
+        }
+
+        // Run as many ticks as fit into one frame at this speed
+        let ticks_per_second = SPEEDS[view.speed];
+        let ticks = if step {
+            1
+        } else if view.paused {
+            0
+        } else {
+            (ticks_per_second / MAX_FPS).max(1)
+        };
+        for _ in 0..ticks {
+            view.ticks += 1;
+            match interpreter.run(RunMode::Step) {
+                ProgramResult::Paused | ProgramResult::AwaitingInput => {}
+                ProgramResult::Hit(_) => {
+                    view.paused = true;
+                    break;
+                }
+                result => {
+                    view.status = match result {
+                        ProgramResult::Done(code) => {
+                            format!("program finished with exit code {}", code)
+                        }
+                        _ => "program aborted".to_owned(),
+                    };
+                    view.paused = true;
+                    draw(&mut out, interpreter, &mut view, output)?;
+                    // Leave the final state on screen until a key is pressed
+                    while !matches!(read()?, Event::Key(_)) {}
+                    return Ok(result);
+                }
+            }
+        }
+        next_frame += Duration::from_secs(ticks as u64) / ticks_per_second;
+        let now = Instant::now();
+        if next_frame < now {
+            next_frame = now;
+        }
+    }
+}
+
+fn scroll(view: &mut View, dx: i64, dy: i64) {
+    view.follow = false;
+    view.origin = (
+        view.origin.0.saturating_add(dx),
+        view.origin.1.saturating_add(dy),
+    );
+}
+
+fn draw<Idx, Space>(
+    out: &mut Stdout,
+    interpreter: &Interpreter<Idx, Space, CmdLineEnv>,
+    view: &mut View,
+    output: &OutputBuffer,
+) -> io::Result<()>
+where
+    Idx: MotionCmds<Space, CmdLineEnv> + SrcIO<Space> + DebugIdx + Display + 'static,
+    Space: FungeSpace<Idx> + 'static,
+    Space::Output: FungeValue,
+{
+    let (cols, rows) = size()?;
+    // Status line at the top, output and help at the bottom
+    let space_rows = rows.saturating_sub(OUTPUT_LINES + 3).max(1);
+    let (width, height) = (cols as i64, space_rows as i64);
+
+    // Highlight the cells the IPs execute next
+    let ips: Vec<(i64, i64)> = interpreter
+        .ips
+        .iter()
+        .map(|ip| interpreter.space.move_by(ip.location, ip.delta).0.to_xy())
+        .collect();
+    if let (true, Some(&(x, y))) = (view.follow, ips.first()) {
+        let (ox, oy) = view.origin;
+        if x < ox || x >= ox.saturating_add(width) {
+            view.origin.0 = x.saturating_sub(width / 2);
+        }
+        if Idx::DIMENSIONS > 1 && (y < oy || y >= oy.saturating_add(height)) {
+            view.origin.1 = y.saturating_sub(height / 2);
+        }
+    }
+    let (ox, oy) = view.origin;
+
+    let state = if view.paused { "paused" } else { "running" };
+    let mut status = format!(
+        " tick {}  {}  {} ticks/s  {} IP{}  top left {},{} ",
+        view.ticks,
+        state,
+        SPEEDS[view.speed],
+        ips.len(),
+        if ips.len() == 1 { "" } else { "s" },
+        ox,
+        oy
+    );
+    if !view.status.is_empty() {
+        status = format!("{} {} ", status, view.status);
+    }
+    queue!(
+        out,
+        MoveTo(0, 0),
+        SetAttribute(Attribute::Reverse),
+        Print(format!("{:<1$.1$}", status, cols as usize)),
+        SetAttribute(Attribute::Reset)
+    )?;
+
+    for row in 0..height {
+        queue!(out, MoveTo(0, row as u16 + 1))?;
+        let y = oy.saturating_add(row);
+        for col in 0..width {
+            let x = ox.saturating_add(col);
+            let c = match Idx::from_xy(x, y) {
+                Some(idx) => interpreter.space[idx].to_char(),
+                None => ' ',
+            };
+            let c = if c.is_control() { '·' } else { c };
+            if ips.contains(&(x, y)) {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(c),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(c))?;
+            }
+        }
+    }
+
+    // The last few lines of output
+    let top = space_rows + 1;
+    queue!(
+        out,
+        MoveTo(0, top),
+        Print(format!("{:─<1$}", "─ output ", cols as usize))
+    )?;
+    let text = String::from_utf8_lossy(&output.contents()).into_owned();
+    let lines: Vec<&str> = text.split('\n').collect();
+    let first = lines.len().saturating_sub(OUTPUT_LINES as usize);
+    for i in 0..OUTPUT_LINES {
+        let line = lines.get(first + i as usize).copied().unwrap_or_default();
+        let line: String = printable(line).chars().take(cols as usize).collect();
+        queue!(
+            out,
+            MoveTo(0, top + 1 + i),
+            Clear(ClearType::CurrentLine),
+            Print(line)
+        )?;
+    }
+    queue!(
+        out,
+        MoveTo(0, top + 1 + OUTPUT_LINES),
+        Clear(ClearType::CurrentLine),
+        SetAttribute(Attribute::Dim),
+        Print(HELP.chars().take(cols as usize).collect::<String>()),
+        SetAttribute(Attribute::Reset)
+    )?;
+    out.flush()
+}
@@ -0,0 +1,100 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Watch mode (`--watch`): re-run the program whenever the source changes
+
+#![cfg(feature = "watch")]
+
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::{recommended_watcher, RecursiveMode, Watcher};
+
+/// Editors tend to write a file in several steps; wait this long for the
+/// dust to settle before re-running
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Run the program in a child process, starting a fresh one (with a fresh
+/// funge-space) every time `path` changes. A run that is still going when
+/// the file changes is killed. Only returns on error.
+///
+/// `args` are the arguments for the child, i.e. our own command line
+/// without `--watch`.
+pub fn watch(path: &Path, args: &[OsString]) -> io::Result<()> {
+    let exe = std::env::current_exe()?;
+    let path = path.canonicalize()?;
+    // Watch the directory rather than the file: many editors save by
+    // replacing the file, which would end a watch on the file itself.
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let (tx, rx) = channel();
+    let mut watcher = recommended_watcher(tx).map_err(to_io_error)?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(to_io_error)?;
+
+    let mut child = Some(start(&exe, args, &path)?);
+    loop {
+        match rx.recv_timeout(DEBOUNCE) {
+            Ok(event) => {
+                let event = event.map_err(to_io_error)?;
+                if event.kind.is_access() || !event.paths.contains(&path) {
+                    continue;
+                }
+                // Swallow the rest of this save
+                while rx.recv_timeout(DEBOUNCE).is_ok() {}
+                if let Some(mut running) = child.take() {
+                    running.kill().ok();
+                    running.wait()?;
+                }
+                child = Some(start(&exe, args, &path)?);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(running) = child.as_mut() {
+                    if let Some(status) = running.try_wait()? {
+                        match status.code() {
+                            Some(code) => eprintln!("--- Exited with status {} ---", code),
+                            None => eprintln!("--- Terminated ---"),
+                        }
+                        eprintln!("--- Waiting for changes to {} ---", path.display());
+                        child = None;
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::other("file watcher stopped"));
+            }
+        }
+    }
+}
+
+fn start(exe: &Path, args: &[OsString], path: &Path) -> io::Result<Child> {
+    eprintln!("--- Running {} ---", path.display());
+    Command::new(exe).args(args).spawn()
+}
+
+fn to_io_error(e: notify::Error) -> io::Error {
+    match e.kind {
+        notify::ErrorKind::Io(e) => e,
+        _ => io::Error::other(e.to_string()),
+    }
+}
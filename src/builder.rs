@@ -0,0 +1,571 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Builder for [Interpreter]s using the default funge-space implementation
+
+use std::any::Any;
+use std::io;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use chrono::prelude::{DateTime, Utc};
+use futures_lite::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "bigint")]
+use num::BigInt;
+
+use crate::fungespace::paged::PageSpaceVector;
+use crate::fungespace::sparse::SparseSpaceVector;
+use crate::fungespace::{
+    bfvec, BefungeVec, Cell16, CellU32, FungeValue, PagedFungeSpace, SparseFungeSpace, SrcIO,
+};
+use crate::interpreter::ip::CreateInstructionPointer;
+use crate::interpreter::{
+    Diagnostic, EnvFuture, ExecMode, Funge, FungeFilesystem, IOMode, InstructionPointer,
+    Interpreter, InterpreterEnv, InterpreterSettings, MotionCmds, ResourceLimits, TraceEvent,
+    UnknownInstructionPolicy,
+};
+
+/// Builder for an [Interpreter] with a [PagedFungeSpace]
+///
+/// Start with [InterpreterBuilder::unefunge] or [InterpreterBuilder::befunge],
+/// pick the cell type, supply an environment, and call
+/// [InterpreterBuilder::build]:
+///
+/// ```
+/// # use rfunge::{bfvec, InterpreterBuilder, ReplayEnv, ReplayLog};
+/// # let env = ReplayEnv::new(ReplayLog::read_from(&mut &b"rfunge-replay 1\n"[..]).unwrap());
+/// let interpreter = InterpreterBuilder::befunge()
+///     .cell_size_32()
+///     .page_size(bfvec(64, 64))
+///     .env(env)
+///     .build();
+/// ```
+///
+/// The default cell type is `i64`.
+pub struct InterpreterBuilder<Idx, Elem, Env> {
+    page_size: Idx,
+    page_limit: Option<usize>,
+    blank: i32,
+    start: Option<(Idx, Idx)>,
+    team: i32,
+    settings: InterpreterSettings,
+    env: Env,
+    _elem: PhantomData<Elem>,
+}
+
+impl InterpreterBuilder<i64, i64, ()> {
+    /// Build a Unefunge interpreter
+    pub fn unefunge() -> Self {
+        Self {
+            page_size: 1000,
+            page_limit: None,
+            blank: ' ' as i32,
+            start: None,
+            team: 0,
+            settings: Default::default(),
+            env: (),
+            _elem: PhantomData,
+        }
+    }
+}
+
+impl InterpreterBuilder<BefungeVec<i64>, i64, ()> {
+    /// Build a Befunge interpreter
+    pub fn befunge() -> Self {
+        InterpreterBuilder {
+            page_size: bfvec(40, 20),
+            page_limit: None,
+            blank: ' ' as i32,
+            start: None,
+            team: 0,
+            settings: Default::default(),
+            env: (),
+            _elem: PhantomData,
+        }
+    }
+}
+
+impl<Idx, Elem, Env> InterpreterBuilder<Idx, Elem, Env> {
+    /// Set the size of the pages of funge-space
+    pub fn page_size(mut self, page_size: Idx) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Limit the number of pages of funge-space that may be allocated. If the
+    /// program needs more, it stops with [ProgramResult::Panic].
+    ///
+    /// [ProgramResult::Panic]: crate::ProgramResult::Panic
+    pub fn page_limit(mut self, limit: usize) -> Self {
+        self.page_limit = Some(limit);
+        self
+    }
+
+    /// Set the value of cells that have never been written to (normally a
+    /// space, 32). Some Befunge-93 programs expect to read 0 from such cells
+    /// with `g`. See [PagedFungeSpace::new_with_blank].
+    pub fn blank(mut self, value: i32) -> Self {
+        self.blank = value;
+        self
+    }
+
+    /// Start the IP at `location` (instead of the origin), moving with
+    /// `delta` (instead of east)
+    pub fn start(mut self, location: Idx, delta: Idx) -> Self {
+        self.start = Some((location, delta));
+        self
+    }
+
+    /// Put the initial IP on team `team` (normally 0, no team). IPs created
+    /// with `t` inherit the team of their parent.
+    pub fn team(mut self, team: i32) -> Self {
+        self.team = team;
+        self
+    }
+
+    /// Set the [InterpreterSettings]
+    pub fn settings(mut self, settings: InterpreterSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Set the environment
+    pub fn env<NewEnv: InterpreterEnv>(self, env: NewEnv) -> InterpreterBuilder<Idx, Elem, NewEnv> {
+        InterpreterBuilder {
+            page_size: self.page_size,
+            page_limit: self.page_limit,
+            blank: self.blank,
+            start: self.start,
+            team: self.team,
+            settings: self.settings,
+            env,
+            _elem: PhantomData,
+        }
+    }
+
+    fn map_idx<NewIdx, NewElem>(
+        self,
+        f: impl Fn(Idx) -> NewIdx,
+    ) -> InterpreterBuilder<NewIdx, NewElem, Env> {
+        InterpreterBuilder {
+            page_size: f(self.page_size),
+            page_limit: self.page_limit,
+            blank: self.blank,
+            start: self.start.map(|(loc, delta)| (f(loc), f(delta))),
+            team: self.team,
+            settings: self.settings,
+            env: self.env,
+            _elem: PhantomData,
+        }
+    }
+}
+
+impl<Idx, Elem, Env> InterpreterBuilder<Idx, Elem, Env>
+where
+    Env: InterpreterEnv,
+{
+    /// Only allow the given fingerprints to be loaded (and only if the
+    /// environment allows them, too)
+    pub fn fingerprints(
+        self,
+        allowed: Vec<i32>,
+    ) -> InterpreterBuilder<Idx, Elem, FingerprintFilter<Env>> {
+        InterpreterBuilder {
+            page_size: self.page_size,
+            page_limit: self.page_limit,
+            blank: self.blank,
+            start: self.start,
+            team: self.team,
+            settings: self.settings,
+            env: FingerprintFilter {
+                env: self.env,
+                allowed,
+            },
+            _elem: PhantomData,
+        }
+    }
+}
+
+impl<T, Elem, Env> InterpreterBuilder<T, Elem, Env>
+where
+    T: FungeValue + Copy,
+{
+    /// Use 32-bit cells
+    pub fn cell_size_32(self) -> InterpreterBuilder<i32, i32, Env> {
+        self.map_idx(|v| v.convert())
+    }
+
+    /// Use 64-bit cells
+    pub fn cell_size_64(self) -> InterpreterBuilder<i64, i64, Env> {
+        self.map_idx(|v| v.convert())
+    }
+
+    /// Use arbitrary-precision cells (with 64-bit coordinates)
+    #[cfg(feature = "bigint")]
+    pub fn bigint(self) -> InterpreterBuilder<i64, BigInt, Env> {
+        self.map_idx(|v| v.convert())
+    }
+
+    /// Use 16-bit cells with wrap-around arithmetic (with 32-bit coordinates)
+    pub fn cell_size_16(self) -> InterpreterBuilder<i32, Cell16, Env> {
+        self.map_idx(|v| v.convert())
+    }
+
+    /// Use unsigned 32-bit cells with wrap-around arithmetic (see [CellU32])
+    pub fn unsigned_32(self) -> InterpreterBuilder<i32, CellU32, Env> {
+        self.map_idx(|v| v.convert())
+    }
+}
+
+impl<T, Elem, Env> InterpreterBuilder<BefungeVec<T>, Elem, Env>
+where
+    T: FungeValue + Copy,
+{
+    /// Use 32-bit cells
+    pub fn cell_size_32(self) -> InterpreterBuilder<BefungeVec<i32>, i32, Env> {
+        self.map_idx(|v| bfvec(v.x.convert::<i32>(), v.y.convert::<i32>()))
+    }
+
+    /// Use 64-bit cells
+    pub fn cell_size_64(self) -> InterpreterBuilder<BefungeVec<i64>, i64, Env> {
+        self.map_idx(|v| bfvec(v.x.convert::<i64>(), v.y.convert::<i64>()))
+    }
+
+    /// Use arbitrary-precision cells (with 64-bit coordinates)
+    #[cfg(feature = "bigint")]
+    pub fn bigint(self) -> InterpreterBuilder<BefungeVec<i64>, BigInt, Env> {
+        self.map_idx(|v| bfvec(v.x.convert::<i64>(), v.y.convert::<i64>()))
+    }
+
+    /// Use 16-bit cells with wrap-around arithmetic (with 32-bit coordinates)
+    pub fn cell_size_16(self) -> InterpreterBuilder<BefungeVec<i32>, Cell16, Env> {
+        self.map_idx(|v| bfvec(v.x.convert::<i32>(), v.y.convert::<i32>()))
+    }
+
+    /// Use unsigned 32-bit cells with wrap-around arithmetic (see [CellU32])
+    pub fn unsigned_32(self) -> InterpreterBuilder<BefungeVec<i32>, CellU32, Env> {
+        self.map_idx(|v| bfvec(v.x.convert::<i32>(), v.y.convert::<i32>()))
+    }
+}
+
+impl<Idx, Elem, Env> InterpreterBuilder<Idx, Elem, Env>
+where
+    Idx: PageSpaceVector
+        + MotionCmds<PagedFungeSpace<Idx, Elem>, Env>
+        + SrcIO<PagedFungeSpace<Idx, Elem>>
+        + CreateInstructionPointer<PagedFungeSpace<Idx, Elem>, Env>
+        + 'static,
+    Elem: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    /// Create the interpreter. Funge-space is empty; use
+    /// [Interpreter::load_program_at] to fill it.
+    pub fn build(self) -> Interpreter<Idx, PagedFungeSpace<Idx, Elem>, Env> {
+        let mut space = PagedFungeSpace::new_with_blank(self.page_size, self.blank.into());
+        space.set_page_limit(self.page_limit);
+        let mut interpreter = Interpreter::new(space, self.env);
+        interpreter.settings = self.settings;
+        interpreter.ips[0].team = self.team;
+        if let Some((location, delta)) = self.start {
+            let ip = &mut interpreter.ips[0];
+            // The IP moves before executing its first instruction
+            ip.location = location + delta * Idx::Scalar::from(-1);
+            ip.delta = delta;
+        }
+        interpreter
+    }
+}
+
+impl<Idx, Elem, Env> InterpreterBuilder<Idx, Elem, Env>
+where
+    Idx: SparseSpaceVector
+        + MotionCmds<SparseFungeSpace<Idx, Elem>, Env>
+        + SrcIO<SparseFungeSpace<Idx, Elem>>
+        + CreateInstructionPointer<SparseFungeSpace<Idx, Elem>, Env>
+        + 'static,
+    Elem: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    /// Create the interpreter with a [SparseFungeSpace] instead of the
+    /// default [PagedFungeSpace] (the page size is ignored). Funge-space is
+    /// empty; use [Interpreter::load_program_at] to fill it.
+    pub fn build_sparse(self) -> Interpreter<Idx, SparseFungeSpace<Idx, Elem>, Env> {
+        let space = SparseFungeSpace::new_with_blank(self.blank.into());
+        let mut interpreter = Interpreter::new(space, self.env);
+        interpreter.settings = self.settings;
+        interpreter.ips[0].team = self.team;
+        if let Some((location, delta)) = self.start {
+            let ip = &mut interpreter.ips[0];
+            // The IP moves before executing its first instruction
+            ip.location = location.plus_steps(&delta, -1);
+            ip.delta = delta;
+        }
+        interpreter
+    }
+}
+
+/// An environment that only allows a given set of fingerprints to be loaded,
+/// and otherwise defers to the wrapped environment. Created by
+/// [InterpreterBuilder::fingerprints].
+pub struct FingerprintFilter<Env> {
+    /// The wrapped environment
+    pub env: Env,
+    /// The fingerprints that may be loaded
+    pub allowed: Vec<i32>,
+}
+
+impl<Env: InterpreterEnv> InterpreterEnv for FingerprintFilter<Env> {
+    fn get_iomode(&self) -> IOMode {
+        self.env.get_iomode()
+    }
+    fn is_io_buffered(&self) -> bool {
+        self.env.is_io_buffered()
+    }
+    fn output_writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
+        self.env.output_writer()
+    }
+    fn input_reader(&mut self) -> &mut (dyn AsyncRead + Unpin) {
+        self.env.input_reader()
+    }
+    fn warn(&mut self, msg: &str) {
+        self.env.warn(msg)
+    }
+    fn diagnostic<F: Funge>(&mut self, diagnostic: &Diagnostic<F>) {
+        self.env.diagnostic(diagnostic)
+    }
+    fn handprint(&self) -> i32 {
+        self.env.handprint()
+    }
+    fn team_number<F: Funge>(&self, ip: &InstructionPointer<F>) -> i32 {
+        self.env.team_number(ip)
+    }
+    fn limits(&self) -> ResourceLimits {
+        self.env.limits()
+    }
+    fn filesystem(&self) -> Option<&dyn FungeFilesystem> {
+        self.env.filesystem()
+    }
+    fn have_file_input(&self) -> bool {
+        self.env.have_file_input()
+    }
+    fn have_file_output(&self) -> bool {
+        self.env.have_file_output()
+    }
+    fn have_execute(&self) -> ExecMode {
+        self.env.have_execute()
+    }
+    fn read_file(&mut self, filename: &str) -> io::Result<Vec<u8>> {
+        self.env.read_file(filename)
+    }
+    fn write_file(&mut self, filename: &str, content: &[u8]) -> io::Result<()> {
+        self.env.write_file(filename, content)
+    }
+    fn write_file_with(
+        &mut self,
+        filename: &str,
+        write: &mut dyn FnMut(&mut dyn io::Write) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.env.write_file_with(filename, write)
+    }
+    fn execute_command(&mut self, command: &str) -> i32 {
+        self.env.execute_command(command)
+    }
+    fn read_file_async<'a>(&'a mut self, filename: &'a str) -> EnvFuture<'a, io::Result<Vec<u8>>> {
+        self.env.read_file_async(filename)
+    }
+    fn write_file_async<'a>(
+        &'a mut self,
+        filename: &'a str,
+        content: &'a [u8],
+    ) -> EnvFuture<'a, io::Result<()>> {
+        self.env.write_file_async(filename, content)
+    }
+    fn write_file_with_async<'a>(
+        &'a mut self,
+        filename: &'a str,
+        write: &'a mut dyn FnMut(&mut dyn io::Write) -> io::Result<()>,
+    ) -> EnvFuture<'a, io::Result<()>> {
+        self.env.write_file_with_async(filename, write)
+    }
+    fn execute_command_async<'a>(&'a mut self, command: &'a str) -> EnvFuture<'a, i32> {
+        self.env.execute_command_async(command)
+    }
+    fn execute_command_capture(&mut self, command: &str) -> (i32, Vec<u8>) {
+        self.env.execute_command_capture(command)
+    }
+    fn execute_command_capture_async<'a>(
+        &'a mut self,
+        command: &'a str,
+    ) -> EnvFuture<'a, (i32, Vec<u8>)> {
+        self.env.execute_command_capture_async(command)
+    }
+    fn env_vars(&mut self) -> Vec<(String, String)> {
+        self.env.env_vars()
+    }
+    fn argv(&mut self) -> Vec<String> {
+        self.env.argv()
+    }
+    fn is_fingerprint_enabled(&self, fpr: i32) -> bool {
+        self.allowed.contains(&fpr) && self.env.is_fingerprint_enabled(fpr)
+    }
+    fn play_tone(&mut self, frequency: f32, duration_ms: u32) -> bool {
+        self.env.play_tone(frequency, duration_ms)
+    }
+    fn random_u32(&mut self) -> u32 {
+        self.env.random_u32()
+    }
+    fn random_u64(&mut self) -> u64 {
+        self.env.random_u64()
+    }
+    fn monotonic_clock(&mut self) -> Duration {
+        self.env.monotonic_clock()
+    }
+    fn monotonic_nanos(&mut self) -> u64 {
+        self.env.monotonic_nanos()
+    }
+    fn now_utc(&mut self) -> DateTime<Utc> {
+        self.env.now_utc()
+    }
+    fn fingerprint_support_library(&mut self, fpr: i32) -> Option<&mut dyn Any> {
+        self.env.fingerprint_support_library(fpr)
+    }
+    #[inline(always)]
+    fn trace<F: Funge>(&mut self, event: &TraceEvent<F>) {
+        self.env.trace(event)
+    }
+    fn on_ip_spawned<F: Funge>(&mut self, id: &F::Value, parent_id: &F::Value) {
+        self.env.on_ip_spawned::<F>(id, parent_id)
+    }
+    fn on_ip_stopped<F: Funge>(&mut self, id: &F::Value) {
+        self.env.on_ip_stopped::<F>(id)
+    }
+    fn unknown_instruction<F: Funge>(
+        &mut self,
+        ip: &InstructionPointer<F>,
+        instruction: &F::Value,
+    ) -> UnknownInstructionPolicy {
+        self.env.unknown_instruction(ip, instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{ProgramResult, RunMode};
+    use crate::{string_to_fingerprint, StringEnv};
+
+    #[test]
+    fn test_builder() {
+        let mut interpreter = InterpreterBuilder::befunge()
+            .cell_size_32()
+            .page_size(bfvec(8, 8))
+            .start(bfvec(1, 1), bfvec(0, 1))
+            .env(StringEnv::default())
+            .build();
+        assert_eq!(interpreter.space.page_size(), bfvec(8, 8));
+        assert_eq!(interpreter.ips[0].location, bfvec(1, 0));
+        interpreter.load_program_at(bfvec(1, 1), "7\n.\n@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.env.output(), "7 ");
+    }
+
+    #[test]
+    fn test_team() {
+        let build = || {
+            InterpreterBuilder::befunge()
+                .team(4)
+                .env(StringEnv::default())
+                .build()
+        };
+        let mut interpreter = build();
+        interpreter.load_program_at(bfvec(0, 0), "9y.@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.env.output(), "4 ");
+
+        let mut interpreter = build();
+        interpreter.load_program_at(bfvec(0, 0), "tzz@");
+        assert_eq!(interpreter.run(RunMode::Step), ProgramResult::Paused);
+        assert_eq!(interpreter.ips.len(), 2);
+        assert!(interpreter.ips.iter().all(|ip| ip.team == 4));
+    }
+
+    #[test]
+    fn test_page_limit() {
+        let build = || {
+            InterpreterBuilder::befunge()
+                .page_size(bfvec(8, 8))
+                .page_limit(1)
+                .env(StringEnv::default())
+                .build()
+        };
+        let mut interpreter = build();
+        interpreter.load_program_at(bfvec(0, 0), "'x77p@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        let mut interpreter = build();
+        interpreter.load_program_at(bfvec(0, 0), "'x88p@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Panic);
+        assert_eq!(interpreter.space[bfvec(8, 8)], ' ' as i64);
+    }
+
+    #[test]
+    fn test_blank() {
+        for sparse in [false, true] {
+            let builder = InterpreterBuilder::befunge()
+                .blank(0)
+                .env(StringEnv::default());
+            let src = "99g.80g. v\n@        <";
+            let output = if sparse {
+                let mut interpreter = builder.build_sparse();
+                interpreter.load_program_at(bfvec(0, 0), src);
+                assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+                interpreter.env.output()
+            } else {
+                let mut interpreter = builder.build();
+                interpreter.load_program_at(bfvec(0, 0), src);
+                assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+                interpreter.env.output()
+            };
+            assert_eq!(output, "0 32 ");
+        }
+    }
+
+    #[test]
+    fn test_build_sparse() {
+        let mut interpreter = InterpreterBuilder::befunge()
+            .env(StringEnv::default())
+            .build_sparse();
+        interpreter.load_program_at(bfvec(0, 0), "v");
+        interpreter.load_program_at(bfvec(0, 1_000_000), "9\n.\n@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.env.output(), "9 ");
+    }
+
+    #[test]
+    fn test_fingerprint_filter() {
+        let interpreter = InterpreterBuilder::unefunge()
+            .env(StringEnv::default())
+            .fingerprints(vec![string_to_fingerprint("NULL")])
+            .build();
+        assert!(interpreter
+            .env
+            .is_fingerprint_enabled(string_to_fingerprint("NULL")));
+        assert!(!interpreter
+            .env
+            .is_fingerprint_enabled(string_to_fingerprint("ROMA")));
+    }
+}
@@ -0,0 +1,337 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Cell types with wrap-around arithmetic, for compatibility with historical
+//! interpreters
+
+use std::fmt::{Display, Formatter};
+use std::num::ParseIntError;
+use std::ops::{Add, Div, Mul, Neg, Not, Rem, Sub};
+use std::ops::{AddAssign, DivAssign, MulAssign, RemAssign, SubAssign};
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign};
+
+use num::{FromPrimitive, Integer, Num, One, Signed, ToPrimitive, Zero};
+
+use super::CellBytes;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+macro_rules! wrapping_cell {
+    ($name:ident, $inner:ty) => {
+        impl From<i32> for $name {
+            #[inline]
+            fn from(n: i32) -> Self {
+                Self(n as $inner)
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl Zero for $name {
+            #[inline]
+            fn zero() -> Self {
+                Self(0)
+            }
+            #[inline]
+            fn is_zero(&self) -> bool {
+                self.0 == 0
+            }
+        }
+
+        impl One for $name {
+            #[inline]
+            fn one() -> Self {
+                Self(1)
+            }
+        }
+
+        impl Num for $name {
+            type FromStrRadixErr = ParseIntError;
+            fn from_str_radix(s: &str, radix: u32) -> Result<Self, ParseIntError> {
+                <$inner>::from_str_radix(s, radix).map(Self)
+            }
+        }
+
+        impl FromPrimitive for $name {
+            #[inline]
+            fn from_i64(n: i64) -> Option<Self> {
+                Some(Self(n as $inner))
+            }
+            #[inline]
+            fn from_u64(n: u64) -> Option<Self> {
+                Some(Self(n as $inner))
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+            #[inline]
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0.wrapping_add(rhs.0))
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+            #[inline]
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0.wrapping_sub(rhs.0))
+            }
+        }
+
+        impl Mul for $name {
+            type Output = Self;
+            #[inline]
+            fn mul(self, rhs: Self) -> Self {
+                Self(self.0.wrapping_mul(rhs.0))
+            }
+        }
+
+        impl Div for $name {
+            type Output = Self;
+            #[inline]
+            fn div(self, rhs: Self) -> Self {
+                Self(self.0.wrapping_div(rhs.0))
+            }
+        }
+
+        impl Rem for $name {
+            type Output = Self;
+            #[inline]
+            fn rem(self, rhs: Self) -> Self {
+                Self(self.0.wrapping_rem(rhs.0))
+            }
+        }
+
+        impl Neg for $name {
+            type Output = Self;
+            #[inline]
+            fn neg(self) -> Self {
+                Self(self.0.wrapping_neg())
+            }
+        }
+
+        impl Not for $name {
+            type Output = Self;
+            #[inline]
+            fn not(self) -> Self {
+                Self(!self.0)
+            }
+        }
+
+        impl BitAnd for $name {
+            type Output = Self;
+            #[inline]
+            fn bitand(self, rhs: Self) -> Self {
+                Self(self.0 & rhs.0)
+            }
+        }
+
+        impl BitOr for $name {
+            type Output = Self;
+            #[inline]
+            fn bitor(self, rhs: Self) -> Self {
+                Self(self.0 | rhs.0)
+            }
+        }
+
+        impl BitXor for $name {
+            type Output = Self;
+            #[inline]
+            fn bitxor(self, rhs: Self) -> Self {
+                Self(self.0 ^ rhs.0)
+            }
+        }
+
+        wrapping_cell!(@assign $name, AddAssign, add_assign, add);
+        wrapping_cell!(@assign $name, SubAssign, sub_assign, sub);
+        wrapping_cell!(@assign $name, MulAssign, mul_assign, mul);
+        wrapping_cell!(@assign $name, DivAssign, div_assign, div);
+        wrapping_cell!(@assign $name, RemAssign, rem_assign, rem);
+        wrapping_cell!(@assign $name, BitAndAssign, bitand_assign, bitand);
+        wrapping_cell!(@assign $name, BitOrAssign, bitor_assign, bitor);
+        wrapping_cell!(@assign $name, BitXorAssign, bitxor_assign, bitxor);
+
+        impl CellBytes for $name {}
+
+        impl Integer for $name {
+            #[inline]
+            fn div_floor(&self, other: &Self) -> Self {
+                // MIN / -1 is the only overflowing case, and it is exact
+                match self.0.overflowing_div(other.0) {
+                    (q, true) => Self(q),
+                    _ => Self(Integer::div_floor(&self.0, &other.0)),
+                }
+            }
+            #[inline]
+            fn mod_floor(&self, other: &Self) -> Self {
+                match self.0.overflowing_rem(other.0) {
+                    (r, true) => Self(r),
+                    _ => Self(Integer::mod_floor(&self.0, &other.0)),
+                }
+            }
+            fn gcd(&self, other: &Self) -> Self {
+                Self(Integer::gcd(&self.0, &other.0))
+            }
+            fn lcm(&self, other: &Self) -> Self {
+                Self(Integer::lcm(&self.0, &other.0))
+            }
+            fn is_multiple_of(&self, other: &Self) -> bool {
+                Integer::is_multiple_of(&self.0, &other.0)
+            }
+            fn is_even(&self) -> bool {
+                Integer::is_even(&self.0)
+            }
+            fn is_odd(&self) -> bool {
+                Integer::is_odd(&self.0)
+            }
+            #[inline]
+            fn div_rem(&self, other: &Self) -> (Self, Self) {
+                (*self / *other, *self % *other)
+            }
+        }
+    };
+    (@assign $name:ident, $trait:ident, $method:ident, $op:ident) => {
+        impl $trait for $name {
+            #[inline]
+            fn $method(&mut self, rhs: Self) {
+                *self = (*self).$op(rhs);
+            }
+        }
+    };
+}
+
+/// A 16-bit signed cell; arithmetic wraps around on overflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Cell16(pub i16);
+
+wrapping_cell!(Cell16, i16);
+
+impl ToPrimitive for Cell16 {
+    #[inline]
+    fn to_i64(&self) -> Option<i64> {
+        Some(self.0 as i64)
+    }
+    #[inline]
+    fn to_u64(&self) -> Option<u64> {
+        self.0.to_u64()
+    }
+}
+
+impl Signed for Cell16 {
+    fn abs(&self) -> Self {
+        Self(self.0.wrapping_abs())
+    }
+    fn abs_sub(&self, other: &Self) -> Self {
+        if self <= other {
+            Self(0)
+        } else {
+            *self - *other
+        }
+    }
+    fn signum(&self) -> Self {
+        Self(self.0.signum())
+    }
+    fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+    fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+}
+
+/// A 32-bit unsigned cell; arithmetic wraps around, so `0 1 -` is
+/// 4294967295, and comparisons and division are unsigned.
+///
+/// When a value is used as a coordinate (or converted to any other signed
+/// type), it is reinterpreted as a 32-bit two's complement number, so that
+/// e.g. a delta of 4294967295 means -1, as it would in an interpreter with
+/// wrapping 32-bit coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct CellU32(pub u32);
+
+wrapping_cell!(CellU32, u32);
+
+impl ToPrimitive for CellU32 {
+    #[inline]
+    fn to_i64(&self) -> Option<i64> {
+        Some(self.0 as i32 as i64)
+    }
+    #[inline]
+    fn to_u64(&self) -> Option<u64> {
+        Some(self.0 as u64)
+    }
+}
+
+/// Unsigned values are never negative: `abs` is the identity
+impl Signed for CellU32 {
+    fn abs(&self) -> Self {
+        *self
+    }
+    fn abs_sub(&self, other: &Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+    fn signum(&self) -> Self {
+        Self((self.0 != 0) as u32)
+    }
+    fn is_positive(&self) -> bool {
+        self.0 != 0
+    }
+    fn is_negative(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fungespace::FungeValue;
+
+    #[test]
+    fn test_cell16() {
+        let max = Cell16(i16::MAX);
+        assert_eq!(max + 1.into(), Cell16(i16::MIN));
+        assert_eq!(Cell16::from(70000), Cell16(4464));
+        assert_eq!(Cell16(-7).div_floor(&2.into()), Cell16(-4));
+        assert_eq!(Cell16(i16::MIN).div_floor(&(-1).into()), Cell16(i16::MIN));
+        assert_eq!(Cell16(i16::MIN).mod_floor(&(-1).into()), Cell16(0));
+        assert_eq!(Cell16(-3).convert::<i64>(), -3);
+        assert_eq!(Cell16('A' as i16).to_char(), 'A');
+    }
+
+    #[test]
+    fn test_cell_u32() {
+        let minus_one = CellU32(0) - 1.into();
+        assert_eq!(minus_one, CellU32(u32::MAX));
+        assert_eq!(minus_one.to_string(), "4294967295");
+        assert!(minus_one > 1.into());
+        assert_eq!(minus_one / 2.into(), CellU32(i32::MAX as u32));
+        assert_eq!(minus_one.convert::<i64>(), -1);
+        assert_eq!(CellU32::from(-2), CellU32(u32::MAX - 1));
+        assert_eq!(minus_one.div_floor(&minus_one), CellU32(1));
+        assert_eq!(minus_one.mod_floor(&2.into()), CellU32(1));
+    }
+}
@@ -0,0 +1,125 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{FungeIndex, FungeSpace, FungeValue};
+
+/// A single changed cell in a [FungeSpaceDiff]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CellChange<Idx, Elem> {
+    pub idx: Idx,
+    pub old: Elem,
+    pub new: Elem,
+}
+
+/// The cells that differ between two states of funge-space, with both the
+/// old and the new value, so that the change can be applied or undone.
+///
+/// Get one from [super::PagedFungeSpace::diff] or
+/// [super::SparseFungeSpace::diff], or build one up with
+/// [FungeSpaceDiff::record].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FungeSpaceDiff<Idx, Elem> {
+    changes: Vec<CellChange<Idx, Elem>>,
+}
+
+impl<Idx, Elem> FungeSpaceDiff<Idx, Elem>
+where
+    Idx: FungeIndex,
+    Elem: FungeValue,
+{
+    /// Create an empty diff
+    pub fn new() -> Self {
+        Self {
+            changes: Vec::new(),
+        }
+    }
+
+    /// Record that the cell at `idx` changed from `old` to `new`. Nothing is
+    /// recorded if the two values are the same.
+    pub fn record(&mut self, idx: Idx, old: Elem, new: Elem) {
+        if old != new {
+            self.changes.push(CellChange { idx, old, new });
+        }
+    }
+
+    /// Get the changed cells
+    pub fn changes(&self) -> &[CellChange<Idx, Elem>] {
+        &self.changes
+    }
+
+    /// Get the number of changed cells
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Are there no changed cells?
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Get the diff that undoes this one
+    pub fn inverse(&self) -> Self {
+        Self {
+            changes: self
+                .changes
+                .iter()
+                .rev()
+                .map(|c| CellChange {
+                    idx: c.idx,
+                    old: c.new.clone(),
+                    new: c.old.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Write the new values into `space`
+    pub fn apply<Space>(&self, space: &mut Space)
+    where
+        Space: FungeSpace<Idx, Output = Elem>,
+    {
+        for c in &self.changes {
+            space[c.idx] = c.new.clone();
+        }
+    }
+
+    /// Write the old values into `space`, undoing [FungeSpaceDiff::apply]
+    pub fn revert<Space>(&self, space: &mut Space)
+    where
+        Space: FungeSpace<Idx, Output = Elem>,
+    {
+        for c in self.changes.iter().rev() {
+            space[c.idx] = c.old.clone();
+        }
+    }
+}
+
+impl<Idx, Elem> Default for FungeSpaceDiff<Idx, Elem>
+where
+    Idx: FungeIndex,
+    Elem: FungeValue,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
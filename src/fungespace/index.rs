@@ -21,6 +21,9 @@ use std::fmt::{Display, Formatter};
 use std::ops::{Add, Div, Mul, Rem, Sub};
 
 use divrem::{DivEuclid, DivRem, DivRemEuclid, RemEuclid};
+use num::Integer;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use super::{FungeArrayIdx, FungeIndex, FungeValue};
 
@@ -30,7 +33,7 @@ use super::{FungeArrayIdx, FungeIndex, FungeValue};
 
 impl<T> FungeIndex for T
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     const RANK: i32 = 1;
 
@@ -85,7 +88,7 @@ where
 
 impl<T> FungeArrayIdx for T
 where
-    T: FungeValue + RemEuclid,
+    T: FungeValue + Copy + RemEuclid,
 {
     fn to_lin_index(&self, array_size: &Self) -> usize {
         self.rem_euclid(*array_size).to_usize().unwrap()
@@ -110,9 +113,10 @@ where
 
 /// A Befunge index
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     pub x: T,
     pub y: T,
@@ -121,7 +125,7 @@ where
 /// Convenience function to create a [BefungeVec]
 pub fn bfvec<Tout, Tin>(x: Tin, y: Tin) -> BefungeVec<Tout>
 where
-    Tout: FungeValue,
+    Tout: FungeValue + Copy,
     Tin: Into<Tout>,
 {
     BefungeVec::<Tout> {
@@ -132,7 +136,7 @@ where
 
 impl<T> Display for BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "({}, {})", self.x, self.y)
@@ -141,7 +145,7 @@ where
 
 impl<T> Add for BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     type Output = Self;
     #[inline(always)]
@@ -155,7 +159,7 @@ where
 
 impl<T> Sub for BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     type Output = Self;
     #[inline(always)]
@@ -169,7 +173,7 @@ where
 
 impl<T> Mul<T> for BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     type Output = Self;
     #[inline(always)]
@@ -183,7 +187,7 @@ where
 
 impl<T> Mul for BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     type Output = Self;
     #[inline(always)]
@@ -197,7 +201,7 @@ where
 
 impl<T> Div for BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     type Output = Self;
     #[inline(always)]
@@ -211,7 +215,7 @@ where
 
 impl<T> Rem for BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     type Output = Self;
     #[inline(always)]
@@ -225,20 +229,20 @@ where
 
 impl<T> DivRem for BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
     type Output = (Self, Self);
     #[inline(always)]
     fn div_rem(self, rhs: Self) -> (Self, Self) {
-        let (x_d, x_r) = self.x.div_rem(rhs.x);
-        let (y_d, y_r) = self.y.div_rem(rhs.y);
+        let (x_d, x_r) = Integer::div_rem(&self.x, &rhs.x);
+        let (y_d, y_r) = Integer::div_rem(&self.y, &rhs.y);
         (Self { x: x_d, y: y_d }, Self { x: x_r, y: y_r })
     }
 }
 
 impl<T> DivEuclid for BefungeVec<T>
 where
-    T: FungeValue + DivEuclid,
+    T: FungeValue + Copy + DivEuclid,
 {
     #[inline(always)]
     fn div_euclid(self, rhs: Self) -> Self {
@@ -251,7 +255,7 @@ where
 
 impl<T> RemEuclid for BefungeVec<T>
 where
-    T: FungeValue + RemEuclid,
+    T: FungeValue + Copy + RemEuclid,
 {
     #[inline(always)]
     fn rem_euclid(self, rhs: Self) -> Self {
@@ -264,7 +268,7 @@ where
 
 impl<T> DivRemEuclid for BefungeVec<T>
 where
-    T: FungeValue + DivRemEuclid,
+    T: FungeValue + Copy + DivRem<Output = (T, T)> + DivRemEuclid,
 {
     #[inline(always)]
     fn div_rem_euclid(self, rhs: Self) -> (Self, Self) {
@@ -276,9 +280,9 @@ where
 
 impl<T> FungeIndex for BefungeVec<T>
 where
-    T: FungeValue,
+    T: FungeValue + Copy,
 {
-    const RANK: i32 = 1;
+    const RANK: i32 = 2;
 
     #[inline(always)]
     fn joint_min(&self, other: &Self) -> Self {
@@ -390,7 +394,7 @@ where
 
 impl<T> FungeArrayIdx for BefungeVec<T>
 where
-    T: FungeValue + RemEuclid,
+    T: FungeValue + Copy + RemEuclid,
 {
     fn to_lin_index(&self, array_size: &Self) -> usize {
         let trunc = self.rem_euclid(*array_size);
@@ -403,7 +407,7 @@ where
 
     fn from_lin_index(lin_idx: usize, array_size: &Self) -> Self {
         let width: T = array_size.x.to_i32().unwrap().into();
-        let (y, x) = T::from(lin_idx as i32).div_rem(width);
+        let (y, x) = Integer::div_rem(&T::from(lin_idx as i32), &width);
         Self { x, y }
     }
 
@@ -434,11 +438,11 @@ mod tests {
 
     #[test]
     fn test_1d_arraymethods() {
-        assert_eq!((100 as i64).to_lin_index(&200), 100);
-        assert_eq!((100 as i64).to_lin_index(&90), 10);
-        assert_eq!((-3 as i64).to_lin_index(&100), 97);
+        assert_eq!(100_i64.to_lin_index(&200), 100);
+        assert_eq!(100_i64.to_lin_index(&90), 10);
+        assert_eq!((-3_i64).to_lin_index(&100), 97);
         assert_eq!(<i64 as FungeArrayIdx>::from_lin_index(76, &100), 76);
-        assert_eq!((874 as i64).lin_size(), 874);
+        assert_eq!(874_i64.lin_size(), 874);
     }
 
     #[test]
@@ -16,25 +16,43 @@ You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+pub mod cells;
+pub mod diff;
 pub mod index;
 pub mod paged;
+pub mod sparse;
 
 use std::cmp::max;
 use std::fmt::{Debug, Display};
-use std::ops::{AddAssign, DivAssign, MulAssign, RemAssign, SubAssign};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::mem::size_of;
+use std::ops::{Add, AddAssign, DivAssign, MulAssign, RemAssign, SubAssign};
 use std::ops::{BitAnd, BitOr, BitXor, Neg, Not};
 use std::ops::{BitAndAssign, BitOrAssign, BitXorAssign};
 use std::ops::{Index, IndexMut};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use divrem::DivRem;
-use num::{FromPrimitive, Num, Signed, ToPrimitive};
+#[cfg(feature = "bigint")]
+use num::BigInt;
+use num::{FromPrimitive, Integer, Num, Signed, ToPrimitive};
 
+pub use self::cells::{Cell16, CellU32};
+pub use self::diff::{CellChange, FungeSpaceDiff};
 pub use self::index::{bfvec, BefungeVec};
 pub use self::paged::PagedFungeSpace;
+pub use self::sparse::SparseFungeSpace;
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Get a new, unique number for [FungeSpace::generation]
+pub fn next_generation() -> u64 {
+    GENERATION.fetch_add(1, Ordering::Relaxed)
+}
 
 /// Generic index into funge space. Specific implementations of funge-space
 /// require additional traits to be implemented, as do some instructions.
-pub trait FungeIndex: Eq + Copy + Debug + 'static {
+pub trait FungeIndex: Eq + Copy + Hash + Debug + 'static {
     /// The number of scalars per vector
     const RANK: i32;
 
@@ -93,6 +111,9 @@ where
     /// Stops at the next non-space character, and returns a tuple of the
     /// index of the new position and (a reference to) the value found there.
     ///
+    /// If `delta` is zero, the position doesn't change: return `start` and
+    /// the value there, even if it is a space.
+    ///
     /// Need not skip over `;` cells
     fn move_by(&self, start: Idx, delta: Idx) -> (Idx, &Self::Output);
 
@@ -111,6 +132,78 @@ where
     ///
     /// Returns `None` when there is no data/code
     fn max_idx(&self) -> Option<Idx>;
+
+    /// The value of cells that have never been written to (normally a space)
+    fn blank(&self) -> Self::Output
+    where
+        Self::Output: FungeValue,
+    {
+        (' ' as i32).into()
+    }
+
+    /// Limit the memory used by funge-space to (about) `bytes` bytes.
+    /// Writes that would need more memory are dropped, and reported by
+    /// [FungeSpace::take_limit_exceeded]. Implementations that can't
+    /// enforce a limit ignore this.
+    fn set_memory_limit(&mut self, _bytes: Option<usize>) {}
+
+    /// Has a write been dropped because funge-space has reached its size
+    /// limit (if it has one) since the last call?
+    fn take_limit_exceeded(&mut self) -> bool {
+        false
+    }
+
+    /// A number that changes whenever funge-space may have been written to,
+    /// or `None` if this implementation doesn't keep track. Numbers are
+    /// never reused (see [next_generation]), so two funge-spaces with the
+    /// same generation have the same contents, even if one is a clone of
+    /// the other.
+    fn generation(&self) -> Option<u64> {
+        None
+    }
+
+    /// Fill the region of size `size` starting at `start` with `value`.
+    /// (`size` must be positive in every direction.)
+    fn fill_region(&mut self, start: Idx, size: Idx, value: Self::Output)
+    where
+        Idx: FungeArrayIdx + Add<Output = Idx>,
+        Self::Output: FungeValue,
+    {
+        for i in 0..size.lin_size() {
+            self[start + Idx::from_lin_index(i, &size)] = value.clone();
+        }
+    }
+
+    /// Copy the region of size `size` starting at `start` so that it starts
+    /// at `dest`. The regions may overlap.
+    fn copy_region(&mut self, start: Idx, size: Idx, dest: Idx)
+    where
+        Idx: FungeArrayIdx + Add<Output = Idx>,
+        Self::Output: FungeValue,
+    {
+        let values: Vec<Self::Output> = (0..size.lin_size())
+            .map(|i| self[start + Idx::from_lin_index(i, &size)].clone())
+            .collect();
+        for (i, value) in values.into_iter().enumerate() {
+            self[dest + Idx::from_lin_index(i, &size)] = value;
+        }
+    }
+
+    /// Move the region of size `size` starting at `start` so that it starts
+    /// at `dest`, leaving blank cells behind. The regions may overlap.
+    fn move_region(&mut self, start: Idx, size: Idx, dest: Idx)
+    where
+        Idx: FungeArrayIdx + Add<Output = Idx>,
+        Self::Output: FungeValue,
+    {
+        let values: Vec<Self::Output> = (0..size.lin_size())
+            .map(|i| self[start + Idx::from_lin_index(i, &size)].clone())
+            .collect();
+        self.fill_region(start, size, self.blank());
+        for (i, value) in values.into_iter().enumerate() {
+            self[dest + Idx::from_lin_index(i, &size)] = value;
+        }
+    }
 }
 
 /// Trait to help use index types when (part of) funge space is stored in an
@@ -137,15 +230,38 @@ pub trait FungeArrayIdx: FungeIndex {
     fn from_lin_index(lin_idx: usize, array_size: &Self) -> Self;
 }
 
+/// How big a funge value is, as reported by `y`.
+///
+/// The default is the size of the type, which is right for fixed-width
+/// integers; types of unbounded size, like `num::BigInt`, report 0.
+pub trait CellBytes: Sized {
+    /// Bytes per cell, or 0 if the size is unbounded
+    const CELL_BYTES: i32 = size_of::<Self>() as i32;
+}
+
+impl CellBytes for i32 {}
+impl CellBytes for i64 {}
+impl CellBytes for i128 {}
+
+#[cfg(feature = "bigint")]
+impl CellBytes for BigInt {
+    const CELL_BYTES: i32 = 0;
+}
+
 /// A value that can live in funge space (automatically implemented for any
-/// type that implements the prerequisites, in particular `i32` and `i64`)
+/// type that implements the prerequisites, in particular `i32`, `i64`, and
+/// `num::BigInt`)
+///
+/// Funge values need not be `Copy`; index types, however, are built from
+/// scalars that are both `FungeValue` and `Copy`.
 pub trait FungeValue:
-    Num
+    CellBytes
+    + Num
     + ToPrimitive
     + FromPrimitive
     + From<i32>
     + Signed
-    + DivRem<Output = (Self, Self)>
+    + Integer
     + BitAnd<Output = Self>
     + BitOr<Output = Self>
     + BitXor<Output = Self>
@@ -161,7 +277,8 @@ pub trait FungeValue:
     + BitXorAssign
     + Ord
     + Eq
-    + Copy
+    + Hash
+    + Clone
     + Display
     + Debug
     + 'static
@@ -174,15 +291,31 @@ pub trait FungeValue:
     fn to_char(&self) -> char {
         self.try_to_char().unwrap_or('�')
     }
+    /// Convert to another funge value type (e.g., a cell value to a
+    /// coordinate). Values outside of the range of `i64` saturate.
+    #[inline]
+    fn convert<T: FungeValue>(&self) -> T {
+        if let Some(v) = self.to_i64().and_then(T::from_i64) {
+            v
+        } else {
+            let saturated = if self.is_negative() {
+                i64::MIN
+            } else {
+                i64::MAX
+            };
+            T::from_i64(saturated).unwrap_or_else(T::zero)
+        }
+    }
 }
 
 impl<T> FungeValue for T where
-    T: Num
+    T: CellBytes
+        + Num
         + ToPrimitive
         + FromPrimitive
         + From<i32>
         + Signed
-        + DivRem<Output = (Self, Self)>
+        + Integer
         + BitAnd<Output = Self>
         + BitOr<Output = Self>
         + BitXor<Output = Self>
@@ -198,7 +331,8 @@ impl<T> FungeValue for T where
         + BitXorAssign
         + Ord
         + Eq
-        + Copy
+        + Hash
+        + Clone
         + Display
         + Debug
         + 'static
@@ -214,16 +348,83 @@ where
 {
     /// Read a binary/latin1 file (`src`) into `space` starting at index
     /// `start`; returns the size of the region written to.
+    ///
+    /// Spaces in the source leave funge-space untouched, unless the blank
+    /// value of `space` is something else (see [FungeSpace::blank]), in which
+    /// case they are written like any other character.
     fn read_bin_at(space: &mut Space, start: &Self, src: &[u8]) -> Self;
 
     /// Read a unicode file (`src`) into `space` starting at index
     /// `start`; returns the size of the region written to.
     fn read_str_at(space: &mut Space, start: &Self, src: &str) -> Self;
 
+    /// Get the size of the region [SrcIO::read_bin_at] would write to
+    fn bin_size(src: &[u8]) -> Self;
+
+    /// Get the size of the region [SrcIO::read_str_at] would write to
+    fn str_size(src: &str) -> Self;
+
+    /// Get the start of a region of size `size` centred on the origin
+    /// (with any odd cell out on the positive side)
+    fn centered_start(size: &Self) -> Self;
+
+    /// Parse an index written as comma-separated components, e.g. `-3,10`
+    fn parse_idx(s: &str) -> Option<Self>;
+
     /// Get the region of `space` starting at `start` with size `size` as
     /// funge-98 source code, independently of encoding. If `strip` is `true`,
-    /// trailing spaces/newlines/etc should be removed.
-    fn get_src_region(space: &Space, start: &Self, size: &Self, strip: bool) -> Vec<Space::Output>;
+    /// trailing spaces/newlines/etc should be removed. Blank cells are
+    /// written as spaces.
+    fn get_src_region(space: &Space, start: &Self, size: &Self, strip: bool) -> Vec<Space::Output> {
+        let mut src = Vec::new();
+        // Collecting into a Vec can't fail
+        let _ = Self::for_each_src_cell(space, start, size, strip, |v| {
+            src.push(v);
+            Ok(())
+        });
+        src
+    }
+
+    /// Call `f` for every value [SrcIO::get_src_region] would return, in
+    /// order, without building the whole buffer. Stops at the first error.
+    fn for_each_src_cell<F>(
+        space: &Space,
+        start: &Self,
+        size: &Self,
+        strip: bool,
+        f: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(Space::Output) -> io::Result<()>;
+
+    /// Like [SrcIO::get_src_str], but writes the UTF-8 source to `out` as it
+    /// goes. (`out` should be buffered.)
+    fn write_src_to<W: Write + ?Sized>(
+        space: &Space,
+        start: &Self,
+        size: &Self,
+        strip: bool,
+        out: &mut W,
+    ) -> io::Result<()> {
+        let mut buf = [0u8; 4];
+        Self::for_each_src_cell(space, start, size, strip, |v| {
+            out.write_all(v.to_char().encode_utf8(&mut buf).as_bytes())
+        })
+    }
+
+    /// Like [SrcIO::get_src_bin], but writes the bytes to `out` as it goes.
+    /// (`out` should be buffered.)
+    fn write_src_bin_to<W: Write + ?Sized>(
+        space: &Space,
+        start: &Self,
+        size: &Self,
+        strip: bool,
+        out: &mut W,
+    ) -> io::Result<()> {
+        Self::for_each_src_cell(space, start, size, strip, |v| {
+            out.write_all(&[v.to_u8().unwrap_or(0xff)])
+        })
+    }
 
     /// Like [SrcIO::get_src_region], but returns a UTF-8 string (replacing
     /// out-of-range values with U+FFFD �)
@@ -248,180 +449,316 @@ where
 impl<Space, T> SrcIO<Space> for T
 where
     T: FungeValue + FungeIndex,
-    Space: FungeSpace<T> + Index<T, Output = T>,
+    Space: FungeSpace<T>,
+    Space::Output: FungeValue,
 {
     /// Read a binary / latin1 file into a unefunge space starting at position `start`
     fn read_bin_at(space: &mut Space, start: &Self, src: &[u8]) -> Self {
-        let mut idx = *start;
-
-        for byte in src {
-            match byte {
-                10 | 12 | 13 => {} // skip CR & FF & LF
-                byte => {
-                    let value = *byte as i32;
-                    if value != (' ' as i32) {
-                        space[idx] = value.into();
-                    }
-                    idx += 1.into();
-                }
-            }
-        }
-
-        idx - *start
+        let blank = space.blank();
+        scan_unefunge_bin(*start, src, |idx, value| {
+            write_cell(space, &blank, idx, value)
+        })
     }
 
     /// Read a string into unifunge space starting at position `start`
     fn read_str_at(space: &mut Space, start: &Self, src: &str) -> Self {
-        let mut i = *start;
+        let blank = space.blank();
+        scan_unefunge_str(*start, src, |idx, value| {
+            write_cell(space, &blank, idx, value)
+        })
+    }
 
-        for line in src.lines() {
-            for c in line.chars() {
-                if c != '\x0c' {
-                    if c != ' ' {
-                        space[i] = (c as i32).into();
-                    }
-                    i += 1.into();
-                }
-            }
-        }
+    fn bin_size(src: &[u8]) -> Self {
+        scan_unefunge_bin(0.into(), src, |_, _| {})
+    }
 
-        i - *start
+    fn str_size(src: &str) -> Self {
+        scan_unefunge_str(0.into(), src, |_, _| {})
     }
 
-    fn get_src_region(space: &Space, start: &Self, size: &Self, strip: bool) -> Vec<Space::Output> {
-        let mut src = Vec::new();
-        if *size < 0.into() {
-            return src;
-        }
-        src.reserve_exact(size.to_usize().unwrap());
-        for i in 0..size.to_i32().unwrap() {
-            src[i as usize] = space[Self::from(i) + *start];
+    fn centered_start(size: &Self) -> Self {
+        -size.div_floor(&2.into())
+    }
+
+    fn parse_idx(s: &str) -> Option<Self> {
+        s.trim().parse::<i64>().ok().and_then(Self::from_i64)
+    }
+
+    fn for_each_src_cell<F>(
+        space: &Space,
+        start: &Self,
+        size: &Self,
+        strip: bool,
+        mut f: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(Space::Output) -> io::Result<()>,
+    {
+        let blank = space.blank();
+        let space_char: Space::Output = (' ' as i32).into();
+        let mut n_spaces = 0;
+        let mut i: Self = 0.into();
+        while i < *size {
+            let val = space[*start + i].clone();
+            if val == space_char || val == blank {
+                // Skip spaces at the end
+                n_spaces += 1;
+            } else {
+                // Put spaces back
+                for _ in 0..n_spaces {
+                    f(space_char.clone())?;
+                }
+                n_spaces = 0;
+                f(val)?;
+            }
+            i += 1.into();
         }
-        if strip {
-            while src[src.len() - 1] == T::from(' ' as i32) {
-                src.pop();
+        if !strip {
+            for _ in 0..n_spaces {
+                f(space_char.clone())?;
             }
         }
-        src
+        Ok(())
     }
 }
 
 /// SrcIO implementation for befunge
 impl<Space, T> SrcIO<Space> for BefungeVec<T>
 where
-    T: FungeValue,
-    Space: FungeSpace<BefungeVec<T>> + Index<BefungeVec<T>, Output = T>,
+    T: FungeValue + Copy,
+    Space: FungeSpace<BefungeVec<T>>,
+    Space::Output: FungeValue,
 {
     /// Read a binary / latin1 file into a unefunge space starting at position `start`
     fn read_bin_at(space: &mut Space, start: &Self, src: &[u8]) -> Self {
-        let mut x: T = start.x;
-        let mut y: T = start.y;
-        let mut max_x: T = start.x;
-        let mut recent_cr = false;
-        for byte in src {
-            match byte {
-                10 => {
-                    // line feed
-                    if !recent_cr {
-                        max_x = max(x, max_x);
-                        x = start.x;
-                        y += 1.into();
-                    }
-                    recent_cr = false;
-                }
-                13 => {
-                    // carriage return
-                    max_x = max(x, max_x);
-                    x = start.x;
-                    y += 1.into();
-                    recent_cr = true;
-                }
-                12 => {
-                    // form feed
-                    // do nothing
-                }
-                byte => {
-                    let value = *byte as i32;
-                    if value != (' ' as i32) {
-                        space[bfvec(x, y)] = value.into();
-                    }
-                    x += 1.into();
-                    recent_cr = false;
-                }
-            }
-        }
-        max_x = max(x, max_x);
-        if x != start.x {
-            y += 1.into();
-        }
-        Self { x: max_x, y } - *start
+        let blank = space.blank();
+        scan_befunge_bin(*start, src, |idx, value| {
+            write_cell(space, &blank, idx, value)
+        })
     }
 
     /// Read a string into unifunge space starting at position `start`
     fn read_str_at(space: &mut Space, start: &Self, src: &str) -> Self {
-        let mut max_x: T = 0.into();
-        let mut max_y: T = 0.into();
-        for (y, line) in src.lines().enumerate() {
-            for (x, c) in line.chars().enumerate() {
-                if c != '\x0c' {
-                    if c != ' ' {
-                        space[*start
-                            + bfvec(T::from_usize(x).unwrap(), T::from_usize(y).unwrap())] =
-                            (c as i32).into();
-                    }
-                    max_x = max(((x + 1) as i32).into(), max_x);
-                }
-            }
-            max_y = max(((y + 1) as i32).into(), max_y);
-        }
-        Self { x: max_x, y: max_y }
+        let blank = space.blank();
+        scan_befunge_str(*start, src, |idx, value| {
+            write_cell(space, &blank, idx, value)
+        })
     }
 
-    fn get_src_region(space: &Space, start: &Self, size: &Self, strip: bool) -> Vec<Space::Output> {
+    fn bin_size(src: &[u8]) -> Self {
+        scan_befunge_bin(Self::origin(), src, |_, _| {})
+    }
+
+    fn str_size(src: &str) -> Self {
+        scan_befunge_str(Self::origin(), src, |_, _| {})
+    }
+
+    fn centered_start(size: &Self) -> Self {
+        bfvec(-size.x.div_floor(&2.into()), -size.y.div_floor(&2.into()))
+    }
+
+    fn parse_idx(s: &str) -> Option<Self> {
+        let (x, y) = s.split_once(',')?;
+        Some(bfvec(
+            T::from_i64(x.trim().parse().ok()?)?,
+            T::from_i64(y.trim().parse().ok()?)?,
+        ))
+    }
+
+    fn for_each_src_cell<F>(
+        space: &Space,
+        start: &Self,
+        size: &Self,
+        strip: bool,
+        mut f: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(Space::Output) -> io::Result<()>,
+    {
         if size.x < 0.into() || size.y < 0.into() {
-            return Vec::new();
+            return Ok(());
         }
 
-        let mut src = Vec::new();
-        let size_x = size.x.to_usize().unwrap();
-        let size_y = size.y.to_usize().unwrap();
-
-        for y_out in 0..size_y {
-            if y_out != 0 {
-                src.push(('\n' as i32).into());
+        let blank = space.blank();
+        let space_char: Space::Output = (' ' as i32).into();
+        let mut n_newlines = 0;
+        let mut y_out: T = 0.into();
+        while y_out < size.y {
+            if y_out != 0.into() {
+                if strip {
+                    // Skip newlines at the end
+                    n_newlines += 1;
+                } else {
+                    f(('\n' as i32).into())?;
+                }
             }
-            let y_in = T::from_usize(y_out).unwrap() + start.y;
+            let y_in = y_out + start.y;
             let mut n_spaces = 0;
-            for x_out in 0..size_x {
-                let x_in = T::from_usize(x_out).unwrap() + start.x;
-                let val = space[Self { x: x_in, y: y_in }];
-                if val == (' ' as i32).into() {
+            let mut x_out: T = 0.into();
+            while x_out < size.x {
+                let x_in = x_out + start.x;
+                let val = space[Self { x: x_in, y: y_in }].clone();
+                if val == space_char || val == blank {
                     // Skip spaces at the end
                     n_spaces += 1;
                 } else {
-                    // Put spaces back
+                    // Put newlines and spaces back
+                    for _ in 0..n_newlines {
+                        f(('\n' as i32).into())?;
+                    }
+                    n_newlines = 0;
                     for _ in 0..n_spaces {
-                        src.push((' ' as i32).into());
+                        f(space_char.clone())?;
                     }
                     n_spaces = 0;
-                    src.push(val);
+                    f(val)?;
                 }
+                x_out += 1.into();
             }
             if !strip {
                 for _ in 0..n_spaces {
-                    src.push((' ' as i32).into());
+                    f(space_char.clone())?;
                 }
             }
+            y_out += 1.into();
+        }
+
+        Ok(())
+    }
+}
+
+/// Write a cell read from a source file, unless it is blank
+fn write_cell<Idx, Space>(space: &mut Space, blank: &Space::Output, idx: Idx, value: i32)
+where
+    Idx: FungeIndex,
+    Space: FungeSpace<Idx>,
+    Space::Output: FungeValue,
+{
+    let value = value.into();
+    if value != *blank {
+        space[idx] = value;
+    }
+}
+
+/// Split a source string into lines, which may end in LF, CR, or CR LF
+fn src_lines(mut src: &str) -> impl Iterator<Item = &str> {
+    std::iter::from_fn(move || {
+        if src.is_empty() {
+            return None;
         }
+        let (line, rest) = match src.find(['\r', '\n']) {
+            Some(i) if src[i..].starts_with("\r\n") => (&src[..i], &src[i + 2..]),
+            Some(i) => (&src[..i], &src[i + 1..]),
+            None => (src, ""),
+        };
+        src = rest;
+        Some(line)
+    })
+}
 
-        if strip {
-            while !src.is_empty() && src[src.len() - 1] == ('\n' as i32).into() {
-                src.pop();
+/// Call `write` for every cell in a binary/latin1 unefunge source
+/// file placed at `start`; returns the size of the region
+fn scan_unefunge_bin<T: FungeValue>(start: T, src: &[u8], mut write: impl FnMut(T, i32)) -> T {
+    let mut idx = start.clone();
+
+    for byte in src {
+        match byte {
+            10 | 12 | 13 => {} // skip CR & FF & LF
+            byte => {
+                write(idx.clone(), *byte as i32);
+                idx += 1.into();
             }
         }
+    }
 
-        src
+    idx - start
+}
+
+/// Call `write` for every cell in a unefunge source string placed
+/// at `start`; returns the size of the region
+fn scan_unefunge_str<T: FungeValue>(start: T, src: &str, mut write: impl FnMut(T, i32)) -> T {
+    let mut i = start.clone();
+
+    for line in src_lines(src) {
+        for c in line.chars() {
+            if c != '\x0c' {
+                write(i.clone(), c as i32);
+                i += 1.into();
+            }
+        }
     }
+
+    i - start
+}
+
+/// Call `write` for every cell in a binary/latin1 befunge source
+/// file placed at `start`; returns the size of the region
+fn scan_befunge_bin<T: FungeValue + Copy>(
+    start: BefungeVec<T>,
+    src: &[u8],
+    mut write: impl FnMut(BefungeVec<T>, i32),
+) -> BefungeVec<T> {
+    let mut x: T = start.x;
+    let mut y: T = start.y;
+    let mut max_x: T = start.x;
+    let mut recent_cr = false;
+    for byte in src {
+        match byte {
+            10 => {
+                // line feed
+                if !recent_cr {
+                    max_x = max(x, max_x);
+                    x = start.x;
+                    y += 1.into();
+                }
+                recent_cr = false;
+            }
+            13 => {
+                // carriage return
+                max_x = max(x, max_x);
+                x = start.x;
+                y += 1.into();
+                recent_cr = true;
+            }
+            12 => {
+                // form feed
+                // do nothing
+            }
+            byte => {
+                write(bfvec(x, y), *byte as i32);
+                x += 1.into();
+                recent_cr = false;
+            }
+        }
+    }
+    max_x = max(x, max_x);
+    if x != start.x {
+        y += 1.into();
+    }
+    BefungeVec { x: max_x, y } - start
+}
+
+/// Call `write` for every cell in a befunge source string placed
+/// at `start`; returns the size of the region
+fn scan_befunge_str<T: FungeValue + Copy>(
+    start: BefungeVec<T>,
+    src: &str,
+    mut write: impl FnMut(BefungeVec<T>, i32),
+) -> BefungeVec<T> {
+    let mut max_x: T = 0.into();
+    let mut max_y: T = 0.into();
+    for (y, line) in src_lines(src).enumerate() {
+        // Form feeds take up no space
+        for (x, c) in line.chars().filter(|c| *c != '\x0c').enumerate() {
+            write(
+                start + bfvec(T::from_usize(x).unwrap(), T::from_usize(y).unwrap()),
+                c as i32,
+            );
+            max_x = max(((x + 1) as i32).into(), max_x);
+        }
+        max_y = max(((y + 1) as i32).into(), max_y);
+    }
+    BefungeVec { x: max_x, y: max_y }
 }
 
 /// Read a string into a funge space
@@ -444,6 +781,106 @@ where
     Idx::read_bin_at(space, &Idx::origin(), src)
 }
 
+/// Where to put source code read with [read_funge_src_placed] or
+/// [read_funge_src_bin_placed]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SrcPlacement<Idx> {
+    /// Start at the given index
+    At(Idx),
+    /// Centre the source on the origin
+    Centered,
+    /// If the first line is a `#!` header with an `origin=X,Y` (or
+    /// `--origin=X,Y`) argument, drop that line and start the rest of the
+    /// source at the given origin. Otherwise, read the whole source starting
+    /// at the given index.
+    Header(Idx),
+}
+
+/// Find the origin in a `#!` header line (see [SrcPlacement::Header]).
+/// Returns the origin and the length of the header line.
+fn parse_origin_header<Idx: FungeIndex + SrcIO<Space>, Space>(src: &[u8]) -> Option<(Idx, usize)>
+where
+    Space: FungeSpace<Idx>,
+    Space::Output: FungeValue,
+{
+    if !src.starts_with(b"#!") {
+        return None;
+    }
+    let line_len = src
+        .iter()
+        .position(|b| *b == b'\n' || *b == b'\r')
+        .unwrap_or(src.len());
+    let mut header_len = line_len;
+    if src[header_len..].starts_with(b"\r") {
+        header_len += 1;
+    }
+    if src[header_len..].starts_with(b"\n") {
+        header_len += 1;
+    }
+    let line = std::str::from_utf8(&src[..line_len]).ok()?;
+    let origin = line
+        .split_whitespace()
+        .find_map(|arg| arg.split_once("origin=").map(|(_, v)| v))?;
+    Some((Idx::parse_idx(origin)?, header_len))
+}
+
+/// Read a string into a funge space, placing it as given by `placement`.
+/// Returns the start and size of the region written to.
+pub fn read_funge_src_placed<Idx, Space>(
+    space: &mut Space,
+    src: &str,
+    placement: SrcPlacement<Idx>,
+) -> (Idx, Idx)
+where
+    Space: FungeSpace<Idx>,
+    Idx: SrcIO<Space>,
+    Space::Output: FungeValue,
+{
+    let (start, src) = match placement {
+        SrcPlacement::At(start) => (start, src),
+        SrcPlacement::Centered => (Idx::centered_start(&Idx::str_size(src)), src),
+        SrcPlacement::Header(start) => match parse_origin_header(src.as_bytes()) {
+            Some((origin, header_len)) => (origin, &src[header_len..]),
+            None => (start, src),
+        },
+    };
+    (start, Idx::read_str_at(space, &start, src))
+}
+
+/// Read a binary/latin-1 buffer into a funge space, placing it as given by
+/// `placement`. Returns the start and size of the region written to.
+pub fn read_funge_src_bin_placed<Idx, Space>(
+    space: &mut Space,
+    src: &[u8],
+    placement: SrcPlacement<Idx>,
+) -> (Idx, Idx)
+where
+    Space: FungeSpace<Idx>,
+    Idx: SrcIO<Space>,
+    Space::Output: FungeValue,
+{
+    let (start, src) = match placement {
+        SrcPlacement::At(start) => (start, src),
+        SrcPlacement::Centered => (Idx::centered_start(&Idx::bin_size(src)), src),
+        SrcPlacement::Header(start) => match parse_origin_header(src) {
+            Some((origin, header_len)) => (origin, &src[header_len..]),
+            None => (start, src),
+        },
+    };
+    (start, Idx::read_bin_at(space, &start, src))
+}
+
+/// Read a string into a funge space so that it is centred on the origin.
+/// Returns the start and size of the region written to.
+pub fn read_funge_src_centered<Idx, Space>(space: &mut Space, src: &str) -> (Idx, Idx)
+where
+    Space: FungeSpace<Idx>,
+    Idx: SrcIO<Space>,
+    Space::Output: FungeValue,
+{
+    read_funge_src_placed(space, src, SrcPlacement::Centered)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -472,7 +909,7 @@ mod tests {
 
     pub fn test_befunge_motion<T, FungeSpaceT>(space: &mut FungeSpaceT)
     where
-        T: FungeValue,
+        T: FungeValue + Copy,
         FungeSpaceT: FungeSpace<BefungeVec<T>> + Index<BefungeVec<T>, Output = T>,
     {
         read_funge_src(space, "1   5  8\n\n  a b    c\r\n A");
@@ -519,4 +956,148 @@ mod tests {
         assert_eq!(space.min_idx(), Some(bfvec(0, 0)));
         assert_eq!(space.max_idx(), Some(bfvec(32000, 8000)));
     }
+
+    #[test]
+    fn test_src_size() {
+        // (source, befunge size, unefunge size)
+        let cases = [
+            ("", bfvec(0, 0), 0),
+            ("abc", bfvec(3, 1), 3),
+            ("ab\ncde\n", bfvec(3, 2), 5),
+            ("ab\r\ncde\r\n\r\n", bfvec(3, 3), 5),
+            ("ab\rc", bfvec(2, 2), 3),
+            ("a\x0cb\x0c\nc", bfvec(2, 2), 3),
+            ("  \n \n", bfvec(2, 2), 3),
+        ];
+        for (src, bf_size, une_size) in cases {
+            let mut space =
+                PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(8, 8));
+            assert_eq!(
+                <BefungeVec<i64> as SrcIO<PagedFungeSpace<_, i64>>>::str_size(src),
+                bf_size,
+                "{:?}",
+                src
+            );
+            assert_eq!(
+                <BefungeVec<i64> as SrcIO<PagedFungeSpace<_, i64>>>::bin_size(src.as_bytes()),
+                bf_size,
+                "{:?}",
+                src
+            );
+            let origin = bfvec(0, 0);
+            assert_eq!(BefungeVec::read_str_at(&mut space, &origin, src), bf_size);
+            assert_eq!(
+                BefungeVec::read_bin_at(&mut space, &origin, src.as_bytes()),
+                bf_size
+            );
+
+            let mut space = PagedFungeSpace::<i64, i64>::new_with_page_size(8);
+            assert_eq!(
+                <i64 as SrcIO<PagedFungeSpace<_, i64>>>::str_size(src),
+                une_size,
+                "{:?}",
+                src
+            );
+            assert_eq!(
+                <i64 as SrcIO<PagedFungeSpace<_, i64>>>::bin_size(src.as_bytes()),
+                une_size,
+                "{:?}",
+                src
+            );
+            assert_eq!(i64::read_str_at(&mut space, &0, src), une_size);
+            assert_eq!(i64::read_bin_at(&mut space, &0, src.as_bytes()), une_size);
+        }
+
+        // Form feeds are skipped in text and binary mode alike
+        for text in [false, true] {
+            let mut space =
+                PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(8, 8));
+            let src = "a\x0cb\n\x0cc";
+            if text {
+                BefungeVec::read_str_at(&mut space, &bfvec(0, 0), src);
+            } else {
+                BefungeVec::read_bin_at(&mut space, &bfvec(0, 0), src.as_bytes());
+            }
+            assert_eq!(space[bfvec(1, 0)], 'b' as i64);
+            assert_eq!(space[bfvec(0, 1)], 'c' as i64);
+        }
+    }
+
+    #[test]
+    fn test_unefunge_src_region() {
+        let mut space = PagedFungeSpace::<i64, i64>::new_with_page_size(8);
+        read_funge_src(&mut space, "ab  c   ");
+        assert_eq!(i64::get_src_str(&space, &-2, &12, false), "  ab  c     ");
+        assert_eq!(i64::get_src_str(&space, &-2, &12, true), "  ab  c");
+        assert_eq!(i64::get_src_str(&space, &5, &3, true), "");
+        assert_eq!(i64::get_src_str(&space, &0, &-3, false), "");
+    }
+
+    #[test]
+    fn test_write_src_to() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(8, 8));
+        read_funge_src(&mut space, "a  b  \n\n c\n   \n\n");
+        space[bfvec(1, 1)] = 0xe9;
+        for strip in [false, true] {
+            for (start, size) in [(bfvec(0, 0), bfvec(6, 5)), (bfvec(-1, -1), bfvec(3, 4))] {
+                let mut out = Vec::new();
+                BefungeVec::write_src_to(&space, &start, &size, strip, &mut out).unwrap();
+                let expected = BefungeVec::get_src_str(&space, &start, &size, strip);
+                assert_eq!(String::from_utf8(out).unwrap(), expected);
+                let mut out = Vec::new();
+                BefungeVec::write_src_bin_to(&space, &start, &size, strip, &mut out).unwrap();
+                let expected = BefungeVec::get_src_bin(&space, &start, &size, strip);
+                assert_eq!(out, expected);
+            }
+        }
+        assert_eq!(
+            BefungeVec::get_src_str(&space, &bfvec(0, 0), &bfvec(6, 5), true),
+            "a  b\n é\n c"
+        );
+    }
+
+    #[test]
+    fn test_read_centered() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(8, 8));
+        let (start, size) = read_funge_src_centered(&mut space, "abcde\nfghij\nklmno\npq");
+        assert_eq!(start, bfvec(-2, -2));
+        assert_eq!(size, bfvec(5, 4));
+        assert_eq!(space[bfvec(0, 0)], 'm' as i64);
+        assert_eq!(space.min_idx(), Some(bfvec(-2, -2)));
+
+        let mut space = PagedFungeSpace::<i64, i64>::new_with_page_size(8);
+        let (start, size) = read_funge_src_bin_placed(&mut space, b"1234", SrcPlacement::Centered);
+        assert_eq!((start, size), (-2, 4));
+        assert_eq!(space[0], '3' as i64);
+    }
+
+    #[test]
+    fn test_read_with_header() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(8, 8));
+        let src = "#!/usr/bin/env rfunge --origin=-10,-3\r\nab\nc";
+        let placement = SrcPlacement::Header(bfvec(0, 0));
+        let (start, size) = read_funge_src_placed(&mut space, src, placement);
+        assert_eq!(start, bfvec(-10, -3));
+        assert_eq!(size, bfvec(2, 2));
+        assert_eq!(space[bfvec(-10, -3)], 'a' as i64);
+        assert_eq!(space[bfvec(-10, -2)], 'c' as i64);
+        assert_eq!(space.max_idx(), Some(bfvec(-9, -2)));
+
+        // An ordinary #! line is kept
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(8, 8));
+        let (start, size) = read_funge_src_bin_placed(
+            &mut space,
+            b"#!rfunge\n@",
+            SrcPlacement::Header(bfvec(1, 1)),
+        );
+        assert_eq!(start, bfvec(1, 1));
+        assert_eq!(size, bfvec(8, 2));
+        assert_eq!(space[bfvec(1, 1)], '#' as i64);
+
+        let mut space = PagedFungeSpace::<i64, i64>::new_with_page_size(8);
+        let (start, _) =
+            read_funge_src_placed(&mut space, "#! origin=-7\n@", SrcPlacement::Header(0));
+        assert_eq!(start, -7);
+        assert_eq!(space[-7], '@' as i64);
+    }
 }
@@ -16,20 +16,25 @@ You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::cmp::Ordering;
+use std::cell::Cell;
+use std::cmp::{max, Ordering, Reverse};
+use std::collections::BinaryHeap;
 use std::hash::Hash;
-use std::ops::{Add, Div, Index, IndexMut, Mul, Rem};
+use std::ops::{Add, Div, Index, IndexMut, Mul, Range, Rem, Sub};
 
 use divrem::{DivEuclid, DivRem, DivRemEuclid, RemEuclid};
 use hashbrown::HashMap;
 use num::{One, Zero};
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-use super::index::{bfvec, BefungeVec};
-use super::{FungeArrayIdx, FungeSpace, FungeValue};
+use super::diff::FungeSpaceDiff;
+use super::index::BefungeVec;
+use super::{next_generation, FungeArrayIdx, FungeSpace, FungeValue};
 
 /// Trait required for indices when used with [PagedFungeSpace]
-pub trait PageSpaceVector<T>:
-    Mul<T, Output = Self>
+pub trait PageSpaceVector:
+    Mul<Self::Scalar, Output = Self>
     + FungeArrayIdx
     + Div<Output = Self>
     + Rem<Output = Self>
@@ -38,11 +43,13 @@ pub trait PageSpaceVector<T>:
     + DivRemEuclid
     + RemEuclid
     + Add<Output = Self>
+    + Sub<Output = Self>
     + Mul<Output = Self>
     + Hash
-where
-    T: FungeValue,
 {
+    /// The type of the components of the index
+    type Scalar: FungeValue + Copy;
+
     /// Return `Some(n)`, where `n` is the smallest integer such that
     /// `self + n * delta` lies within the line segment (unefunge),
     /// rectangle (befunge) or cuboid (trefunge) spannig from `start`
@@ -50,7 +57,17 @@ where
     ///
     /// If there is no such point (becuase the line defined `self + n * delta`
     /// doesn't pass through the region indicated), return `None`.
-    fn dist_of_region(&self, delta: &Self, start: &Self, size: &Self) -> Option<T>;
+    fn dist_of_region(&self, delta: &Self, start: &Self, size: &Self) -> Option<Self::Scalar>;
+
+    /// Like [PageSpaceVector::dist_of_region], but return both the smallest
+    /// and the largest `n` such that `self + n * delta` lies within the
+    /// region.
+    fn dist_range_of_region(
+        &self,
+        delta: &Self,
+        start: &Self,
+        size: &Self,
+    ) -> Option<(Self::Scalar, Self::Scalar)>;
 
     /// Call a closure for every idx = start + n * delta, n = 0, 1, ...,
     /// such that idx lies within a region of size `limit` starting at the
@@ -60,37 +77,449 @@ where
     fn scan_within_region<F>(start: &Self, delta: &Self, limit: &Self, callback: &mut F) -> bool
     where
         F: FnMut(&Self) -> bool;
+
+    /// If the indices visited by [PageSpaceVector::scan_within_region] are
+    /// consecutive linear indices (i.e., `delta` is one step forward or back
+    /// along the first axis), return their range, and whether they are
+    /// visited backwards.
+    fn contiguous_run(_start: &Self, _delta: &Self, _limit: &Self) -> Option<(Range<usize>, bool)> {
+        None
+    }
 }
 
 /// Implementation of funge space that stores fixed-size segments of funge-space
 /// as arrays.
+#[derive(Clone)]
 pub struct PagedFungeSpace<Idx, Elem>
 where
-    Idx: PageSpaceVector<Elem>,
+    Idx: PageSpaceVector,
     Elem: FungeValue,
 {
     page_size: Idx,
     pages: HashMap<Idx, Vec<Elem>>,
+    /// Coarser views of which pages are allocated, to speed up searches
+    page_index: PageIndex<Idx>,
+    /// If dirty tracking is enabled, the least and greatest written index
+    /// within each page (relative to the start of the page)
+    dirty: Option<HashMap<Idx, (Idx, Idx)>>,
+    /// Number of non-space cells in each page
+    counts: HashMap<Idx, usize>,
+    /// The cell last handed out by `index_mut` (the new value is only known
+    /// after the fact)
+    last_write: Option<LastWrite<Idx>>,
+    /// Cached least and greatest non-space indices: `None` if unknown,
+    /// `Some(None)` if funge-space is empty
+    bounds: Cell<Option<Option<(Idx, Idx)>>>,
+    page_limit: Option<usize>,
+    limit_exceeded: bool,
+    /// See [FungeSpace::generation]
+    generation: u64,
     _blank: Elem, // This should really be const but I don't know how to do that
+    _scratch: Elem,
 }
 
 impl<Idx, Elem> PagedFungeSpace<Idx, Elem>
 where
-    Idx: PageSpaceVector<Elem>,
+    Idx: PageSpaceVector,
     Elem: FungeValue,
 {
     pub fn new_with_page_size(page_size: Idx) -> Self {
+        Self::new_with_blank(page_size, Elem::from(' ' as i32))
+    }
+
+    /// Create a funge-space in which cells that have never been written to
+    /// contain `blank` instead of a space. The IP skips over such cells as
+    /// well as spaces, and they don't count towards the bounds of
+    /// funge-space.
+    pub fn new_with_blank(page_size: Idx, blank: Elem) -> Self {
         Self {
             page_size,
             pages: HashMap::new(),
-            _blank: Elem::from(' ' as i32),
+            page_index: PageIndex::new(),
+            dirty: None,
+            counts: HashMap::new(),
+            last_write: None,
+            bounds: Cell::new(Some(None)),
+            page_limit: None,
+            limit_exceeded: false,
+            generation: next_generation(),
+            _scratch: blank.clone(),
+            _blank: blank,
+        }
+    }
+
+    /// Get the size of a page
+    pub fn page_size(&self) -> Idx {
+        self.page_size
+    }
+
+    /// Iterate over all pages that have been allocated, as pairs of the page
+    /// index (the location of the page's first cell divided by the page size)
+    /// and the page's contents
+    pub fn pages(&self) -> impl Iterator<Item = (&Idx, &[Elem])> {
+        self.pages.iter().map(|(k, p)| (k, &p[..]))
+    }
+
+    /// Get the number of pages currently allocated
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Limit the number of pages that may be allocated. Writes that would
+    /// need another page are dropped, and [FungeSpace::take_limit_exceeded]
+    /// reports them. Pages already allocated are kept.
+    pub fn set_page_limit(&mut self, limit: Option<usize>) {
+        self.page_limit = limit;
+    }
+
+    /// Get the maximum number of pages
+    pub fn page_limit(&self) -> Option<usize> {
+        self.page_limit
+    }
+
+    /// Replace a page. Returns `false` (and does nothing) if `page` is not the
+    /// right length, or if it would exceed the page limit. A page of spaces
+    /// is not stored at all.
+    pub fn insert_page(&mut self, page_idx: Idx, page: Vec<Elem>) -> bool {
+        self.settle_last_write();
+        self.generation = next_generation();
+        if page.len() != self.page_size.lin_size() {
+            return false;
+        }
+        let count = page.iter().filter(|v| **v != self._blank).count();
+        self.bounds.set(None);
+        if count == 0 {
+            self.remove_page(page_idx);
+        } else {
+            if !self.pages.contains_key(&page_idx) {
+                if self.is_full() {
+                    return false;
+                }
+                self.page_index.insert(page_idx, self.page_size);
+            }
+            self.pages.insert(page_idx, page);
+            self.counts.insert(page_idx, count);
+        }
+        self.mark_page_dirty(page_idx);
+        true
+    }
+
+    fn is_full(&self) -> bool {
+        matches!(self.page_limit, Some(limit) if self.pages.len() >= limit)
+    }
+
+    fn remove_page(&mut self, page_idx: Idx) {
+        if self.pages.remove(&page_idx).is_some() {
+            self.counts.remove(&page_idx);
+            self.page_index.remove(page_idx, self.page_size);
+        }
+    }
+
+    /// Get the index that is 1 in every direction
+    fn unit(&self) -> Idx {
+        self.page_size - Idx::from_lin_index(self.page_size.lin_size() - 1, &self.page_size)
+    }
+
+    /// Get the first page and the size (in pages) of the block of pages
+    /// covering a region, or `None` if the region is empty
+    fn region_pages(&self, start: Idx, size: Idx) -> Option<(Idx, Idx)> {
+        let one = self.unit();
+        if size.joint_min(&one) != one {
+            return None;
+        }
+        let first_page = start.div_euclid(self.page_size);
+        let last_page = (start + size - one).div_euclid(self.page_size);
+        Some((first_page, last_page - first_page + one))
+    }
+
+    /// Get the cells of a region that lie within a page, as pairs of the
+    /// linear index within the page, and within the region
+    fn region_cells_in_page(&self, start: Idx, size: Idx, page_idx: Idx) -> Vec<(usize, usize)> {
+        let one = self.unit();
+        let page_start = page_idx * self.page_size;
+        let least = start.joint_max(&page_start);
+        let greatest = (start + size - one).joint_min(&(page_start + self.page_size - one));
+        let sub_size = greatest - least + one;
+        (0..sub_size.lin_size())
+            .map(|i| {
+                let idx = least + Idx::from_lin_index(i, &sub_size);
+                (
+                    (idx - page_start).to_lin_index(&self.page_size),
+                    (idx - start).to_lin_index(&size),
+                )
+            })
+            .collect()
+    }
+
+    /// Read a region into a `Vec` (in the order given by
+    /// [FungeArrayIdx::to_lin_index])
+    fn read_region(&self, start: Idx, size: Idx) -> Vec<Elem> {
+        let mut values = Vec::new();
+        if let Some((first_page, n_pages)) = self.region_pages(start, size) {
+            values.resize(size.lin_size(), self._blank.clone());
+            for i in 0..n_pages.lin_size() {
+                let page_idx = first_page + Idx::from_lin_index(i, &n_pages);
+                if let Some(page) = self.pages.get(&page_idx) {
+                    for (page_lin, region_lin) in self.region_cells_in_page(start, size, page_idx) {
+                        values[region_lin] = page[page_lin].clone();
+                    }
+                }
+            }
+        }
+        values
+    }
+
+    /// Write to a region, page by page, taking the value for each cell
+    /// (by its linear index within the region) from `value_at`
+    fn write_region<'v>(&mut self, start: Idx, size: Idx, value_at: impl Fn(usize) -> &'v Elem)
+    where
+        Elem: 'v,
+    {
+        self.settle_last_write();
+        self.generation = next_generation();
+        let (first_page, n_pages) = match self.region_pages(start, size) {
+            Some(pages) => pages,
+            None => return,
+        };
+        for i in 0..n_pages.lin_size() {
+            let page_idx = first_page + Idx::from_lin_index(i, &n_pages);
+            let cells = self.region_cells_in_page(start, size, page_idx);
+            if !self.pages.contains_key(&page_idx) {
+                if cells.iter().all(|(_, j)| *value_at(*j) == self._blank) {
+                    continue;
+                }
+                if self.is_full() {
+                    self.limit_exceeded = true;
+                    continue;
+                }
+                let mut v = Vec::new();
+                v.resize(self.page_size.lin_size(), self._blank.clone());
+                self.pages.insert(page_idx, v);
+                self.page_index.insert(page_idx, self.page_size);
+            }
+            let page = self.pages.get_mut(&page_idx).unwrap();
+            for (page_lin, region_lin) in cells.iter() {
+                page[*page_lin] = value_at(*region_lin).clone();
+            }
+            let count = page.iter().filter(|v| **v != self._blank).count();
+            if let (Some((first, _)), Some((last, _))) = (cells.first(), cells.last()) {
+                self.mark_dirty(page_idx, Idx::from_lin_index(*first, &self.page_size));
+                self.mark_dirty(page_idx, Idx::from_lin_index(*last, &self.page_size));
+            }
+            if count == 0 {
+                self.remove_page(page_idx);
+            } else {
+                self.counts.insert(page_idx, count);
+            }
+        }
+        self.bounds.set(None);
+    }
+
+    /// Account for the last write, and free the page written to if it is
+    /// now entirely blank
+    fn settle_last_write(&mut self) {
+        if let Some(write) = self.last_write.take() {
+            if let Some(page) = self.pages.get(&write.page_idx) {
+                let is_blank = page[write.lin_idx] == self._blank;
+                self.update_bounds(&write, is_blank);
+                let count = self.counts.entry(write.page_idx).or_insert(0);
+                match (write.was_blank, is_blank) {
+                    (true, false) => *count += 1,
+                    (false, true) => *count -= 1,
+                    _ => {}
+                }
+                if *count == 0 {
+                    self.remove_page(write.page_idx);
+                }
+            }
+        }
+    }
+
+    /// Update the cached bounds for a write, unless that's already been done
+    fn update_bounds(&self, write: &LastWrite<Idx>, is_blank: bool) {
+        if write.bounds_updated.replace(true) {
+            return;
+        }
+        match (write.was_blank, is_blank) {
+            (true, false) => {
+                if let Some(bounds) = self.bounds.get() {
+                    self.bounds.set(Some(Some(match bounds {
+                        Some((least, greatest)) => {
+                            (least.joint_min(&write.idx), greatest.joint_max(&write.idx))
+                        }
+                        None => (write.idx, write.idx),
+                    })));
+                }
+            }
+            // This may have been the last cell on the edge
+            (false, true) => self.bounds.set(None),
+            _ => {}
+        }
+    }
+
+    /// Get the least and greatest non-space indices, from the cache if
+    /// possible
+    fn bounds(&self) -> Option<(Idx, Idx)> {
+        if let Some(write) = &self.last_write {
+            if let Some(page) = self.pages.get(&write.page_idx) {
+                self.update_bounds(write, page[write.lin_idx] == self._blank);
+            }
+        }
+        if let Some(bounds) = self.bounds.get() {
+            return bounds;
+        }
+        let bounds = self.find_least_idx().zip(self.find_greatest_idx());
+        self.bounds.set(Some(bounds));
+        bounds
+    }
+
+    fn find_least_idx(&self) -> Option<Idx> {
+        self.pages
+            .iter()
+            .filter_map(|(k, p)| {
+                Idx::find_joint_min_where(
+                    &mut |idx: &Idx| p[idx.to_lin_index(&self.page_size)] != self._blank,
+                    &Idx::origin(),
+                    &self.page_size,
+                )
+                .map(|min_idx| min_idx + (*k * self.page_size))
+            })
+            .reduce(|i1, i2| i1.joint_min(&i2))
+    }
+
+    fn find_greatest_idx(&self) -> Option<Idx> {
+        self.pages
+            .iter()
+            .filter_map(|(k, p)| {
+                Idx::find_joint_max_where(
+                    &mut |idx: &Idx| p[idx.to_lin_index(&self.page_size)] != self._blank,
+                    &Idx::origin(),
+                    &self.page_size,
+                )
+                .map(|max_idx| max_idx + (*k * self.page_size))
+            })
+            .reduce(|i1, i2| i1.joint_max(&i2))
+    }
+
+    /// Remove all pages, i.e., fill all of funge-space with blank cells
+    pub fn clear(&mut self) {
+        self.generation = next_generation();
+        self.last_write = None;
+        self.bounds.set(Some(None));
+        self.counts.clear();
+        if self.dirty.is_some() {
+            let page_idxs: Vec<Idx> = self.pages.keys().cloned().collect();
+            for page_idx in page_idxs {
+                self.mark_page_dirty(page_idx);
+            }
+        }
+        self.pages.clear();
+        self.page_index = PageIndex::new();
+    }
+
+    /// Get the cells that differ between `self` (the old state) and `newer`
+    pub fn diff(&self, newer: &Self) -> FungeSpaceDiff<Idx, Elem> {
+        let mut diff = FungeSpaceDiff::new();
+        if self.page_size == newer.page_size {
+            for (page_idx, old_page) in self.pages.iter() {
+                let page_start = *page_idx * self.page_size;
+                let new_page = newer.pages.get(page_idx);
+                for (i, old) in old_page.iter().enumerate() {
+                    let new = new_page.map(|p| &p[i]).unwrap_or(&self._blank);
+                    let idx = page_start + Idx::from_lin_index(i, &self.page_size);
+                    diff.record(idx, old.clone(), new.clone());
+                }
+            }
+            for (page_idx, new_page) in newer.pages.iter() {
+                if !self.pages.contains_key(page_idx) {
+                    let page_start = *page_idx * self.page_size;
+                    for (i, new) in new_page.iter().enumerate() {
+                        let idx = page_start + Idx::from_lin_index(i, &self.page_size);
+                        diff.record(idx, self._blank.clone(), new.clone());
+                    }
+                }
+            }
+        } else {
+            for (page_idx, old_page) in self.pages.iter() {
+                let page_start = *page_idx * self.page_size;
+                for (i, old) in old_page.iter().enumerate() {
+                    let idx = page_start + Idx::from_lin_index(i, &self.page_size);
+                    diff.record(idx, old.clone(), newer[idx].clone());
+                }
+            }
+            for (page_idx, new_page) in newer.pages.iter() {
+                let page_start = *page_idx * newer.page_size;
+                for (i, new) in new_page.iter().enumerate() {
+                    let idx = page_start + Idx::from_lin_index(i, &newer.page_size);
+                    if self[idx] == self._blank {
+                        diff.record(idx, self._blank.clone(), new.clone());
+                    }
+                }
+            }
+        }
+        diff
+    }
+
+    /// Enable or disable recording of which parts of funge-space are written
+    /// to (see [PagedFungeSpace::take_dirty_regions]). Disabling tracking
+    /// discards anything recorded so far.
+    pub fn set_dirty_tracking(&mut self, enabled: bool) {
+        if !enabled {
+            self.dirty = None;
+        } else if self.dirty.is_none() {
+            self.dirty = Some(HashMap::new());
+        }
+    }
+
+    /// Is dirty tracking enabled?
+    pub fn is_dirty_tracking(&self) -> bool {
+        self.dirty.is_some()
+    }
+
+    /// Get the regions that have been written to since the last call (or
+    /// since tracking was enabled), and start afresh. Each region is given as
+    /// a pair of its least and greatest (inclusive) indices; there is at most
+    /// one region per page.
+    ///
+    /// Cells count as written whenever they are accessed mutably, even if
+    /// their value is unchanged. Returns nothing if tracking is disabled.
+    pub fn take_dirty_regions(&mut self) -> Vec<(Idx, Idx)> {
+        let page_size = self.page_size;
+        match self.dirty.as_mut() {
+            Some(dirty) => dirty
+                .drain()
+                .map(|(page_idx, (least, greatest))| {
+                    let page_start = page_idx * page_size;
+                    (page_start + least, page_start + greatest)
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn mark_dirty(&mut self, page_idx: Idx, idx_in_page: Idx) {
+        if let Some(dirty) = self.dirty.as_mut() {
+            dirty
+                .entry(page_idx)
+                .and_modify(|(least, greatest)| {
+                    *least = least.joint_min(&idx_in_page);
+                    *greatest = greatest.joint_max(&idx_in_page);
+                })
+                .or_insert((idx_in_page, idx_in_page));
+        }
+    }
+
+    fn mark_page_dirty(&mut self, page_idx: Idx) {
+        let last = Idx::from_lin_index(self.page_size.lin_size() - 1, &self.page_size);
+        if let Some(dirty) = self.dirty.as_mut() {
+            dirty.insert(page_idx, (Idx::origin(), last));
         }
     }
 }
 
 impl<Idx, Elem> Index<Idx> for PagedFungeSpace<Idx, Elem>
 where
-    Idx: PageSpaceVector<Elem>,
+    Idx: PageSpaceVector,
     Elem: FungeValue,
 {
     type Output = Elem;
@@ -106,28 +535,50 @@ where
 
 impl<Idx, Elem> IndexMut<Idx> for PagedFungeSpace<Idx, Elem>
 where
-    Idx: PageSpaceVector<Elem>,
+    Idx: PageSpaceVector,
     Elem: FungeValue,
 {
     fn index_mut(&mut self, idx: Idx) -> &mut Elem {
+        self.settle_last_write();
+        self.generation = next_generation();
         let (page_idx, idx_in_page) = idx.div_rem_euclid(self.page_size);
         if !self.pages.contains_key(&page_idx) {
+            if self.is_full() {
+                // Drop the write
+                self.limit_exceeded = true;
+                self._scratch = self._blank.clone();
+                return &mut self._scratch;
+            }
             let mut v = Vec::new();
-            v.resize(self.page_size.lin_size(), self._blank);
+            v.resize(self.page_size.lin_size(), self._blank.clone());
             self.pages.insert(page_idx, v);
+            self.counts.insert(page_idx, 0);
+            self.page_index.insert(page_idx, self.page_size);
         }
+        self.mark_dirty(page_idx, idx_in_page);
         let page = self.pages.get_mut(&page_idx).unwrap();
         let lin_idx = idx_in_page.to_lin_index(&self.page_size);
+        self.last_write = Some(LastWrite {
+            page_idx,
+            lin_idx,
+            idx,
+            was_blank: page[lin_idx] == self._blank,
+            bounds_updated: Cell::new(false),
+        });
         page.index_mut(lin_idx)
     }
 }
 
 impl<Idx, Elem> FungeSpace<Idx> for PagedFungeSpace<Idx, Elem>
 where
-    Idx: PageSpaceVector<Elem>,
+    Idx: PageSpaceVector,
     Elem: FungeValue,
 {
     fn move_by(&self, start: Idx, delta: Idx) -> (Idx, &Elem) {
+        if delta == Idx::origin() {
+            // Nowhere to go (and scanning along the line would never end)
+            return (start, &self[start]);
+        }
         let mut idx = start + delta;
         let (mut page_idx, mut idx_in_page) = idx.div_rem_euclid(self.page_size);
 
@@ -147,34 +598,47 @@ where
             }
         }
 
-        // We've hit the edge, time for some maths
-        let cur_dist = idx
+        // We've hit the edge. Look for the next page along the line, or the
+        // furthest one back if we have to wrap around, going from coarse
+        // blocks of pages to the pages themselves.
+        let cur_dist = start
             .dist_of_region(&delta, &(page_idx * self.page_size), &self.page_size)
             .unwrap();
+        let mut candidates = BinaryHeap::new();
+        for block_idx in self.page_index.top_blocks() {
+            self.push_candidate(
+                &mut candidates,
+                &start,
+                &delta,
+                cur_dist,
+                PageIndex::<Idx>::LEVELS,
+                *block_idx,
+            );
+        }
 
-        let mut page_dists: Vec<(Idx, Elem)> = self
-            .pages
-            .keys()
-            .filter_map(|k| {
-                Some((
-                    *k,
-                    start.dist_of_region(&delta, &(*k * self.page_size), &self.page_size)?,
-                ))
-            })
-            .filter(|(_, d)| *d > cur_dist || *d <= Zero::zero())
-            .collect();
-        page_dists.sort_by_key(|(_, d)| (*d <= Zero::zero(), *d));
-
-        for (target_page_idx, dist) in page_dists.into_iter() {
-            idx = start + delta * dist;
-            page_idx = target_page_idx;
-            idx_in_page = idx.rem_euclid(self.page_size);
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            if candidate.level == 0 {
+                idx = start + delta * candidate.dist;
+                page_idx = candidate.idx;
+                idx_in_page = idx.rem_euclid(self.page_size);
 
-            let this_page = &self.pages[&page_idx];
-            if let Ok(result) =
-                self.scan_within_page(this_page, &idx, &page_idx, &idx_in_page, &delta)
-            {
-                return result;
+                let this_page = &self.pages[&page_idx];
+                if let Ok(result) =
+                    self.scan_within_page(this_page, &idx, &page_idx, &idx_in_page, &delta)
+                {
+                    return result;
+                }
+            } else {
+                for child_idx in self.page_index.children(candidate.level, &candidate.idx) {
+                    self.push_candidate(
+                        &mut candidates,
+                        &start,
+                        &delta,
+                        cur_dist,
+                        candidate.level - 1,
+                        *child_idx,
+                    );
+                }
             }
         }
 
@@ -183,39 +647,89 @@ where
     }
 
     fn min_idx(&self) -> Option<Idx> {
-        self.pages
-            .iter()
-            .filter_map(|(k, p)| {
-                Idx::find_joint_min_where(
-                    &mut |idx: &Idx| p[idx.to_lin_index(&self.page_size)] != (' ' as i32).into(),
-                    &Idx::origin(),
-                    &self.page_size,
-                )
-                .map(|min_idx| min_idx + (*k * self.page_size))
-            })
-            .reduce(|i1, i2| i1.joint_min(&i2))
+        self.bounds().map(|(least, _)| least)
     }
 
     fn max_idx(&self) -> Option<Idx> {
-        self.pages
-            .iter()
-            .filter_map(|(k, p)| {
-                Idx::find_joint_max_where(
-                    &mut |idx: &Idx| p[idx.to_lin_index(&self.page_size)] != (' ' as i32).into(),
-                    &Idx::origin(),
-                    &self.page_size,
-                )
-                .map(|max_idx| max_idx + (*k * self.page_size))
-            })
-            .reduce(|i1, i2| i1.joint_max(&i2))
+        self.bounds().map(|(_, greatest)| greatest)
+    }
+
+    /// This sets the page limit (see [PagedFungeSpace::set_page_limit])
+    fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        let page_bytes = self.page_size.lin_size() * std::mem::size_of::<Elem>();
+        self.page_limit = bytes.map(|b| b / page_bytes);
+    }
+
+    fn take_limit_exceeded(&mut self) -> bool {
+        std::mem::take(&mut self.limit_exceeded)
+    }
+
+    fn generation(&self) -> Option<u64> {
+        Some(self.generation)
+    }
+
+    fn blank(&self) -> Elem {
+        self._blank.clone()
+    }
+
+    fn fill_region(&mut self, start: Idx, size: Idx, value: Elem) {
+        self.write_region(start, size, |_| &value);
+    }
+
+    fn copy_region(&mut self, start: Idx, size: Idx, dest: Idx) {
+        let values = self.read_region(start, size);
+        self.write_region(dest, size, |i| &values[i]);
+    }
+
+    fn move_region(&mut self, start: Idx, size: Idx, dest: Idx) {
+        let values = self.read_region(start, size);
+        let blank = self._blank.clone();
+        self.write_region(start, size, |_| &blank);
+        self.write_region(dest, size, |i| &values[i]);
     }
 }
 
 impl<Idx, Elem> PagedFungeSpace<Idx, Elem>
 where
-    Idx: PageSpaceVector<Elem>,
+    Idx: PageSpaceVector,
     Elem: FungeValue,
 {
+    /// Queue a page (level 0) or block of pages for
+    /// [FungeSpace::move_by] if the line from `start` along `delta` may find
+    /// something there. Pages are visited in order of the distance at which
+    /// the line enters them, skipping those that have already been scanned
+    /// (up to `cur_dist`), and then wrapping around.
+    fn push_candidate(
+        &self,
+        candidates: &mut BinaryHeap<Reverse<Candidate<Idx>>>,
+        start: &Idx,
+        delta: &Idx,
+        cur_dist: Idx::Scalar,
+        level: usize,
+        idx: Idx,
+    ) {
+        let size = self.page_index.block_size(level, self.page_size);
+        let (entry, exit) = match start.dist_range_of_region(delta, &(idx * size), &size) {
+            Some(range) => range,
+            None => return,
+        };
+        // Order by (wrapped around, distance). For a block, use the smallest
+        // key any page within it could have.
+        let key = if exit > cur_dist {
+            (false, max(entry, cur_dist + One::one()))
+        } else if entry <= Zero::zero() {
+            (true, entry)
+        } else {
+            return;
+        };
+        candidates.push(Reverse(Candidate {
+            wrapped: key.0,
+            dist: key.1,
+            level,
+            idx,
+        }));
+    }
+
     fn scan_within_page<'s, 'i>(
         &'s self,
         page: &'s [Elem],
@@ -224,6 +738,25 @@ where
         idx_in_page: &'i Idx,
         delta: &'i Idx,
     ) -> Result<(Idx, &'s Elem), Idx> {
+        let space: Elem = (' ' as i32).into();
+        // Moving straight along a row, we can search the row as a slice
+        if let Some((run, backwards)) = Idx::contiguous_run(idx_in_page, delta, &self.page_size) {
+            let cells = &page[run];
+            let is_set = |v: &Elem| *v != self._blank && *v != space;
+            let found = if backwards {
+                cells
+                    .iter()
+                    .rposition(is_set)
+                    .map(|i| (cells.len() - 1 - i, i))
+            } else {
+                cells.iter().position(is_set).map(|i| (i, i))
+            };
+            let steps = |n: usize| *idx_in_page + *delta * Idx::Scalar::from(n as i32);
+            return match found {
+                Some((n, i)) => Ok((*page_idx * self.page_size + steps(n), &cells[i])),
+                None => Err(steps(cells.len() - 1)),
+            };
+        }
         let mut the_value = &self._blank;
         let mut the_idx = *idx;
         let mut last_idx_in_page = *idx_in_page;
@@ -231,7 +764,7 @@ where
             last_idx_in_page = *this_idx;
             let lin_idx = this_idx.to_lin_index_unchecked(&self.page_size);
             let v = &page[lin_idx];
-            if *v != (b' ' as i32).into() {
+            if *v != self._blank && *v != space {
                 the_value = v;
                 the_idx = *page_idx * self.page_size + *this_idx;
                 true
@@ -247,10 +780,204 @@ where
     }
 }
 
-impl<T> PageSpaceVector<T> for T
+/// Serialized form of a [PagedFungeSpace]: the page size, the blank value,
+/// and runs of non-blank cells, each given by the index of its first cell and its
+/// values. A run stays within one page, and continues in the order given by
+/// [FungeArrayIdx::to_lin_index] (i.e., in 2D, it may wrap around to the next
+/// line of the page). Data written before the blank value was configurable
+/// has no `blank`, and gets a space.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SpaceData<Idx, Elem> {
+    page_size: Idx,
+    blank: Option<Elem>,
+    runs: Vec<(Idx, Vec<Elem>)>,
+}
+
+#[cfg(feature = "serde")]
+impl<Idx, Elem> Serialize for PagedFungeSpace<Idx, Elem>
 where
-    T: FungeValue + Hash + DivEuclid + RemEuclid + DivRemEuclid,
+    Idx: PageSpaceVector + Serialize,
+    Elem: FungeValue + Serialize,
 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut runs = Vec::new();
+        for (page_idx, page) in self.pages.iter() {
+            let page_start = *page_idx * self.page_size;
+            let mut run: Option<(Idx, Vec<Elem>)> = None;
+            for (i, value) in page.iter().enumerate() {
+                if *value == self._blank {
+                    runs.extend(run.take());
+                } else {
+                    run.get_or_insert_with(|| {
+                        (
+                            page_start + Idx::from_lin_index(i, &self.page_size),
+                            Vec::new(),
+                        )
+                    })
+                    .1
+                    .push(value.clone());
+                }
+            }
+            runs.extend(run);
+        }
+        SpaceData {
+            page_size: self.page_size,
+            blank: Some(self._blank.clone()),
+            runs,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Idx, Elem> Deserialize<'de> for PagedFungeSpace<Idx, Elem>
+where
+    Idx: PageSpaceVector + Deserialize<'de>,
+    Elem: FungeValue + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = SpaceData::<Idx, Elem>::deserialize(deserializer)?;
+        let blank = data.blank.unwrap_or_else(|| Elem::from(' ' as i32));
+        let mut space = Self::new_with_blank(data.page_size, blank);
+        let page_len = data.page_size.lin_size();
+        let mut pages: HashMap<Idx, Vec<Elem>> = HashMap::new();
+        for (start, values) in data.runs {
+            let (page_idx, idx_in_page) = start.div_rem_euclid(data.page_size);
+            let first = idx_in_page.to_lin_index(&data.page_size);
+            if first + values.len() > page_len {
+                return Err(de::Error::custom("run of cells crosses a page boundary"));
+            }
+            let page = pages
+                .entry(page_idx)
+                .or_insert_with(|| vec![space._blank.clone(); page_len]);
+            for (i, value) in values.into_iter().enumerate() {
+                page[first + i] = value;
+            }
+        }
+        for (page_idx, page) in pages {
+            space.insert_page(page_idx, page);
+        }
+        Ok(space)
+    }
+}
+
+/// A cell handed out by `index_mut`
+#[derive(Clone)]
+struct LastWrite<Idx> {
+    page_idx: Idx,
+    lin_idx: usize,
+    idx: Idx,
+    was_blank: bool,
+    bounds_updated: Cell<bool>,
+}
+
+/// Index of which blocks of `BRANCHING` pages (in each direction), blocks of
+/// such blocks, etc., contain allocated pages
+#[derive(Clone)]
+struct PageIndex<Idx> {
+    /// For each level above the pages, the (indices of the) allocated blocks
+    /// or pages on the level below, by block
+    levels: Vec<HashMap<Idx, Vec<Idx>>>,
+}
+
+impl<Idx: PageSpaceVector> PageIndex<Idx> {
+    const LEVELS: usize = 4;
+    const BRANCHING: i32 = 8;
+
+    fn new() -> Self {
+        Self {
+            levels: (0..Self::LEVELS).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    /// Size of a block on the given level (0 being a single page)
+    fn block_size(&self, level: usize, page_size: Idx) -> Idx {
+        page_size * Idx::Scalar::from(Self::BRANCHING.pow(level as u32))
+    }
+
+    fn insert(&mut self, page_idx: Idx, page_size: Idx) {
+        let mut child = page_idx;
+        for level in 1..=Self::LEVELS {
+            let block = (child * self.block_size(level - 1, page_size))
+                .div_euclid(self.block_size(level, page_size));
+            let children = self.levels[level - 1].entry(block).or_default();
+            if children.contains(&child) {
+                break;
+            }
+            children.push(child);
+            child = block;
+        }
+    }
+
+    fn remove(&mut self, page_idx: Idx, page_size: Idx) {
+        let mut child = page_idx;
+        for level in 1..=Self::LEVELS {
+            let block = (child * self.block_size(level - 1, page_size))
+                .div_euclid(self.block_size(level, page_size));
+            if let Some(children) = self.levels[level - 1].get_mut(&block) {
+                children.retain(|c| *c != child);
+                if !children.is_empty() {
+                    break;
+                }
+                self.levels[level - 1].remove(&block);
+            }
+            child = block;
+        }
+    }
+
+    fn top_blocks(&self) -> impl Iterator<Item = &Idx> {
+        self.levels[Self::LEVELS - 1].keys()
+    }
+
+    fn children(&self, level: usize, block: &Idx) -> &[Idx] {
+        self.levels[level - 1]
+            .get(block)
+            .map(|v| &v[..])
+            .unwrap_or(&[])
+    }
+}
+
+/// A page or block of pages to visit in [FungeSpace::move_by]
+struct Candidate<Idx: PageSpaceVector> {
+    wrapped: bool,
+    dist: Idx::Scalar,
+    level: usize,
+    idx: Idx,
+}
+
+impl<Idx: PageSpaceVector> Candidate<Idx> {
+    fn key(&self) -> (bool, &Idx::Scalar, usize) {
+        (self.wrapped, &self.dist, self.level)
+    }
+}
+
+impl<Idx: PageSpaceVector> PartialEq for Candidate<Idx> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<Idx: PageSpaceVector> Eq for Candidate<Idx> {}
+
+impl<Idx: PageSpaceVector> PartialOrd for Candidate<Idx> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Idx: PageSpaceVector> Ord for Candidate<Idx> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl<T> PageSpaceVector for T
+where
+    T: FungeValue + Copy + Hash + DivEuclid + RemEuclid + DivRem<Output = (T, T)> + DivRemEuclid,
+{
+    type Scalar = T;
+
     fn dist_of_region(&self, delta: &Self, start: &Self, size: &Self) -> Option<T> {
         match (*delta).cmp(&Zero::zero()) {
             Ordering::Greater => {
@@ -279,6 +1006,13 @@ where
         }
     }
 
+    fn dist_range_of_region(&self, delta: &Self, start: &Self, size: &Self) -> Option<(T, T)> {
+        let first = self.dist_of_region(delta, start, size)?;
+        // The last stop going forward is the first stop going backward
+        let last = -self.dist_of_region(&-*delta, start, size)?;
+        Some((first, last))
+    }
+
     fn scan_within_region<F>(start: &Self, delta: &Self, limit: &Self, callback: &mut F) -> bool
     where
         F: FnMut(&Self) -> bool,
@@ -292,43 +1026,54 @@ where
         }
         false
     }
+
+    fn contiguous_run(start: &Self, delta: &Self, limit: &Self) -> Option<(Range<usize>, bool)> {
+        let pos = start.to_lin_index_unchecked(limit);
+        if delta.is_one() {
+            Some((pos..limit.lin_size(), false))
+        } else if *delta == -T::one() {
+            Some((0..pos + 1, true))
+        } else {
+            None
+        }
+    }
 }
 
-impl<T> PageSpaceVector<T> for BefungeVec<T>
+impl<T> PageSpaceVector for BefungeVec<T>
 where
-    T: FungeValue + RemEuclid + Hash + DivEuclid + DivRemEuclid,
+    T: FungeValue + Copy + Hash + DivEuclid + RemEuclid + DivRem<Output = (T, T)> + DivRemEuclid,
 {
+    type Scalar = T;
+
     fn dist_of_region(&self, delta: &Self, start: &Self, size: &Self) -> Option<T> {
-        // If the top-left corner and the bottom-right corner of the region
-        // are on opposite sides of the line, we might have a hit.
-        let rel_topleft = *start - *self;
-        let rel_bottomright = (*start + *size) - *self;
-        let rel_topright = bfvec::<T, T>(rel_bottomright.x, rel_topleft.y);
-        let rel_bottomleft = bfvec::<T, T>(rel_topleft.x, rel_bottomright.y);
-        let cross_tl = rel_topleft.x * delta.y - delta.x * rel_topleft.y;
-        let cross_br = rel_bottomright.x * delta.y - delta.x * rel_bottomright.y;
-        let cross_tr = rel_topright.x * delta.y - delta.x * rel_topright.y;
-        let cross_bl = rel_bottomleft.x * delta.y - delta.x * rel_bottomleft.y;
-        if cross_tl.signum() != cross_br.signum() || cross_tr.signum() != cross_bl.signum() {
-            // The line crosses our region. Is there a "stop"?
-            if Zero::is_zero(&delta.x) {
-                self.y.dist_of_region(&delta.y, &start.y, &size.y)
-            } else {
-                let mut dist = self.x.dist_of_region(&delta.x, &start.x, &size.x)?;
-                let mut first_pos = *self + *delta * dist;
-
-                // Make sure Y in in bounds
-                while first_pos.y < start.y || first_pos.y >= start.y + size.y {
-                    dist += One::one();
-                    first_pos = *self + *delta * dist;
-                    if first_pos.x >= start.x + size.x {
-                        // Oops, we overshot
-                        return None;
-                    }
-                }
+        self.dist_range_of_region(delta, start, size)
+            .map(|(first, _)| first)
+    }
 
-                Some(dist)
+    fn dist_range_of_region(&self, delta: &Self, start: &Self, size: &Self) -> Option<(T, T)> {
+        // Find the range of stops within the region's extent along each
+        // axis, and intersect them. An axis along which we don't move puts no
+        // limit on the range, as long as we're within the region's extent.
+        let axis_range = |pos: T, delta: T, start: T, size: T| {
+            if Zero::is_zero(&delta) {
+                if pos >= start && pos < start + size {
+                    Some(None)
+                } else {
+                    None
+                }
+            } else {
+                pos.dist_range_of_region(&delta, &start, &size).map(Some)
             }
+        };
+        let x_range = axis_range(self.x, delta.x, start.x, size.x)?;
+        let y_range = axis_range(self.y, delta.y, start.y, size.y)?;
+        let (first, last) = match (x_range, y_range) {
+            (Some((x0, x1)), Some((y0, y1))) => (max(x0, y0), x1.min(y1)),
+            (Some(r), None) | (None, Some(r)) => r,
+            (None, None) => return None,
+        };
+        if first <= last {
+            Some((first, last))
         } else {
             None
         }
@@ -347,12 +1092,29 @@ where
         }
         false
     }
+
+    fn contiguous_run(start: &Self, delta: &Self, limit: &Self) -> Option<(Range<usize>, bool)> {
+        if !delta.y.is_zero() {
+            return None;
+        }
+        let pos = start.to_lin_index_unchecked(limit);
+        let row_start = pos - start.x.to_usize()?;
+        if delta.x.is_one() {
+            Some((pos..row_start + limit.x.to_usize()?, false))
+        } else if delta.x == -T::one() {
+            Some((row_start..pos + 1, true))
+        } else {
+            None
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::index::{bfvec, BefungeVec};
+    use super::super::sparse::SparseFungeSpace;
     use super::super::tests as gen_tests;
+    use super::super::SrcIO;
     use super::*;
 
     #[test]
@@ -366,4 +1128,487 @@ mod tests {
         let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(80, 25));
         gen_tests::test_befunge_motion(&mut space);
     }
+
+    #[test]
+    fn test_dist_range_of_region() {
+        assert_eq!(3i64.dist_range_of_region(&2, &10, &5), Some((4, 5)));
+        assert_eq!(3i64.dist_range_of_region(&-2, &-10, &5), Some((5, 6)));
+        assert_eq!(3i64.dist_range_of_region(&2, &-10, &5), Some((-6, -5)));
+        assert_eq!(3i64.dist_range_of_region(&0, &-10, &5), None);
+        let origin = bfvec::<i64, i64>(0, 0);
+        let start = bfvec(100, -50);
+        let size = bfvec(20, 10);
+        assert_eq!(
+            origin.dist_range_of_region(&bfvec(2, -1), &start, &size),
+            Some((50, 50))
+        );
+        assert_eq!(
+            origin.dist_range_of_region(&bfvec(-2, 1), &start, &size),
+            Some((-50, -50))
+        );
+        assert_eq!(
+            origin.dist_range_of_region(&bfvec(5, 0), &start, &size),
+            None
+        );
+        assert_eq!(
+            bfvec(0, -45).dist_range_of_region(&bfvec(-5, 0), &start, &size),
+            Some((-23, -20))
+        );
+        // The line passes between the stops
+        assert_eq!(
+            origin.dist_range_of_region(&bfvec(40, -20), &bfvec(100, -55), &bfvec(20, 10)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_long_range_motion() {
+        // Compare against the (simple, exhaustive) sparse implementation
+        let mut paged = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(8, 4));
+        let mut sparse = SparseFungeSpace::<BefungeVec<i64>, i64>::new();
+        let mut state = 12345u64;
+        let mut random = move |range: i64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            ((state >> 33) as i64).rem_euclid(2 * range + 1) - range
+        };
+        let mut cells = Vec::new();
+        for i in 0..200 {
+            let range = if i % 2 == 0 { 100 } else { 100_000 };
+            let idx = bfvec(random(range), random(range));
+            paged[idx] = 'x' as i64;
+            sparse[idx] = 'x' as i64;
+            cells.push(idx);
+        }
+        for i in 0..2000 {
+            let start = if i % 3 == 0 {
+                cells[random(99).unsigned_abs() as usize]
+            } else {
+                bfvec(random(1000), random(1000))
+            };
+            let delta = bfvec(random(7), random(7));
+            if delta == bfvec(0, 0) {
+                continue;
+            }
+            assert_eq!(
+                paged.move_by(start, delta),
+                sparse.move_by(start, delta),
+                "from {:?} by {:?}",
+                start,
+                delta
+            );
+        }
+    }
+
+    #[test]
+    fn test_row_scan() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(8, 4));
+        // Fill the pages around the row with blanks, so only the cells on
+        // the row itself can stop the scan
+        for x in -16..16 {
+            space[bfvec(x, 1)] = ' ' as i64;
+            space[bfvec(x, 2)] = 'y' as i64;
+        }
+        space[bfvec(-13, 1)] = 'a' as i64;
+        space[bfvec(3, 1)] = 'b' as i64;
+        space[bfvec(9, 1)] = 'c' as i64;
+        let east = bfvec(1, 0);
+        let west = bfvec(-1, 0);
+        assert_eq!(
+            space.move_by(bfvec(-13, 1), east),
+            (bfvec(3, 1), &('b' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(3, 1), east),
+            (bfvec(9, 1), &('c' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(9, 1), east),
+            (bfvec(-13, 1), &('a' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(9, 1), west),
+            (bfvec(3, 1), &('b' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(3, 1), west),
+            (bfvec(-13, 1), &('a' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(-13, 1), west),
+            (bfvec(9, 1), &('c' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(5, 1), bfvec(2, 0)),
+            (bfvec(9, 1), &('c' as i64))
+        );
+
+        let mut space = PagedFungeSpace::<i64, i64>::new_with_page_size(10);
+        space[-25] = 'a' as i64;
+        space[7] = 'b' as i64;
+        assert_eq!(space.move_by(-25, 1), (7, &('b' as i64)));
+        assert_eq!(space.move_by(-25, -1), (7, &('b' as i64)));
+        assert_eq!(space.move_by(6, -1), (-25, &('a' as i64)));
+    }
+
+    #[test]
+    fn test_zero_delta() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(4, 4));
+        space[bfvec(1, 1)] = 'x' as i64;
+        assert_eq!(
+            space.move_by(bfvec(1, 1), bfvec(0, 0)),
+            (bfvec(1, 1), &('x' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(2, 1), bfvec(0, 0)),
+            (bfvec(2, 1), &(' ' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(-100, 7), bfvec(0, 0)),
+            (bfvec(-100, 7), &(' ' as i64))
+        );
+        let mut space = PagedFungeSpace::<i64, i64>::new_with_page_size(16);
+        space[3] = 'x' as i64;
+        assert_eq!(space.move_by(5, 0), (5, &(' ' as i64)));
+    }
+
+    // proptest does not build for wasm32-unknown-unknown
+    #[cfg(not(target_family = "wasm"))]
+    mod fuzz {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Number of steps of `delta` from `start` to `target` (component by
+        /// component, 0 for unefunge)
+        fn naive_steps(start: &[i64], delta: &[i64], target: &[i64]) -> Option<i64> {
+            let mut steps = None;
+            for ((s, d), t) in start.iter().zip(delta).zip(target) {
+                if *d == 0 {
+                    if s != t {
+                        return None;
+                    }
+                } else if (t - s) % d != 0 || steps.is_some_and(|n| n != (t - s) / d) {
+                    return None;
+                } else {
+                    steps = Some((t - s) / d);
+                }
+            }
+            Some(steps.unwrap_or(0))
+        }
+
+        /// Reference implementation of Lahey-space motion: check every cell
+        fn naive_move_by(
+            cells: &HashMap<Vec<i64>, i64>,
+            start: &[i64],
+            delta: &[i64],
+        ) -> (Vec<i64>, i64) {
+            let blank = ' ' as i64;
+            let at_start = *cells.get(start).unwrap_or(&blank);
+            if delta.iter().all(|d| *d == 0) {
+                return (start.to_vec(), at_start);
+            }
+            let on_line = cells
+                .iter()
+                .filter(|(_, v)| **v != blank)
+                .filter_map(|(idx, v)| naive_steps(start, delta, idx).map(|n| (n, idx, *v)));
+            let ahead = on_line.clone().filter(|(n, _, _)| *n > 0).min();
+            match ahead.or_else(|| on_line.min()) {
+                Some((_, idx, v)) => (idx.clone(), v),
+                None => (start.to_vec(), at_start),
+            }
+        }
+
+        fn fuzz_delta() -> impl Strategy<Value = i64> {
+            prop_oneof![-5i64..=5, -1_000_000i64..=1_000_000]
+        }
+
+        fn fuzz_value() -> impl Strategy<Value = i64> {
+            prop_oneof![Just(' ' as i64), ('a' as i64)..=('z' as i64)]
+        }
+
+        proptest! {
+            #[test]
+            fn fuzz_befunge_motion(
+                page_size in (1i64..=9, 1i64..=9),
+                start in (-1000i64..=1000, -1000i64..=1000),
+                delta in (fuzz_delta(), fuzz_delta()),
+                on_line in prop::collection::vec((-60i64..=60, fuzz_value()), 0..8),
+                scattered in prop::collection::vec(((-200i64..=200, -200i64..=200), fuzz_value()), 0..20),
+            ) {
+                let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(
+                    bfvec(page_size.0, page_size.1),
+                );
+                let mut cells = HashMap::new();
+                let cell_list = on_line
+                    .iter()
+                    .map(|(n, v)| ((start.0 + n * delta.0, start.1 + n * delta.1), *v))
+                    .chain(scattered.iter().cloned());
+                for ((x, y), v) in cell_list {
+                    space[bfvec(x, y)] = v;
+                    cells.insert(vec![x, y], v);
+                }
+                let (idx, value) = space.move_by(bfvec(start.0, start.1), bfvec(delta.0, delta.1));
+                let (expected_idx, expected_value) =
+                    naive_move_by(&cells, &[start.0, start.1], &[delta.0, delta.1]);
+                prop_assert_eq!((vec![idx.x, idx.y], *value), (expected_idx, expected_value));
+            }
+
+            #[test]
+            fn fuzz_unefunge_motion(
+                page_size in 1i64..=100,
+                start in -1000i64..=1000,
+                delta in fuzz_delta(),
+                on_line in prop::collection::vec((-60i64..=60, fuzz_value()), 0..8),
+                scattered in prop::collection::vec((-2000i64..=2000, fuzz_value()), 0..20),
+            ) {
+                let mut space = PagedFungeSpace::<i64, i64>::new_with_page_size(page_size);
+                let mut cells = HashMap::new();
+                let cell_list = on_line
+                    .iter()
+                    .map(|(n, v)| (start + n * delta, *v))
+                    .chain(scattered.iter().cloned());
+                for (idx, v) in cell_list {
+                    space[idx] = v;
+                    cells.insert(vec![idx], v);
+                }
+                let (idx, value) = space.move_by(start, delta);
+                let (expected_idx, expected_value) = naive_move_by(&cells, &[start], &[delta]);
+                prop_assert_eq!((vec![idx], *value), (expected_idx, expected_value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_blank_zero() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_blank(bfvec(4, 4), 0);
+        assert_eq!(space.blank(), 0);
+        BefungeVec::read_str_at(&mut space, &bfvec(0, 0), "a  b\n\n  c ");
+        assert_eq!(space[bfvec(1, 0)], ' ' as i64);
+        assert_eq!(space[bfvec(1, 1)], 0);
+        assert_eq!(space[bfvec(-9, 30)], 0);
+        // Spaces are data, but the IP skips them (and blank cells)
+        assert_eq!(space.min_idx(), Some(bfvec(0, 0)));
+        assert_eq!(space.max_idx(), Some(bfvec(3, 2)));
+        assert_eq!(
+            space.move_by(bfvec(0, 0), bfvec(1, 0)),
+            (bfvec(3, 0), &('b' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(2, 0), bfvec(0, 1)),
+            (bfvec(2, 2), &('c' as i64))
+        );
+        assert_eq!(
+            space.move_by(bfvec(3, 0), bfvec(0, 1)),
+            (bfvec(3, 0), &('b' as i64))
+        );
+        assert_eq!(
+            BefungeVec::get_src_str(&space, &bfvec(0, 0), &bfvec(4, 3), false),
+            "a  b\n    \n  c "
+        );
+        // Pages of zeros are freed (on the next write)
+        space[bfvec(10, 10)] = 'x' as i64;
+        space[bfvec(10, 10)] = 0;
+        space[bfvec(0, 0)] = 'A' as i64;
+        assert_eq!(space.page_count(), 1);
+    }
+
+    #[test]
+    fn test_free_blank_pages() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(10, 10));
+        space[bfvec(1, 1)] = 'a' as i64;
+        space[bfvec(2, 1)] = 'b' as i64;
+        space[bfvec(-5, 1)] = 'c' as i64;
+        space[bfvec(1, 1)] = ' ' as i64;
+        space[bfvec(2, 1)] = ' ' as i64;
+        // The page is freed on the next write
+        space[bfvec(-5, 1)] = 'd' as i64;
+        assert_eq!(space.page_count(), 1);
+        assert_eq!(space.min_idx(), Some(bfvec(-5, 1)));
+        assert_eq!(
+            space.move_by(bfvec(-5, 1), bfvec(1, 0)),
+            (bfvec(-5, 1), &('d' as i64))
+        );
+        // Blank pages are not inserted
+        assert!(space.insert_page(bfvec(3, 3), vec![' ' as i64; 100]));
+        assert_eq!(space.page_count(), 1);
+    }
+
+    #[test]
+    fn test_page_limit() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(10, 10));
+        space.set_memory_limit(Some(2 * 100 * 8));
+        assert_eq!(space.page_limit(), Some(2));
+        space[bfvec(1, 1)] = 'a' as i64;
+        space[bfvec(11, 1)] = 'b' as i64;
+        assert!(!space.take_limit_exceeded());
+        space[bfvec(21, 1)] = 'c' as i64;
+        assert!(space.take_limit_exceeded());
+        assert!(!space.take_limit_exceeded());
+        assert_eq!(space[bfvec(21, 1)], ' ' as i64);
+        assert!(!space.insert_page(bfvec(5, 5), vec!['x' as i64; 100]));
+        // Writing within existing pages is fine
+        space[bfvec(12, 1)] = 'c' as i64;
+        assert!(!space.take_limit_exceeded());
+        assert_eq!(space.page_count(), 2);
+    }
+
+    #[test]
+    fn test_cached_bounds() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(10, 10));
+        assert_eq!(space.min_idx(), None);
+        let mut state = 4321u64;
+        let mut random = move |range: i64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            ((state >> 33) as i64).rem_euclid(2 * range + 1) - range
+        };
+        for _ in 0..500 {
+            let idx = bfvec(random(30), random(30));
+            space[idx] = if random(1) == 0 {
+                ' ' as i64
+            } else {
+                'x' as i64
+            };
+            assert_eq!(space.min_idx(), space.find_least_idx());
+            assert_eq!(space.max_idx(), space.find_greatest_idx());
+        }
+        space.clear();
+        assert_eq!(space.max_idx(), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(4, 4));
+        BefungeVec::read_str_at(&mut old, &bfvec(-1, -1), "abc\nd f");
+        let mut new = old.clone();
+        new[bfvec(0, -1)] = 'B' as i64;
+        new[bfvec(-1, 0)] = ' ' as i64;
+        new[bfvec(0, 0)] = 'e' as i64;
+        new[bfvec(50, 50)] = 'z' as i64;
+        new[bfvec(1, -1)] = 'c' as i64;
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.len(), 4);
+        let mut patched = old.clone();
+        diff.apply(&mut patched);
+        assert!(patched.diff(&new).is_empty());
+        diff.revert(&mut patched);
+        assert!(patched.diff(&old).is_empty());
+        assert_eq!(patched.page_count(), old.page_count());
+        let mut unpatched = new.clone();
+        diff.inverse().apply(&mut unpatched);
+        assert!(unpatched.diff(&old).is_empty());
+
+        // Different page sizes
+        let mut other = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(3, 7));
+        diff.apply(&mut other);
+        let diff2 = old.diff(&other);
+        let changed: HashMap<_, _> = diff2.changes().iter().map(|c| (c.idx, &c.new)).collect();
+        assert_eq!(changed.len(), 7);
+        assert_eq!(changed[&bfvec(1, -1)], &(' ' as i64));
+        assert_eq!(changed[&bfvec(-1, 0)], &(' ' as i64));
+        assert_eq!(changed[&bfvec(50, 50)], &('z' as i64));
+    }
+
+    #[test]
+    fn test_regions() {
+        // Compare against the default (cell by cell) implementation
+        let mut paged = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(8, 4));
+        let mut sparse = SparseFungeSpace::<BefungeVec<i64>, i64>::new();
+        let mut state = 999u64;
+        let mut random = move |range: i64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            ((state >> 33) as i64).rem_euclid(2 * range + 1) - range
+        };
+        for i in 0..300 {
+            let start = bfvec(random(30), random(30));
+            let size = bfvec(random(10).abs() + 1, random(10).abs() + 1);
+            let dest = start + bfvec(random(12), random(12));
+            match i % 4 {
+                0 => {
+                    let value = ['x' as i64, ' ' as i64][random(1).unsigned_abs() as usize];
+                    paged.fill_region(start, size, value);
+                    sparse.fill_region(start, size, value);
+                }
+                1 | 2 => {
+                    paged.copy_region(start, size, dest);
+                    sparse.copy_region(start, size, dest);
+                }
+                _ => {
+                    paged.move_region(start, size, dest);
+                    sparse.move_region(start, size, dest);
+                }
+            }
+            // A different value for each cell
+            paged[start] = i;
+            sparse[start] = i;
+        }
+        for x in -50..50 {
+            for y in -50..50 {
+                assert_eq!(paged[bfvec(x, y)], sparse[bfvec(x, y)]);
+            }
+        }
+        assert_eq!(paged.min_idx(), sparse.min_idx());
+        assert_eq!(paged.max_idx(), sparse.max_idx());
+        // Filling with spaces frees pages
+        paged.fill_region(bfvec(-100, -100), bfvec(200, 200), ' ' as i64);
+        assert_eq!(paged.page_count(), 0);
+        assert_eq!(paged.min_idx(), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(4, 2));
+        BefungeVec::read_str_at(&mut space, &bfvec(-2, 0), "abcdef\n  x");
+        space[bfvec(100, -7)] = 0x7fff_ffff_ffff;
+        let json = serde_json::to_string(&space).unwrap();
+        let copy: PagedFungeSpace<BefungeVec<i64>, i64> = serde_json::from_str(&json).unwrap();
+        assert_eq!(copy.page_size(), bfvec(4, 2));
+        assert_eq!(copy.page_count(), space.page_count());
+        for (page_idx, page) in space.pages() {
+            assert_eq!(copy.pages().find(|(k, _)| *k == page_idx).unwrap().1, page);
+        }
+        assert_eq!(copy.min_idx(), Some(bfvec(-2, -7)));
+        // A run that doesn't fit its page
+        let bad = r#"{"page_size":{"x":4,"y":2},"blank":32,"runs":[[{"x":1,"y":1},[1,2,3,4]]]}"#;
+        let result = serde_json::from_str::<PagedFungeSpace<BefungeVec<i64>, i64>>(bad);
+        assert!(matches!(result, Err(e) if e.to_string().contains("crosses a page boundary")));
+        // Without a blank value (as written by older versions), it's a space
+        let old = r#"{"page_size":{"x":4,"y":2},"runs":[[{"x":1,"y":1},[1,2,3]]]}"#;
+        let copy: PagedFungeSpace<BefungeVec<i64>, i64> = serde_json::from_str(old).unwrap();
+        assert_eq!(copy[bfvec(1, 1)], 1);
+        assert_eq!(copy[bfvec(0, 1)], ' ' as i64);
+        assert_eq!(copy[bfvec(9, 9)], ' ' as i64);
+    }
+
+    #[test]
+    fn test_dirty_regions() {
+        let mut space = PagedFungeSpace::<BefungeVec<i64>, i64>::new_with_page_size(bfvec(10, 10));
+        space[bfvec(1, 1)] = 1;
+        assert!(space.take_dirty_regions().is_empty());
+
+        space.set_dirty_tracking(true);
+        space[bfvec(2, 3)] = 1;
+        space[bfvec(5, 1)] = 1;
+        space[bfvec(-1, -1)] = 1;
+        let _ = space[bfvec(8, 8)]; // reading doesn't count
+        let mut regions = space.take_dirty_regions();
+        regions.sort_by_key(|(least, _)| (least.x, least.y));
+        assert_eq!(
+            regions,
+            vec![(bfvec(-1, -1), bfvec(-1, -1)), (bfvec(2, 1), bfvec(5, 3))]
+        );
+        assert!(space.take_dirty_regions().is_empty());
+
+        space.clear();
+        let mut regions = space.take_dirty_regions();
+        regions.sort_by_key(|(least, _)| (least.x, least.y));
+        assert_eq!(
+            regions,
+            vec![(bfvec(-10, -10), bfvec(-1, -1)), (bfvec(0, 0), bfvec(9, 9))]
+        );
+
+        space.set_dirty_tracking(false);
+        space[bfvec(2, 3)] = 1;
+        assert!(space.take_dirty_regions().is_empty());
+    }
 }
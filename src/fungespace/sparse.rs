@@ -0,0 +1,299 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::ops::{Index, IndexMut};
+
+use hashbrown::HashMap;
+use num::Zero;
+
+use super::diff::FungeSpaceDiff;
+use super::index::BefungeVec;
+use super::{next_generation, FungeIndex, FungeSpace, FungeValue};
+
+/// Trait required for indices when used with [SparseFungeSpace]
+pub trait SparseSpaceVector: FungeIndex {
+    /// Return `Some(n)` such that `self + n * delta == target`, or `None` if
+    /// `target` does not lie on the line through `self` along `delta`.
+    fn steps_to(&self, delta: &Self, target: &Self) -> Option<i64>;
+
+    /// `self + n * delta`
+    fn plus_steps(&self, delta: &Self, n: i64) -> Self;
+}
+
+/// Implementation of funge space that stores every cell individually in a
+/// hash map.
+///
+/// This uses far less memory than [super::PagedFungeSpace] for programs that
+/// are scattered all over funge-space, but moving an IP past a gap means
+/// searching all the stored cells, so it is slower for ordinary programs.
+///
+/// Cells that have been accessed mutably are stored even if they contain a
+/// space.
+#[derive(Clone)]
+pub struct SparseFungeSpace<Idx, Elem>
+where
+    Idx: SparseSpaceVector,
+    Elem: FungeValue,
+{
+    cells: HashMap<Idx, Elem>,
+    /// See [FungeSpace::generation]
+    generation: u64,
+    _blank: Elem,
+}
+
+impl<Idx, Elem> SparseFungeSpace<Idx, Elem>
+where
+    Idx: SparseSpaceVector,
+    Elem: FungeValue,
+{
+    pub fn new() -> Self {
+        Self::new_with_blank(Elem::from(' ' as i32))
+    }
+
+    /// Create a funge-space in which cells that have never been written to
+    /// contain `blank` instead of a space (see
+    /// [super::PagedFungeSpace::new_with_blank])
+    pub fn new_with_blank(blank: Elem) -> Self {
+        Self {
+            cells: HashMap::new(),
+            generation: next_generation(),
+            _blank: blank,
+        }
+    }
+
+    /// Iterate over all stored cells that are not blank
+    pub fn cells(&self) -> impl Iterator<Item = (&Idx, &Elem)> {
+        self.cells.iter().filter(|(_, v)| **v != self._blank)
+    }
+
+    /// Remove all cells, i.e., fill all of funge-space with blank cells
+    pub fn clear(&mut self) {
+        self.generation = next_generation();
+        self.cells.clear();
+    }
+
+    /// Get the cells that differ between `self` (the old state) and `newer`
+    pub fn diff(&self, newer: &Self) -> FungeSpaceDiff<Idx, Elem> {
+        let mut diff = FungeSpaceDiff::new();
+        for (idx, old) in self.cells() {
+            diff.record(*idx, old.clone(), newer[*idx].clone());
+        }
+        for (idx, new) in newer.cells() {
+            if self[*idx] == self._blank {
+                diff.record(*idx, self._blank.clone(), new.clone());
+            }
+        }
+        diff
+    }
+
+    /// Drop any stored cells that are blank
+    pub fn shrink(&mut self) {
+        let blank = self._blank.clone();
+        self.cells.retain(|_, v| *v != blank);
+        self.cells.shrink_to_fit();
+    }
+}
+
+impl<Idx, Elem> Default for SparseFungeSpace<Idx, Elem>
+where
+    Idx: SparseSpaceVector,
+    Elem: FungeValue,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Idx, Elem> Index<Idx> for SparseFungeSpace<Idx, Elem>
+where
+    Idx: SparseSpaceVector,
+    Elem: FungeValue,
+{
+    type Output = Elem;
+    fn index(&self, idx: Idx) -> &Elem {
+        self.cells.get(&idx).unwrap_or(&self._blank)
+    }
+}
+
+impl<Idx, Elem> IndexMut<Idx> for SparseFungeSpace<Idx, Elem>
+where
+    Idx: SparseSpaceVector,
+    Elem: FungeValue,
+{
+    fn index_mut(&mut self, idx: Idx) -> &mut Elem {
+        self.generation = next_generation();
+        let blank = &self._blank;
+        self.cells.entry(idx).or_insert_with(|| blank.clone())
+    }
+}
+
+impl<Idx, Elem> FungeSpace<Idx> for SparseFungeSpace<Idx, Elem>
+where
+    Idx: SparseSpaceVector,
+    Elem: FungeValue,
+{
+    fn move_by(&self, start: Idx, delta: Idx) -> (Idx, &Elem) {
+        let space: Elem = (' ' as i32).into();
+        // The common case: the next cell is occupied
+        let next = start.plus_steps(&delta, 1);
+        if let Some(value) = self.cells.get(&next) {
+            if *value != self._blank && (*value != space || next == start) {
+                return (next, value);
+            }
+        }
+
+        // Otherwise, find the nearest cell ahead of us, or, failing that,
+        // wrap around to the furthest cell behind us (which may be `start`)
+        let mut best: Option<(i64, Idx, &Elem)> = None;
+        for (idx, value) in self.cells().filter(|(_, v)| **v != space) {
+            if let Some(n) = start.steps_to(&delta, idx) {
+                let better = match best {
+                    None => true,
+                    Some((best_n, _, _)) => {
+                        if n > 0 {
+                            best_n <= 0 || n < best_n
+                        } else {
+                            best_n <= 0 && n < best_n
+                        }
+                    }
+                };
+                if better {
+                    best = Some((n, *idx, value));
+                }
+            }
+        }
+
+        match best {
+            Some((_, idx, value)) => (idx, value),
+            // NOTHING found? This is a problem, but probably the IP's
+            None => (start, &self[start]),
+        }
+    }
+
+    fn generation(&self) -> Option<u64> {
+        Some(self.generation)
+    }
+
+    fn blank(&self) -> Elem {
+        self._blank.clone()
+    }
+
+    fn min_idx(&self) -> Option<Idx> {
+        self.cells()
+            .map(|(idx, _)| *idx)
+            .reduce(|i1, i2| i1.joint_min(&i2))
+    }
+
+    fn max_idx(&self) -> Option<Idx> {
+        self.cells()
+            .map(|(idx, _)| *idx)
+            .reduce(|i1, i2| i1.joint_max(&i2))
+    }
+}
+
+fn scalar_steps<T: FungeValue>(dist: T, delta: T) -> Option<i64> {
+    if delta.is_zero() {
+        if dist.is_zero() {
+            Some(0)
+        } else {
+            None
+        }
+    } else if (dist.clone() % delta.clone()).is_zero() {
+        (dist / delta).to_i64()
+    } else {
+        None
+    }
+}
+
+impl<T> SparseSpaceVector for T
+where
+    T: FungeValue + Copy,
+{
+    fn steps_to(&self, delta: &Self, target: &Self) -> Option<i64> {
+        scalar_steps(*target - *self, *delta)
+    }
+
+    fn plus_steps(&self, delta: &Self, n: i64) -> Self {
+        *self + *delta * T::from_i64(n).unwrap_or_else(Zero::zero)
+    }
+}
+
+impl<T> SparseSpaceVector for BefungeVec<T>
+where
+    T: FungeValue + Copy,
+{
+    fn steps_to(&self, delta: &Self, target: &Self) -> Option<i64> {
+        let dist = *target - *self;
+        if delta.x.is_zero() && delta.y.is_zero() {
+            return if dist.x.is_zero() && dist.y.is_zero() {
+                Some(0)
+            } else {
+                None
+            };
+        }
+        let nx = scalar_steps(dist.x, delta.x)?;
+        let ny = scalar_steps(dist.y, delta.y)?;
+        // A zero component of delta allows any number of steps in that
+        // direction (as long as the distance is zero, too)
+        if delta.x.is_zero() {
+            Some(ny)
+        } else if delta.y.is_zero() || nx == ny {
+            Some(nx)
+        } else {
+            None
+        }
+    }
+
+    fn plus_steps(&self, delta: &Self, n: i64) -> Self {
+        *self + *delta * T::from_i64(n).unwrap_or_else(Zero::zero)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::index::bfvec;
+    use super::super::tests as gen_tests;
+    use super::*;
+
+    #[test]
+    fn test_unefunge_motion() {
+        let mut space = SparseFungeSpace::<i64, i64>::new();
+        gen_tests::test_unefunge_motion(&mut space);
+    }
+
+    #[test]
+    fn test_befunge_motion() {
+        let mut space = SparseFungeSpace::<BefungeVec<i64>, i64>::new();
+        gen_tests::test_befunge_motion(&mut space);
+    }
+
+    #[test]
+    fn test_blank_cells() {
+        let mut space = SparseFungeSpace::<BefungeVec<i64>, i64>::new();
+        space[bfvec(-3, 5)] = 'x' as i64;
+        space[bfvec(10, 5)] = ' ' as i64;
+        assert_eq!(space.min_idx(), Some(bfvec(-3, 5)));
+        assert_eq!(space.max_idx(), Some(bfvec(-3, 5)));
+        assert_eq!(
+            space.move_by(bfvec(-3, 5), bfvec(1, 0)),
+            (bfvec(-3, 5), &('x' as i64))
+        );
+        space.shrink();
+        assert_eq!(space.cells.len(), 1);
+    }
+}
@@ -0,0 +1,324 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Decoding straight-line runs of simple instructions ahead of time, see
+//! [InterpreterSettings::block_cache](super::InterpreterSettings::block_cache)
+
+use std::ops::Add;
+
+use hashbrown::HashMap;
+
+use super::instruction_set::divide;
+use super::{Funge, InstructionPointer, InterpreterEnv, TraceEvent};
+use crate::fungespace::{FungeIndex, FungeSpace, FungeValue};
+
+/// Shorter runs aren't worth caching
+const MIN_BLOCK_LEN: usize = 2;
+/// Longest run decoded as a single block
+const MAX_BLOCK_LEN: usize = 64;
+/// Start afresh once this many locations have been cached
+const MAX_CACHED: usize = 1 << 16;
+
+/// An instruction that only touches the stack and always continues in the
+/// same direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Push(i32),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Greater,
+    Not,
+    Swap,
+    Dup,
+    Pop,
+    Clear,
+    Nop,
+}
+
+impl Op {
+    fn decode(c: char) -> Option<Op> {
+        Some(match c {
+            '0'..='9' => Op::Push(c as i32 - '0' as i32),
+            'a'..='f' => Op::Push(0xa + c as i32 - 'a' as i32),
+            '+' => Op::Add,
+            '-' => Op::Sub,
+            '*' => Op::Mul,
+            '/' => Op::Div,
+            '%' => Op::Rem,
+            '`' => Op::Greater,
+            '!' => Op::Not,
+            '\\' => Op::Swap,
+            ':' => Op::Dup,
+            '$' => Op::Pop,
+            'n' => Op::Clear,
+            'z' => Op::Nop,
+            _ => return None,
+        })
+    }
+
+    /// Do what the instruction does (see `exec_normal_instruction`)
+    #[inline]
+    fn exec<F: Funge>(self, ip: &mut InstructionPointer<F>) {
+        match self {
+            Op::Push(n) => ip.push(n.into()),
+            Op::Add => {
+                let b = ip.pop();
+                let a = ip.pop();
+                ip.push(a + b);
+            }
+            Op::Sub => {
+                let b = ip.pop();
+                let a = ip.pop();
+                ip.push(a - b);
+            }
+            Op::Mul => {
+                let b = ip.pop();
+                let a = ip.pop();
+                ip.push(a * b);
+            }
+            Op::Div | Op::Rem => {
+                let b = ip.pop();
+                let a = ip.pop();
+                ip.push(if b == 0.into() {
+                    0.into()
+                } else if self == Op::Div {
+                    divide(a, b, ip.instructions.division).0
+                } else {
+                    divide(a, b, ip.instructions.division).1
+                });
+            }
+            Op::Greater => {
+                let b = ip.pop();
+                let a = ip.pop();
+                ip.push(if a > b { 1.into() } else { 0.into() });
+            }
+            Op::Not => {
+                let v = ip.pop();
+                ip.push(if v == 0.into() { 1.into() } else { 0.into() });
+            }
+            Op::Swap => {
+                let a = ip.pop();
+                let b = ip.pop();
+                ip.push(a);
+                ip.push(b);
+            }
+            Op::Dup => {
+                let n = ip.pop();
+                ip.push(n.clone());
+                ip.push(n);
+            }
+            Op::Pop => {
+                ip.pop();
+            }
+            Op::Clear => ip.stack_mut().clear(),
+            Op::Nop => {}
+        }
+    }
+}
+
+/// The run of simple instructions in adjacent cells that an IP would
+/// execute next
+struct Block<Idx, Value> {
+    /// Every cell looked at while decoding (including the one that ended
+    /// the run) and its contents at the time
+    cells: Vec<(Idx, Value)>,
+    /// The instructions in the first `ops.len()` cells
+    ops: Vec<Op>,
+    /// The [generation](FungeSpace::generation) of funge-space at which the
+    /// cells were last checked
+    generation: Option<u64>,
+}
+
+impl<Idx, Value> Block<Idx, Value>
+where
+    Idx: FungeIndex + Add<Output = Idx>,
+    Value: FungeValue,
+{
+    /// Decode the run starting next to `start` in direction `delta`. Cells
+    /// are only adjacent if neither is blank: skipping over spaces (or
+    /// wrapping around) ends the run.
+    fn decode<Space>(space: &Space, start: Idx, delta: Idx) -> Self
+    where
+        Space: FungeSpace<Idx, Output = Value>,
+    {
+        let blank = space.blank();
+        let mut cells = Vec::new();
+        let mut ops = Vec::new();
+        let mut loc = start;
+        while ops.len() < MAX_BLOCK_LEN {
+            loc = loc + delta;
+            let value = space[loc].clone();
+            let op = if value == blank {
+                None
+            } else {
+                value.try_to_char().and_then(Op::decode)
+            };
+            cells.push((loc, value));
+            match op {
+                Some(op) => ops.push(op),
+                None => break,
+            }
+        }
+        Self {
+            cells,
+            ops,
+            generation: space.generation(),
+        }
+    }
+
+    /// Is funge-space still the same where it matters?
+    fn is_valid<Space>(&mut self, space: &Space) -> bool
+    where
+        Space: FungeSpace<Idx, Output = Value>,
+    {
+        let generation = space.generation();
+        if generation.is_some() && generation == self.generation {
+            true
+        } else if self.cells.iter().all(|(idx, value)| space[*idx] == *value) {
+            self.generation = generation;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Decoded runs of simple instructions, by the location and delta of the IP
+/// before it moves into them
+pub(super) struct BlockCache<Idx, Value> {
+    blocks: HashMap<(Idx, Idx), Block<Idx, Value>>,
+}
+
+impl<Idx, Value> BlockCache<Idx, Value>
+where
+    Idx: FungeIndex + Add<Output = Idx>,
+    Value: FungeValue,
+{
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// If the IP is about to execute a run of simple instructions, execute
+    /// (up to `max_len` of) them in one go, calling [InterpreterEnv::trace]
+    /// for each as if they were executed in consecutive ticks starting with
+    /// `tick`. Returns the number of instructions executed.
+    pub fn run<F>(
+        &mut self,
+        ip: &mut InstructionPointer<F>,
+        space: &F::Space,
+        env: &mut F::Env,
+        tick: u64,
+        max_len: usize,
+    ) -> usize
+    where
+        F: Funge<Idx = Idx, Value = Value>,
+    {
+        if self.blocks.len() >= MAX_CACHED {
+            self.blocks.clear();
+        }
+        let block = self
+            .blocks
+            .entry((ip.location, ip.delta))
+            .or_insert_with(|| Block::decode(space, ip.location, ip.delta));
+        if !block.is_valid(space) {
+            *block = Block::decode(space, ip.location, ip.delta);
+        }
+        if block.ops.len() < MIN_BLOCK_LEN {
+            return 0;
+        }
+        let n = block.ops.len().min(max_len);
+        for (i, op) in block.ops[..n].iter().enumerate() {
+            let (location, instruction) = &block.cells[i];
+            env.trace(&TraceEvent::<F> {
+                tick: tick + i as u64,
+                ip_id: &ip.id,
+                location: *location,
+                instruction: instruction.to_char(),
+                stack_depth: ip.stack().len(),
+                stack_top: ip.stack().last(),
+            });
+            op.exec(ip);
+        }
+        if n > 0 {
+            ip.location = block.cells[n - 1].0;
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{befunge_space, befunge_with, NoEnv, TestFunge};
+    use super::super::{ProgramResult, RunMode, StringEnv};
+    use super::*;
+    use crate::fungespace::bfvec;
+
+    const COUNTDOWN: &str = "a>:.1-:v\n ^     _@";
+
+    fn run(src: &str, block_cache: bool, mode: RunMode) -> (ProgramResult, String, u64) {
+        let mut interpreter = befunge_with(src, StringEnv::default());
+        interpreter.settings.block_cache = block_cache;
+        let result = interpreter.run(mode);
+        (result, interpreter.env.output(), interpreter.ticks())
+    }
+
+    #[test]
+    fn test_same_behaviour() {
+        for src in [COUNTDOWN, "96+3%2/c*:!\\`.@", "123n45$\\..@"] {
+            assert_eq!(
+                run(src, true, RunMode::Limited(1000)),
+                run(src, false, RunMode::Limited(1000)),
+                "{:?}",
+                src
+            );
+        }
+        assert_eq!(
+            run(COUNTDOWN, true, RunMode::Limited(7)),
+            run(COUNTDOWN, false, RunMode::Limited(7))
+        );
+    }
+
+    #[test]
+    fn test_invalidation() {
+        let mut space = befunge_space("123@");
+        let mut env = NoEnv::new();
+        let mut cache = BlockCache::new();
+        let mut ip = InstructionPointer::<TestFunge>::new();
+
+        assert_eq!(cache.run(&mut ip, &space, &mut env, 0, usize::MAX), 3);
+        assert_eq!(ip.location, bfvec(2, 0));
+        assert_eq!(ip.stack()[..], [1, 2, 3]);
+
+        space[bfvec(1, 0)] = '+' as i64;
+        ip.location = bfvec(-1, 0);
+        assert_eq!(cache.run(&mut ip, &space, &mut env, 0, 2), 2);
+        assert_eq!(ip.location, bfvec(1, 0));
+        assert_eq!(ip.stack()[..], [1, 2, 4]);
+
+        // A run that is too short isn't executed
+        space[bfvec(1, 0)] = ' ' as i64;
+        ip.location = bfvec(-1, 0);
+        assert_eq!(cache.run(&mut ip, &space, &mut env, 0, usize::MAX), 0);
+        assert_eq!(ip.location, bfvec(-1, 0));
+    }
+}
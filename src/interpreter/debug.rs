@@ -0,0 +1,205 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::{Funge, InstructionPointer};
+use crate::fungespace::{FungeIndex, FungeSpace, FungeValue};
+
+/// Identifier of a breakpoint, as returned by [DebugController::add]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BreakpointId(pub u32);
+
+/// Condition for a breakpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint<Idx> {
+    /// An IP is about to execute the instruction at this location
+    Location(Idx),
+    /// The value of the cell at this location has changed
+    Watch(Idx),
+    /// An IP has at least this many values on its stack (TOSS)
+    StackDepth(usize),
+}
+
+/// Breakpoints and watchpoints
+///
+/// The conditions are checked between ticks, after all IPs have moved. If one
+/// of them is met, [Interpreter::run][super::Interpreter::run] returns
+/// [ProgramResult::Hit][super::ProgramResult::Hit]; running the interpreter
+/// again resumes the program.
+#[derive(Debug, Clone)]
+pub struct DebugController<Idx, Value> {
+    breakpoints: Vec<(BreakpointId, Breakpoint<Idx>)>,
+    /// Last seen values of watched cells
+    watched_values: Vec<(Idx, Value)>,
+    next_id: u32,
+}
+
+impl<Idx, Value> Default for DebugController<Idx, Value> {
+    fn default() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            watched_values: Vec::new(),
+            next_id: 1,
+        }
+    }
+}
+
+impl<Idx, Value> DebugController<Idx, Value>
+where
+    Idx: FungeIndex,
+    Value: FungeValue,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a breakpoint
+    pub fn add(&mut self, breakpoint: Breakpoint<Idx>) -> BreakpointId {
+        let id = BreakpointId(self.next_id);
+        self.next_id += 1;
+        self.breakpoints.push((id, breakpoint));
+        id
+    }
+
+    /// Remove a breakpoint. Returns `false` if there is no such breakpoint.
+    pub fn remove(&mut self, id: BreakpointId) -> bool {
+        let n_before = self.breakpoints.len();
+        self.breakpoints.retain(|(bp_id, _)| *bp_id != id);
+        let watched: Vec<Idx> = self.watched_cells().collect();
+        self.watched_values.retain(|(idx, _)| watched.contains(idx));
+        self.breakpoints.len() != n_before
+    }
+
+    /// Remove all breakpoints
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watched_values.clear();
+    }
+
+    /// Get a breakpoint by ID
+    pub fn get(&self, id: BreakpointId) -> Option<&Breakpoint<Idx>> {
+        self.breakpoints
+            .iter()
+            .find(|(bp_id, _)| *bp_id == id)
+            .map(|(_, bp)| bp)
+    }
+
+    /// Iterate over all breakpoints
+    pub fn breakpoints(&self) -> impl Iterator<Item = &(BreakpointId, Breakpoint<Idx>)> {
+        self.breakpoints.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    fn watched_cells(&self) -> impl Iterator<Item = Idx> + '_ {
+        self.breakpoints.iter().filter_map(|(_, bp)| match bp {
+            Breakpoint::Watch(idx) => Some(*idx),
+            _ => None,
+        })
+    }
+
+    /// Remember the current values of watched cells that haven't been seen
+    /// before (called before the program is resumed)
+    pub(crate) fn sync<Space>(&mut self, space: &Space)
+    where
+        Space: FungeSpace<Idx, Output = Value>,
+    {
+        let new_cells: Vec<Idx> = self
+            .watched_cells()
+            .filter(|idx| !self.watched_values.iter().any(|(i, _)| i == idx))
+            .collect();
+        for idx in new_cells {
+            self.watched_values.push((idx, space[idx].clone()));
+        }
+    }
+
+    /// Check all conditions, returning the first breakpoint that was hit
+    pub(crate) fn check<F>(
+        &mut self,
+        ips: &[InstructionPointer<F>],
+        space: &F::Space,
+    ) -> Option<BreakpointId>
+    where
+        F: Funge<Idx = Idx, Value = Value>,
+    {
+        // Update the watched values first, so that a change only fires once
+        let mut changed = Vec::new();
+        for (idx, last_value) in self.watched_values.iter_mut() {
+            let value = &space[*idx];
+            if value != last_value {
+                *last_value = value.clone();
+                changed.push(*idx);
+            }
+        }
+
+        self.breakpoints
+            .iter()
+            .find(|(_, bp)| match bp {
+                Breakpoint::Location(loc) => ips
+                    .iter()
+                    .any(|ip| space.move_by(ip.location, ip.delta).0 == *loc),
+                Breakpoint::Watch(idx) => changed.contains(idx),
+                Breakpoint::StackDepth(depth) => ips.iter().any(|ip| ip.stack().len() >= *depth),
+            })
+            .map(|(id, _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::befunge;
+    use super::super::{ProgramResult, RunMode};
+    use super::*;
+    use crate::fungespace::bfvec;
+
+    #[test]
+    fn test_location_breakpoint() {
+        let mut interpreter = befunge("12+01p@");
+        let id = interpreter.debugger.add(Breakpoint::Location(bfvec(5, 0)));
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Hit(id));
+        assert_eq!(interpreter.ips[0].location, bfvec(4, 0));
+        assert_eq!(interpreter.space[bfvec(0, 1)], ' ' as i64);
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.space[bfvec(0, 1)], 3);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut interpreter = befunge("12+01p01p@");
+        let id = interpreter.debugger.add(Breakpoint::Watch(bfvec(0, 1)));
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Hit(id));
+        assert_eq!(interpreter.ips[0].location, bfvec(5, 0));
+        assert_eq!(interpreter.space[bfvec(0, 1)], 3);
+        // The second p writes a 0 to the same cell
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Hit(id));
+        assert_eq!(interpreter.space[bfvec(0, 1)], 0);
+        assert!(interpreter.debugger.remove(id));
+        assert!(!interpreter.debugger.remove(id));
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+    }
+
+    #[test]
+    fn test_stack_depth_breakpoint() {
+        let mut interpreter = befunge("12+01p@");
+        interpreter.debugger.add(Breakpoint::StackDepth(4));
+        let id = interpreter.debugger.add(Breakpoint::StackDepth(2));
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Hit(id));
+        assert_eq!(interpreter.ips[0].stack()[..], [1, 2]);
+    }
+}
@@ -0,0 +1,130 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Structured warnings, see [InterpreterEnv::diagnostic][super::InterpreterEnv::diagnostic]
+
+use std::fmt;
+
+use super::{Funge, InstructionPointer, MotionCmds};
+
+/// How bad a [Diagnostic] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Something is probably wrong, but the program carries on
+    Warning,
+    /// The program is aborted
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// What a [Diagnostic] is about, so that front ends can filter them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticCode {
+    /// An instruction that isn't defined (see
+    /// [UnknownInstructionPolicy][super::UnknownInstructionPolicy])
+    UnknownInstruction,
+    /// Output could not be written
+    IoError,
+    /// Funge-space has reached its size limit
+    SpaceFull,
+    /// A stack has grown beyond the limit (see
+    /// [ResourceLimits][super::ResourceLimits])
+    StackLimit,
+    /// An IP is caught in a loop without executing any instructions, e.g.
+    /// between two `#` or `;`
+    InfiniteLoop,
+}
+
+impl DiagnosticCode {
+    /// A short name, e.g. `"unknown-instruction"`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticCode::UnknownInstruction => "unknown-instruction",
+            DiagnosticCode::IoError => "io-error",
+            DiagnosticCode::SpaceFull => "space-full",
+            DiagnosticCode::StackLimit => "stack-limit",
+            DiagnosticCode::InfiniteLoop => "infinite-loop",
+        }
+    }
+}
+
+/// A warning or error about the running program, passed to
+/// [InterpreterEnv::diagnostic][super::InterpreterEnv::diagnostic]
+///
+/// When displayed, this is the message followed by the IP and location, if
+/// known.
+#[derive(Debug)]
+pub struct Diagnostic<'a, F: Funge> {
+    pub severity: Severity,
+    pub code: DiagnosticCode,
+    /// Where the instruction that caused the problem is
+    pub location: Option<F::Idx>,
+    /// ID of the IP that caused the problem
+    pub ip_id: Option<&'a F::Value>,
+    pub message: String,
+}
+
+impl<'a, F: Funge> Diagnostic<'a, F> {
+    /// A diagnostic about the instruction `ip` is executing
+    pub fn at_ip(
+        ip: &'a InstructionPointer<F>,
+        severity: Severity,
+        code: DiagnosticCode,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            severity,
+            code,
+            location: Some(ip.location),
+            ip_id: Some(&ip.id),
+            message: message.into(),
+        }
+    }
+
+    /// The coordinates of the location, `x` first
+    pub fn coordinates(&self) -> Option<Vec<F::Value>> {
+        self.location.map(|location| {
+            let mut coords = Vec::new();
+            MotionCmds::push_vector_onto(&mut coords, location);
+            coords
+        })
+    }
+}
+
+impl<F: Funge> fmt::Display for Diagnostic<'_, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(id) = self.ip_id {
+            write!(f, " (IP {}", id)?;
+            if let Some(coords) = self.coordinates() {
+                let coords: Vec<String> = coords.iter().map(|c| c.to_string()).collect();
+                write!(f, " at ({})", coords.join(", "))?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Ready-made [InterpreterEnv] implementations for embedding the interpreter
+//! without writing an environment of your own
+
+use futures_lite::io::{empty, sink, AsyncRead, AsyncWrite, Cursor, Empty, Sink};
+
+use super::{safe_fingerprints, IOMode, InterpreterEnv};
+
+/// An environment that reads the program's input from a string and collects
+/// its output in memory.
+///
+/// Warnings are collected in [StringEnv::warnings]. Only the fingerprints in
+/// [safe_fingerprints] are available, and there is no file or command access.
+pub struct StringEnv {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+    fingerprints: Vec<i32>,
+    /// Warnings issued by the interpreter, in order
+    pub warnings: Vec<String>,
+}
+
+impl StringEnv {
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: Cursor::new(input.as_bytes().to_vec()),
+            output: Vec::new(),
+            fingerprints: safe_fingerprints(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Everything the program has written so far (invalid UTF-8 is replaced)
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+
+    /// Everything the program has written so far, as raw bytes
+    pub fn output_bytes(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl Default for StringEnv {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl InterpreterEnv for StringEnv {
+    fn get_iomode(&self) -> IOMode {
+        IOMode::Text
+    }
+    fn is_io_buffered(&self) -> bool {
+        true
+    }
+    fn output_writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
+        &mut self.output
+    }
+    fn input_reader(&mut self) -> &mut (dyn AsyncRead + Unpin) {
+        &mut self.input
+    }
+    fn warn(&mut self, msg: &str) {
+        self.warnings.push(msg.to_owned());
+    }
+    fn is_fingerprint_enabled(&self, fpr: i32) -> bool {
+        self.fingerprints.contains(&fpr)
+    }
+}
+
+/// An environment that connects the program to an arbitrary reader and
+/// writer, e.g. a socket or one end of a pipe.
+///
+/// Binary IO mode is used unless changed with [PipeEnv::with_iomode].
+/// Warnings are collected in [PipeEnv::warnings]. Only the fingerprints in
+/// [safe_fingerprints] are available, and there is no file or command access.
+pub struct PipeEnv<R, W> {
+    reader: R,
+    writer: W,
+    iomode: IOMode,
+    fingerprints: Vec<i32>,
+    /// Warnings issued by the interpreter, in order
+    pub warnings: Vec<String>,
+}
+
+impl<R, W> PipeEnv<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            iomode: IOMode::Binary,
+            fingerprints: safe_fingerprints(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Use text or binary mode
+    pub fn with_iomode(mut self, iomode: IOMode) -> Self {
+        self.iomode = iomode;
+        self
+    }
+
+    /// Get the reader and writer back
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<R, W> InterpreterEnv for PipeEnv<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    fn get_iomode(&self) -> IOMode {
+        self.iomode
+    }
+    fn is_io_buffered(&self) -> bool {
+        false
+    }
+    fn output_writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
+        &mut self.writer
+    }
+    fn input_reader(&mut self) -> &mut (dyn AsyncRead + Unpin) {
+        &mut self.reader
+    }
+    fn warn(&mut self, msg: &str) {
+        self.warnings.push(msg.to_owned());
+    }
+    fn is_fingerprint_enabled(&self, fpr: i32) -> bool {
+        self.fingerprints.contains(&fpr)
+    }
+}
+
+/// An environment with no input, that discards all output and warnings.
+/// Only the fingerprints in [safe_fingerprints] are available.
+pub struct SilentEnv {
+    input: Empty,
+    output: Sink,
+    fingerprints: Vec<i32>,
+}
+
+impl SilentEnv {
+    pub fn new() -> Self {
+        Self {
+            input: empty(),
+            output: sink(),
+            fingerprints: safe_fingerprints(),
+        }
+    }
+}
+
+impl Default for SilentEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterpreterEnv for SilentEnv {
+    fn get_iomode(&self) -> IOMode {
+        IOMode::Binary
+    }
+    fn is_io_buffered(&self) -> bool {
+        true
+    }
+    fn output_writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
+        &mut self.output
+    }
+    fn input_reader(&mut self) -> &mut (dyn AsyncRead + Unpin) {
+        &mut self.input
+    }
+    fn warn(&mut self, _msg: &str) {}
+    fn is_fingerprint_enabled(&self, fpr: i32) -> bool {
+        self.fingerprints.contains(&fpr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{ProgramResult, RunMode};
+    use crate::{bfvec, new_befunge_interpreter};
+
+    #[test]
+    fn test_string_env() {
+        let mut interpreter = new_befunge_interpreter::<i32, _>(StringEnv::new("12\nx"));
+        interpreter.load_program_at(bfvec(0, 0), "&.~,#@H");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.env.output(), "12 x");
+        assert_eq!(interpreter.env.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_pipe_env() {
+        let env = PipeEnv::new(Cursor::new(b"ab".to_vec()), Vec::new());
+        let mut interpreter = new_befunge_interpreter::<i32, _>(env);
+        interpreter.load_program_at(bfvec(0, 0), "~~,,@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        let (_, output) = interpreter.env.into_inner();
+        assert_eq!(output, b"ba");
+    }
+
+    #[test]
+    fn test_silent_env() {
+        let mut interpreter = new_befunge_interpreter::<i32, _>(SilentEnv::new());
+        interpreter.load_program_at(bfvec(0, 0), "\"ahC\"4(~.@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+    }
+}
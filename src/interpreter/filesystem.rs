@@ -0,0 +1,299 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Filesystems for `i` and `o`, see [super::InterpreterEnv::filesystem]
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Access to files, as seen by a Funge program
+///
+/// The methods take `&self` so that the interpreter can use the filesystem
+/// while it holds other references into the environment; implementations
+/// that keep state use interior mutability.
+pub trait FungeFilesystem {
+    /// Open a file for reading
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + '_>>;
+    /// Create (or truncate) a file for writing
+    fn create(&self, path: &str) -> io::Result<Box<dyn Write + '_>>;
+    /// List the names of the entries in a directory
+    fn list(&self, path: &str) -> io::Result<Vec<String>>;
+    /// Can files be written at all? (If not, `o` is not available)
+    fn is_writable(&self) -> bool {
+        true
+    }
+    /// Get the contents of a file
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.open(path)?.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+    /// Replace the contents of a file
+    fn write(&self, path: &str, content: &[u8]) -> io::Result<()> {
+        let mut file = self.create(path)?;
+        file.write_all(content)?;
+        file.flush()
+    }
+}
+
+fn list_dir(path: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path)? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// The real filesystem, with relative paths resolved against the working
+/// directory
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFilesystem;
+
+impl FungeFilesystem for RealFilesystem {
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        list_dir(Path::new(path))
+    }
+}
+
+/// Read-only access to a single directory (and its subdirectories)
+///
+/// Paths are resolved relative to the directory, with `/` standing for the
+/// directory itself. Paths leading outside of it, including through
+/// symbolic links, are refused.
+#[derive(Debug, Clone)]
+pub struct DirJail {
+    root: PathBuf,
+}
+
+impl DirJail {
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            root: root.as_ref().canonicalize()?,
+        })
+    }
+
+    /// The directory the program is confined to
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Find the real path of an existing file or directory inside the jail
+    fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let relative: PathBuf = Path::new(path)
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_) | Component::ParentDir))
+            .collect();
+        let resolved = self.root.join(relative).canonicalize()?;
+        if resolved.starts_with(&self.root) {
+            Ok(resolved)
+        } else {
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        }
+    }
+}
+
+impl FungeFilesystem for DirJail {
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(File::open(self.resolve(path)?)?))
+    }
+
+    fn create(&self, _path: &str) -> io::Result<Box<dyn Write + '_>> {
+        Err(io::Error::from(io::ErrorKind::PermissionDenied))
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        list_dir(&self.resolve(path)?)
+    }
+
+    fn is_writable(&self) -> bool {
+        false
+    }
+}
+
+/// A filesystem that only exists in memory, e.g. for tests and sandboxes
+///
+/// Paths are names of files; directories exist implicitly, wherever there
+/// are files with names of the form `dir/name`.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFilesystem {
+    files: RefCell<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryFilesystem {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add (or replace) a file
+    pub fn insert(&self, path: &str, content: impl Into<Vec<u8>>) {
+        self.files
+            .borrow_mut()
+            .insert(normalize_path(path), content.into());
+    }
+
+    /// Get a copy of the contents of a file, if it exists
+    pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+        self.files.borrow().get(&normalize_path(path)).cloned()
+    }
+}
+
+/// Strip leading slashes and `.` components, so that `/a/./b` and `a/b` are
+/// the same file
+fn normalize_path(path: &str) -> String {
+    path.split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// A file being written to a [MemoryFilesystem]; the contents are stored
+/// when it is flushed or dropped
+struct MemoryFile<'a> {
+    fs: &'a MemoryFilesystem,
+    path: String,
+    buf: Vec<u8>,
+}
+
+impl Write for MemoryFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.fs
+            .files
+            .borrow_mut()
+            .insert(self.path.clone(), self.buf.clone());
+        Ok(())
+    }
+}
+
+impl Drop for MemoryFile<'_> {
+    fn drop(&mut self) {
+        self.flush().ok();
+    }
+}
+
+impl FungeFilesystem for MemoryFilesystem {
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + '_>> {
+        match self.get(path) {
+            Some(content) => Ok(Box::new(io::Cursor::new(content))),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn Write + '_>> {
+        let path = normalize_path(path);
+        self.files.borrow_mut().insert(path.clone(), Vec::new());
+        Ok(Box::new(MemoryFile {
+            fs: self,
+            path,
+            buf: Vec::new(),
+        }))
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        let dir = normalize_path(path);
+        let prefix = if dir.is_empty() { dir } else { dir + "/" };
+        let mut names: Vec<String> = self
+            .files
+            .borrow()
+            .keys()
+            .filter_map(|name| name.strip_prefix(&prefix))
+            .map(|rest| rest.split('/').next().unwrap_or(rest).to_owned())
+            .collect();
+        names.dedup();
+        if names.is_empty() {
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        } else {
+            Ok(names)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_filesystem() {
+        let fs = MemoryFilesystem::new();
+        fs.insert("/dir/a.txt", "A");
+        fs.write("dir/sub/b.txt", b"B").unwrap();
+        fs.write("c.txt", b"C").unwrap();
+
+        assert_eq!(fs.read("dir/a.txt").unwrap(), b"A");
+        assert_eq!(fs.read("./dir/sub/b.txt").unwrap(), b"B");
+        assert!(fs.read("b.txt").is_err());
+        assert_eq!(fs.list("/").unwrap(), vec!["c.txt", "dir"]);
+        assert_eq!(fs.list("dir").unwrap(), vec!["a.txt", "sub"]);
+        assert!(fs.list("nowhere").is_err());
+
+        {
+            let mut file = fs.create("c.txt").unwrap();
+            file.write_all(b"new ").unwrap();
+            file.write_all(b"content").unwrap();
+        }
+        assert_eq!(fs.get("c.txt").unwrap(), b"new content");
+    }
+
+    #[test]
+    fn test_dir_jail() {
+        let root = std::env::temp_dir().join(format!("rfunge-jail-{}", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/file.txt"), "inside").unwrap();
+
+        let jail = DirJail::new(root.join("sub")).unwrap();
+        assert_eq!(jail.read("file.txt").unwrap(), b"inside");
+        assert_eq!(jail.read("/file.txt").unwrap(), b"inside");
+        assert_eq!(jail.list("/").unwrap(), vec!["file.txt"]);
+        // can't get out, or write anything
+        assert!(jail.read("../sub/file.txt").is_ok());
+        fs::write(root.join("secret.txt"), "outside").unwrap();
+        assert_eq!(
+            jail.read("../secret.txt").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(jail.write("new.txt", b"").is_err());
+        assert!(!jail.is_writable());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("secret.txt"), root.join("sub/link")).unwrap();
+            assert_eq!(
+                jail.read("link").unwrap_err().kind(),
+                io::ErrorKind::PermissionDenied
+            );
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
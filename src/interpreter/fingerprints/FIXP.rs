@@ -24,7 +24,7 @@ use num::{Signed, ToPrimitive};
 use super::BOOL;
 use crate::interpreter::{
     instruction_set::{sync_instruction, Instruction},
-    Funge, InstructionPointer, InstructionResult,
+    Funge, InstructionPointer, InstructionResult, InterpreterEnv,
 };
 
 /// From the rcFunge docs:
@@ -158,15 +158,19 @@ fn tan<F: Funge>(
 fn rnd<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
-    _env: &mut F::Env,
+    env: &mut F::Env,
 ) -> InstructionResult {
     let limit = ip.pop();
     let sgn = limit.signum();
-    let abs_limit = (limit * sgn).to_i32().unwrap_or_else(i32::max_value);
+    let abs_limit = (limit * sgn.clone())
+        .to_i32()
+        .unwrap_or_else(i32::max_value);
     let number = if abs_limit == 0 {
         0.into()
     } else {
-        let rndnum = rand::random::<f64>() * (abs_limit as f64);
+        // 53 random bits give a uniform float in [0, 1)
+        let unit = (env.random_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        let rndnum = unit * (abs_limit as f64);
         F::Value::from(rndnum as i32) * sgn
     };
 
@@ -231,6 +235,6 @@ fn abs<F: Funge>(
     _env: &mut F::Env,
 ) -> InstructionResult {
     let n = ip.pop();
-    ip.push(n * n.signum());
+    ip.push(n.abs());
     InstructionResult::Continue
 }
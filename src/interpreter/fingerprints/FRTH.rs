@@ -16,8 +16,6 @@ You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::cmp::Ordering;
-
 use hashbrown::HashMap;
 use num::{FromPrimitive, ToPrimitive, Zero};
 
@@ -83,31 +81,8 @@ fn roll<F: Funge>(
     _space: &mut F::Space,
     _env: &mut F::Env,
 ) -> InstructionResult {
-    let stack = ip.stack_mut();
-    let u = stack.pop().and_then(|v| v.to_isize()).unwrap_or_default();
-    match u.cmp(&Zero::zero()) {
-        Ordering::Greater => {
-            // roll mode
-            let u = u as usize;
-            let l = stack.len();
-            let v = if u < l {
-                stack.remove(l - 1 - u)
-            } else {
-                Zero::zero()
-            };
-            ip.push(v);
-        }
-        Ordering::Less => {
-            // -roll mode
-            let u = (-u) as usize;
-            let v = stack.pop().unwrap_or_else(Zero::zero);
-            while stack.len() < u {
-                stack.insert(0, Zero::zero());
-            }
-            stack.insert(stack.len() - u, v);
-        }
-        _ => {}
-    }
+    let u = ip.pop().to_isize().unwrap_or_default();
+    ip.roll(u);
     InstructionResult::Continue
 }
 
@@ -116,12 +91,7 @@ fn over<F: Funge>(
     _space: &mut F::Space,
     _env: &mut F::Env,
 ) -> InstructionResult {
-    let stack = ip.stack();
-    let v = if stack.len() >= 2 {
-        stack[stack.len() - 2]
-    } else {
-        Zero::zero()
-    };
+    let v = ip.peek_nth(1);
     ip.push(v);
 
     InstructionResult::Continue
@@ -136,14 +106,7 @@ fn pick<F: Funge>(
     if u < Zero::zero() {
         ip.reflect()
     } else {
-        let u = u.to_usize().unwrap_or_default();
-        let stack = ip.stack();
-        let l = stack.len();
-        let v = if u < l {
-            stack[l - 1 - u]
-        } else {
-            Zero::zero()
-        };
+        let v = ip.peek_nth(u.to_usize().unwrap_or(usize::MAX));
         ip.push(v);
     }
 
@@ -155,14 +118,7 @@ fn rot<F: Funge>(
     _space: &mut F::Space,
     _env: &mut F::Env,
 ) -> InstructionResult {
-    let stack = ip.stack_mut();
-    let l = stack.len();
-    let v = if l >= 3 {
-        stack.remove(l - 3)
-    } else {
-        Zero::zero()
-    };
-    ip.push(v);
+    ip.roll(2);
 
     InstructionResult::Continue
 }
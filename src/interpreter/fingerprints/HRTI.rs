@@ -17,13 +17,13 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
 use std::rc::Rc;
+use std::time::Duration;
 
-use chrono::prelude::Utc;
 use hashbrown::HashMap;
 
 use crate::interpreter::{
     instruction_set::{sync_instruction, Instruction},
-    Funge, InstructionPointer, InstructionResult,
+    Funge, InstructionPointer, InstructionResult, InterpreterEnv,
 };
 
 /// The HRTI fingerprint allows a Funge program to measure elapsed time much
@@ -31,6 +31,10 @@ use crate::interpreter::{
 ///
 /// After successfully loading HRTI, the instructions `E`, `G`, `M`, `S`,
 /// and `T` take on new semantics.
+///
+/// The time is taken from [InterpreterEnv::monotonic_clock]. Marks belong to
+/// the IP that made them: an IP created with `t` does not inherit its
+/// parent's mark.
 pub fn load<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
@@ -70,11 +74,11 @@ fn granularity<F: Funge>(
 fn mark<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
-    _env: &mut F::Env,
+    env: &mut F::Env,
 ) -> InstructionResult {
-    let ts_micros: i64 = Utc::now().timestamp_nanos() / 1000;
+    let mark: (F::Value, Duration) = (ip.id.clone(), env.monotonic_clock());
     ip.private_data
-        .insert("HRTI.mark".to_owned(), Rc::new(ts_micros));
+        .insert("HRTI.mark".to_owned(), Rc::new(mark));
     InstructionResult::Continue
 }
 
@@ -84,16 +88,18 @@ fn mark<F: Funge>(
 fn timer<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
-    _env: &mut F::Env,
+    env: &mut F::Env,
 ) -> InstructionResult {
-    if let Some(mark) = ip.private_data.get("HRTI.mark") {
-        if let Some(ts_ref) = mark.downcast_ref::<i64>() {
-            let ts_micros: i64 = Utc::now().timestamp_nanos() / 1000;
-            let ts_diff = ts_micros - *ts_ref;
-            ip.push((ts_diff as i32).into());
-        } else {
-            ip.reflect();
-        }
+    // The mark may have been copied from the parent IP when forking
+    let mark_time = ip
+        .private_data
+        .get("HRTI.mark")
+        .and_then(|mark| mark.downcast_ref::<(F::Value, Duration)>())
+        .filter(|(ip_id, _)| *ip_id == ip.id)
+        .map(|(_, t)| *t);
+    if let Some(mark_time) = mark_time {
+        let elapsed = env.monotonic_clock().saturating_sub(mark_time);
+        ip.push((elapsed.as_micros() as i32).into());
     } else {
         ip.reflect();
     }
@@ -112,12 +118,12 @@ fn erase<F: Funge>(
 }
 
 /// `S` 'Second' pushes the number of microseconds elapsed since the last
-/// whole second.
+/// whole second (of the wall clock, so it agrees with `y`).
 fn second<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
-    _env: &mut F::Env,
+    env: &mut F::Env,
 ) -> InstructionResult {
-    ip.push((Utc::now().timestamp_subsec_micros() as i32).into());
+    ip.push((env.now_utc().timestamp_subsec_micros() as i32).into());
     InstructionResult::Continue
 }
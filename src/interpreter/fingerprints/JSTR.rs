@@ -18,6 +18,7 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use hashbrown::HashMap;
 
+use crate::fungespace::SrcIO;
 use crate::interpreter::MotionCmds;
 use crate::interpreter::{
     instruction_set::{sync_instruction, Instruction},
@@ -34,6 +35,20 @@ use crate::interpreter::{
 ///
 /// NOTE: The rcFunge docs swap `G` and `P`, but rcFunge still implements the
 /// fingerprint as documented here!
+///
+/// RFunge extensions:
+///
+/// W (0gnirts Va -- Vs)    encode the string as UTF-8 and write it to Va, one
+///                         byte per cell, like a file loaded with `i` in
+///                         binary mode. Pushes the size of the region written.
+/// R (Va Vs -- 0gnirts)    decode the region at Va with size Vs (as written
+///                         with `o` in binary mode) from UTF-8 and push it as
+///                         a string. Invalid UTF-8 is replaced with U+FFFD.
+/// L (Va Vs -- s n)        push the region at Va with size Vs, with trailing
+///                         whitespace removed, as n cells with n on top (the
+///                         first character right below n)
+///
+/// Va is relative to the storage offset.
 pub fn load<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
@@ -42,6 +57,9 @@ pub fn load<F: Funge>(
     let mut layer = HashMap::<char, Instruction<F>>::new();
     layer.insert('P', sync_instruction(put));
     layer.insert('G', sync_instruction(get));
+    layer.insert('W', sync_instruction(write_utf8));
+    layer.insert('R', sync_instruction(read_utf8));
+    layer.insert('L', sync_instruction(get_counted));
     ip.instructions.add_layer(layer);
     true
 }
@@ -51,7 +69,7 @@ pub fn unload<F: Funge>(
     _space: &mut F::Space,
     _env: &mut F::Env,
 ) -> bool {
-    ip.instructions.pop_layer(&['P', 'G'][..])
+    ip.instructions.pop_layer(&['P', 'G', 'W', 'R', 'L'][..])
 }
 
 fn put<F: Funge>(
@@ -88,10 +106,48 @@ fn get<F: Funge>(
     let mut pos = va + ip.storage_offset;
     let mut remaining = n;
     while remaining > 0.into() {
-        ip.push(space[pos]);
+        ip.push(space[pos].clone());
         pos = pos + vd;
         remaining -= 1.into();
     }
 
     InstructionResult::Continue
 }
+
+fn write_utf8<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let va = MotionCmds::pop_vector(ip) + ip.storage_offset;
+    let s = ip.pop_0gnirts();
+    let size = F::Idx::read_bin_at(space, &va, s.as_bytes());
+    MotionCmds::push_vector(ip, size);
+    InstructionResult::Continue
+}
+
+fn read_utf8<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let vs = MotionCmds::pop_vector(ip);
+    let va = MotionCmds::pop_vector(ip) + ip.storage_offset;
+    let bytes = F::Idx::get_src_bin(space, &va, &vs, true);
+    ip.push_0gnirts(&String::from_utf8_lossy(&bytes));
+    InstructionResult::Continue
+}
+
+fn get_counted<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let vs = MotionCmds::pop_vector(ip);
+    let va = MotionCmds::pop_vector(ip) + ip.storage_offset;
+    let cells = F::Idx::get_src_region(space, &va, &vs, true);
+    let n = cells.len();
+    ip.push_cells(cells.into_iter().rev());
+    ip.push((n as i32).into());
+    InstructionResult::Continue
+}
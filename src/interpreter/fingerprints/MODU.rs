@@ -16,8 +16,8 @@ You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
-use divrem::DivRem;
 use hashbrown::HashMap;
+use num::Integer;
 
 use crate::interpreter::{
     instruction_set::{sync_instruction, Instruction},
@@ -92,7 +92,7 @@ fn signed_rem<F: Funge>(
     if b == 0.into() {
         ip.push(0.into());
     } else {
-        let (q, r) = a.div_rem(b); // truncating
+        let (q, r) = a.div_rem(&b); // truncating
         ip.push(if q < 0.into() { r + b } else { r });
     }
     InstructionResult::Continue
@@ -108,7 +108,7 @@ fn unsigned_rem<F: Funge>(
     if b == 0.into() {
         ip.push(0.into());
     } else {
-        let r = a % b; // truncating
+        let r = a % b.clone(); // truncating
         ip.push(if r < 0.into() {
             if b > 0.into() {
                 r + b
@@ -19,6 +19,7 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 #![cfg(all(feature = "ncurses", not(target_family = "wasm")))]
 
 use std::cell::RefCell;
+use std::rc::Rc;
 
 use ncurses as nc;
 use ncurses::constants::ERR;
@@ -31,8 +32,23 @@ use crate::interpreter::{
     Funge, InstructionPointer, InstructionResult,
 };
 
-thread_local! {
-    static STDSCR: RefCell<Option<nc::WINDOW>> = RefCell::default();
+/// The curses screen, shared by all IPs of an interpreter. If curses mode is
+/// still on when the interpreter is dropped, it is ended then.
+#[derive(Default)]
+struct Screen {
+    stdscr: Option<nc::WINDOW>,
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        if self.stdscr.take().is_some() {
+            nc::endwin();
+        }
+    }
+}
+
+fn get_screen<F: Funge>(ip: &InstructionPointer<F>) -> Rc<RefCell<Screen>> {
+    ip.shared.get_or_default()
 }
 
 /// From https://web.archive.org/web/20070525220700/http://www.jess2.net:80/code/funge/myexts.txt
@@ -57,10 +73,25 @@ thread_local! {
 /// other operations to be displayed. You *must* call 'I' at the beginning
 /// *and* end of each program that uses NCRS.
 ///
+/// RFunge extensions:
+///
+/// A (a m -- )     switch attribute a on (m == 1) or off (m == 0): 1 bold,
+///                 2 underline, 3 reverse, 4 blink, 5 dim, 6 standout.
+///                 0 0A switches all attributes (and colours) off.
+/// L (n f b -- )   define colour pair n (n >= 1) with foreground colour f and
+///                 background colour b (0-7 are the standard curses colours,
+///                 -1 is the terminal default)
+/// O (n -- )       use colour pair n for subsequent output (0 == default)
+/// T (t -- )       set the timeout of G to t milliseconds (t < 0: wait
+///                 forever, t == 0: don't wait). G acts as r on timeout.
+/// W ( -- w h)     get the width and height of the screen
+///
+/// L and O act as r if the terminal does not support colours.
+///
 pub fn load<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> bool {
     let mut layer = HashMap::<char, Instruction<F>>::new();
     layer.insert('B', sync_instruction(beep));
@@ -75,6 +106,11 @@ pub fn load<F: Funge>(
     layer.insert('P', sync_instruction(addch));
     layer.insert('S', sync_instruction(addstr));
     layer.insert('C', sync_instruction(clear));
+    layer.insert('A', sync_instruction(set_attribute));
+    layer.insert('L', sync_instruction(init_colour_pair));
+    layer.insert('O', sync_instruction(use_colour_pair));
+    layer.insert('T', sync_instruction(set_timeout));
+    layer.insert('W', sync_instruction(screen_size));
 
     ip.instructions.add_layer(layer);
     true
@@ -82,17 +118,18 @@ pub fn load<F: Funge>(
 
 pub fn unload<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> bool {
-    ip.instructions
-        .pop_layer(&['B', 'E', 'G', 'I', 'K', 'M', 'N', 'R', 'U', 'P', 'P', 'C'])
+    ip.instructions.pop_layer(&[
+        'B', 'E', 'G', 'I', 'K', 'M', 'N', 'R', 'U', 'P', 'S', 'C', 'A', 'L', 'O', 'T', 'W',
+    ])
 }
 
 fn beep<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     if nc::flash() == ERR {
         ip.reflect()
@@ -102,8 +139,8 @@ fn beep<F: Funge>(
 
 fn echo_mode<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     let m = ip.pop().to_i32().unwrap_or(-1);
     if match m {
@@ -119,8 +156,8 @@ fn echo_mode<F: Funge>(
 
 fn getch<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     let c = nc::getch();
     if c == ERR {
@@ -133,54 +170,57 @@ fn getch<F: Funge>(
 
 fn init_curses<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
-    STDSCR.with(|stdscr_rc| {
-        let m = ip.pop().to_i32().unwrap_or_default();
-        if m == 1 {
-            stdscr_rc.replace(Some(nc::initscr()));
-        } else {
-            stdscr_rc.borrow_mut().take();
-            if nc::endwin() == ERR {
-                ip.reflect();
-            }
+    let screen = get_screen(ip);
+    let mut screen = screen.borrow_mut();
+    let m = ip.pop().to_i32().unwrap_or_default();
+    if m == 1 {
+        screen.stdscr = Some(nc::initscr());
+        if nc::has_colors() {
+            nc::start_color();
+            nc::use_default_colors();
+        }
+    } else {
+        screen.stdscr = None;
+        if nc::endwin() == ERR {
+            ip.reflect();
         }
-        InstructionResult::Continue
-    })
+    }
+    InstructionResult::Continue
 }
 
 fn keypad_mode<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
-    STDSCR.with(|stdscr_rc| {
-        if let Some(stdscr) = *(stdscr_rc.borrow()) {
-            let m = ip.pop().to_i32().unwrap_or(-1);
-            if match m {
-                0 => nc::keypad(stdscr, false),
-                1 => nc::keypad(stdscr, true),
-                _ => ERR,
-            } == ERR
-            {
-                ip.reflect();
-            }
-        } else {
+    let stdscr = get_screen(ip).borrow().stdscr;
+    if let Some(stdscr) = stdscr {
+        let m = ip.pop().to_i32().unwrap_or(-1);
+        if match m {
+            0 => nc::keypad(stdscr, false),
+            1 => nc::keypad(stdscr, true),
+            _ => ERR,
+        } == ERR
+        {
             ip.reflect();
         }
-        InstructionResult::Continue
-    })
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
 }
 
 fn move_cursor<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     let y = ip.pop().to_i32().unwrap_or_default();
     let x = ip.pop().to_i32().unwrap_or_default();
-    if nc::mv(x, y) == ERR {
+    if nc::mv(y, x) == ERR {
         ip.reflect();
     }
     InstructionResult::Continue
@@ -188,8 +228,8 @@ fn move_cursor<F: Funge>(
 
 fn input_mode<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     let m = ip.pop().to_i32().unwrap_or(-1);
     if match m {
@@ -205,8 +245,8 @@ fn input_mode<F: Funge>(
 
 fn refresh<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     if nc::refresh() == ERR {
         ip.reflect();
@@ -216,8 +256,8 @@ fn refresh<F: Funge>(
 
 fn ungetch<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     let c = ip.pop().to_i32().unwrap_or_default();
     if nc::ungetch(c) == ERR {
@@ -228,8 +268,8 @@ fn ungetch<F: Funge>(
 
 fn addch<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     let c = ip.pop().to_u32().unwrap_or_default() as nc::chtype;
     if nc::addch(c) == ERR {
@@ -240,8 +280,8 @@ fn addch<F: Funge>(
 
 fn addstr<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     let s = ip.pop_0gnirts();
     if nc::addstr(&s) == ERR {
@@ -252,8 +292,8 @@ fn addstr<F: Funge>(
 
 fn clear<F: Funge>(
     ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
 ) -> InstructionResult {
     let m = ip.pop().to_i32().unwrap_or(-1);
     if match m {
@@ -267,3 +307,97 @@ fn clear<F: Funge>(
     }
     InstructionResult::Continue
 }
+
+fn set_attribute<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let m = ip.pop().to_i32().unwrap_or(-1);
+    let a = ip.pop().to_i32().unwrap_or(-1);
+    let attr = match a {
+        1 => Some(nc::A_BOLD()),
+        2 => Some(nc::A_UNDERLINE()),
+        3 => Some(nc::A_REVERSE()),
+        4 => Some(nc::A_BLINK()),
+        5 => Some(nc::A_DIM()),
+        6 => Some(nc::A_STANDOUT()),
+        _ => None,
+    };
+    if match (attr, m) {
+        (None, 0) if a == 0 => nc::attrset(nc::A_NORMAL()),
+        (Some(attr), 0) => nc::attroff(attr),
+        (Some(attr), 1) => nc::attron(attr),
+        _ => ERR,
+    } == ERR
+    {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn init_colour_pair<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let b = ip.pop().to_i16();
+    let f = ip.pop().to_i16();
+    let n = ip.pop().to_i16().filter(|n| *n > 0);
+    if match (n, f, b) {
+        (Some(n), Some(f), Some(b)) if nc::has_colors() => nc::init_pair(n, f, b),
+        _ => ERR,
+    } == ERR
+    {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn use_colour_pair<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let n = ip.pop().to_i16().filter(|n| *n >= 0);
+    if match n {
+        Some(n) if nc::has_colors() => {
+            // Replace the colour bits, but keep the other attributes
+            nc::attroff(nc::A_COLOR());
+            nc::attron(nc::COLOR_PAIR(n))
+        }
+        _ => ERR,
+    } == ERR
+    {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn set_timeout<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let t = ip.pop().to_i32().unwrap_or(-1);
+    nc::timeout(t.max(-1));
+    InstructionResult::Continue
+}
+
+fn screen_size<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let stdscr = get_screen(ip).borrow().stdscr;
+    if let Some(stdscr) = stdscr {
+        let mut h = 0;
+        let mut w = 0;
+        nc::getmaxyx(stdscr, &mut h, &mut w);
+        ip.push(w.into());
+        ip.push(h.into());
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
@@ -75,7 +75,7 @@ pub fn unload<F: Funge>(
     ip.instructions.pop_layer(&['R', 'D'])
 }
 
-fn get_reflist<F: Funge>(ip: &mut InstructionPointer<F>) -> RefMut<Vec<F::Idx>> {
+fn get_reflist<F: Funge>(ip: &mut InstructionPointer<F>) -> RefMut<'_, Vec<F::Idx>> {
     if !ip.private_data.contains_key("REFC.reflist") {
         ip.private_data.insert(
             "REFC.reflist".to_owned(),
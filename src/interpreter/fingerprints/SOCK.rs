@@ -18,12 +18,19 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 #![cfg(not(target_family = "wasm"))]
 
-use std::cell::{RefCell, RefMut};
-use std::io::{Read, Write};
-use std::net::{Ipv4Addr, Shutdown, SocketAddrV4};
+use std::cell::RefCell;
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4};
 use std::os::raw::c_int;
+use std::pin::Pin;
 use std::rc::Rc;
+use std::time::Duration;
 
+use async_io::Async;
+use async_std::net::{TcpListener, TcpStream};
+use futures_lite::future::poll_once;
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
 use hashbrown::HashMap;
 use num::{FromPrimitive, ToPrimitive};
 use socket2::{Domain, Protocol, Socket, Type};
@@ -59,6 +66,13 @@ use crate::InstructionPointer;
 ///     * 4=SO_DONTROUTE
 ///     * 5=SO_BROADCAST
 ///     * 6=OOBINLINE
+///     * 7=non-blocking mode (rfunge extension): A, C, R and W reflect
+///       instead of waiting (C keeps connecting in the background). Sockets
+///       returned by A are blocking.
+///     * 8=receive timeout in milliseconds, 0 for none (rfunge extension):
+///       A and R reflect if nothing arrives in time
+///     * 9=send timeout in milliseconds, 0 for none (rfunge extension):
+///       C and W reflect if they can't finish in time
 ///  - pf:
 ///     * 1=PF_UNIX
 ///     * 2=PF_INET
@@ -82,22 +96,26 @@ use crate::InstructionPointer;
 ///
 /// ct=1 and pf=1 are a broken spec and should not be implemented. Usage of
 /// either of these should reflect.
+///
+/// A, C, R and W are asynchronous: while they wait for the network, the
+/// future returned by [Interpreter::run_async](crate::Interpreter::run_async)
+/// is pending rather than blocking the thread.
 pub fn load<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
     _env: &mut F::Env,
 ) -> bool {
     let mut layer = HashMap::<char, Instruction<F>>::new();
-    layer.insert('A', sync_instruction(accept));
+    layer.insert('A', Instruction::AsyncInstruction(accept));
     layer.insert('B', sync_instruction(bind));
-    layer.insert('C', sync_instruction(connect));
+    layer.insert('C', Instruction::AsyncInstruction(connect));
     layer.insert('I', sync_instruction(ipaddr));
     layer.insert('K', sync_instruction(kill));
     layer.insert('L', sync_instruction(listen));
     layer.insert('O', sync_instruction(setopt));
-    layer.insert('R', sync_instruction(recv));
+    layer.insert('R', Instruction::AsyncInstruction(recv));
     layer.insert('S', sync_instruction(socket_create));
-    layer.insert('W', sync_instruction(write));
+    layer.insert('W', Instruction::AsyncInstruction(write));
     ip.instructions.add_layer(layer);
     true
 }
@@ -111,24 +129,71 @@ pub fn unload<F: Funge>(
         .pop_layer(&"ABCIKLORSW".chars().collect::<Vec<char>>())
 }
 
-fn get_socketlist<F: Funge>(ip: &mut InstructionPointer<F>) -> RefMut<Vec<Option<Socket>>> {
-    if !ip.private_data.contains_key("SOCK.sockets") {
-        ip.private_data.insert(
-            "SOCK.sockets".to_owned(),
-            Rc::new(RefCell::new(Vec::<Option<Socket>>::new())),
-        );
+/// An entry in the socket table
+struct FungeSocket {
+    socket: Socket,
+    /// Set with option 7: give up instead of waiting
+    nonblocking: bool,
+    /// Set with option 8
+    read_timeout: Option<Duration>,
+    /// Set with option 9
+    write_timeout: Option<Duration>,
+}
+
+/// The socket table is shared by all IPs, so a socket created by one IP can
+/// be used by any other
+fn get_socketlist<F: Funge>(ip: &InstructionPointer<F>) -> Rc<RefCell<Vec<Option<FungeSocket>>>> {
+    ip.shared.get_or_default()
+}
+
+/// Duplicate the handle of a socket for an asynchronous operation (so the
+/// socket table isn't borrowed while waiting), along with its non-blocking
+/// flag and the receive or send timeout.
+fn socket_for_io<F: Funge>(
+    ip: &InstructionPointer<F>,
+    sock_id: usize,
+    sending: bool,
+) -> Option<(Socket, bool, Option<Duration>)> {
+    let sockets = get_socketlist(ip);
+    let sl = sockets.borrow();
+    let entry = sl.get(sock_id)?.as_ref()?;
+    let timeout = if sending {
+        entry.write_timeout
+    } else {
+        entry.read_timeout
+    };
+    Some((entry.socket.try_clone().ok()?, entry.nonblocking, timeout))
+}
+
+/// Wait for a socket operation to finish. In non-blocking mode, give up if
+/// it can't finish straight away; otherwise, give up after the timeout.
+async fn wait_for<T>(
+    op: impl Future<Output = io::Result<T>>,
+    nonblocking: bool,
+    timeout: Option<Duration>,
+) -> Option<T> {
+    if nonblocking {
+        poll_once(op).await.and_then(|r| r.ok())
+    } else if let Some(timeout) = timeout {
+        async_std::future::timeout(timeout, op)
+            .await
+            .ok()
+            .and_then(|r| r.ok())
+    } else {
+        op.await.ok()
     }
-    ip.private_data
-        .get("SOCK.sockets")
-        .and_then(|any_ref| any_ref.downcast_ref::<RefCell<Vec<Option<Socket>>>>())
-        .map(|refcell| refcell.borrow_mut())
-        .unwrap()
 }
 
 fn push_socket<F: Funge>(ip: &mut InstructionPointer<F>, socket: Socket) -> usize {
+    let socket = FungeSocket {
+        socket,
+        nonblocking: false,
+        read_timeout: None,
+        write_timeout: None,
+    };
     let mut sock_idx = None;
-    // scope to limit the lifetime of sl
-    let mut sl = get_socketlist(ip);
+    let sockets = get_socketlist(ip);
+    let mut sl = sockets.borrow_mut();
     for (i, s) in sl.iter().enumerate() {
         if s.is_none() {
             sock_idx = Some(i);
@@ -197,10 +262,11 @@ fn kill<F: Funge>(
     };
 
     let success = {
-        let mut sl = get_socketlist(ip);
-        if sock_id <= sl.len() {
+        let sockets = get_socketlist(ip);
+        let mut sl = sockets.borrow_mut();
+        if sock_id < sl.len() {
             if let Some(sock) = &sl[sock_id] {
-                sock.shutdown(Shutdown::Both).ok();
+                sock.socket.shutdown(Shutdown::Both).ok();
             }
             sl[sock_id] = None;
             true
@@ -229,16 +295,22 @@ fn setopt<F: Funge>(
         return InstructionResult::Continue;
     };
     let opt = ip.pop();
-    let flag = ip.pop() != 0.into();
+    let value = ip.pop();
+    let flag = value != 0.into();
+    let timeout = value
+        .to_u64()
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis);
 
     let mut had_error = false;
 
     // Get the socket
-    if let Some(sock) = get_socketlist(ip)
-        .get(sock_id)
-        .map(|o| o.as_ref())
-        .unwrap_or_default()
+    if let Some(entry) = get_socketlist(ip)
+        .borrow_mut()
+        .get_mut(sock_id)
+        .and_then(|o| o.as_mut())
     {
+        let sock = &entry.socket;
         if match opt.to_i32().unwrap_or_default() {
             // 1 => SO_DEBUG not supported
             2 => {
@@ -256,6 +328,21 @@ fn setopt<F: Funge>(
             }
             // 6 => OOBINLINE not supported
             // (though we could if we don't want Redox support)
+            7 => {
+                // Non-blocking mode: anything that would block reflects
+                entry.nonblocking = flag;
+                Some(())
+            }
+            8 => {
+                // Receive timeout
+                entry.read_timeout = timeout;
+                Some(())
+            }
+            9 => {
+                // Send timeout
+                entry.write_timeout = timeout;
+                Some(())
+            }
             _ => None,
         }
         .is_none()
@@ -307,11 +394,12 @@ fn bind<F: Funge>(
 
     // Get the socket
     if let Some(sock) = get_socketlist(ip)
+        .borrow_mut()
         .get(sock_id)
         .map(|o| o.as_ref())
         .unwrap_or_default()
     {
-        success = sock.bind(&addr.into()).is_ok();
+        success = sock.socket.bind(&addr.into()).is_ok();
     }
 
     if !success {
@@ -321,51 +409,72 @@ fn bind<F: Funge>(
     InstructionResult::Continue
 }
 
-fn connect<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    _space: &mut F::Space,
-    _env: &mut F::Env,
-) -> InstructionResult {
-    // get the parameters
-    let addr = ip.pop().to_i32().unwrap_or_default();
-    let port = if let Some(prt16) = ip.pop().to_u16() {
-        prt16
-    } else {
-        ip.reflect();
-        return InstructionResult::Continue;
-    };
-    let ct = ip.pop();
-    let sock_id = if let Some(sock_id_usize) = ip.pop().to_usize() {
-        sock_id_usize
-    } else {
-        ip.reflect();
-        return InstructionResult::Continue;
-    };
+fn connect<'a, F: Funge>(
+    ip: &'a mut InstructionPointer<F>,
+    _space: &'a mut F::Space,
+    _env: &'a mut F::Env,
+) -> Pin<Box<dyn Future<Output = InstructionResult> + 'a>> {
+    Box::pin(async move {
+        // get the parameters
+        let addr = ip.pop().to_i32().unwrap_or_default();
+        let port = if let Some(prt16) = ip.pop().to_u16() {
+            prt16
+        } else {
+            ip.reflect();
+            return InstructionResult::Continue;
+        };
+        let ct = ip.pop();
+        let sock_id = if let Some(sock_id_usize) = ip.pop().to_usize() {
+            sock_id_usize
+        } else {
+            ip.reflect();
+            return InstructionResult::Continue;
+        };
 
-    if ct != 2.into() {
-        // must be AF_INET
-        ip.reflect();
-        return InstructionResult::Continue;
-    }
+        if ct != 2.into() {
+            // must be AF_INET
+            ip.reflect();
+            return InstructionResult::Continue;
+        }
 
-    let addr = SocketAddrV4::new((addr as u32).into(), port);
+        let addr = SocketAddrV4::new((addr as u32).into(), port);
 
-    let mut success = false;
+        let success = match socket_for_io(ip, sock_id, true) {
+            Some((sock, nonblocking, timeout)) => {
+                wait_for(connect_socket(sock, addr), nonblocking, timeout)
+                    .await
+                    .is_some()
+            }
+            None => false,
+        };
 
-    // Get the socket
-    if let Some(sock) = get_socketlist(ip)
-        .get(sock_id)
-        .map(|o| o.as_ref())
-        .unwrap_or_default()
-    {
-        success = sock.connect(&addr.into()).is_ok();
-    }
+        if !success {
+            ip.reflect();
+        }
 
-    if !success {
-        ip.reflect();
-    }
+        InstructionResult::Continue
+    })
+}
 
-    InstructionResult::Continue
+/// Connect without blocking the thread: start connecting, and wait for the
+/// socket to become writable. If this is given up on (in non-blocking mode,
+/// or after the send timeout), the connection is still made in the
+/// background, and a later `C` or `W` can pick it up.
+async fn connect_socket(sock: Socket, addr: SocketAddrV4) -> io::Result<()> {
+    sock.set_nonblocking(true)?;
+    // Usually "in progress"
+    let started = sock.connect(&addr.into());
+    if started.is_ok() {
+        return Ok(());
+    }
+    let stream = Async::new(std::net::TcpStream::from(sock))?;
+    stream.writable().await?;
+    if let Some(e) = stream.get_ref().take_error()? {
+        return Err(e);
+    }
+    // If connecting didn't start at all, the socket is writable (or rather,
+    // hung up) straight away, and this reports the original error
+    stream.get_ref().peer_addr().map(|_| ()).or(started)
 }
 
 fn listen<F: Funge>(
@@ -387,11 +496,12 @@ fn listen<F: Funge>(
 
     // Get the socket
     if let Some(sock) = get_socketlist(ip)
+        .borrow_mut()
         .get(sock_id)
         .map(|o| o.as_ref())
         .unwrap_or_default()
     {
-        success = sock.listen(backlog).is_ok();
+        success = sock.socket.listen(backlog).is_ok();
     }
 
     if !success {
@@ -401,113 +511,125 @@ fn listen<F: Funge>(
     InstructionResult::Continue
 }
 
-fn accept<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    _space: &mut F::Space,
-    _env: &mut F::Env,
-) -> InstructionResult {
-    // get the parameters
-    let sock_id = if let Some(sock_id_usize) = ip.pop().to_usize() {
-        sock_id_usize
-    } else {
-        ip.reflect();
-        return InstructionResult::Continue;
-    };
-
-    let mut success = false;
-
-    let accept_result = get_socketlist(ip)
-        .get(sock_id)
-        .map(|o| o.as_ref())
-        .unwrap_or_default()
-        .and_then(|sock| sock.accept().ok());
-
-    if let Some((client_sock, client_addr)) = accept_result {
-        success = true;
-        let v4_addr = client_addr.as_socket_ipv4().unwrap();
-        ip.push((v4_addr.port() as i32).into());
-        ip.push((u32::from(*v4_addr.ip()) as i32).into());
-        // store the socket
-        let sock_idx = push_socket(ip, client_sock);
-        ip.push(FromPrimitive::from_usize(sock_idx).unwrap());
-    }
+fn accept<'a, F: Funge>(
+    ip: &'a mut InstructionPointer<F>,
+    _space: &'a mut F::Space,
+    _env: &'a mut F::Env,
+) -> Pin<Box<dyn Future<Output = InstructionResult> + 'a>> {
+    Box::pin(async move {
+        // get the parameters
+        let sock_id = if let Some(sock_id_usize) = ip.pop().to_usize() {
+            sock_id_usize
+        } else {
+            ip.reflect();
+            return InstructionResult::Continue;
+        };
 
-    if !success {
-        ip.reflect();
-    }
+        let accept_result = match socket_for_io(ip, sock_id, false) {
+            Some((sock, nonblocking, timeout)) => {
+                let listener = TcpListener::from(std::net::TcpListener::from(sock));
+                wait_for(listener.accept(), nonblocking, timeout).await
+            }
+            None => None,
+        };
+
+        match accept_result.and_then(|(client_sock, client_addr)| {
+            let client_sock = std::net::TcpStream::try_from(client_sock).ok()?;
+            Some((Socket::from(client_sock), client_addr))
+        }) {
+            Some((client_sock, SocketAddr::V4(v4_addr))) => {
+                ip.push((v4_addr.port() as i32).into());
+                ip.push((u32::from(*v4_addr.ip()) as i32).into());
+                // store the socket
+                let sock_idx = push_socket(ip, client_sock);
+                ip.push(FromPrimitive::from_usize(sock_idx).unwrap());
+            }
+            _ => ip.reflect(),
+        }
 
-    InstructionResult::Continue
+        InstructionResult::Continue
+    })
 }
 
-fn recv<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    _env: &mut F::Env,
-) -> InstructionResult {
-    // get the parameters
-    let sock_id = if let Some(sock_id_usize) = ip.pop().to_usize() {
-        sock_id_usize
-    } else {
-        ip.reflect();
-        return InstructionResult::Continue;
-    };
-    let max_count = ip.pop();
-    let mut loc = MotionCmds::pop_vector(ip) + ip.storage_offset;
-    let mut buf = vec![0_u8; max_count.to_usize().unwrap_or_default()];
-
-    let read_result = get_socketlist(ip)
-        .get_mut(sock_id)
-        .map(|o| o.as_ref())
-        .unwrap_or_default()
-        .and_then(|mut sock| sock.read(&mut buf).ok());
-
-    if let Some(count) = read_result {
-        // copy data to fungespace
-        for b in buf[0..count].iter() {
-            space[loc] = (*b as i32).into();
-            loc = loc.one_further();
+fn recv<'a, F: Funge>(
+    ip: &'a mut InstructionPointer<F>,
+    space: &'a mut F::Space,
+    _env: &'a mut F::Env,
+) -> Pin<Box<dyn Future<Output = InstructionResult> + 'a>> {
+    Box::pin(async move {
+        // get the parameters
+        let sock_id = if let Some(sock_id_usize) = ip.pop().to_usize() {
+            sock_id_usize
+        } else {
+            ip.reflect();
+            return InstructionResult::Continue;
+        };
+        let max_count = ip.pop();
+        let mut loc = MotionCmds::pop_vector(ip) + ip.storage_offset;
+        let mut buf = vec![0_u8; max_count.to_usize().unwrap_or_default()];
+
+        let read_result = match socket_for_io(ip, sock_id, false) {
+            Some((sock, nonblocking, timeout)) => {
+                let mut stream = TcpStream::from(std::net::TcpStream::from(sock));
+                wait_for(stream.read(&mut buf), nonblocking, timeout).await
+            }
+            None => None,
+        };
+
+        if let Some(count) = read_result {
+            // copy data to fungespace
+            for b in buf[0..count].iter() {
+                space[loc] = (*b as i32).into();
+                loc = loc.one_further();
+            }
+            ip.push(F::Value::from_usize(count).unwrap_or_else(|| 0.into()));
+        } else {
+            ip.reflect();
         }
-        ip.push(F::Value::from_usize(count).unwrap_or_else(|| 0.into()));
-    } else {
-        ip.reflect();
-    }
 
-    InstructionResult::Continue
+        InstructionResult::Continue
+    })
 }
 
-fn write<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    _env: &mut F::Env,
-) -> InstructionResult {
-    // get the parameters
-    let sock_id = if let Some(sock_id_usize) = ip.pop().to_usize() {
-        sock_id_usize
-    } else {
-        ip.reflect();
-        return InstructionResult::Continue;
-    };
-    let count = ip.pop().to_usize().unwrap_or_default();
-    let mut loc = MotionCmds::pop_vector(ip) + ip.storage_offset;
-    let mut buf = vec![0_u8; count];
-    for elem in buf.iter_mut().take(count) {
-        *elem = (space[loc] & 0xff.into()).to_u8().unwrap_or_default();
-        loc = loc.one_further();
-    }
+fn write<'a, F: Funge>(
+    ip: &'a mut InstructionPointer<F>,
+    space: &'a mut F::Space,
+    _env: &'a mut F::Env,
+) -> Pin<Box<dyn Future<Output = InstructionResult> + 'a>> {
+    Box::pin(async move {
+        // get the parameters
+        let sock_id = if let Some(sock_id_usize) = ip.pop().to_usize() {
+            sock_id_usize
+        } else {
+            ip.reflect();
+            return InstructionResult::Continue;
+        };
+        let count = ip.pop().to_usize().unwrap_or_default();
+        let mut loc = MotionCmds::pop_vector(ip) + ip.storage_offset;
+        let mut buf = vec![0_u8; count];
+        for elem in buf.iter_mut().take(count) {
+            *elem = (space[loc].clone() & 0xff.into())
+                .to_u8()
+                .unwrap_or_default();
+            loc = loc.one_further();
+        }
 
-    let write_result = get_socketlist(ip)
-        .get_mut(sock_id)
-        .map(|o| o.as_ref())
-        .unwrap_or_default()
-        .and_then(|mut sock| sock.write_all(&buf).ok());
+        let write_result = match socket_for_io(ip, sock_id, true) {
+            Some((sock, nonblocking, timeout)) => {
+                let mut stream = TcpStream::from(std::net::TcpStream::from(sock));
+                wait_for(stream.write_all(&buf), nonblocking, timeout).await
+            }
+            None => None,
+        };
 
-    if write_result.is_some() {
-        ip.push(FromPrimitive::from_usize(buf.len()).unwrap_or_else(|| 0.into()));
-    } else {
-        ip.reflect();
-    }
+        if write_result.is_some() {
+            ip.push(FromPrimitive::from_usize(buf.len()).unwrap_or_else(|| 0.into()));
+        } else {
+            ip.reflect();
+        }
 
-    InstructionResult::Continue
+        InstructionResult::Continue
+    })
 }
 
 fn ipaddr<F: Funge>(
@@ -526,3 +648,144 @@ fn ipaddr<F: Funge>(
 
     InstructionResult::Continue
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::fungespace::bfvec;
+    use crate::interpreter::fingerprints::string_to_fingerprint;
+    use crate::interpreter::tests::{befunge_with, NoEnv, TestInterpreter};
+    use crate::interpreter::{ProgramResult, RunMode};
+
+    fn sock_interpreter(src: &str) -> TestInterpreter<NoEnv> {
+        let mut env = NoEnv::new();
+        env.extra_fingerprints = vec![string_to_fingerprint("SOCK")];
+        befunge_with(src, env)
+    }
+
+    #[test]
+    fn test_sock_shared_between_ips() {
+        use std::io::Read;
+        use std::net::{Ipv4Addr, TcpListener, TcpStream};
+        use std::thread;
+        use std::time::Duration;
+
+        // Find a free port for the program to listen on
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|l| l.local_addr())
+            .unwrap()
+            .port();
+
+        // The IP that accepts the connection puts the new socket's id at
+        // (0, 2); the IP it forked waits for that and writes "hi" (from
+        // (0, 4)) to the socket. The port is at (0, 3).
+        let src = concat!(
+            "\"KCOS\"4(221S:203g0\"1.0.0.721\"IB:1\\L#vtA02p@\n",
+            "\n",
+            "\n",
+            "\n",
+            "hi\n",
+            "                                            >04202gW@\n",
+            "                                    >02g\" \"-|\n",
+            "                                    ^       <\n",
+        );
+        let mut interpreter = sock_interpreter(src);
+        interpreter.space[bfvec(0, 3)] = port as i64;
+
+        let client = thread::spawn(move || {
+            for _ in 0..3000 {
+                if let Ok(mut stream) = TcpStream::connect((Ipv4Addr::LOCALHOST, port)) {
+                    let mut buf = [0; 2];
+                    stream.read_exact(&mut buf).unwrap();
+                    return buf;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            panic!("could not connect");
+        });
+
+        // The forked IP spins until the connection has been accepted, so
+        // leave plenty of room for a slow client
+        assert_eq!(
+            interpreter.run(RunMode::Limited(10_000_000)),
+            ProgramResult::Done(0)
+        );
+        assert_eq!(&client.join().unwrap(), b"hi");
+    }
+
+    #[test]
+    fn test_sock_nonblocking_and_timeout() {
+        use std::net::{Ipv4Addr, TcpListener};
+
+        // Listen on any port, switch to non-blocking mode, and accept: with
+        // nobody connecting, A reflects onto the @ it jumped over
+        let mut interpreter = sock_interpreter("\"KCOS\"4(221S0200\"1.0.0.721\"IB10L170O0#@A1q");
+        assert_eq!(
+            interpreter.run(RunMode::Limited(1000)),
+            ProgramResult::Done(0)
+        );
+
+        // Connect to a server that never sends anything, with a receive
+        // timeout of 50 ms: R reflects
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut interpreter =
+            sock_interpreter("\"KCOS\"4(221S0201g0\"1.0.0.721\"IC55*2*80O0250#@R1q");
+        interpreter.space[bfvec(0, 1)] = server.local_addr().unwrap().port() as i64;
+        assert_eq!(
+            interpreter.run(RunMode::Limited(1000)),
+            ProgramResult::Done(0)
+        );
+    }
+
+    #[test]
+    fn test_sock_connect_refused() {
+        use std::net::{Ipv4Addr, TcpListener};
+
+        // A port that nobody is listening on (any more)
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|l| l.local_addr())
+            .unwrap()
+            .port();
+
+        // C reflects onto the @ it jumped over: blocking, with a send
+        // timeout of 50 ms, and in non-blocking mode
+        for options in &["", "55*2*90O", "170O"] {
+            let src = format!("\"KCOS\"4(221S{}0201g0\"1.0.0.721\"I#@C1q", options);
+            let mut interpreter = sock_interpreter(&src);
+            interpreter.space[bfvec(0, 1)] = port as i64;
+            assert_eq!(
+                interpreter.run(RunMode::Limited(1000)),
+                ProgramResult::Done(0),
+                "{:?}",
+                options
+            );
+        }
+
+        // ... and it doesn't when there is someone listening
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut interpreter = sock_interpreter("\"KCOS\"4(221S0201g0\"1.0.0.721\"I#@C1q");
+        interpreter.space[bfvec(0, 1)] = server.local_addr().unwrap().port() as i64;
+        assert_eq!(
+            interpreter.run(RunMode::Limited(1000)),
+            ProgramResult::Done(1)
+        );
+    }
+
+    #[test]
+    fn test_sock_async_accept() {
+        use futures_lite::future::{block_on, poll_once};
+        use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|l| l.local_addr())
+            .unwrap()
+            .port();
+        let mut interpreter = sock_interpreter("\"KCOS\"4(221S0201g0\"1.0.0.721\"IB10L0A@");
+        interpreter.space[bfvec(0, 1)] = port as i64;
+
+        // Waiting for a connection doesn't block the thread
+        let mut run = Box::pin(interpreter.run_async(RunMode::Limited(1000)));
+        assert!(block_on(poll_once(&mut run)).is_none());
+        let _client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert_eq!(block_on(run), ProgramResult::Done(0));
+    }
+}
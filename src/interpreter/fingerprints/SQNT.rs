@@ -0,0 +1,112 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use hashbrown::HashMap;
+use num::ToPrimitive;
+
+use crate::interpreter::{
+    instruction_set::{sync_instruction, Instruction},
+    Funge, InstructionPointer, InstructionResult, InterpreterEnv,
+};
+
+/// RFunge-specific sequencer fingerprint
+///
+/// "SQNT" 0x53514e54
+/// N (n d -- )     Play MIDI note n (60 = middle C, 69 = A 440 Hz) for d
+///                 milliseconds
+/// R (d -- )       Rest (stay silent) for d milliseconds
+/// T (f d -- )     Play a tone with frequency f Hz for d milliseconds
+///
+/// Notes are played one after the other: the next note starts once the
+/// previous note (or rest) has finished.
+///
+/// All instructions act as r if the environment cannot produce sound (see
+/// [InterpreterEnv::play_tone]) or if the arguments are out of range.
+pub fn load<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> bool {
+    let mut layer = HashMap::<char, Instruction<F>>::new();
+    layer.insert('N', sync_instruction(play_note));
+    layer.insert('R', sync_instruction(rest));
+    layer.insert('T', sync_instruction(play_tone));
+    ip.instructions.add_layer(layer);
+    true
+}
+
+pub fn unload<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> bool {
+    ip.instructions.pop_layer(&['N', 'R', 'T'])
+}
+
+/// Frequency of a MIDI note number in equal temperament
+fn midi_note_frequency(note: i32) -> f32 {
+    440.0 * 2.0_f32.powf((note - 69) as f32 / 12.0)
+}
+
+fn play_note<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    let duration = ip.pop().to_u32();
+    let note = ip.pop().to_i32().filter(|n| (0..128).contains(n));
+    if !match (note, duration) {
+        (Some(n), Some(d)) => env.play_tone(midi_note_frequency(n), d),
+        _ => false,
+    } {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn rest<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if !ip
+        .pop()
+        .to_u32()
+        .map(|d| env.play_tone(0.0, d))
+        .unwrap_or(false)
+    {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn play_tone<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    let duration = ip.pop().to_u32();
+    let freq = ip.pop().to_u32().filter(|f| *f > 0);
+    if !match (freq, duration) {
+        (Some(f), Some(d)) => env.play_tone(f as f32, d),
+        _ => false,
+    } {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
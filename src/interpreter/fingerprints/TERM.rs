@@ -21,9 +21,10 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 use std::io::stdout;
 
 use crossterm::{
-    cursor::{MoveDown, MoveTo, MoveUp},
+    cursor::{MoveDown, MoveTo, MoveUp, RestorePosition, SavePosition},
     execute,
-    terminal::{Clear, ClearType},
+    style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType},
     ExecutableCommand,
 };
 use hashbrown::HashMap;
@@ -45,6 +46,21 @@ use crate::interpreter::{
 /// S   ( -- )  Clear to end of screen
 /// U   ( n -- )    Move cursor up n lines
 ///
+/// RFunge extensions:
+///
+/// B   (c -- )     Set the background colour
+/// F   (c -- )     Set the foreground colour
+/// N   ( -- )      Reset to the normal (default) colours
+/// P   ( -- )      Save the cursor position
+/// R   ( -- )      Restore the cursor position saved with P
+/// W   ( -- w h)   Get the width and height of the terminal
+///
+/// Colours c are indices into the 256-colour ANSI palette (0-7 are the
+/// standard colours, 8-15 their bright variants); -1 is the terminal default.
+///
+/// All instructions act as r if writing to (or querying) the terminal fails
+/// or the arguments are out of range.
+///
 pub fn load<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
@@ -58,6 +74,12 @@ pub fn load<F: Funge>(
     layer.insert('L', sync_instruction(clear_to_eol));
     layer.insert('S', sync_instruction(clear_to_eos));
     layer.insert('U', sync_instruction(up));
+    layer.insert('B', sync_instruction(background));
+    layer.insert('F', sync_instruction(foreground));
+    layer.insert('N', sync_instruction(reset_colours));
+    layer.insert('P', sync_instruction(save_position));
+    layer.insert('R', sync_instruction(restore_position));
+    layer.insert('W', sync_instruction(size));
 
     ip.instructions.add_layer(layer);
     true
@@ -68,8 +90,9 @@ pub fn unload<F: Funge>(
     _space: &mut F::Space,
     _env: &mut F::Env,
 ) -> bool {
-    ip.instructions
-        .pop_layer(&['C', 'D', 'G', 'H', 'L', 'S', 'U'])
+    ip.instructions.pop_layer(&[
+        'C', 'D', 'G', 'H', 'L', 'S', 'U', 'B', 'F', 'N', 'P', 'R', 'W',
+    ])
 }
 
 fn clear_screen<F: Funge>(
@@ -162,3 +185,89 @@ fn up<F: Funge>(
     .unwrap_or_else(|| ip.reflect());
     InstructionResult::Continue
 }
+
+fn to_colour(c: i32) -> Option<Color> {
+    match c {
+        -1 => Some(Color::Reset),
+        0..=255 => Some(Color::AnsiValue(c as u8)),
+        _ => None,
+    }
+}
+
+fn background<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    (|| -> Option<()> {
+        let mut stdout = stdout();
+        let c = to_colour(ip.pop().to_i32()?)?;
+        execute!(stdout, SetBackgroundColor(c)).ok()
+    })()
+    .unwrap_or_else(|| ip.reflect());
+    InstructionResult::Continue
+}
+
+fn foreground<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    (|| -> Option<()> {
+        let mut stdout = stdout();
+        let c = to_colour(ip.pop().to_i32()?)?;
+        execute!(stdout, SetForegroundColor(c)).ok()
+    })()
+    .unwrap_or_else(|| ip.reflect());
+    InstructionResult::Continue
+}
+
+fn reset_colours<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let mut stdout = stdout();
+    if stdout.execute(ResetColor).is_err() {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn save_position<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let mut stdout = stdout();
+    if stdout.execute(SavePosition).is_err() {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn restore_position<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let mut stdout = stdout();
+    if stdout.execute(RestorePosition).is_err() {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn size<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    if let Ok((w, h)) = terminal::size() {
+        ip.push((w as i32).into());
+        ip.push((h as i32).into());
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
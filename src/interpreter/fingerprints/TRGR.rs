@@ -0,0 +1,214 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::cell::{RefCell, RefMut};
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+use crate::interpreter::instruction_set::{sync_instruction, Instruction, InstructionResult};
+use crate::interpreter::Funge;
+use crate::interpreter::MotionCmds;
+use crate::InstructionPointer;
+
+/// Shared state of the TRGR fingerprint
+struct TriggerTable<F: Funge> {
+    /// Registered triggers: (watched cell, IP ID, handler location)
+    triggers: Vec<(F::Idx, F::Value, F::Idx)>,
+    /// Triggers that have fired, but not yet been handled by their IP:
+    /// (IP ID, handler location, watched cell)
+    pending: Vec<(F::Value, F::Idx, F::Idx)>,
+}
+
+impl<F: Funge> TriggerTable<F> {
+    /// Remove all triggers registered by, and pending for, the IP `ip_id`
+    fn forget_ip(&mut self, ip_id: &F::Value) {
+        self.triggers.retain(|(_, id, _)| id != ip_id);
+        self.pending.retain(|(id, _, _)| id != ip_id);
+    }
+}
+
+/// RFunge-specific trigger fingerprint
+///
+/// "TRGR" 0x54524752
+/// R (Vc Vh -- )   Register a trigger: when the cell Vc is written to with
+///                 `p`, this IP is diverted to Vh
+/// U (Vc -- )      Unregister this IP's trigger(s) on the cell Vc
+/// C ( -- )        Clear all of this IP's triggers
+///
+/// Both Vc and Vh are relative to the storage offset at the time `R` is
+/// executed.
+///
+/// When a trigger fires, the IP that registered it continues at the handler
+/// location Vh (keeping its delta) on its next tick, and finds the absolute
+/// location of the cell that was written on top of its stack. If the same
+/// cell is watched by several IPs, all of them are diverted.
+///
+/// `U` acts like `r` if there is no trigger on Vc.
+///
+/// An IP's triggers are removed when it stops, even if they have fired.
+///
+/// The trigger table is shared between an IP and the IPs forked off it after
+/// loading TRGR, but IPs that load TRGR independently get separate tables (as
+/// with REFC). Only writes by IPs that share the table fire the triggers.
+pub fn load<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> bool {
+    if !ip.private_data.contains_key("TRGR.triggers") {
+        ip.private_data.insert(
+            "TRGR.triggers".to_owned(),
+            Rc::new(RefCell::new(TriggerTable::<F> {
+                triggers: Vec::new(),
+                pending: Vec::new(),
+            })),
+        );
+    }
+    let mut layer = HashMap::<char, Instruction<F>>::new();
+    layer.insert('R', sync_instruction(register));
+    layer.insert('U', sync_instruction(unregister));
+    layer.insert('C', sync_instruction(clear));
+    ip.instructions.add_layer(layer);
+    true
+}
+
+pub fn unload<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> bool {
+    ip.instructions.pop_layer(&['R', 'U', 'C'])
+}
+
+fn get_table<F: Funge>(ip: &InstructionPointer<F>) -> Option<RefMut<'_, TriggerTable<F>>> {
+    ip.private_data
+        .get("TRGR.triggers")
+        .and_then(|any_ref| any_ref.downcast_ref::<RefCell<TriggerTable<F>>>())
+        .map(|refcell| refcell.borrow_mut())
+}
+
+/// Called after `ip` has written to the cell `loc` using `p`
+pub(super) fn cell_written<F: Funge>(ip: &InstructionPointer<F>, loc: F::Idx) {
+    if let Some(mut table) = get_table(ip) {
+        let TriggerTable { triggers, pending } = &mut *table;
+        for (cell, ip_id, handler) in triggers.iter() {
+            if *cell == loc {
+                pending.push((ip_id.clone(), *handler, *cell));
+            }
+        }
+    }
+}
+
+/// Called after `ip` has stopped: its triggers, including any that have
+/// fired but not been handled, are removed
+pub(super) fn ip_removed<F: Funge>(ip: &InstructionPointer<F>) {
+    if let Some(mut table) = get_table(ip) {
+        table.forget_ip(&ip.id);
+    }
+}
+
+/// Called before `ip` moves; diverts the IP if one of its triggers has fired
+pub(super) fn divert_if_triggered<F: Funge>(ip: &mut InstructionPointer<F>) {
+    let fired = if let Some(mut table) = get_table(ip) {
+        match table.pending.iter().position(|(id, _, _)| *id == ip.id) {
+            Some(idx) => Some(table.pending.remove(idx)),
+            None => None,
+        }
+    } else {
+        None
+    };
+    if let Some((_, handler, cell)) = fired {
+        ip.location = handler - ip.delta;
+        MotionCmds::push_vector(ip, cell);
+    }
+}
+
+fn register<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let handler = MotionCmds::pop_vector(ip) + ip.storage_offset;
+    let cell = MotionCmds::pop_vector(ip) + ip.storage_offset;
+    let ip_id = ip.id.clone();
+    let registered = if let Some(mut table) = get_table(ip) {
+        table.triggers.push((cell, ip_id, handler));
+        true
+    } else {
+        false
+    };
+    if !registered {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn unregister<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    let cell = MotionCmds::pop_vector(ip) + ip.storage_offset;
+    let ip_id = ip.id.clone();
+    let removed_any = if let Some(mut table) = get_table(ip) {
+        let n_before = table.triggers.len();
+        table
+            .triggers
+            .retain(|(c, id, _)| !(*c == cell && *id == ip_id));
+        table.triggers.len() != n_before
+    } else {
+        false
+    };
+    if !removed_any {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn clear<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(mut table) = get_table(ip) {
+        table.forget_ip(&ip.id);
+    }
+    InstructionResult::Continue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fungespace::bfvec;
+    use crate::interpreter::tests::befunge;
+    use crate::interpreter::{ProgramResult, RunMode};
+
+    #[test]
+    fn test_stopped_ip_is_forgotten() {
+        // The IP forked by t registers a trigger on (0, 5) and stops in the
+        // same tick as its parent writes to (0, 5)
+        let mut interpreter = befunge("\"RGRT\"4(0505v\n           @>#Rtzzpzz@");
+        assert_eq!(interpreter.run(RunMode::Limited(19)), ProgramResult::Paused);
+        assert_eq!(interpreter.ips.len(), 1);
+        assert_eq!(interpreter.space[bfvec(0, 5)], 5);
+        let table = get_table(&interpreter.ips[0]).unwrap();
+        assert!(table.triggers.is_empty());
+        assert!(table.pending.is_empty());
+    }
+}
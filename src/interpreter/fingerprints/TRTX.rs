@@ -0,0 +1,371 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use hashbrown::HashMap;
+
+use num::ToPrimitive;
+
+use super::string_to_fingerprint;
+use super::TURT::TurtleRobotBox;
+use crate::interpreter::instruction_set::{sync_instruction, Instruction, InstructionResult};
+use crate::interpreter::{Funge, InstructionPointer, InterpreterEnv};
+
+/// rfunge extensions to TURT
+///
+/// ### Fingerprint 0x54525458 ('TRTX')
+///
+/// TRTX adds instructions for the turtle of the TURT fingerprint (which
+/// does not have to be loaded as well, but the environment has to support
+/// it). It draws with the same turtle on the same display.
+///
+/// There can be more than one turtle. Each has its own position, heading,
+/// pen and colour, and the TURT and TRTX instructions act on the selected
+/// one. They all draw on the same paper.
+///
+/// These instructions pop one value off the stack:
+///
+/// -   `J` 'Select Turtle' (turtle id; reflects if there is no such turtle)
+/// -   `W` 'Pen Width' (in pixels; at least 1)
+/// -   `S` 'Set Heading' (angle in thousandths of a degree)
+/// -   `Y` 'Turn Left' (angle in thousandths of a degree)
+/// -   `Z` 'Turn Right' (angle in thousandths of a degree)
+///
+/// These push one value each:
+///
+/// -   `G` 'Query Heading' (angle in thousandths of a degree)
+/// -   `M` 'New Turtle': add a turtle at the origin with its pen up, and push
+///     its id. The original turtle is turtle 0. The new turtle is not
+///     selected.
+/// -   `K` 'Query Turtle': the id of the selected turtle
+///
+/// And these don't use the stack:
+///
+/// -   `O` 'Begin Fill': start a polygon at the turtle's position. Every
+///     place the turtle moves to (with `F`, `B` or `T`, whether the pen is
+///     up or down) becomes a corner.
+/// -   `X` 'End Fill': fill the polygon in the pen colour
+pub fn load<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> bool {
+    // Do we have TURT support from the environment?
+    if env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_ref::<TurtleRobotBox>())
+        .is_none()
+    {
+        false
+    } else {
+        let mut layer = HashMap::<char, Instruction<F>>::new();
+        layer.insert('W', sync_instruction(pen_width));
+        layer.insert('S', sync_instruction(set_heading));
+        layer.insert('Y', sync_instruction(turn_left));
+        layer.insert('Z', sync_instruction(turn_right));
+        layer.insert('G', sync_instruction(query_heading));
+        layer.insert('O', sync_instruction(begin_fill));
+        layer.insert('X', sync_instruction(end_fill));
+        layer.insert('M', sync_instruction(new_turtle));
+        layer.insert('J', sync_instruction(select_turtle));
+        layer.insert('K', sync_instruction(query_turtle));
+        ip.instructions.add_layer(layer);
+        true
+    }
+}
+
+pub fn unload<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    _env: &mut F::Env,
+) -> bool {
+    ip.instructions
+        .pop_layer(&"WSYZGOXMJK".chars().collect::<Vec<char>>())
+}
+
+fn pop_millidegrees<F: Funge>(ip: &mut InstructionPointer<F>) -> f64 {
+    ip.pop().to_i32().unwrap_or_default() as f64 / 1000.
+}
+
+fn pen_width<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_mut::<TurtleRobotBox>())
+    {
+        let width = ip.pop().to_i32().unwrap_or_default();
+        robot.set_pen_width(width);
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn set_heading<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_mut::<TurtleRobotBox>())
+    {
+        robot.set_heading_precise(pop_millidegrees(ip));
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn turn_left<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_mut::<TurtleRobotBox>())
+    {
+        robot.turn_left_precise(pop_millidegrees(ip));
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn turn_right<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_mut::<TurtleRobotBox>())
+    {
+        robot.turn_left_precise(-pop_millidegrees(ip));
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn query_heading<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_ref::<TurtleRobotBox>())
+    {
+        let millidegrees = (robot.heading_precise() * 1000.).round() as i32;
+        ip.push(millidegrees.into());
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn begin_fill<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_mut::<TurtleRobotBox>())
+    {
+        robot.begin_fill();
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn end_fill<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_mut::<TurtleRobotBox>())
+    {
+        robot.end_fill();
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn new_turtle<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_mut::<TurtleRobotBox>())
+    {
+        ip.push(robot.new_turtle().into());
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn select_turtle<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_mut::<TurtleRobotBox>())
+    {
+        let id = ip.pop().to_i32().unwrap_or(-1);
+        if !robot.select_turtle(id) {
+            ip.reflect();
+        }
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+fn query_turtle<F: Funge>(
+    ip: &mut InstructionPointer<F>,
+    _space: &mut F::Space,
+    env: &mut F::Env,
+) -> InstructionResult {
+    if let Some(robot) = env
+        .fingerprint_support_library(string_to_fingerprint("TURT"))
+        .and_then(|lib| lib.downcast_ref::<TurtleRobotBox>())
+    {
+        ip.push(robot.current_turtle().into());
+    } else {
+        ip.reflect();
+    }
+    InstructionResult::Continue
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::fungespace::bfvec;
+    use crate::interpreter::fingerprints::TURT::{
+        Colour, Dot, Fill, Line, Point, SimpleRobot, TurtleDisplay, VecDisplay,
+    };
+    use crate::interpreter::tests::{befunge_with, NoEnv, TestInterpreter};
+    use crate::interpreter::{ProgramResult, RunMode};
+
+    /// Lets the test look at the drawing while the robot owns the display
+    struct SharedDisplay(Rc<RefCell<VecDisplay>>);
+
+    impl TurtleDisplay for SharedDisplay {
+        fn display(&mut self, show: bool) {
+            self.0.borrow_mut().display(show);
+        }
+        fn display_visible(&self) -> bool {
+            self.0.borrow().display_visible()
+        }
+        fn draw(
+            &mut self,
+            turtle: i32,
+            background: Option<Colour>,
+            fills: &[Fill],
+            lines: &[Line],
+            dots: &[Dot],
+        ) {
+            self.0
+                .borrow_mut()
+                .draw(turtle, background, fills, lines, dots);
+        }
+        fn print(
+            &mut self,
+            background: Option<Colour>,
+            fills: &[Fill],
+            lines: &[Line],
+            dots: &[Dot],
+        ) {
+            self.0.borrow_mut().print(background, fills, lines, dots);
+        }
+    }
+
+    /// Run `src` (after loading TURT and TRTX) with a turtle, and return
+    /// the interpreter and the drawing
+    fn run_turtle(src: &str) -> (TestInterpreter<NoEnv>, Rc<RefCell<VecDisplay>>) {
+        let display = Rc::new(RefCell::new(VecDisplay::new()));
+        let mut env = NoEnv::new();
+        env.extra_fingerprints = vec![string_to_fingerprint("TURT"), string_to_fingerprint("TRTX")];
+        env.turtle = Some(SimpleRobot::new_in_box(SharedDisplay(display.clone())));
+        let mut interpreter = befunge_with(&format!(r#""TRUT"4("XTRT"4({}"#, src), env);
+        assert_eq!(
+            interpreter.run(RunMode::Limited(1000)),
+            ProgramResult::Done(0)
+        );
+        (interpreter, display)
+    }
+
+    #[test]
+    fn test_pen_width() {
+        // Widths below 1 count as 1
+        let (_, display) = run_turtle("1P5WaF0WaF@");
+        let display = display.borrow();
+        let widths: Vec<_> = display.lines.iter().map(|l| l.width).collect();
+        assert_eq!(widths, vec![5, 1]);
+        assert_eq!(display.lines[1].to, Point { x: 20, y: 0 });
+    }
+
+    #[test]
+    fn test_sub_degree_heading() {
+        // Heading 1.5°, then turn right and left by 0.5°, storing the
+        // heading after each step in row 1
+        let (interpreter, display) = run_turtle("1Paa*f*SG01paa*a*Faa*5*ZG11paa*5*YG21p@");
+        assert_eq!(interpreter.space[bfvec(0, 1)], 1500);
+        assert_eq!(interpreter.space[bfvec(1, 1)], 2000);
+        assert_eq!(interpreter.space[bfvec(2, 1)], 1500);
+        // 1000 * (cos 1.5°, sin 1.5°), rounded
+        assert_eq!(display.borrow().lines[0].to, Point { x: 1000, y: 26 });
+    }
+
+    #[test]
+    fn test_fill() {
+        // A triangle, drawn with the pen up, and a "polygon" with only two
+        // corners, which isn't filled
+        let (_, display) = run_turtle("OaF9a*LaFXOaFX@");
+        let display = display.borrow();
+        assert!(display.lines.is_empty());
+        assert_eq!(
+            display.fills,
+            vec![Fill {
+                points: vec![
+                    Point { x: 0, y: 0 },
+                    Point { x: 10, y: 0 },
+                    Point { x: 10, y: -10 },
+                ],
+                colour: Colour { r: 0, g: 0, b: 0 },
+            }]
+        );
+    }
+}
@@ -28,7 +28,7 @@ use crate::interpreter::instruction_set::{sync_instruction, Instruction, Instruc
 use crate::interpreter::{Funge, InstructionPointer, InterpreterEnv};
 
 #[cfg_attr(target_family = "wasm", derive(Serialize, Deserialize))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Colour {
     pub r: u8,
     pub g: u8,
@@ -36,25 +36,37 @@ pub struct Colour {
 }
 
 #[cfg_attr(target_family = "wasm", derive(Serialize, Deserialize))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Point {
     pub x: i32,
     pub y: i32,
 }
 
 #[cfg_attr(target_family = "wasm", derive(Serialize, Deserialize))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Line {
     pub from: Point,
     pub to: Point,
     pub colour: Colour,
+    /// Pen width in pixels
+    pub width: i32,
 }
 
 #[cfg_attr(target_family = "wasm", derive(Serialize, Deserialize))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Dot {
     pub pos: Point,
     pub colour: Colour,
+    /// Pen width (i.e., diameter) in pixels
+    pub width: i32,
+}
+
+/// A filled polygon (see the TRTX fingerprint)
+#[cfg_attr(target_family = "wasm", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fill {
+    pub points: Vec<Point>,
+    pub colour: Colour,
 }
 
 /// Trait for a general turtle robot implementation
@@ -64,7 +76,20 @@ pub trait TurtleRobot {
     fn turn_left(&mut self, degrees: i32);
     fn set_heading(&mut self, degrees: i32);
     fn heading(&self) -> i32;
+    /// Turn left by a fraction of a degree
+    fn turn_left_precise(&mut self, degrees: f64);
+    fn set_heading_precise(&mut self, degrees: f64);
+    /// The heading, without rounding to whole degrees
+    fn heading_precise(&self) -> f64;
     fn set_pen(&mut self, down: bool);
+    fn set_pen_width(&mut self, pixels: i32);
+    fn pen_width(&self) -> i32;
+    /// Start a polygon at the current position. Every place the turtle goes
+    /// until [TurtleRobot::end_fill] is a corner.
+    fn begin_fill(&mut self);
+    /// Fill the polygon started with [TurtleRobot::begin_fill] in the pen
+    /// colour
+    fn end_fill(&mut self);
     fn is_pen_down(&self) -> bool;
     fn forward(&mut self, pixels: i32);
     fn set_colour(&mut self, rgb: Colour);
@@ -74,28 +99,100 @@ pub trait TurtleRobot {
     fn position(&self) -> Point;
     fn bounds(&self) -> (Point, Point);
     fn print(&mut self);
+    /// Add a turtle (at the origin, with its pen up) and return its id. The
+    /// first turtle is turtle 0.
+    fn new_turtle(&mut self) -> i32;
+    /// Choose the turtle the other methods act on. Returns false if there is
+    /// no turtle with this id.
+    fn select_turtle(&mut self, id: i32) -> bool;
+    fn current_turtle(&self) -> i32;
 }
 
 /// Trait for a typical graphical display (could also be a bitmap of vector graphic)
 /// used by the virtual turtle
+///
+/// Fills are drawn first, then lines, then dots. All turtles draw onto the
+/// same display; `draw` is told which one caused the update.
 pub trait TurtleDisplay {
     fn display(&mut self, show: bool);
     fn display_visible(&self) -> bool;
-    fn draw(&mut self, background: Option<Colour>, lines: &[Line], dots: &[Dot]);
-    fn print(&mut self, background: Option<Colour>, lines: &[Line], dots: &[Dot]);
+    fn draw(
+        &mut self,
+        turtle: i32,
+        background: Option<Colour>,
+        fills: &[Fill],
+        lines: &[Line],
+        dots: &[Dot],
+    );
+    fn print(&mut self, background: Option<Colour>, fills: &[Fill], lines: &[Line], dots: &[Dot]);
 }
 
 /// Struct implementing TurtleRobot for a generic graphical output
+///
+/// There can be any number of turtles, all drawing on the same display.
 pub struct SimpleRobot<D: TurtleDisplay> {
     display: D,
+    fills: Vec<Fill>,
     lines: Vec<Line>,
     dots: Vec<Dot>,
-    heading: i32,
+    background: Option<Colour>,
+    turtles: Vec<Turtle>,
+    current: usize,
+}
+
+/// The state of one turtle in a [SimpleRobot]
+#[derive(Debug, Clone)]
+struct Turtle {
+    heading: f64,
     position: Point,
     pen_down: bool,
+    pen_width: i32,
     colour: Colour,
-    background: Option<Colour>,
     have_drawn: bool,
+    /// Corners of the polygon being filled, if any
+    fill_points: Option<Vec<Point>>,
+}
+
+impl Default for Turtle {
+    fn default() -> Self {
+        Self {
+            heading: 0.,
+            position: Point { x: 0, y: 0 },
+            pen_down: false,
+            pen_width: 1,
+            colour: Colour { r: 0, g: 0, b: 0 },
+            have_drawn: false,
+            fill_points: None,
+        }
+    }
+}
+
+impl Turtle {
+    fn current_dot(&self) -> Dot {
+        Dot {
+            pos: self.position,
+            colour: self.colour,
+            width: self.pen_width,
+        }
+    }
+
+    /// Go to `dest`, adding a corner to the polygon being filled
+    fn move_to(&mut self, dest: Point) {
+        self.position = dest;
+        if let Some(points) = self.fill_points.as_mut() {
+            points.push(dest);
+        }
+    }
+
+    /// A turtle with its pen down that hasn't drawn anything yet shows up
+    /// as a dot
+    fn pending_dot(&self) -> Option<Dot> {
+        if self.pen_down && !self.have_drawn {
+            Some(self.current_dot())
+        } else {
+            None
+        }
+    }
 }
 
 /// Type expected from env.fingerprint_support_library()
@@ -105,33 +202,48 @@ impl<D: TurtleDisplay> SimpleRobot<D> {
     pub fn new(display: D) -> Self {
         Self {
             display,
+            fills: vec![],
             lines: vec![],
             dots: vec![],
-            heading: 0,
-            position: Point { x: 0, y: 0 },
-            pen_down: false,
-            colour: Colour { r: 0, g: 0, b: 0 },
             background: None,
-            have_drawn: false,
+            turtles: vec![Turtle::default()],
+            current: 0,
         }
     }
 
+    fn turtle(&self) -> &Turtle {
+        &self.turtles[self.current]
+    }
+
+    fn turtle_mut(&mut self) -> &mut Turtle {
+        &mut self.turtles[self.current]
+    }
+
     fn redraw(&mut self, print: bool) {
         if print || self.display.display_visible() {
             let mut all_dots;
             let mut dots: &[Dot] = &self.dots;
-            if self.pen_down && !self.have_drawn {
+            let pending: Vec<Dot> = self
+                .turtles
+                .iter()
+                .filter_map(Turtle::pending_dot)
+                .collect();
+            if !pending.is_empty() {
                 all_dots = Some(self.dots.clone());
-                all_dots.as_mut().unwrap().push(Dot {
-                    pos: self.position,
-                    colour: self.colour,
-                });
+                all_dots.as_mut().unwrap().extend(pending);
                 dots = all_dots.as_ref().unwrap();
             }
             if print {
-                self.display.print(self.background, &self.lines, dots);
+                self.display
+                    .print(self.background, &self.fills, &self.lines, dots);
             } else {
-                self.display.draw(self.background, &self.lines, dots);
+                self.display.draw(
+                    self.current as i32,
+                    self.background,
+                    &self.fills,
+                    &self.lines,
+                    dots,
+                );
             }
         }
     }
@@ -143,6 +255,68 @@ impl<D: TurtleDisplay + 'static> SimpleRobot<D> {
     }
 }
 
+impl<D: TurtleDisplay> SimpleRobot<D> {
+    pub fn get_display(&self) -> &D {
+        &self.display
+    }
+
+    pub fn get_display_mut(&mut self) -> &mut D {
+        &mut self.display
+    }
+}
+
+/// TurtleDisplay that simply keeps the latest drawing, for running TURT
+/// programs without a GUI. Use [VecDisplay::to_svg] to get an image.
+#[derive(Debug, Clone, Default)]
+pub struct VecDisplay {
+    pub background: Option<Colour>,
+    pub fills: Vec<Fill>,
+    pub lines: Vec<Line>,
+    pub dots: Vec<Dot>,
+    /// Has the program asked for the display to be shown?
+    pub shown: bool,
+    /// Every image printed with `I`, as SVG
+    pub printed: Vec<String>,
+}
+
+impl VecDisplay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render the current drawing as SVG
+    pub fn to_svg(&self) -> String {
+        render_turt_to_svg(&self.fills, &self.lines, &self.dots, self.background)
+    }
+}
+
+impl TurtleDisplay for VecDisplay {
+    fn display(&mut self, show: bool) {
+        self.shown = show;
+    }
+    fn display_visible(&self) -> bool {
+        // Always keep up with the drawing, whether it's shown or not
+        true
+    }
+    fn draw(
+        &mut self,
+        _turtle: i32,
+        background: Option<Colour>,
+        fills: &[Fill],
+        lines: &[Line],
+        dots: &[Dot],
+    ) {
+        self.background = background;
+        self.fills = fills.to_vec();
+        self.lines = lines.to_vec();
+        self.dots = dots.to_vec();
+    }
+    fn print(&mut self, background: Option<Colour>, fills: &[Fill], lines: &[Line], dots: &[Dot]) {
+        self.draw(0, background, fills, lines, dots);
+        self.printed.push(self.to_svg());
+    }
+}
+
 pub fn calc_bounds<'a, LI, DI>(lines: LI, dots: DI) -> (Point, Point)
 where
     LI: Iterator<Item = &'a Line>,
@@ -154,6 +328,16 @@ where
     calc_bounds_from_points(points)
 }
 
+/// Bounds of a drawing including its fills
+pub fn calc_drawing_bounds(fills: &[Fill], lines: &[Line], dots: &[Dot]) -> (Point, Point) {
+    let points = fills
+        .iter()
+        .flat_map(|f| f.points.iter().copied())
+        .chain(lines.iter().flat_map(|l| [l.from, l.to]))
+        .chain(dots.iter().map(|d| d.pos));
+    calc_bounds_from_points(points)
+}
+
 pub fn calc_bounds_from_points<I>(points: I) -> (Point, Point)
 where
     I: Iterator<Item = Point>,
@@ -177,57 +361,243 @@ where
     }
 }
 
+/// The area covered by a drawing: (x0, y0, width, height), with room for
+/// the width of the pen
+pub fn calc_image_box(fills: &[Fill], lines: &[Line], dots: &[Dot]) -> (f64, f64, f64, f64) {
+    let (topleft, bottomright) = calc_drawing_bounds(fills, lines, dots);
+    let pen_width = lines
+        .iter()
+        .map(|l| l.width)
+        .chain(dots.iter().map(|d| d.width))
+        .max()
+        .unwrap_or(1) as f64;
+    (
+        topleft.x as f64 - pen_width / 2.,
+        topleft.y as f64 - pen_width / 2.,
+        (bottomright.x - topleft.x) as f64 + pen_width,
+        (bottomright.y - topleft.y) as f64 + pen_width,
+    )
+}
+
+pub fn css_colour(clr: Colour) -> String {
+    format!("rgb({}, {}, {})", clr.r, clr.g, clr.b)
+}
+
+/// SVG points attribute for a polygon
+pub fn svg_points(points: &[Point]) -> String {
+    points
+        .iter()
+        .map(|p| format!("{},{}", p.x, p.y))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// One element of a drawing, as it appears in SVG
+#[derive(Debug, Clone, Copy)]
+pub enum Element<'a> {
+    /// A rectangle covering the whole image
+    Background(Colour),
+    Fill(&'a Fill),
+    Line(&'a Line),
+    Dot(&'a Dot),
+}
+
+/// Additional SVG for one element of a drawing (e.g. for animation)
+#[derive(Debug, Clone, Default)]
+pub struct SvgExtra {
+    /// Extra attributes, each with a leading space
+    pub attributes: String,
+    /// Child elements
+    pub children: String,
+}
+
+/// Format one SVG element
+fn svg_element(tag: &str, attributes: String, extra: SvgExtra) -> String {
+    if extra.children.is_empty() {
+        format!("<{} {}{}/>", tag, attributes, extra.attributes)
+    } else {
+        format!(
+            "<{0} {1}{2}>{3}</{0}>",
+            tag, attributes, extra.attributes, extra.children
+        )
+    }
+}
+
+/// Render the elements of a drawing, in order, as an SVG image showing
+/// `image_box` (see [calc_image_box]). `extra` is called with the index and
+/// the element for every element, and gives any SVG to add to it.
+pub fn render_turt_elements<'a>(
+    image_box: (f64, f64, f64, f64),
+    elements: impl IntoIterator<Item = Element<'a>>,
+    mut extra: impl FnMut(usize, &Element) -> SvgExtra,
+) -> String {
+    let (x0, y0, width, height) = image_box;
+    let mut svg = r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_owned();
+    svg.push_str(&format!(
+        r#"<svg viewBox="{} {} {} {}" xmlns="http://www.w3.org/2000/svg" stroke-linecap="round" stroke-width="1">"#,
+        x0, y0, width, height));
+    for (idx, element) in elements.into_iter().enumerate() {
+        let extra = extra(idx, &element);
+        svg.push_str(&match element {
+            Element::Background(clr) => svg_element(
+                "rect",
+                format!(
+                    r#"x="{}" y="{}" width="{}" height="{}" fill="{}""#,
+                    x0,
+                    y0,
+                    width,
+                    height,
+                    css_colour(clr)
+                ),
+                extra,
+            ),
+            Element::Fill(fill) => svg_element(
+                "polygon",
+                format!(
+                    r#"points="{}" fill="{}""#,
+                    svg_points(&fill.points),
+                    css_colour(fill.colour)
+                ),
+                extra,
+            ),
+            Element::Line(line) => svg_element(
+                "line",
+                format!(
+                    r#"x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-width="{}""#,
+                    line.from.x,
+                    line.from.y,
+                    line.to.x,
+                    line.to.y,
+                    css_colour(line.colour),
+                    line.width
+                ),
+                extra,
+            ),
+            Element::Dot(dot) => svg_element(
+                "circle",
+                format!(
+                    r#"cx="{}" cy="{}" r="{}" fill="{}""#,
+                    dot.pos.x,
+                    dot.pos.y,
+                    dot.width as f64 / 2.,
+                    css_colour(dot.colour)
+                ),
+                extra,
+            ),
+        });
+    }
+    // Close tag
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Render a drawing as an SVG image (this is what rfunge writes for `I`)
+pub fn render_turt_to_svg(
+    fills: &[Fill],
+    lines: &[Line],
+    dots: &[Dot],
+    background: Option<Colour>,
+) -> String {
+    // The background goes first, and the dots go on top
+    let elements = background
+        .map(Element::Background)
+        .into_iter()
+        .chain(fills.iter().map(Element::Fill))
+        .chain(lines.iter().map(Element::Line))
+        .chain(dots.iter().map(Element::Dot));
+    render_turt_elements(calc_image_box(fills, lines, dots), elements, |_, _| {
+        SvgExtra::default()
+    })
+}
+
 impl<D: TurtleDisplay> TurtleRobot for SimpleRobot<D> {
     fn turn_left(&mut self, degrees: i32) {
-        self.heading -= degrees;
+        self.turn_left_precise(degrees as f64);
     }
     fn set_heading(&mut self, degrees: i32) {
-        self.heading = degrees;
+        self.set_heading_precise(degrees as f64);
     }
     fn heading(&self) -> i32 {
-        self.heading
+        self.turtle().heading.round() as i32
+    }
+    fn turn_left_precise(&mut self, degrees: f64) {
+        self.turtle_mut().heading -= degrees;
+    }
+    fn set_heading_precise(&mut self, degrees: f64) {
+        self.turtle_mut().heading = degrees;
+    }
+    fn heading_precise(&self) -> f64 {
+        self.turtle().heading
     }
     fn set_pen(&mut self, down: bool) {
-        if self.pen_down && !down && !self.have_drawn {
+        let turtle = &mut self.turtles[self.current];
+        if turtle.pen_down && !down && !turtle.have_drawn {
             // make a dot
-            self.dots.push(Dot {
-                pos: self.position,
-                colour: self.colour,
-            });
-        } else if !self.pen_down {
-            self.have_drawn = false;
+            self.dots.push(turtle.current_dot());
+        } else if !turtle.pen_down {
+            turtle.have_drawn = false;
         }
-        self.pen_down = down;
+        turtle.pen_down = down;
         self.redraw(false);
     }
     fn is_pen_down(&self) -> bool {
-        self.pen_down
+        self.turtle().pen_down
+    }
+    fn set_pen_width(&mut self, pixels: i32) {
+        self.turtle_mut().pen_width = pixels.max(1);
+        self.redraw(false);
+    }
+    fn pen_width(&self) -> i32 {
+        self.turtle().pen_width
+    }
+    fn begin_fill(&mut self) {
+        let turtle = self.turtle_mut();
+        turtle.fill_points = Some(vec![turtle.position]);
+    }
+    fn end_fill(&mut self) {
+        let turtle = &mut self.turtles[self.current];
+        if let Some(points) = turtle.fill_points.take() {
+            if points.len() >= 3 {
+                self.fills.push(Fill {
+                    points,
+                    colour: turtle.colour,
+                });
+                self.redraw(false);
+            }
+        }
     }
     fn forward(&mut self, pixels: i32) {
-        let heading_rad = (self.heading as f64) / 180.0 * std::f64::consts::PI;
+        let turtle = &mut self.turtles[self.current];
+        let heading_rad = turtle.heading.to_radians();
         let dest = Point {
-            x: self.position.x + (pixels as f64 * heading_rad.cos()).round() as i32,
-            y: self.position.y + (pixels as f64 * heading_rad.sin()).round() as i32,
+            x: turtle.position.x + (pixels as f64 * heading_rad.cos()).round() as i32,
+            y: turtle.position.y + (pixels as f64 * heading_rad.sin()).round() as i32,
         };
-        if self.pen_down {
+        if turtle.pen_down {
             self.lines.push(Line {
-                from: self.position,
+                from: turtle.position,
                 to: dest,
-                colour: self.colour,
+                colour: turtle.colour,
+                width: turtle.pen_width,
             });
-            self.have_drawn = true;
+            turtle.have_drawn = true;
+            turtle.move_to(dest);
             self.redraw(false)
+        } else {
+            turtle.move_to(dest);
         }
-        self.position = dest;
     }
     fn set_colour(&mut self, rgb: Colour) {
-        self.colour = rgb;
+        self.turtle_mut().colour = rgb;
     }
     fn clear_with_colour(&mut self, rgb: Colour) {
         self.background = Some(rgb);
+        self.fills.clear();
         self.lines.clear();
         self.dots.clear();
-        self.have_drawn = false;
+        for turtle in self.turtles.iter_mut() {
+            turtle.have_drawn = false;
+        }
         self.redraw(false)
     }
     fn display(&mut self, show: bool) {
@@ -235,25 +605,39 @@ impl<D: TurtleDisplay> TurtleRobot for SimpleRobot<D> {
         self.redraw(false);
     }
     fn teleport(&mut self, dest: Point) {
-        if self.pen_down && !self.have_drawn {
+        let turtle = &mut self.turtles[self.current];
+        if let Some(dot) = turtle.pending_dot() {
             // Leave a dot at the old location
-            self.dots.push(Dot {
-                pos: self.position,
-                colour: self.colour,
-            });
+            self.dots.push(dot);
         }
-        self.position = dest;
+        turtle.move_to(dest);
         self.redraw(false);
     }
     fn position(&self) -> Point {
-        self.position
+        self.turtle().position
     }
     fn bounds(&self) -> (Point, Point) {
-        calc_bounds(self.lines.iter(), self.dots.iter())
+        calc_drawing_bounds(&self.fills, &self.lines, &self.dots)
     }
     fn print(&mut self) {
         self.redraw(true);
     }
+    fn new_turtle(&mut self) -> i32 {
+        self.turtles.push(Turtle::default());
+        (self.turtles.len() - 1) as i32
+    }
+    fn select_turtle(&mut self, id: i32) -> bool {
+        match usize::try_from(id) {
+            Ok(idx) if idx < self.turtles.len() => {
+                self.current = idx;
+                true
+            }
+            _ => false,
+        }
+    }
+    fn current_turtle(&self) -> i32 {
+        self.current as i32
+    }
 }
 
 /// From the catseye library
@@ -621,3 +1005,73 @@ fn print_drawing<F: Funge>(
     }
     InstructionResult::Continue
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vec_display() {
+        let mut robot = SimpleRobot::new(VecDisplay::new());
+        robot.set_pen(true);
+        robot.forward(10);
+        robot.turn_left(90);
+        robot.forward(10);
+        robot.print();
+
+        let display = robot.get_display();
+        assert_eq!(display.lines.len(), 2);
+        assert_eq!(display.printed, vec![display.to_svg()]);
+        assert!(display.printed[0].contains(r#"<line x1="0" y1="0" x2="10" y2="0""#));
+        assert!(display.printed[0].contains(r#"<line x1="10" y1="0" x2="10" y2="-10""#));
+    }
+
+    #[test]
+    fn test_multiple_turtles() {
+        let mut robot = SimpleRobot::new(VecDisplay::new());
+        let second = robot.new_turtle();
+        assert_eq!(second, 1);
+        assert_eq!(robot.current_turtle(), 0);
+        assert!(!robot.select_turtle(2));
+
+        robot.set_pen(true);
+        robot.forward(10);
+        assert!(robot.select_turtle(second));
+        robot.turn_left(90);
+        robot.set_pen(true);
+        robot.forward(5);
+        assert_eq!(robot.position(), Point { x: 0, y: -5 });
+        robot.select_turtle(0);
+        assert_eq!(robot.position(), Point { x: 10, y: 0 });
+        assert_eq!(robot.heading(), 0);
+        assert_eq!(robot.get_display().lines.len(), 2);
+    }
+
+    #[test]
+    fn test_render_elements_with_extra() {
+        let dot = Dot {
+            pos: Point { x: 1, y: 2 },
+            colour: Colour { r: 0, g: 0, b: 0 },
+            width: 2,
+        };
+        let elements = [
+            Element::Background(Colour { r: 9, g: 9, b: 9 }),
+            Element::Dot(&dot),
+        ];
+        let svg = render_turt_elements(calc_image_box(&[], &[], &[dot]), elements, |idx, _| {
+            if idx == 1 {
+                SvgExtra {
+                    attributes: r#" id="dot""#.to_owned(),
+                    children: "<title>dot</title>".to_owned(),
+                }
+            } else {
+                SvgExtra::default()
+            }
+        });
+        assert!(svg.contains(r#"fill="rgb(9, 9, 9)"/><circle"#));
+        assert!(svg.contains(
+            r#"<circle cx="1" cy="2" r="1" fill="rgb(0, 0, 0)" id="dot"><title>dot</title></circle>"#
+        ));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+}
@@ -31,6 +31,9 @@ mod MODU;
 mod NULL;
 mod REFC;
 mod ROMA;
+mod SQNT;
+mod TRGR;
+mod TRTX;
 pub mod TURT;
 
 #[cfg(all(feature = "ncurses", not(target_family = "wasm")))]
@@ -42,6 +45,8 @@ mod SOCK;
 #[cfg(not(target_family = "wasm"))]
 mod TERM;
 
+use hashbrown::HashMap;
+
 use super::{Funge, InstructionPointer};
 
 /// Convert a fingerprint string to a numeric fingerprint
@@ -54,6 +59,27 @@ pub fn string_to_fingerprint(fpr_str: &str) -> i32 {
     fpr as i32
 }
 
+/// Convert a fingerprint string to a numeric fingerprint, or `None` if it
+/// isn't a valid fingerprint name (one to four ASCII characters)
+pub fn try_string_to_fingerprint(fpr_str: &str) -> Option<i32> {
+    if (1..=4).contains(&fpr_str.len()) && fpr_str.is_ascii() {
+        Some(string_to_fingerprint(fpr_str))
+    } else {
+        None
+    }
+}
+
+/// Convert a numeric fingerprint to a fingerprint string (the inverse of
+/// [string_to_fingerprint])
+pub fn fingerprint_to_string(fpr: i32) -> String {
+    (fpr as u32)
+        .to_be_bytes()
+        .iter()
+        .skip_while(|b| **b == 0)
+        .map(|b| *b as char)
+        .collect()
+}
+
 /// Get a list of all available fingerprints that are considered "safe" (i.e.,
 /// no executing external commands, no IO)
 pub fn safe_fingerprints() -> Vec<i32> {
@@ -71,6 +97,8 @@ pub fn safe_fingerprints() -> Vec<i32> {
         string_to_fingerprint("FPRT"),
         string_to_fingerprint("JSTR"),
         string_to_fingerprint("FRTH"),
+        string_to_fingerprint("SQNT"),
+        string_to_fingerprint("TRGR"),
     ];
     if cfg!(not(target_family = "wasm")) {
         fprts.push(string_to_fingerprint("TERM"));
@@ -82,6 +110,7 @@ pub fn safe_fingerprints() -> Vec<i32> {
 pub fn all_fingerprints() -> Vec<i32> {
     let mut fprts = safe_fingerprints();
     fprts.push(string_to_fingerprint("TURT"));
+    fprts.push(string_to_fingerprint("TRTX"));
     if cfg!(not(target_family = "wasm")) {
         fprts.push(string_to_fingerprint("SOCK"));
         if cfg!(feature = "ncurses") {
@@ -91,180 +120,172 @@ pub fn all_fingerprints() -> Vec<i32> {
     fprts
 }
 
-pub(crate) fn load<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-    fpr: i32,
-) -> bool {
-    if fpr == string_to_fingerprint("NULL") {
-        NULL::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("BOOL") {
-        BOOL::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("HRTI") {
-        HRTI::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("FIXP") {
-        FIXP::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("ROMA") {
-        ROMA::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("MODU") {
-        MODU::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("REFC") {
-        REFC::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("FPSP") {
-        FPSP::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("FPDP") {
-        FPDP::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("LONG") {
-        LONG::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("FPRT") {
-        FPRT::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("JSTR") {
-        JSTR::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("FRTH") {
-        FRTH::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("TURT") {
-        TURT::load(ip, space, env)
-    } else {
-        load_platform_specific(ip, space, env, fpr)
-    }
+/// Hook called after `ip` has written to the cell at `loc` with `p`
+pub(crate) fn on_put<F: Funge>(ip: &InstructionPointer<F>, loc: F::Idx) {
+    TRGR::cell_written(ip, loc);
 }
 
-#[cfg(not(target_family = "wasm"))]
-pub(crate) fn load_platform_specific<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-    fpr: i32,
-) -> bool {
-    if fpr == string_to_fingerprint("SOCK") {
-        SOCK::load(ip, space, env)
-    } else if fpr == string_to_fingerprint("TERM") {
-        TERM::load(ip, space, env)
-    } else {
-        maybe_load_ncrs(ip, space, env, fpr)
-    }
+/// Hook called at the start of `ip`'s turn, before it moves
+pub(crate) fn before_move<F: Funge>(ip: &mut InstructionPointer<F>) {
+    TRGR::divert_if_triggered(ip);
 }
 
-#[cfg(all(feature = "ncurses", not(target_family = "wasm")))]
-fn maybe_load_ncrs<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-    fpr: i32,
-) -> bool {
-    if fpr == string_to_fingerprint("NCRS") {
-        NCRS::load(ip, space, env)
-    } else {
-        false
-    }
+/// Hook called after `ip` has stopped and been removed from the interpreter
+pub(crate) fn on_ip_removed<F: Funge>(ip: &InstructionPointer<F>) {
+    TRGR::ip_removed(ip);
 }
 
-#[cfg(not(any(feature = "ncurses", target_family = "wasm")))]
-fn maybe_load_ncrs<F: Funge>(
-    _ip: &mut InstructionPointer<F>,
-    _space: &mut F::Space,
-    _env: &mut F::Env,
-    _fpr: i32,
-) -> bool {
-    false
-}
+/// Function loading or unloading a fingerprint's semantics into an IP.
+/// Returns `false` on failure (in which case the IP reflects).
+pub type FingerprintFn<F> =
+    fn(&mut InstructionPointer<F>, &mut <F as Funge>::Space, &mut <F as Funge>::Env) -> bool;
 
-#[cfg(target_family = "wasm")]
-pub(crate) fn load_platform_specific<F: Funge>(
-    _ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-    _fpr: i32,
-) -> bool {
-    false
+struct RegistryEntry<F: Funge + 'static> {
+    safe: bool,
+    load: FingerprintFn<F>,
+    unload: FingerprintFn<F>,
 }
 
-pub(crate) fn unload<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-    fpr: i32,
-) -> bool {
-    if fpr == string_to_fingerprint("NULL") {
-        NULL::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("BOOL") {
-        BOOL::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("HRTI") {
-        HRTI::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("FIXP") {
-        FIXP::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("ROMA") {
-        ROMA::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("MODU") {
-        MODU::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("REFC") {
-        REFC::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("FPSP") {
-        FPSP::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("FPDP") {
-        FPDP::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("LONG") {
-        LONG::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("FPRT") {
-        FPRT::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("JSTR") {
-        JSTR::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("FRTH") {
-        FRTH::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("TURT") {
-        TURT::unload(ip, space, env)
-    } else {
-        unload_platform_specific(ip, space, env, fpr)
+// Can't derive Clone by macro because it requires the type parameters to be
+// Clone...
+impl<F: Funge + 'static> Clone for RegistryEntry<F> {
+    fn clone(&self) -> Self {
+        Self {
+            safe: self.safe,
+            load: self.load,
+            unload: self.unload,
+        }
     }
 }
 
-#[cfg(not(target_family = "wasm"))]
-pub(crate) fn unload_platform_specific<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-    fpr: i32,
-) -> bool {
-    if fpr == string_to_fingerprint("SOCK") {
-        SOCK::unload(ip, space, env)
-    } else if fpr == string_to_fingerprint("TERM") {
-        TERM::unload(ip, space, env)
-    } else {
-        maybe_unload_ncrs(ip, space, env, fpr)
-    }
+/// The fingerprints an IP can load with `(`
+///
+/// [FingerprintRegistry::new] registers all of rfunge's built-in
+/// fingerprints; custom fingerprints can be added with
+/// [Interpreter::register_fingerprint](super::Interpreter::register_fingerprint).
+/// Whether a fingerprint can actually be loaded is still up to
+/// [InterpreterEnv::is_fingerprint_enabled](super::InterpreterEnv::is_fingerprint_enabled).
+pub struct FingerprintRegistry<F: Funge + 'static> {
+    entries: HashMap<i32, RegistryEntry<F>>,
 }
 
-#[cfg(all(feature = "ncurses", not(target_family = "wasm")))]
-fn maybe_unload_ncrs<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-    fpr: i32,
-) -> bool {
-    if fpr == string_to_fingerprint("NCRS") {
-        NCRS::unload(ip, space, env)
-    } else {
-        false
+impl<F: Funge + 'static> Clone for FingerprintRegistry<F> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+        }
     }
 }
 
-#[cfg(not(any(feature = "ncurses", target_family = "wasm")))]
-fn maybe_unload_ncrs<F: Funge>(
-    _ip: &mut InstructionPointer<F>,
-    _space: &mut F::Space,
-    _env: &mut F::Env,
-    _fpr: i32,
-) -> bool {
-    false
+impl<F: Funge + 'static> Default for FingerprintRegistry<F> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-#[cfg(target_family = "wasm")]
-pub(crate) fn unload_platform_specific<F: Funge>(
-    _ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-    _fpr: i32,
-) -> bool {
-    false
+impl<F: Funge + 'static> FingerprintRegistry<F> {
+    /// Create a registry containing the built-in fingerprints
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        let safe = safe_fingerprints();
+        let mut add = |name: &str, load: FingerprintFn<F>, unload: FingerprintFn<F>| {
+            let fpr = string_to_fingerprint(name);
+            registry.register(fpr, safe.contains(&fpr), load, unload);
+        };
+        add("NULL", NULL::load, NULL::unload);
+        add("BOOL", BOOL::load, BOOL::unload);
+        add("HRTI", HRTI::load, HRTI::unload);
+        add("FIXP", FIXP::load, FIXP::unload);
+        add("ROMA", ROMA::load, ROMA::unload);
+        add("MODU", MODU::load, MODU::unload);
+        add("REFC", REFC::load, REFC::unload);
+        add("FPSP", FPSP::load, FPSP::unload);
+        add("FPDP", FPDP::load, FPDP::unload);
+        add("LONG", LONG::load, LONG::unload);
+        add("FPRT", FPRT::load, FPRT::unload);
+        add("JSTR", JSTR::load, JSTR::unload);
+        add("FRTH", FRTH::load, FRTH::unload);
+        add("SQNT", SQNT::load, SQNT::unload);
+        add("TRGR", TRGR::load, TRGR::unload);
+        add("TURT", TURT::load, TURT::unload);
+        add("TRTX", TRTX::load, TRTX::unload);
+        #[cfg(not(target_family = "wasm"))]
+        {
+            add("SOCK", SOCK::load, SOCK::unload);
+            add("TERM", TERM::load, TERM::unload);
+        }
+        #[cfg(all(feature = "ncurses", not(target_family = "wasm")))]
+        add("NCRS", NCRS::load, NCRS::unload);
+        registry
+    }
+
+    /// Create a registry without any fingerprints
+    pub fn empty() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Add a fingerprint, replacing any fingerprint with the same ID.
+    /// `safe` indicates whether the fingerprint is "safe" in the sense of
+    /// [safe_fingerprints].
+    pub fn register(
+        &mut self,
+        fpr: i32,
+        safe: bool,
+        load: FingerprintFn<F>,
+        unload: FingerprintFn<F>,
+    ) {
+        self.entries
+            .insert(fpr, RegistryEntry { safe, load, unload });
+    }
+
+    /// Remove a fingerprint. Returns `false` if it wasn't registered.
+    pub fn unregister(&mut self, fpr: i32) -> bool {
+        self.entries.remove(&fpr).is_some()
+    }
+
+    pub fn contains(&self, fpr: i32) -> bool {
+        self.entries.contains_key(&fpr)
+    }
+
+    /// All registered fingerprints
+    pub fn fingerprints(&self) -> Vec<i32> {
+        self.entries.keys().copied().collect()
+    }
+
+    /// All registered fingerprints that are marked as safe
+    pub fn safe_fingerprints(&self) -> Vec<i32> {
+        self.entries
+            .iter()
+            .filter(|(_, e)| e.safe)
+            .map(|(fpr, _)| *fpr)
+            .collect()
+    }
+
+    pub(crate) fn load(
+        &self,
+        ip: &mut InstructionPointer<F>,
+        space: &mut F::Space,
+        env: &mut F::Env,
+        fpr: i32,
+    ) -> bool {
+        match self.entries.get(&fpr) {
+            Some(entry) => (entry.load)(ip, space, env),
+            None => false,
+        }
+    }
+
+    pub(crate) fn unload(
+        &self,
+        ip: &mut InstructionPointer<F>,
+        space: &mut F::Space,
+        env: &mut F::Env,
+        fpr: i32,
+    ) -> bool {
+        match self.entries.get(&fpr) {
+            Some(entry) => (entry.unload)(ip, space, env),
+            None => false,
+        }
+    }
 }
@@ -20,17 +20,20 @@ use hashbrown::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::future::Future;
 use std::pin::Pin;
-// use std::rc::Rc;
+use std::rc::Rc;
 use std::str;
 
 use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
 use num::ToPrimitive;
 
-use super::fingerprints;
+use super::fingerprints::{self, FingerprintRegistry};
 use super::instructions;
-use super::ip::InstructionPointer;
+use super::ip::{InstructionPointer, IpMode};
 use super::motion::MotionCmds;
-use super::{Funge, IOMode, InterpreterEnv};
+use super::{
+    Diagnostic, DiagnosticCode, DivisionMode, Funge, IOMode, InterpreterEnv, Severity,
+    UnknownInstructionPolicy,
+};
 use crate::fungespace::{FungeSpace, FungeValue};
 
 /// Result of a single instruction. Most instructions return
@@ -45,10 +48,21 @@ pub enum InstructionResult {
     Fork(i32),
     /// Stop this IP (only used by `@`)
     Stop,
+    /// Spawn new IPs, then stop this IP (only used by `k`, e.g. if a `t`
+    /// is followed by an `@` in nested `k` loops)
+    ForkAndStop(i32),
     /// Exit the program with a supplied code (only used by `q`)
     Exit(i32),
     /// Abort/panic. Do not use if it can be at all avoided.
     Panic,
+    /// The instruction can't complete yet (e.g. because it is waiting for
+    /// input). The instruction must leave the IP unchanged: it will be
+    /// executed again when the program is resumed, and [Interpreter::run]
+    /// returns [ProgramResult::AwaitingInput] in the meantime.
+    ///
+    /// [Interpreter::run]: super::Interpreter::run
+    /// [ProgramResult::AwaitingInput]: super::ProgramResult::AwaitingInput
+    Yield,
 }
 
 pub enum Instruction<F: Funge + 'static> {
@@ -71,33 +85,39 @@ pub type AsyncInstructionPtr<F> =
 
 impl<F: Funge + 'static> Clone for Instruction<F> {
     fn clone(&self) -> Self {
-        match self {
-            Instruction::SyncInstruction(f) => Instruction::SyncInstruction(*f),
-            Instruction::AsyncInstruction(af) => Instruction::AsyncInstruction(*af),
-        }
+        *self
     }
 }
 
+impl<F: Funge + 'static> Copy for Instruction<F> {}
+
 /// Turn a regular fuction into an `Instruction`
-pub fn sync_instruction<F: Funge + 'static>(func: SyncInstructionPtr<F>) -> Instruction<F>
+pub fn sync_instruction<F>(func: SyncInstructionPtr<F>) -> Instruction<F>
 where
     F: Funge + 'static,
 {
     Instruction::SyncInstruction(func)
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum InstructionMode {
-    Normal,
-    String,
-}
-
 /// Struct encapulating the dynamic instructions loaded for an IP
 /// It has multiple layers, and fingerprints are able to add a new
 /// layer to the instruction set (which can later be popped)
+///
+/// The top layer is kept flattened into a table indexed by character, which
+/// is updated whenever a layer is added or removed, so that looking up an
+/// instruction is a single array access.
 pub struct InstructionSet<F: Funge + 'static> {
-    pub mode: InstructionMode,
+    /// Copy of [InterpreterSettings::unknown_instructions](super::InterpreterSettings::unknown_instructions)
+    pub(crate) unknown_policy: UnknownInstructionPolicy,
+    /// Copy of [InterpreterSettings::division](super::InterpreterSettings::division)
+    pub(crate) division: DivisionMode,
+    /// All layers for each character, bottom first
     instructions: Vec<Vec<Instruction<F>>>,
+    /// The top layer for each character
+    table: Vec<Option<Instruction<F>>>,
+    fingerprints: Vec<i32>,
+    registry: Rc<FingerprintRegistry<F>>,
+    overrides: Rc<HashMap<char, Instruction<F>>>,
 }
 
 // Can't derive Clone by macro because it requires the type parameters to be
@@ -105,8 +125,13 @@ pub struct InstructionSet<F: Funge + 'static> {
 impl<F: Funge + 'static> Clone for InstructionSet<F> {
     fn clone(&self) -> Self {
         Self {
-            mode: self.mode,
+            unknown_policy: self.unknown_policy,
+            division: self.division,
             instructions: self.instructions.clone(),
+            table: self.table.clone(),
+            fingerprints: self.fingerprints.clone(),
+            registry: self.registry.clone(),
+            overrides: self.overrides.clone(),
         }
     }
 }
@@ -137,25 +162,73 @@ impl<F: Funge + 'static> InstructionSet<F> {
         instruction_vec['{' as usize].push(sync_instruction(instructions::begin_block));
         instruction_vec['}' as usize].push(sync_instruction(instructions::end_block));
         instruction_vec['u' as usize].push(sync_instruction(instructions::stack_under_stack));
-        instruction_vec['i' as usize].push(sync_instruction(instructions::input_file));
-        instruction_vec['o' as usize].push(sync_instruction(instructions::output_file));
-        instruction_vec['=' as usize].push(sync_instruction(instructions::execute));
+        instruction_vec['i' as usize].push(Instruction::AsyncInstruction(instructions::input_file));
+        instruction_vec['o' as usize]
+            .push(Instruction::AsyncInstruction(instructions::output_file));
+        instruction_vec['=' as usize].push(Instruction::AsyncInstruction(instructions::execute));
         instruction_vec['y' as usize].push(sync_instruction(instructions::sysinfo));
 
         Self {
-            mode: InstructionMode::Normal,
+            unknown_policy: Default::default(),
+            division: Default::default(),
+            table: instruction_vec
+                .iter()
+                .map(|layers| layers.last().copied())
+                .collect(),
             instructions: instruction_vec,
+            fingerprints: Vec::new(),
+            registry: Rc::new(FingerprintRegistry::new()),
+            overrides: Rc::new(HashMap::new()),
+        }
+    }
+
+    /// The fingerprints that are currently loaded, in the order in which
+    /// they were loaded
+    pub fn loaded_fingerprints(&self) -> &[i32] {
+        &self.fingerprints
+    }
+
+    /// The fingerprints available to `(`
+    pub fn registry(&self) -> &Rc<FingerprintRegistry<F>> {
+        &self.registry
+    }
+
+    /// Replace the fingerprints available to `(`
+    pub fn set_registry(&mut self, registry: Rc<FingerprintRegistry<F>>) {
+        self.registry = registry;
+    }
+
+    /// Instructions that take precedence over both the standard instructions
+    /// and fingerprints
+    pub fn overrides(&self) -> &Rc<HashMap<char, Instruction<F>>> {
+        &self.overrides
+    }
+
+    /// Replace the instruction overrides
+    pub fn set_overrides(&mut self, overrides: Rc<HashMap<char, Instruction<F>>>) {
+        self.overrides = overrides;
+    }
+
+    /// Record that a fingerprint has been loaded
+    pub(crate) fn fingerprint_loaded(&mut self, fpr: i32) {
+        self.fingerprints.push(fpr);
+    }
+
+    /// Record that a fingerprint has been unloaded
+    pub(crate) fn fingerprint_unloaded(&mut self, fpr: i32) {
+        if let Some(pos) = self.fingerprints.iter().rposition(|f| *f == fpr) {
+            self.fingerprints.remove(pos);
         }
     }
 
     /// Get the function associated with a given character, if any
     pub fn get_instruction(&self, instruction: F::Value) -> Option<Instruction<F>> {
-        let instr_stack = self.instructions.get(instruction.to_usize()?)?;
-        if !instr_stack.is_empty() {
-            Some(instr_stack[instr_stack.len() - 1].clone())
-        } else {
-            None
-        }
+        self.lookup(instruction.try_to_char()?)
+    }
+
+    #[inline]
+    fn lookup(&self, c: char) -> Option<Instruction<F>> {
+        self.table.get(c as usize).copied().flatten()
     }
 
     /// Add a set of instructions as a new layer
@@ -163,8 +236,10 @@ impl<F: Funge + 'static> InstructionSet<F> {
         for (&i, f) in instructions.iter() {
             if i as usize >= self.instructions.len() {
                 self.instructions.resize_with((i as usize) + 1, Vec::new);
+                self.table.resize((i as usize) + 1, None);
             }
-            self.instructions[i as usize].push(f.clone());
+            self.instructions[i as usize].push(*f);
+            self.table[i as usize] = Some(*f);
         }
     }
 
@@ -175,6 +250,7 @@ impl<F: Funge + 'static> InstructionSet<F> {
             let i = *c as usize;
             if i < self.instructions.len() && !self.instructions[i].is_empty() {
                 self.instructions[i].pop();
+                self.table[i] = self.instructions[i].last().copied();
                 any_popped = true;
             }
         }
@@ -189,9 +265,10 @@ pub(super) async fn exec_instruction<'a, F: Funge + 'static>(
     space: &'a mut F::Space,
     env: &'a mut F::Env,
 ) -> InstructionResult {
-    match ip.instructions.mode {
-        InstructionMode::Normal => exec_normal_instruction(raw_instruction, ip, space, env).await,
-        InstructionMode::String => exec_string_instruction(raw_instruction, ip, space, env).await,
+    if ip.mode.contains(IpMode::STRING) {
+        exec_string_instruction(raw_instruction, ip, space, env).await
+    } else {
+        exec_normal_instruction(raw_instruction, ip, space, env).await
     }
 }
 
@@ -202,6 +279,18 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
     space: &'a mut F::Space,
     env: &'a mut F::Env,
 ) -> InstructionResult {
+    if !ip.instructions.overrides.is_empty() {
+        if let Some(instr) = raw_instruction
+            .try_to_char()
+            .and_then(|c| ip.instructions.overrides.get(&c))
+            .copied()
+        {
+            return match instr {
+                Instruction::SyncInstruction(func) => func(ip, space, env),
+                Instruction::AsyncInstruction(async_func) => (async_func)(ip, space, env).await,
+            };
+        }
+    }
     match raw_instruction.try_to_char() {
         Some(' ') => {
             return InstructionResult::Skip;
@@ -244,21 +333,21 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
         }
         Some(':') => {
             let n = ip.pop();
-            ip.push(n);
+            ip.push(n.clone());
             ip.push(n);
         }
-        Some(digit) if ('0'..='9').contains(&digit) => {
+        Some(digit) if digit.is_ascii_digit() => {
             ip.push(((digit as i32) - ('0' as i32)).into());
         }
         Some(digit) if ('a'..='f').contains(&digit) => {
             ip.push((0xa + (digit as i32) - ('a' as i32)).into());
         }
         Some('"') => {
-            ip.instructions.mode = InstructionMode::String;
+            ip.mode.insert(IpMode::STRING);
         }
         Some('\'') => {
             let loc = ip.location + ip.delta;
-            ip.push(space[loc]);
+            ip.push(space[loc].clone());
             ip.location = loc;
         }
         Some('s') => {
@@ -269,7 +358,7 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
         Some('.') => {
             let s = format!("{} ", ip.pop());
             if env.output_writer().write(s.as_bytes()).await.is_err() {
-                env.warn("IO Error");
+                env.diagnostic(&io_error(ip));
             }
         }
         Some(',') => {
@@ -279,7 +368,7 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
                 IOMode::Binary => vec![(c & 0xff.into()).to_u8().unwrap()],
             };
             if env.output_writer().write(&buf).await.is_err() {
-                env.warn("IO Error");
+                env.diagnostic(&io_error(ip));
             }
         }
         Some('~') => {
@@ -382,12 +471,20 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
         Some('/') => {
             let b = ip.pop();
             let a = ip.pop();
-            ip.push(if b != 0.into() { a / b } else { 0.into() });
+            ip.push(if b != 0.into() {
+                divide(a, b, ip.instructions.division).0
+            } else {
+                0.into()
+            });
         }
         Some('%') => {
             let b = ip.pop();
             let a = ip.pop();
-            ip.push(if b != 0.into() { a % b } else { 0.into() });
+            ip.push(if b != 0.into() {
+                divide(a, b, ip.instructions.division).1
+            } else {
+                0.into()
+            });
         }
         Some('`') => {
             let b = ip.pop();
@@ -399,7 +496,8 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
             ip.push(if v == 0.into() { 1.into() } else { 0.into() });
         }
         Some('j') => {
-            ip.location = ip.location + ip.delta * ip.pop();
+            let n = ip.pop();
+            ip.location = ip.location + ip.delta.scaled_by(n);
         }
         Some('x') => {
             ip.delta = MotionCmds::pop_vector(ip);
@@ -407,10 +505,13 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
         Some('p') => {
             let loc = MotionCmds::pop_vector(ip) + ip.storage_offset;
             space[loc] = ip.pop();
+            if !ip.private_data.is_empty() {
+                fingerprints::on_put(ip, loc);
+            }
         }
         Some('g') => {
             let loc = MotionCmds::pop_vector(ip) + ip.storage_offset;
-            ip.push(space[loc]);
+            ip.push(space[loc].clone());
         }
         Some('(') => {
             let count = ip.pop().to_usize().unwrap_or(0);
@@ -420,7 +521,9 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
                 fpr += ip.pop().to_i32().unwrap_or(0);
             }
             if fpr != 0 && env.is_fingerprint_enabled(fpr) {
-                if fingerprints::load(ip, space, env, fpr) {
+                let registry = ip.instructions.registry().clone();
+                if registry.load(ip, space, env, fpr) {
+                    ip.instructions.fingerprint_loaded(fpr);
                     ip.push(fpr.into());
                     ip.push(1.into());
                 } else {
@@ -438,7 +541,9 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
                 fpr += ip.pop().to_i32().unwrap_or(0);
             }
             if fpr != 0 {
-                if fingerprints::unload(ip, space, env, fpr) {
+                let registry = ip.instructions.registry().clone();
+                if registry.unload(ip, space, env, fpr) {
+                    ip.instructions.fingerprint_unloaded(fpr);
                     ip.push(fpr.into());
                     ip.push(1.into());
                 } else {
@@ -452,28 +557,85 @@ async fn exec_normal_instruction<'a, F: Funge + 'static>(
             ip.reflect();
         }
         Some('z') => {}
+        Some('?') => {
+            ip.delta = MotionCmds::random_delta(env);
+        }
         Some(c) => {
             if MotionCmds::apply_delta(c, ip) {
                 // ok
-            } else if let Some(instr) = ip.instructions.get_instruction(raw_instruction) {
-                // return (instr_fn)(ctx).await;
+            } else if let Some(instr) = ip.instructions.lookup(c) {
                 return match instr {
                     Instruction::SyncInstruction(func) => func(ip, space, env),
                     Instruction::AsyncInstruction(async_func) => (async_func)(ip, space, env).await,
                 };
             } else {
-                ip.reflect();
-                env.warn(&format!("Unknown instruction: '{}'", c));
+                return unknown_instruction(raw_instruction, ip, env);
             }
         }
         None => {
-            ip.reflect();
-            env.warn("Unknown non-Unicode instruction!");
+            return unknown_instruction(raw_instruction, ip, env);
         }
     }
     InstructionResult::Continue
 }
 
+/// Quotient and remainder of `a / b` (`b` must not be zero)
+pub(super) fn divide<T: FungeValue>(a: T, b: T, mode: DivisionMode) -> (T, T) {
+    match mode {
+        DivisionMode::Truncate => a.div_rem(&b),
+        DivisionMode::Floor => a.div_mod_floor(&b),
+        DivisionMode::Euclid => {
+            let r = a.mod_floor(&b.abs());
+            ((a - r.clone()) / b, r)
+        }
+    }
+}
+
+fn io_error<F: Funge>(ip: &InstructionPointer<F>) -> Diagnostic<'_, F> {
+    Diagnostic::at_ip(ip, Severity::Warning, DiagnosticCode::IoError, "IO Error")
+}
+
+/// Deal with an unknown instruction according to the [UnknownInstructionPolicy]
+fn unknown_instruction<F: Funge + 'static>(
+    raw_instruction: F::Value,
+    ip: &mut InstructionPointer<F>,
+    env: &mut F::Env,
+) -> InstructionResult {
+    let mut policy = ip.instructions.unknown_policy;
+    if policy == UnknownInstructionPolicy::Callback {
+        policy = env.unknown_instruction(ip, &raw_instruction);
+    }
+    let warning = || match raw_instruction.try_to_char() {
+        Some(c) => format!("Unknown instruction: '{}'", c),
+        None => "Unknown non-Unicode instruction!".to_owned(),
+    };
+    match policy {
+        UnknownInstructionPolicy::Reflect => {
+            ip.reflect();
+            InstructionResult::Continue
+        }
+        UnknownInstructionPolicy::WarnAndReflect | UnknownInstructionPolicy::Callback => {
+            env.diagnostic(&Diagnostic::at_ip(
+                ip,
+                Severity::Warning,
+                DiagnosticCode::UnknownInstruction,
+                warning(),
+            ));
+            ip.reflect();
+            InstructionResult::Continue
+        }
+        UnknownInstructionPolicy::Abort => {
+            env.diagnostic(&Diagnostic::at_ip(
+                ip,
+                Severity::Error,
+                DiagnosticCode::UnknownInstruction,
+                warning(),
+            ));
+            InstructionResult::Panic
+        }
+    }
+}
+
 #[inline]
 async fn exec_string_instruction<F: Funge + 'static>(
     raw_instruction: F::Value,
@@ -482,14 +644,16 @@ async fn exec_string_instruction<F: Funge + 'static>(
     _env: &mut F::Env,
 ) -> InstructionResult {
     // did we just skip over a space?
-    let prev_loc = ip.location - ip.delta;
-    let prev_val = space[prev_loc];
-    if prev_val == (' ' as i32).into() {
-        ip.push(prev_val);
+    if ip.mode.contains(IpMode::SGML_SPACES) {
+        let prev_loc = ip.location - ip.delta;
+        let prev_val = space[prev_loc].clone();
+        if prev_val == (' ' as i32).into() {
+            ip.push(prev_val);
+        }
     }
     match raw_instruction.to_char() {
         '"' => {
-            ip.instructions.mode = InstructionMode::Normal;
+            ip.mode.remove(IpMode::STRING);
         }
         _ => {
             // Push this character.
@@ -501,26 +665,54 @@ async fn exec_string_instruction<F: Funge + 'static>(
 
 #[cfg(test)]
 mod tests {
-    use super::super::tests::TestFunge;
+    use super::super::tests::{befunge_space, NoEnv, TestFunge};
     use super::*;
+    use crate::fungespace::bfvec;
 
     #[test]
     fn test_instruction_layers() {
         let mut is = InstructionSet::<TestFunge>::new();
-        assert!(matches!(is.get_instruction('1' as i64), None));
-        assert!(matches!(is.get_instruction('2' as i64), None));
-        assert!(matches!(is.get_instruction('3' as i64), None));
+        assert!(is.get_instruction('1' as i64).is_none());
+        assert!(is.get_instruction('2' as i64).is_none());
+        assert!(is.get_instruction('3' as i64).is_none());
         let mut new_layer = HashMap::new();
         new_layer.insert('2', sync_instruction(nop_for_test));
         new_layer.insert('5', sync_instruction(nop_for_test));
         is.add_layer(new_layer);
-        assert!(matches!(is.get_instruction('1' as i64), None));
-        assert!(matches!(is.get_instruction('2' as i64), Some(_)));
-        assert!(matches!(is.get_instruction('3' as i64), None));
+        assert!(is.get_instruction('1' as i64).is_none());
+        assert!(is.get_instruction('2' as i64).is_some());
+        assert!(is.get_instruction('3' as i64).is_none());
+        let mut upper_layer = HashMap::new();
+        upper_layer.insert('2', sync_instruction(reflect_for_test));
+        is.add_layer(upper_layer);
+        assert!(reflects(is.get_instruction('2' as i64)));
+        is.pop_layer(&['2']);
+        assert!(!reflects(is.get_instruction('2' as i64)));
         is.pop_layer(&['2', '5']);
-        assert!(matches!(is.get_instruction('1' as i64), None));
-        assert!(matches!(is.get_instruction('2' as i64), None));
-        assert!(matches!(is.get_instruction('3' as i64), None));
+        assert!(is.get_instruction('1' as i64).is_none());
+        assert!(is.get_instruction('2' as i64).is_none());
+        assert!(is.get_instruction('3' as i64).is_none());
+    }
+
+    /// Run a sync instruction on a fresh IP: does it turn around?
+    fn reflects(instruction: Option<Instruction<TestFunge>>) -> bool {
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        let mut space = befunge_space("");
+        let mut env = NoEnv::new();
+        match instruction {
+            Some(Instruction::SyncInstruction(f)) => f(&mut ip, &mut space, &mut env),
+            _ => panic!("expected a sync instruction"),
+        };
+        ip.delta == bfvec(-1, 0)
+    }
+
+    fn reflect_for_test(
+        ip: &mut InstructionPointer<TestFunge>,
+        _space: &mut <TestFunge as Funge>::Space,
+        _env: &mut <TestFunge as Funge>::Env,
+    ) -> InstructionResult {
+        ip.reflect();
+        InstructionResult::Continue
     }
 
     fn nop_for_test(
@@ -19,23 +19,30 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 //! This module contains only complex instructions; most instructions are
 //! built into the interpreter
 
-use std::cmp::Ordering;
 use std::cmp::{max, min};
 use std::future::Future;
-use std::mem::size_of;
+use std::io;
 use std::pin::Pin;
 
-use chrono::prelude::Utc;
 use chrono::{Datelike, Timelike};
-use num::ToPrimitive;
+use num::{FromPrimitive, ToPrimitive};
 use pkg_version::{pkg_version_major, pkg_version_minor, pkg_version_patch};
 
 use super::instruction_set::exec_instruction;
 use super::motion::MotionCmds;
 use super::{ExecMode, IOMode};
-use super::{Funge, InstructionPointer, InstructionResult, InterpreterEnv};
-use crate::fungespace::{FungeIndex, FungeSpace, FungeValue, SrcIO};
-
+use super::{Funge, InstructionPointer, InstructionResult, InterpreterEnv, Stack};
+use crate::fungespace::{CellBytes, FungeIndex, FungeSpace, SrcIO};
+
+/// `k`: execute the next instruction n times.
+///
+/// The instruction is executed in place (the IP only moves if the
+/// instruction itself moves it), skipping over any spaces and `;` blocks in
+/// between. The results of the individual iterations are combined: all forks
+/// are accumulated; `@` stops iterating, but keeps the forks made before it;
+/// `q` and panics end the loop immediately. If the instruction yields in the
+/// first iteration, `k` yields too (and will be re-executed); a yield in a
+/// later iteration ends the loop.
 pub fn iterate<'a, F: Funge>(
     ip: &'a mut InstructionPointer<F>,
     space: &'a mut F::Space,
@@ -43,65 +50,108 @@ pub fn iterate<'a, F: Funge>(
 ) -> Pin<Box<dyn Future<Output = InstructionResult> + 'a>> {
     Box::pin(async move {
         let n = ip.pop();
+        // Find the next instruction, skipping markers. (move_by already
+        // skips spaces)
         let (mut new_loc, new_val_ref) = space.move_by(ip.location, ip.delta);
-        let mut new_val = *new_val_ref;
-        let mut loop_result = InstructionResult::Continue;
-        let mut new_val_c = new_val.to_char();
-        while new_val_c == ';' {
-            // skip what must be skipped
-            // fake-execute!
-            let old_loc = ip.location;
-            ip.location = new_loc;
-            exec_instruction(new_val, ip, space, env).await;
-            let (new_loc2, new_val_ref) = space.move_by(ip.location, ip.delta);
-            new_loc = new_loc2;
-            new_val = *new_val_ref;
-            ip.location = old_loc;
-            new_val_c = new_val.to_char();
-        }
-        if let Some(n) = n.to_usize() {
-            if n == 0 {
-                // surprising behaviour! 1k leads to the next instruction
-                // being executed twice, 0k to it being skipped
-                ip.location = new_loc;
-                loop_result = InstructionResult::Continue;
-            } else {
-                let mut forks = 0;
-                for _ in 0..n {
-                    match exec_instruction(new_val, ip, space, env).await {
-                        InstructionResult::Continue => {}
-                        InstructionResult::Fork(n) => {
-                            forks += n;
-                            loop_result = InstructionResult::Fork(forks)
-                        }
-                        res => {
-                            loop_result = res;
-                            break;
-                        }
-                    }
+        let mut new_val = new_val_ref.clone();
+        while new_val == (';' as i32).into() {
+            loop {
+                let (loc, val) = space.move_by(new_loc, ip.delta);
+                new_loc = loc;
+                if *val == (';' as i32).into() {
+                    break;
                 }
             }
+            let (loc, val) = space.move_by(new_loc, ip.delta);
+            new_loc = loc;
+            new_val = val.clone();
+        }
+
+        let n = if let Some(n) = n.to_usize() {
+            n
         } else {
             // Reflect on over- or underflow
             ip.reflect();
+            return InstructionResult::Continue;
+        };
+
+        if n == 0 {
+            // surprising behaviour! 1k leads to the next instruction
+            // being executed twice, 0k to it being skipped
+            ip.location = new_loc;
+            return InstructionResult::Continue;
+        }
+
+        let limits = env.limits();
+        let mut forks = 0;
+        for i in 0..n {
+            if !limits.allows_stack_depth(ip.stack().len()) {
+                // leave it to the interpreter to abort the program
+                break;
+            }
+            match exec_instruction(new_val.clone(), ip, space, env).await {
+                InstructionResult::Continue | InstructionResult::Skip => {}
+                InstructionResult::Yield if i == 0 => {
+                    // Put the count back so k can run again
+                    ip.push(F::Value::from_usize(n).unwrap_or_else(|| 0.into()));
+                    return InstructionResult::Yield;
+                }
+                InstructionResult::Yield => {
+                    break;
+                }
+                InstructionResult::Fork(n) => {
+                    forks += n;
+                }
+                InstructionResult::Stop if forks == 0 => {
+                    return InstructionResult::Stop;
+                }
+                InstructionResult::Stop => {
+                    return InstructionResult::ForkAndStop(forks);
+                }
+                InstructionResult::ForkAndStop(n) => {
+                    return InstructionResult::ForkAndStop(forks + n);
+                }
+                res @ (InstructionResult::Exit(_) | InstructionResult::Panic) => {
+                    return res;
+                }
+            }
+        }
+
+        if forks == 0 {
+            InstructionResult::Continue
+        } else {
+            InstructionResult::Fork(forks)
         }
-        loop_result
     })
 }
 
+/// Would moving or creating `n` cells overflow a stack, or would there be
+/// too many stacks? (see [InterpreterEnv::limits])
+fn exceeds_limits(env: &impl InterpreterEnv, n: isize, stacks: usize) -> bool {
+    let limits = env.limits();
+    !limits.allows_stack_depth(n.unsigned_abs())
+        || limits
+            .max_stack_stack_height
+            .is_some_and(|max| stacks > max)
+}
+
 pub fn begin_block<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
-    _env: &mut F::Env,
+    env: &mut F::Env,
 ) -> InstructionResult {
-    if let Some(n) = ip.pop().to_isize() {
+    if let Some(n) = ip
+        .pop()
+        .to_isize()
+        .filter(|n| !exceeds_limits(env, *n, ip.stack_stack.len() + 1))
+    {
         // take n items off the SOSS (old TOSS)
         let n_to_take = max(0, min(n, ip.stack().len() as isize));
         let zeros_for_toss = max(0, n - n_to_take);
         let zeros_for_soss = max(0, -n);
 
         let split_idx = ip.stack().len() - n_to_take as usize;
-        let mut transfer_elems = ip.stack_mut().split_off(split_idx);
+        let transfer_elems: Stack<F::Value> = ip.stack_mut().drain(split_idx..).collect();
 
         for _ in 0..zeros_for_soss {
             ip.push(0.into());
@@ -111,13 +161,13 @@ pub fn begin_block<F: Funge>(
         MotionCmds::push_vector(ip, offset); // onto SOSS / old TOSS
 
         // create a new stack
-        ip.stack_stack.insert(0, Vec::new());
+        ip.push_stack();
 
         for _ in 0..zeros_for_toss {
             ip.push(0.into());
         }
 
-        ip.stack_mut().append(&mut transfer_elems);
+        ip.stack_mut().extend(transfer_elems);
 
         ip.storage_offset = ip.location + ip.delta;
     } else {
@@ -130,11 +180,11 @@ pub fn begin_block<F: Funge>(
 pub fn end_block<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
-    _env: &mut F::Env,
+    env: &mut F::Env,
 ) -> InstructionResult {
-    if ip.stack_stack.len() > 1 {
-        if let Some(n) = ip.pop().to_isize() {
-            let mut toss = ip.stack_stack.remove(0);
+    if ip.soss().is_some() {
+        if let Some(n) = ip.pop().to_isize().filter(|n| !exceeds_limits(env, *n, 0)) {
+            let mut toss = ip.pop_stack().unwrap();
 
             // restore the storage offset
             ip.storage_offset = MotionCmds::pop_vector(ip);
@@ -153,7 +203,7 @@ pub fn end_block<F: Funge>(
                 }
 
                 let split_idx = toss.len() - n_to_take as usize;
-                ip.stack_mut().append(&mut toss.split_off(split_idx));
+                ip.stack_mut().extend(toss.drain(split_idx..));
             }
         } else {
             ip.reflect();
@@ -168,26 +218,11 @@ pub fn end_block<F: Funge>(
 pub fn stack_under_stack<F: Funge>(
     ip: &mut InstructionPointer<F>,
     _space: &mut F::Space,
-    _env: &mut F::Env,
+    env: &mut F::Env,
 ) -> InstructionResult {
-    let nstacks = ip.stack_stack.len();
-    if nstacks > 1 {
-        if let Some(n) = ip.pop().to_isize() {
-            match n.cmp(&0) {
-                Ordering::Greater => {
-                    for _ in 0..n {
-                        let v = ip.stack_stack[1].pop().unwrap_or_else(|| 0.into());
-                        ip.push(v);
-                    }
-                }
-                Ordering::Less => {
-                    for _ in 0..(-n) {
-                        let v = ip.pop();
-                        ip.stack_stack[1].push(v);
-                    }
-                }
-                Ordering::Equal => {}
-            }
+    if ip.soss().is_some() {
+        if let Some(n) = ip.pop().to_isize().filter(|n| !exceeds_limits(env, *n, 0)) {
+            ip.transfer_to_soss(-n);
         } else {
             ip.reflect();
         }
@@ -197,105 +232,135 @@ pub fn stack_under_stack<F: Funge>(
     InstructionResult::Continue
 }
 
-pub fn input_file<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-) -> InstructionResult {
-    let filename = ip.pop_0gnirts();
-    let flags = ip.pop();
-    let dest = MotionCmds::pop_vector(ip);
-
-    match env.get_iomode() {
-        IOMode::Binary => {
-            if let Ok(src) = env.read_file(&filename) {
-                if flags & 1.into() == 1.into() {
-                    // "binary mode" = linear mode
-                    let mut dest = dest;
-                    for b in src {
-                        space[dest] = (b as i32).into();
-                        dest = dest.one_further();
+pub fn input_file<'a, F: Funge>(
+    ip: &'a mut InstructionPointer<F>,
+    space: &'a mut F::Space,
+    env: &'a mut F::Env,
+) -> Pin<Box<dyn Future<Output = InstructionResult> + 'a>> {
+    Box::pin(async move {
+        let filename = ip.pop_0gnirts();
+        let flags = ip.pop();
+        let dest = MotionCmds::pop_vector(ip);
+
+        match env.get_iomode() {
+            IOMode::Binary => {
+                if let Ok(src) = env.read_file_async(&filename).await {
+                    if flags & 1.into() == 1.into() {
+                        // "binary mode" = linear mode
+                        let mut dest = dest;
+                        for b in src {
+                            space[dest] = (b as i32).into();
+                            dest = dest.one_further();
+                        }
+                    } else {
+                        // "text mode"
+                        let size = F::Idx::read_bin_at(space, &dest, &src);
+                        MotionCmds::push_vector(ip, size);
+                        MotionCmds::push_vector(ip, dest);
                     }
                 } else {
-                    // "text mode"
-                    let size = F::Idx::read_bin_at(space, &dest, &src);
-                    MotionCmds::push_vector(ip, size);
-                    MotionCmds::push_vector(ip, dest);
+                    ip.reflect();
                 }
-            } else {
-                ip.reflect();
             }
-        }
-        IOMode::Text => {
-            if let Some(src) = env
-                .read_file(&filename)
-                .ok()
-                .and_then(|v| String::from_utf8(v).ok())
-            {
-                if flags & 1.into() == 1.into() {
-                    // "binary mode" = linear mode
-                    let mut dest = dest;
-                    for c in src.chars() {
-                        space[dest] = (c as i32).into();
-                        dest = dest.one_further();
+            IOMode::Text => {
+                if let Some(src) = env
+                    .read_file_async(&filename)
+                    .await
+                    .ok()
+                    .and_then(|v| String::from_utf8(v).ok())
+                {
+                    if flags & 1.into() == 1.into() {
+                        // "binary mode" = linear mode
+                        let mut dest = dest;
+                        for c in src.chars() {
+                            space[dest] = (c as i32).into();
+                            dest = dest.one_further();
+                        }
+                    } else {
+                        // "text mode"
+                        let size = F::Idx::read_str_at(space, &dest, &src);
+                        MotionCmds::push_vector(ip, size);
+                        MotionCmds::push_vector(ip, dest);
                     }
                 } else {
-                    // "text mode"
-                    let size = F::Idx::read_str_at(space, &dest, &src);
-                    MotionCmds::push_vector(ip, size);
-                    MotionCmds::push_vector(ip, dest);
+                    ip.reflect();
                 }
-            } else {
-                ip.reflect();
             }
         }
-    }
 
-    InstructionResult::Continue
+        InstructionResult::Continue
+    })
 }
 
-pub fn output_file<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    space: &mut F::Space,
-    env: &mut F::Env,
-) -> InstructionResult {
-    let filename = ip.pop_0gnirts();
-    let flags = ip.pop();
-    let start = MotionCmds::pop_vector(ip);
-    let size = MotionCmds::pop_vector(ip);
-
-    let strip = (flags & 1.into()) == 1.into();
-
-    if match env.get_iomode() {
-        IOMode::Binary => {
-            env.write_file(&filename, &F::Idx::get_src_bin(space, &start, &size, strip))
+pub fn output_file<'a, F: Funge>(
+    ip: &'a mut InstructionPointer<F>,
+    space: &'a mut F::Space,
+    env: &'a mut F::Env,
+) -> Pin<Box<dyn Future<Output = InstructionResult> + 'a>> {
+    Box::pin(async move {
+        let filename = ip.pop_0gnirts();
+        let flags = ip.pop();
+        let start = MotionCmds::pop_vector(ip);
+        let size = MotionCmds::pop_vector(ip);
+
+        let strip = (flags & 1.into()) == 1.into();
+
+        let iomode = env.get_iomode();
+        let space = &*space;
+        let mut write = |out: &mut dyn io::Write| match iomode {
+            IOMode::Binary => F::Idx::write_src_bin_to(space, &start, &size, strip, out),
+            IOMode::Text => F::Idx::write_src_to(space, &start, &size, strip, out),
+        };
+        if env
+            .write_file_with_async(&filename, &mut write)
+            .await
+            .is_err()
+        {
+            ip.reflect();
         }
-        IOMode::Text => env.write_file(
-            &filename,
-            F::Idx::get_src_str(space, &start, &size, strip).as_bytes(),
-        ),
-    }
-    .is_err()
-    {
-        ip.reflect();
-    }
 
-    InstructionResult::Continue
+        InstructionResult::Continue
+    })
 }
 
-pub fn execute<F: Funge>(
-    ip: &mut InstructionPointer<F>,
-    _space: &mut F::Space,
-    env: &mut F::Env,
-) -> InstructionResult {
-    if env.have_execute() == ExecMode::Disabled {
-        ip.reflect();
-    } else {
-        let cmd = ip.pop_0gnirts();
-        ip.push(env.execute_command(&cmd).into());
-    }
+pub fn execute<'a, F: Funge>(
+    ip: &'a mut InstructionPointer<F>,
+    space: &'a mut F::Space,
+    env: &'a mut F::Env,
+) -> Pin<Box<dyn Future<Output = InstructionResult> + 'a>> {
+    Box::pin(async move {
+        match env.have_execute() {
+            ExecMode::Disabled => ip.reflect(),
+            ExecMode::Capture => {
+                let cmd = ip.pop_0gnirts();
+                let flags = ip.pop();
+                let (status, output) = env.execute_command_capture_async(&cmd).await;
+                if flags & 1.into() == 1.into() {
+                    let dest = MotionCmds::pop_vector(ip);
+                    let size = match env.get_iomode() {
+                        IOMode::Binary => F::Idx::read_bin_at(space, &dest, &output),
+                        IOMode::Text => {
+                            F::Idx::read_str_at(space, &dest, &String::from_utf8_lossy(&output))
+                        }
+                    };
+                    MotionCmds::push_vector(ip, size);
+                    MotionCmds::push_vector(ip, dest);
+                } else {
+                    let output = String::from_utf8_lossy(&output);
+                    let line = output.lines().next().unwrap_or_default();
+                    ip.push_0gnirts(line);
+                }
+                ip.push(status.into());
+            }
+            _ => {
+                let cmd = ip.pop_0gnirts();
+                let status = env.execute_command_async(&cmd).await;
+                ip.push(status.into());
+            }
+        }
 
-    InstructionResult::Continue
+        InstructionResult::Continue
+    })
 }
 
 pub fn sysinfo<F: Funge>(
@@ -326,7 +391,7 @@ pub fn sysinfo<F: Funge>(
     sysinfo_cells.push(impl_flags.into());
 
     // 2. size of cell
-    sysinfo_cells.push((size_of::<F::Value>() as i32).into());
+    sysinfo_cells.push(F::Value::CELL_BYTES.into());
 
     // 3. handprint
     sysinfo_cells.push(env.handprint().into());
@@ -344,6 +409,8 @@ pub fn sysinfo<F: Funge>(
             ExecMode::System => 1,
             ExecMode::SpecificShell => 2,
             ExecMode::SameShell => 3,
+            // Not in the spec: = takes and returns more than it should
+            ExecMode::Capture => 4,
         }
         .into(),
     );
@@ -355,10 +422,10 @@ pub fn sysinfo<F: Funge>(
     sysinfo_cells.push(F::Idx::RANK.into());
 
     // 8. IP ID
-    sysinfo_cells.push(ip.id);
+    sysinfo_cells.push(ip.id.clone());
 
     // 9. IP team number
-    sysinfo_cells.push(0.into());
+    sysinfo_cells.push(env.team_number(ip).into());
 
     // 10. Position
     let mut tmp_vec = Vec::new();
@@ -399,7 +466,7 @@ pub fn sysinfo<F: Funge>(
     }
 
     // 15 & 16: Time
-    let datetime = Utc::now();
+    let datetime = env.now_utc();
 
     // 15. ((year - 1900) * 256 * 256) + (month * 256) + (day of month)
     sysinfo_cells.push(
@@ -420,8 +487,8 @@ pub fn sysinfo<F: Funge>(
     // 17. size of stack stack
     sysinfo_cells.push((ip.stack_stack.len() as i32).into());
 
-    // 18. sizes of stacks
-    for stack in ip.stack_stack.iter() {
+    // 18. sizes of stacks, from the TOSS down
+    for stack in ip.stack_stack.iter().rev() {
         sysinfo_cells.push((stack.len() as i32).into());
     }
 
@@ -449,13 +516,12 @@ pub fn sysinfo<F: Funge>(
     if n > (sysinfo_cells.len() as i32).into() {
         // pick one pre-sysinfo cell
         let pick_n = n - (sysinfo_cells.len() as i32).into();
-        let idx = ip.stack().len() as isize - pick_n.to_isize().unwrap();
-        if idx >= 0 {
-            ip.push(ip.stack()[idx as usize]);
-        }
+        // picking from below the bottom of the stack gives 0, like `pop`
+        let v = ip.peek_nth(pick_n.to_usize().unwrap_or(usize::MAX) - 1);
+        ip.push(v);
     } else if n > 0.into() {
         // pick one cell from sysinfo
-        ip.push(sysinfo_cells[n.to_usize().unwrap() - 1]);
+        ip.push(sysinfo_cells[n.to_usize().unwrap() - 1].clone());
     } else {
         // push it all
         for cell in sysinfo_cells.into_iter().rev() {
@@ -465,3 +531,419 @@ pub fn sysinfo<F: Funge>(
 
     InstructionResult::Continue
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::{TimeZone, Utc};
+    use futures_lite::future::block_on;
+    use futures_lite::io::{AsyncRead, AsyncWrite};
+    use hashbrown::HashMap;
+
+    use super::super::filesystem::MemoryFilesystem;
+    use super::super::instruction_set::sync_instruction;
+    use super::super::tests::{befunge_space, NoEnv, TestFunge};
+    use super::super::EnvFuture;
+    use super::*;
+    use crate::fungespace::{bfvec, BefungeVec, PagedFungeSpace};
+
+    /// Test instruction `X` with a scripted result: 0 continue, 1 fork,
+    /// 2 stop, 3 exit, 4 skip, 5 fork twice and stop
+    fn scripted(
+        ip: &mut InstructionPointer<TestFunge>,
+        _space: &mut <TestFunge as Funge>::Space,
+        _env: &mut <TestFunge as Funge>::Env,
+    ) -> InstructionResult {
+        match ip.pop() {
+            0 => InstructionResult::Continue,
+            1 => InstructionResult::Fork(1),
+            2 => InstructionResult::Stop,
+            3 => InstructionResult::Exit(3),
+            4 => InstructionResult::Skip,
+            5 => InstructionResult::ForkAndStop(2),
+            _ => InstructionResult::Panic,
+        }
+    }
+
+    /// Run the `k` at the origin of `src` with the given stack (top last)
+    fn run_k(src: &str, stack: &[i64]) -> (InstructionResult, InstructionPointer<TestFunge>) {
+        let mut space = befunge_space(src);
+        let mut env = NoEnv::new();
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        ip.location = bfvec(0, 0);
+        let mut layer = HashMap::new();
+        layer.insert('X', sync_instruction(scripted));
+        ip.instructions.add_layer(layer);
+        for v in stack {
+            ip.push(*v);
+        }
+        let result = block_on(iterate(&mut ip, &mut space, &mut env));
+        (result, ip)
+    }
+
+    #[test]
+    fn test_k_results() {
+        let matrix: &[(&[i64], InstructionResult, usize)] = &[
+            (&[0, 0, 3], InstructionResult::Continue, 0),
+            (&[4, 4, 2], InstructionResult::Continue, 0),
+            (&[1, 1, 1, 3], InstructionResult::Fork(3), 0),
+            (&[1, 0, 1, 3], InstructionResult::Fork(2), 0),
+            (&[0, 2, 2], InstructionResult::Stop, 1),
+            (&[2, 1, 1, 3], InstructionResult::ForkAndStop(2), 0),
+            (&[1, 5, 2], InstructionResult::ForkAndStop(2), 1),
+            (&[5, 1, 2], InstructionResult::ForkAndStop(3), 0),
+            (&[3, 1, 2], InstructionResult::Exit(3), 0),
+            (&[1, 3, 2], InstructionResult::Exit(3), 1),
+            (&[0, 6, 2], InstructionResult::Panic, 1),
+        ];
+        for (stack, expected, remaining) in matrix {
+            let (result, ip) = run_k("kX", stack);
+            assert_eq!(result, *expected, "stack {:?}", stack);
+            assert_eq!(ip.stack().len(), *remaining, "stack {:?}", stack);
+            assert_eq!(ip.location, bfvec(0, 0));
+        }
+    }
+
+    #[test]
+    fn test_k_over_markers() {
+        for src in &["k;abc;X", "k  X", "k ; @ ; ;;X", "k;;X"] {
+            let (result, ip) = run_k(src, &[1, 1, 2]);
+            assert_eq!(result, InstructionResult::Fork(2), "{:?}", src);
+            assert_eq!(ip.location, bfvec(0, 0));
+        }
+    }
+
+    #[test]
+    fn test_k_zero_and_negative() {
+        // 0k skips the next instruction (including any markers before it)
+        let (result, ip) = run_k("k ;abc; X", &[2, 0]);
+        assert_eq!(result, InstructionResult::Continue);
+        assert_eq!(ip.location, bfvec(8, 0));
+        assert_eq!(ip.stack()[..], [2]);
+        // negative counts reflect
+        let (result, ip) = run_k("kX", &[2, -1]);
+        assert_eq!(result, InstructionResult::Continue);
+        assert_eq!(ip.delta, bfvec(-1, 0));
+        assert_eq!(ip.stack()[..], [2]);
+    }
+
+    /// Run `y` at (7, 2) with the given stack (top last), returning the
+    /// resulting stack
+    fn run_y(space: &mut <TestFunge as Funge>::Space, env: &mut NoEnv, stack: &[i64]) -> Vec<i64> {
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        ip.location = bfvec(7, 2);
+        ip.delta = bfvec(0, -1);
+        ip.storage_offset = bfvec(3, 4);
+        for v in stack {
+            ip.push(*v);
+        }
+        assert_eq!(sysinfo(&mut ip, space, env), InstructionResult::Continue);
+        ip.stack().to_vec()
+    }
+
+    /// Execute `1kp` at the origin to write `value` at `(x, y)`
+    fn p_at(space: &mut <TestFunge as Funge>::Space, value: i64, x: i64, y: i64) {
+        let mut env = NoEnv::new();
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        BefungeVec::read_str_at(space, &bfvec(0, 0), "kp");
+        ip.location = bfvec(0, 0);
+        ip.push(value);
+        ip.push(x);
+        ip.push(y);
+        ip.push(1);
+        let result = block_on(iterate(&mut ip, space, &mut env));
+        assert_eq!(result, InstructionResult::Continue);
+    }
+
+    #[test]
+    fn test_input_file_size() {
+        // (file, expected size)
+        let cases: &[(&[u8], (i64, i64))] = &[
+            (b"abc", (3, 1)),
+            (b"ab\ncde\n", (3, 2)),
+            (b"ab\r\n\r\ncde", (3, 3)),
+            (b"a\x0cb\x0c\n\x0cc", (2, 2)),
+            (b"", (0, 0)),
+        ];
+        for (file, (size_x, size_y)) in cases {
+            let mut space = befunge_space("");
+            let mut env = NoEnv::new();
+            env.fs = Some(MemoryFilesystem::new());
+            env.fs.as_ref().unwrap().insert("f", *file);
+            let mut ip = InstructionPointer::<TestFunge>::new();
+            // 0gnirts "f", flags, destination
+            for v in [5, -2, 0, 0, 'f' as i64] {
+                ip.push(v);
+            }
+            assert_eq!(
+                block_on(input_file(&mut ip, &mut space, &mut env)),
+                InstructionResult::Continue
+            );
+            assert_eq!(ip.stack()[..], [*size_x, *size_y, 5, -2]);
+        }
+    }
+
+    #[test]
+    fn test_output_file() {
+        let mut space = befunge_space("abc  \nde");
+        let mut env = NoEnv::new();
+        env.fs = Some(MemoryFilesystem::new());
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        // size, start, flags (strip), 0gnirts "out"
+        for v in [5, 2, 0, 0, 1, 0, 't' as i64, 'u' as i64, 'o' as i64] {
+            ip.push(v);
+        }
+        assert_eq!(
+            block_on(output_file(&mut ip, &mut space, &mut env)),
+            InstructionResult::Continue
+        );
+        assert_eq!(env.fs.unwrap().get("out").unwrap(), b"abc\nde");
+    }
+
+    #[test]
+    fn test_execute_capture() {
+        let mut space = befunge_space("");
+        let mut env = NoEnv::new();
+        env.exec_output = Some(b"one\ntwo\n".to_vec());
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        // flags, 0gnirts "ls": push the first line, and the status on top
+        for v in [7, 0, 0, 's' as i64, 'l' as i64] {
+            ip.push(v);
+        }
+        assert_eq!(
+            block_on(execute(&mut ip, &mut space, &mut env)),
+            InstructionResult::Continue
+        );
+        assert_eq!(
+            ip.stack()[..],
+            [7, 0, 'e' as i64, 'n' as i64, 'o' as i64, 2]
+        );
+
+        // destination, flags, 0gnirts "ls": write the output to funge-space
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        for v in [3, 4, 1, 0, 's' as i64, 'l' as i64] {
+            ip.push(v);
+        }
+        assert_eq!(
+            block_on(execute(&mut ip, &mut space, &mut env)),
+            InstructionResult::Continue
+        );
+        assert_eq!(ip.stack()[..], [3, 2, 3, 4, 2]);
+        assert_eq!(space[bfvec(3, 4)], 'o' as i64);
+        assert_eq!(space[bfvec(5, 5)], 'o' as i64);
+
+        // y tells programs that = works differently: the flags say = is
+        // there, but the operating paradigm isn't the standard system() one
+        let cells = run_y(&mut space, &mut env, &[0]);
+        let cells: Vec<i64> = cells.iter().rev().cloned().collect();
+        assert_eq!(cells[0] & 0x8, 0x8);
+        assert_eq!(cells[4], 4);
+    }
+
+    #[test]
+    fn test_sysinfo_cells() {
+        let mut space = befunge_space("y");
+        let mut env = NoEnv::new();
+        env.team = 3;
+        let all = run_y(&mut space, &mut env, &[10, 20, 0]);
+        // 27 cells with no argv or environment, on top of the existing stack
+        assert_eq!(all.len(), 2 + 27);
+        assert_eq!(&all[..2], &[10, 20]);
+        let cells: Vec<i64> = all[2..].iter().rev().cloned().collect();
+        assert_eq!(cells[0], 0x1); // concurrent, no i/o/=, buffered
+        assert_eq!(cells[1], 8);
+        assert_eq!(cells[2], 0x52464e47);
+        assert_eq!(cells[4], 0);
+        assert_eq!(cells[5], std::path::MAIN_SEPARATOR as i64);
+        assert_eq!(cells[6], 2);
+        assert_eq!(cells[7], 0);
+        assert_eq!(cells[8], 3);
+        // vectors are listed as they would be pushed: y above x
+        assert_eq!(&cells[9..11], &[2, 7]);
+        assert_eq!(&cells[11..13], &[-1, 0]);
+        assert_eq!(&cells[13..15], &[4, 3]);
+        // the only non-space cell is the `y` at the origin
+        assert_eq!(&cells[15..17], &[0, 0]);
+        assert_eq!(&cells[17..19], &[0, 0]);
+        // day: month and day of month are in range
+        assert!((1..=12).contains(&(cells[19] / 256 % 256)));
+        assert!((1..=31).contains(&(cells[19] % 256)));
+        assert!(cells[19] / 65536 >= 121);
+        // time
+        assert!(cells[20] / 65536 < 24 && cells[20] / 256 % 256 < 60 && cells[20] % 256 < 60);
+        // one stack, holding 2 cells once n has been popped
+        assert_eq!(&cells[21..23], &[1, 2]);
+        // empty argv and environment
+        assert_eq!(&cells[23..], &[0, 0, 0, 0]);
+
+        // picking individual cells gives the same results (except the clock)
+        for n in 1..=27 {
+            if n == 20 || n == 21 {
+                continue;
+            }
+            let picked = run_y(&mut space, &mut env, &[10, 20, n]);
+            assert_eq!(picked, vec![10, 20, cells[n as usize - 1]], "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn test_sysinfo_frozen_time() {
+        let mut space = befunge_space("");
+        let mut env = NoEnv::new();
+        env.now = Some(Utc.with_ymd_and_hms(2021, 10, 1, 13, 45, 6).unwrap());
+        assert_eq!(
+            run_y(&mut space, &mut env, &[20]),
+            vec![121 * 65536 + 10 * 256 + 1]
+        );
+        assert_eq!(
+            run_y(&mut space, &mut env, &[21]),
+            vec![13 * 65536 + 45 * 256 + 6]
+        );
+    }
+
+    #[test]
+    fn test_sysinfo_pick_from_stack() {
+        let mut space = befunge_space("");
+        let mut env = NoEnv::new();
+        assert_eq!(run_y(&mut space, &mut env, &[10, 20, 28]), vec![10, 20, 20]);
+        assert_eq!(run_y(&mut space, &mut env, &[10, 20, 29]), vec![10, 20, 10]);
+        // below the bottom of the stack
+        assert_eq!(run_y(&mut space, &mut env, &[10, 20, 30]), vec![10, 20, 0]);
+        assert_eq!(run_y(&mut space, &mut env, &[1000]), vec![0]);
+    }
+
+    #[test]
+    fn test_sysinfo_bounds_with_negative_coordinates() {
+        let mut space = befunge_space("");
+        let mut env = NoEnv::new();
+        // `kp` at the origin, and an `a` written to the left of it
+        p_at(&mut space, 'a' as i64, -5, -3);
+        assert_eq!(run_y(&mut space, &mut env, &[16]), vec![-3]);
+        assert_eq!(run_y(&mut space, &mut env, &[17]), vec![-5]);
+        assert_eq!(run_y(&mut space, &mut env, &[18]), vec![3]);
+        assert_eq!(run_y(&mut space, &mut env, &[19]), vec![6]);
+
+        // further away, in other pages; blanks don't count
+        p_at(&mut space, 'b' as i64, -100, 7);
+        p_at(&mut space, 'c' as i64, 30, -40);
+        p_at(&mut space, ' ' as i64, -500, -500);
+        let cells: Vec<i64> = run_y(&mut space, &mut env, &[0])
+            .into_iter()
+            .rev()
+            .collect();
+        assert_eq!(&cells[15..17], &[-40, -100]);
+        assert_eq!(&cells[17..19], &[47, 130]);
+    }
+
+    /// Only provides the async file and command methods, so `i`, `o` and
+    /// `=` only work if they go through them
+    struct AsyncOnlyEnv {
+        input: async_std::io::Empty,
+        output: async_std::io::Sink,
+        files: HashMap<String, Vec<u8>>,
+        commands: Vec<String>,
+    }
+
+    impl InterpreterEnv for AsyncOnlyEnv {
+        fn get_iomode(&self) -> IOMode {
+            IOMode::Text
+        }
+        fn is_io_buffered(&self) -> bool {
+            true
+        }
+        fn output_writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
+            &mut self.output
+        }
+        fn input_reader(&mut self) -> &mut (dyn AsyncRead + Unpin) {
+            &mut self.input
+        }
+        fn warn(&mut self, _msg: &str) {}
+        fn have_execute(&self) -> ExecMode {
+            ExecMode::System
+        }
+        fn read_file_async<'a>(
+            &'a mut self,
+            filename: &'a str,
+        ) -> EnvFuture<'a, io::Result<Vec<u8>>> {
+            Box::pin(async move {
+                self.files
+                    .get(filename)
+                    .cloned()
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+            })
+        }
+        fn write_file_with_async<'a>(
+            &'a mut self,
+            filename: &'a str,
+            write: &'a mut dyn FnMut(&mut dyn io::Write) -> io::Result<()>,
+        ) -> EnvFuture<'a, io::Result<()>> {
+            Box::pin(async move {
+                let mut content = Vec::new();
+                write(&mut content)?;
+                self.files.insert(filename.to_owned(), content);
+                Ok(())
+            })
+        }
+        fn execute_command_async<'a>(&'a mut self, command: &'a str) -> EnvFuture<'a, i32> {
+            Box::pin(async move {
+                self.commands.push(command.to_owned());
+                42
+            })
+        }
+    }
+
+    struct AsyncFunge {}
+
+    impl Funge for AsyncFunge {
+        type Idx = BefungeVec<i64>;
+        type Space = PagedFungeSpace<BefungeVec<i64>, i64>;
+        type Value = i64;
+        type Env = AsyncOnlyEnv;
+    }
+
+    #[test]
+    fn test_async_env_methods() {
+        let mut space = befunge_space("");
+        let mut env = AsyncOnlyEnv {
+            input: async_std::io::empty(),
+            output: async_std::io::sink(),
+            files: HashMap::new(),
+            commands: Vec::new(),
+        };
+        env.files.insert("in".to_owned(), b"xy".to_vec());
+        let mut ip = InstructionPointer::<AsyncFunge>::new();
+
+        // i: destination, flags, 0gnirts "in"
+        for v in [0, 1, 0, 0, 'n' as i64, 'i' as i64] {
+            ip.push(v);
+        }
+        assert_eq!(
+            block_on(input_file(&mut ip, &mut space, &mut env)),
+            InstructionResult::Continue
+        );
+        assert_eq!(ip.stack()[..], [2, 1, 0, 1]);
+        assert_eq!(space[bfvec(1, 1)], 'y' as i64);
+
+        // o: size, start, flags, 0gnirts "out"
+        ip.stack_mut().clear();
+        for v in [2, 1, 0, 1, 0, 0, 't' as i64, 'u' as i64, 'o' as i64] {
+            ip.push(v);
+        }
+        assert_eq!(
+            block_on(output_file(&mut ip, &mut space, &mut env)),
+            InstructionResult::Continue
+        );
+        assert!(ip.stack().is_empty());
+        assert_eq!(env.files["out"], b"xy");
+
+        // =: 0gnirts "cmd"
+        for v in [0, 'd' as i64, 'm' as i64, 'c' as i64] {
+            ip.push(v);
+        }
+        assert_eq!(
+            block_on(execute(&mut ip, &mut space, &mut env)),
+            InstructionResult::Continue
+        );
+        assert_eq!(ip.stack()[..], [42]);
+        assert_eq!(env.commands, vec!["cmd"]);
+    }
+}
@@ -17,16 +17,160 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
 use hashbrown::HashMap;
+use num::ToPrimitive;
+use smallvec::SmallVec;
 use std::any::Any;
-use std::ops::Index;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
 use std::rc::Rc;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use super::instruction_set::InstructionSet;
 use super::motion::MotionCmds;
-use super::{Funge, InterpreterEnv};
+use super::{Funge, InterpreterEnv, SharedServices};
 use crate::fungespace::index::{bfvec, BefungeVec};
 use crate::fungespace::{FungeSpace, FungeValue, SrcIO};
 
+/// A single stack on the stack stack
+///
+/// Stacks of up to [STACK_INLINE] cells are stored inside the IP itself, so
+/// that new IPs (and new stacks from `{`) don't need a heap allocation until
+/// their stack grows.
+pub type Stack<T> = SmallVec<[T; STACK_INLINE]>;
+
+/// How many cells a [Stack] holds before moving to the heap
+pub const STACK_INLINE: usize = 8;
+
+/// The modes an IP can be in, as a set of flags that can be combined with `|`
+///
+/// Modes are part of the state of the IP, so an IP created with `t` starts
+/// out in the same modes as its parent.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IpMode(u32);
+
+impl IpMode {
+    /// String mode (`"`): cells are pushed rather than executed
+    pub const STRING: Self = Self(1);
+    /// In string mode, push a run of spaces as a single space, as Funge-98
+    /// requires. Without this, every space is pushed, as in Befunge-93. Set
+    /// by default.
+    pub const SGML_SPACES: Self = Self(1 << 1);
+    /// Hover mode: `<`, `>`, `^`, `v`, `_` and `|` add to the delta rather
+    /// than replacing it
+    pub const HOVER: Self = Self(1 << 2);
+    /// Invert mode: [InstructionPointer::push] pushes onto the bottom of the
+    /// stack
+    pub const INVERT: Self = Self(1 << 3);
+    /// Queue mode: [InstructionPointer::pop] pops from the bottom of the
+    /// stack
+    pub const QUEUE: Self = Self(1 << 4);
+
+    const NAMES: [(Self, &'static str); 5] = [
+        (Self::STRING, "STRING"),
+        (Self::SGML_SPACES, "SGML_SPACES"),
+        (Self::HOVER, "HOVER"),
+        (Self::INVERT, "INVERT"),
+        (Self::QUEUE, "QUEUE"),
+    ];
+
+    /// No modes at all
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Convert from bits, dropping any that don't stand for a mode
+    pub const fn from_bits_truncate(bits: u32) -> Self {
+        Self(bits & 0x1f)
+    }
+
+    /// Are all the modes in `other` set?
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    pub fn toggle(&mut self, other: Self) {
+        self.0 ^= other.0;
+    }
+
+    /// Insert or remove the modes in `other`
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+}
+
+impl Default for IpMode {
+    fn default() -> Self {
+        Self::SGML_SPACES
+    }
+}
+
+impl BitOr for IpMode {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for IpMode {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for IpMode {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl fmt::Debug for IpMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .filter(|(mode, _)| self.contains(*mode))
+            .map(|(_, name)| *name)
+            .collect();
+        if names.is_empty() {
+            write!(f, "IpMode(empty)")
+        } else {
+            write!(f, "IpMode({})", names.join(" | "))
+        }
+    }
+}
+
+/// One step in an IP's [history](InstructionPointer::history)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry<Idx, Value> {
+    /// The cell the IP moved to
+    pub location: Idx,
+    /// The delta the IP arrived with
+    pub delta: Idx,
+    /// The instruction in that cell
+    pub instruction: Value,
+}
+
 /// Struct encapsulating the state of the/an IP
 #[derive(Debug)]
 pub struct InstructionPointer<F: Funge + 'static> {
@@ -38,13 +182,27 @@ pub struct InstructionPointer<F: Funge + 'static> {
     pub delta: F::Idx,
     /// Current storage offset (initial: the origin)
     pub storage_offset: F::Idx,
-    /// The stack stack
-    pub stack_stack: Vec<Vec<F::Value>>,
+    /// The stack stack. Like the values on each stack, the stacks are in
+    /// order from the bottom up: the last one is the TOSS. There is always
+    /// at least one stack.
+    pub stack_stack: Vec<Stack<F::Value>>,
+    /// String mode etc. (initial: [IpMode::SGML_SPACES])
+    pub mode: IpMode,
+    /// Team number, as reported by sysinfo (`y`) (initial: 0, no team). IPs
+    /// created with `t` join the team of their parent.
+    pub team: i32,
+    /// The last instructions executed, oldest first, if enabled with
+    /// [Interpreter::set_history_length](super::Interpreter::set_history_length).
+    /// IPs created with `t` start with a copy of their parent's history.
+    pub history: VecDeque<HistoryEntry<F::Idx, F::Value>>,
     /// The currently available
     pub instructions: InstructionSet<F>,
     /// If instructions or fingerprints need to store additional data with the
     /// IP, put them here.
     pub private_data: HashMap<String, Rc<dyn Any>>,
+    /// Services shared with all other IPs (the same as
+    /// [Interpreter::shared](super::Interpreter::shared))
+    pub shared: SharedServices,
 }
 
 // Can't derive Clone by macro because it requires the type parameters to be
@@ -52,13 +210,17 @@ pub struct InstructionPointer<F: Funge + 'static> {
 impl<F: Funge + 'static> Clone for InstructionPointer<F> {
     fn clone(&self) -> Self {
         Self {
-            id: self.id,
+            id: self.id.clone(),
             location: self.location,
             delta: self.delta,
             storage_offset: self.storage_offset,
             stack_stack: self.stack_stack.clone(),
+            mode: self.mode,
+            team: self.team,
+            history: self.history.clone(),
             instructions: self.instructions.clone(),
             private_data: self.private_data.clone(),
+            shared: self.shared.clone(),
         }
     }
 }
@@ -77,8 +239,9 @@ where
 
 impl<T, Space, Env> CreateInstructionPointer<Space, Env> for T
 where
-    T: FungeValue,
-    Space: FungeSpace<T> + Index<T, Output = T>,
+    T: FungeValue + Copy,
+    Space: FungeSpace<T>,
+    Space::Output: FungeValue,
     Env: InterpreterEnv,
 {
     fn new_ip<F: Funge<Idx = Self>>() -> InstructionPointer<F> {
@@ -87,17 +250,22 @@ where
             location: (-1).into(),
             delta: 1.into(),
             storage_offset: 0.into(),
-            stack_stack: vec![Vec::new()],
+            stack_stack: vec![Stack::new()],
+            mode: IpMode::default(),
+            team: 0,
+            history: VecDeque::new(),
             instructions: InstructionSet::new(),
             private_data: HashMap::new(),
+            shared: SharedServices::new(),
         }
     }
 }
 
 impl<T, Space, Env> CreateInstructionPointer<Space, Env> for BefungeVec<T>
 where
-    T: FungeValue,
-    Space: FungeSpace<BefungeVec<T>, Output = T>,
+    T: FungeValue + Copy,
+    Space: FungeSpace<BefungeVec<T>>,
+    Space::Output: FungeValue,
     Env: InterpreterEnv,
 {
     fn new_ip<F: Funge<Idx = Self>>() -> InstructionPointer<F> {
@@ -106,9 +274,13 @@ where
             location: bfvec(-1, 0),
             delta: bfvec(1, 0),
             storage_offset: bfvec(0, 0),
-            stack_stack: vec![Vec::new()],
+            stack_stack: vec![Stack::new()],
+            mode: IpMode::default(),
+            team: 0,
+            history: VecDeque::new(),
             instructions: InstructionSet::new(),
             private_data: HashMap::new(),
+            shared: SharedServices::new(),
         }
     }
 }
@@ -134,53 +306,257 @@ where
 }
 
 impl<F: Funge + 'static> InstructionPointer<F> {
-    /// Get the top of the stack stack
+    /// Get the top of the stack stack (the TOSS)
     #[inline]
-    pub fn stack(&self) -> &Vec<F::Value> {
-        &self.stack_stack[0]
+    pub fn stack(&self) -> &Stack<F::Value> {
+        let toss = self.stack_stack.len() - 1;
+        &self.stack_stack[toss]
     }
 
     /// Get the top of the stack stack (mutable version)
     #[inline]
-    pub fn stack_mut(&mut self) -> &mut Vec<F::Value> {
-        &mut self.stack_stack[0]
+    pub fn stack_mut(&mut self) -> &mut Stack<F::Value> {
+        let toss = self.stack_stack.len() - 1;
+        &mut self.stack_stack[toss]
+    }
+
+    /// Get the second stack on the stack stack (the SOSS), if there is one
+    pub fn soss(&self) -> Option<&Stack<F::Value>> {
+        let n = self.stack_stack.len();
+        if n > 1 {
+            Some(&self.stack_stack[n - 2])
+        } else {
+            None
+        }
+    }
+
+    /// Get the second stack on the stack stack (mutable version)
+    pub fn soss_mut(&mut self) -> Option<&mut Stack<F::Value>> {
+        let n = self.stack_stack.len();
+        if n > 1 {
+            Some(&mut self.stack_stack[n - 2])
+        } else {
+            None
+        }
+    }
+
+    /// Put a new, empty stack on top of the stack stack
+    pub fn push_stack(&mut self) {
+        self.stack_stack.push(Stack::new());
+    }
+
+    /// Take the TOSS off the stack stack, unless it is the only stack
+    pub fn pop_stack(&mut self) -> Option<Stack<F::Value>> {
+        if self.stack_stack.len() > 1 {
+            self.stack_stack.pop()
+        } else {
+            None
+        }
+    }
+
+    /// Get the `n`th value from the top of the stack (0 being the top)
+    /// without popping anything, or zero if the stack isn't that deep
+    pub fn peek_nth(&self, n: usize) -> F::Value {
+        let stack = self.stack();
+        if n < stack.len() {
+            stack[stack.len() - 1 - n].clone()
+        } else {
+            0.into()
+        }
+    }
+
+    /// Rotate the top of the stack like Forth's `roll`: for positive `n`,
+    /// move the `n`th value from the top (0 being the top) to the top, or
+    /// push a zero if the stack isn't that deep. For negative `n`, move the
+    /// top value down below `-n` others, adding zeros at the bottom of the
+    /// stack as needed.
+    pub fn roll(&mut self, n: isize) {
+        let stack = self.stack_mut();
+        let depth = n.unsigned_abs();
+        match n.cmp(&0) {
+            Ordering::Greater => {
+                let v = if depth < stack.len() {
+                    stack.remove(stack.len() - 1 - depth)
+                } else {
+                    0.into()
+                };
+                stack.push(v);
+            }
+            Ordering::Less => {
+                let v = stack.pop().unwrap_or_else(|| 0.into());
+                if stack.len() < depth {
+                    let missing = depth - stack.len();
+                    stack.insert_many(0, (0..missing).map(|_| 0.into()));
+                }
+                let idx = stack.len() - depth;
+                stack.insert(idx, v);
+            }
+            Ordering::Equal => {}
+        }
+    }
+
+    /// Move `n` values from the TOSS to the SOSS one at a time (reversing
+    /// their order), or, for negative `n`, `-n` values from the SOSS to the
+    /// TOSS. This is what `u` does with a count of `-n`. Values missing from
+    /// the source stack are taken to be zero.
+    ///
+    /// Returns `false` (and does nothing) if there is no SOSS.
+    pub fn transfer_to_soss(&mut self, n: isize) -> bool {
+        if self.soss().is_none() {
+            return false;
+        }
+        if n > 0 {
+            for _ in 0..n {
+                let v = self.pop();
+                self.soss_mut().unwrap().push(v);
+            }
+        } else {
+            for _ in 0..(-n) {
+                let v = self.soss_mut().unwrap().pop().unwrap_or_else(|| 0.into());
+                self.push(v);
+            }
+        }
+        true
     }
 
-    /// Pop one number from the stack and return it
+    /// Pop one number from the stack and return it (from the bottom of the
+    /// stack in [IpMode::QUEUE])
     #[inline]
     pub fn pop(&mut self) -> F::Value {
-        self.stack_mut().pop().unwrap_or_else(|| 0.into())
+        if self.mode.contains(IpMode::QUEUE) {
+            let stack = self.stack_mut();
+            if stack.is_empty() {
+                0.into()
+            } else {
+                stack.remove(0)
+            }
+        } else {
+            self.stack_mut().pop().unwrap_or_else(|| 0.into())
+        }
     }
 
-    /// Push a number onto the stack
+    /// Push a number onto the stack (onto the bottom of the stack in
+    /// [IpMode::INVERT])
     #[inline]
     pub fn push(&mut self, v: F::Value) {
-        self.stack_mut().push(v)
+        if self.mode.contains(IpMode::INVERT) {
+            self.stack_mut().insert(0, v)
+        } else {
+            self.stack_mut().push(v)
+        }
+    }
+
+    /// Push several numbers, in order, as if with [Self::push] (so that the
+    /// last one ends up on top, or at the bottom in [IpMode::INVERT])
+    pub fn push_cells<I: IntoIterator<Item = F::Value>>(&mut self, values: I) {
+        if self.mode.contains(IpMode::INVERT) {
+            for v in values {
+                self.push(v);
+            }
+        } else {
+            self.stack_mut().extend(values);
+        }
+    }
+
+    /// Pop a 0gnirts and add its cells, first character first and
+    /// converted with `f`, to `buf`.
+    ///
+    /// Unless the IP is in [IpMode::QUEUE], the string is found in the stack
+    /// and copied over in one go, rather than popped cell by cell.
+    fn pop_0gnirts_with<T, B: Extend<T>>(&mut self, buf: &mut B, f: impl Fn(&F::Value) -> T) {
+        let zero: F::Value = 0.into();
+        if self.mode.contains(IpMode::QUEUE) {
+            buf.extend(std::iter::from_fn(|| {
+                let c = self.pop();
+                (c != zero).then(|| f(&c))
+            }));
+            return;
+        }
+        let stack = self.stack_mut();
+        let terminator = stack.iter().rposition(|c| *c == zero);
+        buf.extend(stack[terminator.map_or(0, |i| i + 1)..].iter().rev().map(f));
+        stack.truncate(terminator.unwrap_or(0));
     }
 
-    /// Pop a 0gnirts off the stack as a string
+    /// Pop a 0gnirts off the stack as a string, one character per cell.
+    ///
+    /// A 0gnirts is a string pushed in reverse, so that its first character
+    /// is on top of the stack, terminated by a zero cell. Cells that are not
+    /// valid Unicode code points become U+FFFD REPLACEMENT CHARACTER. An
+    /// empty stack counts as the terminator.
     pub fn pop_0gnirts(&mut self) -> String {
-        let mut c = self.pop();
         let mut s = String::new();
-        while c != 0.into() {
-            s.push(c.to_char());
-            c = self.pop();
-        }
+        self.pop_0gnirts_into(&mut s);
         s
     }
 
-    /// Push a string onto the stack as a 0gnirts
+    /// Like [Self::pop_0gnirts], but append the string to `buf`, so that a
+    /// buffer can be reused
+    pub fn pop_0gnirts_into(&mut self, buf: &mut String) {
+        self.pop_0gnirts_with(buf, |c| c.to_char());
+    }
+
+    /// Push a string onto the stack as a 0gnirts, one character (Unicode
+    /// code point) per cell. The inverse of [Self::pop_0gnirts].
     pub fn push_0gnirts(&mut self, s: &str) {
-        self.push(0.into());
-        for c in s.chars().rev() {
-            self.push((c as i32).into());
-        }
+        let chars = s.chars().rev().map(|c| (c as i32).into());
+        self.push_cells(std::iter::once(0.into()).chain(chars));
+    }
+
+    /// Pop a 0gnirts off the stack as raw bytes, keeping only the lowest
+    /// eight bits of each cell.
+    pub fn pop_0gnirts_bytes(&mut self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.pop_0gnirts_bytes_into(&mut bytes);
+        bytes
+    }
+
+    /// Like [Self::pop_0gnirts_bytes], but append the bytes to `buf`
+    pub fn pop_0gnirts_bytes_into(&mut self, buf: &mut Vec<u8>) {
+        self.pop_0gnirts_with(buf, |c| {
+            (c.clone() & 0xff.into()).to_u8().unwrap_or_default()
+        });
+    }
+
+    /// Push raw bytes onto the stack as a 0gnirts, one byte per cell. The
+    /// inverse of [Self::pop_0gnirts_bytes].
+    ///
+    /// Zero bytes are pushed as-is, so they will end the string early when
+    /// it is popped again.
+    pub fn push_0gnirts_bytes(&mut self, bytes: &[u8]) {
+        let cells = bytes.iter().rev().map(|b| (*b as i32).into());
+        self.push_cells(std::iter::once(0.into()).chain(cells));
+    }
+
+    /// Pop a 0gnirts of UTF-8 code units (one byte per cell, as pushed by
+    /// [Self::push_0gnirts_utf8]) and decode it. Invalid sequences become
+    /// U+FFFD REPLACEMENT CHARACTER.
+    pub fn pop_0gnirts_utf8(&mut self) -> String {
+        String::from_utf8(self.pop_0gnirts_bytes())
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+    }
+
+    /// Push a string onto the stack as a 0gnirts of UTF-8 code units, one
+    /// byte per cell, for programs that expect byte strings
+    pub fn push_0gnirts_utf8(&mut self, s: &str) {
+        self.push_0gnirts_bytes(s.as_bytes());
     }
 
     /// Reflect the IP
     #[inline]
     pub fn reflect(&mut self) {
-        self.delta = self.delta * (-1).into();
+        self.delta = self.delta.scaled_by((-1).into());
+    }
+
+    /// Change direction like `>` and friends: set the delta, or, in
+    /// [IpMode::HOVER], add to it
+    #[inline]
+    pub fn steer(&mut self, delta: F::Idx) {
+        if self.mode.contains(IpMode::HOVER) {
+            self.delta = self.delta + delta;
+        } else {
+            self.delta = delta;
+        }
     }
 }
 
@@ -188,6 +564,7 @@ impl<F: Funge + 'static> InstructionPointer<F> {
 mod tests {
     use super::super::tests::TestFunge;
     use super::*;
+    use smallvec::smallvec;
 
     #[test]
     fn test_stack() {
@@ -203,11 +580,132 @@ mod tests {
         ip.push(4);
         ip.push(5);
 
-        ip.stack_stack.insert(0, Vec::new());
+        ip.push_stack();
         assert_eq!(ip.pop(), 0);
 
-        ip.stack_stack.remove(0);
+        assert_eq!(ip.pop_stack(), Some(Stack::new()));
+        assert_eq!(ip.pop_stack(), None);
         assert_eq!(ip.pop(), 5);
         assert_eq!(ip.stack().len(), 1);
     }
+
+    #[test]
+    fn test_peek_and_roll() {
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        ip.stack_mut().extend([1, 2, 3]);
+        assert_eq!(ip.peek_nth(0), 3);
+        assert_eq!(ip.peek_nth(2), 1);
+        assert_eq!(ip.peek_nth(3), 0);
+        ip.roll(2);
+        assert_eq!(ip.stack()[..], [2, 3, 1]);
+        ip.roll(0);
+        assert_eq!(ip.stack()[..], [2, 3, 1]);
+        ip.roll(-2);
+        assert_eq!(ip.stack()[..], [1, 2, 3]);
+        ip.roll(5);
+        assert_eq!(ip.stack()[..], [1, 2, 3, 0]);
+        ip.roll(-6);
+        assert_eq!(ip.stack()[..], [0, 0, 0, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_transfer_to_soss() {
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        ip.push(1);
+        assert!(!ip.transfer_to_soss(1));
+        ip.push_stack();
+        ip.stack_mut().extend([2, 3]);
+        assert!(ip.transfer_to_soss(3));
+        assert_eq!(ip.soss().unwrap()[..], [1, 3, 2, 0]);
+        assert!(ip.stack().is_empty());
+        assert!(ip.transfer_to_soss(-2));
+        assert_eq!(ip.stack()[..], [0, 2]);
+        assert_eq!(ip.soss().unwrap()[..], [1, 3]);
+        let expected: Vec<Stack<i64>> = vec![smallvec![1, 3], smallvec![0, 2]];
+        assert_eq!(ip.stack_stack, expected);
+    }
+
+    #[test]
+    fn test_0gnirts() {
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        ip.push(7);
+        ip.push_0gnirts("hé☃");
+        assert_eq!(ip.stack()[..], [7, 0, 0x2603, 0xe9, 'h' as i64]);
+        assert_eq!(ip.pop_0gnirts(), "hé☃");
+        assert_eq!(ip.pop(), 7);
+        // an empty stack terminates the string
+        ip.stack_mut().extend([-1, 'a' as i64]);
+        assert_eq!(ip.pop_0gnirts(), "a\u{fffd}");
+        assert!(ip.stack().is_empty());
+    }
+
+    #[test]
+    fn test_0gnirts_bytes_and_utf8() {
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        ip.push_0gnirts_bytes(b"\xffA");
+        assert_eq!(ip.stack()[..], [0, 'A' as i64, 0xff]);
+        assert_eq!(ip.pop_0gnirts_bytes(), b"\xffA");
+
+        ip.push_0gnirts_utf8("hé");
+        assert_eq!(ip.stack()[..], [0, 0xa9, 0xc3, 'h' as i64]);
+        assert_eq!(ip.pop_0gnirts_utf8(), "hé");
+
+        // only the low byte of each cell counts
+        ip.stack_mut().extend([0, 0x141, 0x80]);
+        assert_eq!(ip.pop_0gnirts_utf8(), "\u{fffd}A");
+    }
+
+    #[test]
+    fn test_bulk_push_and_pop() {
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        ip.push_cells([1, 2]);
+        ip.mode.insert(IpMode::INVERT);
+        ip.push_cells([3, 4]);
+        assert_eq!(ip.stack()[..], [4, 3, 1, 2]);
+        ip.mode.remove(IpMode::INVERT);
+
+        let mut buf = String::from(">");
+        ip.push_0gnirts("ab");
+        ip.push_0gnirts("cd");
+        ip.pop_0gnirts_into(&mut buf);
+        ip.pop_0gnirts_into(&mut buf);
+        assert_eq!(buf, ">cdab");
+        assert_eq!(ip.stack()[..], [4, 3, 1, 2]);
+
+        // in queue mode, the string is read from the bottom of the stack
+        ip.stack_mut().clear();
+        ip.stack_mut()
+            .extend(['a' as i64, 'b' as i64, 0, 'c' as i64]);
+        ip.mode.insert(IpMode::QUEUE);
+        assert_eq!(ip.pop_0gnirts(), "ab");
+        assert_eq!(ip.stack()[..], ['c' as i64]);
+    }
+
+    #[test]
+    fn test_invert_and_queue_modes() {
+        let mut ip = InstructionPointer::<TestFunge>::new();
+        ip.push(1);
+        ip.mode.insert(IpMode::INVERT);
+        ip.push(2);
+        assert_eq!(ip.stack()[..], [2, 1]);
+        ip.mode.insert(IpMode::QUEUE);
+        assert_eq!(ip.pop(), 2);
+        ip.mode.remove(IpMode::INVERT | IpMode::QUEUE);
+        ip.push(3);
+        assert_eq!(ip.stack()[..], [1, 3]);
+    }
+
+    #[test]
+    fn test_ip_mode_flags() {
+        let mut mode = IpMode::default();
+        assert!(mode.contains(IpMode::SGML_SPACES));
+        assert!(!mode.contains(IpMode::STRING));
+        mode.toggle(IpMode::STRING);
+        assert!(mode.contains(IpMode::STRING | IpMode::SGML_SPACES));
+        mode.set(IpMode::SGML_SPACES, false);
+        assert_eq!(mode, IpMode::STRING);
+        assert_eq!(IpMode::from_bits_truncate(mode.bits() | 0x100), mode);
+        assert_eq!(format!("{:?}", mode), "IpMode(STRING)");
+        assert_eq!(format!("{:?}", IpMode::empty()), "IpMode(empty)");
+    }
 }
@@ -16,27 +16,215 @@ You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+mod blocks;
+mod debug;
+mod diagnostic;
+mod envs;
+pub mod filesystem;
 pub mod fingerprints;
 pub mod instruction_set;
 mod instructions;
 pub mod ip;
 pub mod motion;
+mod profile;
+mod replay;
+mod shared;
+#[cfg(feature = "serde")]
+mod state;
+mod tick;
 
 use std::any::Any;
+use std::future::{ready, Future};
 use std::io;
 use std::marker::Unpin;
+use std::ops::Add;
+use std::pin::Pin;
+use std::rc::Rc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 
+use chrono::prelude::{DateTime, Utc};
 use futures_lite::future::block_on;
 use futures_lite::io::{AsyncRead, AsyncWrite};
+use hashbrown::HashMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
+use self::blocks::BlockCache;
 use self::instruction_set::exec_instruction;
 use self::ip::CreateInstructionPointer;
-use super::fungespace::{FungeSpace, FungeValue, SrcIO};
+use super::fungespace::{FungeIndex, FungeSpace, FungeValue, SrcIO};
 
-pub use self::instruction_set::{InstructionMode, InstructionResult};
-pub use self::ip::InstructionPointer;
+pub use self::debug::{Breakpoint, BreakpointId, DebugController};
+pub use self::diagnostic::{Diagnostic, DiagnosticCode, Severity};
+pub use self::envs::{PipeEnv, SilentEnv, StringEnv};
+pub use self::filesystem::{DirJail, FungeFilesystem, MemoryFilesystem, RealFilesystem};
+pub use self::instruction_set::{Instruction, InstructionResult};
+pub use self::ip::{HistoryEntry, InstructionPointer, IpMode, Stack};
 pub use self::motion::MotionCmds;
-pub use fingerprints::{all_fingerprints, safe_fingerprints, string_to_fingerprint};
+pub use self::profile::{ProfileReport, Profiler};
+pub use self::replay::{RecordingEnv, ReplayEnv, ReplayEvent, ReplayLog};
+pub use self::shared::SharedServices;
+#[cfg(feature = "serde")]
+pub use self::state::{InterpreterState, IpState};
+pub use self::tick::{TickReport, TickStep};
+pub use fingerprints::{
+    all_fingerprints, fingerprint_to_string, safe_fingerprints, string_to_fingerprint,
+    try_string_to_fingerprint, FingerprintFn, FingerprintRegistry,
+};
+
+/// Future returned by the asynchronous methods of [InterpreterEnv]
+pub type EnvFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Order in which the IPs take their turns within a tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SchedulingPolicy {
+    /// Funge-98 order: the IPs move in list order, and an IP created by `t`
+    /// is inserted into the list right before its parent (so the child moves
+    /// before its parent from the next tick onwards)
+    #[default]
+    RoundRobin,
+    /// Move the IPs in reverse list order (parents before their children)
+    Reverse,
+    /// Shuffle the IPs every tick, using [InterpreterEnv::random_u32]
+    Random,
+}
+
+/// What to do when an IP encounters an instruction it doesn't know
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UnknownInstructionPolicy {
+    /// Reflect silently
+    Reflect,
+    /// Reflect, and issue a warning via [InterpreterEnv::warn]
+    #[default]
+    WarnAndReflect,
+    /// Abort the program with [ProgramResult::Panic]
+    Abort,
+    /// Let [InterpreterEnv::unknown_instruction] decide
+    Callback,
+}
+
+/// How the division (`/`) and remainder (`%`) instructions round when the
+/// operands have different signs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DivisionMode {
+    /// Round towards zero (like C); the remainder has the sign of the
+    /// dividend
+    #[default]
+    Truncate,
+    /// Round towards negative infinity; the remainder has the sign of the
+    /// divisor
+    Floor,
+    /// Euclidean division; the remainder is never negative
+    Euclid,
+}
+
+/// How to catch an IP that never gets to the end of its turn because it keeps
+/// skipping over spaces and `;` markers (e.g. `;;` alone in a row). The
+/// program is stopped with [ProgramResult::LoopDetected].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LoopDetection {
+    /// Don't check: such a program hangs (even with [RunMode::Timeout], since
+    /// the deadline is only checked between turns)
+    Off,
+    /// Stop once an IP has moved to this many cells in a single turn
+    CellLimit(u32),
+    /// Stop as soon as an IP comes back to a cell it has already passed
+    /// through in the same turn, going in the same direction
+    #[default]
+    Heuristic,
+}
+
+/// Limit on the total number of instructions an interpreter may execute,
+/// across all calls to [Interpreter::run]
+///
+/// The budget is checked at the end of every tick, so a program may overshoot
+/// it by the instructions of a single tick before it is aborted with
+/// [ProgramResult::TimedOut].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InstructionBudget {
+    /// Maximum number of instructions
+    pub limit: u64,
+    /// Number of instructions executed so far
+    pub used: u64,
+}
+
+impl InstructionBudget {
+    pub fn new(limit: u64) -> Self {
+        Self { limit, used: 0 }
+    }
+
+    /// Number of instructions left before the program is aborted
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.used >= self.limit
+    }
+}
+
+/// Limits on the resources a program may use, see [InterpreterEnv::limits]
+///
+/// Instructions that would go over a limit reflect where they can (`{`, `}`,
+/// `u` and `t`); a program that grows its stack too far any other way is
+/// aborted with [ProgramResult::Panic].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceLimits {
+    /// Maximum number of cells on a single stack
+    pub max_stack_depth: Option<usize>,
+    /// Maximum number of stacks on the stack stack of an IP
+    pub max_stack_stack_height: Option<usize>,
+    /// Maximum number of IPs
+    pub max_ips: Option<usize>,
+    /// Maximum memory used by funge-space, in bytes (see
+    /// [FungeSpace::set_memory_limit])
+    pub max_space_bytes: Option<usize>,
+}
+
+impl ResourceLimits {
+    /// May a stack hold `depth` cells?
+    pub fn allows_stack_depth(&self, depth: usize) -> bool {
+        self.max_stack_depth.is_none_or(|max| depth <= max)
+    }
+}
+
+/// Settings that affect how the interpreter runs a program (as opposed to
+/// how it interacts with the outside world, which is up to the
+/// [InterpreterEnv])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InterpreterSettings {
+    /// The order in which IPs are scheduled
+    pub scheduling: SchedulingPolicy,
+    /// Abort the program once it has executed this many instructions
+    pub instruction_budget: Option<InstructionBudget>,
+    /// What to do about unknown instructions
+    pub unknown_instructions: UnknownInstructionPolicy,
+    /// Rounding of `/` and `%`
+    pub division: DivisionMode,
+    /// How to catch IPs stuck in a loop within one turn
+    pub loop_detection: LoopDetection,
+    /// Execute runs of simple instructions (digits, arithmetic and stack
+    /// manipulation) in adjacent cells in one go, decoding them once and
+    /// then only checking them again when funge-space is written to.
+    ///
+    /// This only kicks in while there is a single IP, and not while
+    /// single-stepping, with breakpoints, profiling, IP histories,
+    /// [Interpreter::tick], a stack size limit, instruction overrides or
+    /// fingerprints that watch the IP's movements. The program behaves
+    /// exactly the same, except that an [InstructionBudget] may be overshot
+    /// by a few more instructions.
+    pub block_cache: bool,
+}
 
 /// Possible results of calling [Interpreter::run]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -45,8 +233,20 @@ pub enum ProgramResult {
     Done(i32),
     /// Catastrophic failure
     Panic,
-    /// Program is paused (only returned if using [RunMode::Step])
+    /// Program is paused (only returned if using [RunMode::Step],
+    /// [RunMode::StepIp] or [RunMode::Limited])
     Paused,
+    /// Program was aborted because it ran out of time (see
+    /// [RunMode::Timeout]) or used up its [InstructionBudget]
+    TimedOut,
+    /// Program is paused because a breakpoint was hit (see [DebugController])
+    Hit(BreakpointId),
+    /// Program is paused because an instruction returned
+    /// [InstructionResult::Yield]; run the interpreter again to resume it
+    AwaitingInput,
+    /// Program was aborted because an IP got stuck in a loop within a single
+    /// turn (see [LoopDetection])
+    LoopDetected,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -62,6 +262,11 @@ pub enum ExecMode {
     System,
     SpecificShell,
     SameShell,
+    /// Like [ExecMode::System], but `=` captures the command's output, see
+    /// [InterpreterEnv::execute_command_capture]. Since that changes what
+    /// `=` pops and pushes, `y` reports this as operating paradigm 4 (which
+    /// the spec doesn't define) rather than 1.
+    Capture,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -70,10 +275,21 @@ pub enum RunMode {
     Run,
     /// Execute a single tick (for all IPs)
     Step,
+    /// Move a single IP (the next one in this tick)
+    StepIp,
     /// Run up to a certain number of instructions
     Limited(u32),
+    /// Run program to the end, but give up after the given (wall clock)
+    /// time, as measured by [InterpreterEnv::monotonic_clock]
+    Timeout(Duration),
+    /// Run for the given (wall clock) time, then pause, e.g. to keep a UI
+    /// responsive. The clock is only checked every few ticks.
+    TimeSlice(Duration),
 }
 
+/// How often [RunMode::TimeSlice] checks the clock
+const TIME_SLICE_TICKS: u32 = 64;
+
 pub trait Funge {
     type Idx: MotionCmds<Self::Space, Self::Env> + SrcIO<Self::Space> + 'static;
     type Space: FungeSpace<Self::Idx, Output = Self::Value> + 'static;
@@ -95,6 +311,41 @@ where
     pub space: Space,
     /// User-supplied environment permitting access to the outside world
     pub env: Env,
+    /// Interpreter settings
+    pub settings: InterpreterSettings,
+    /// Breakpoints and watchpoints
+    pub debugger: DebugController<Idx, Space::Output>,
+    /// Services shared by all IPs, such as SOCK's sockets
+    pub shared: SharedServices,
+    /// Number of steps kept in [InstructionPointer::history]
+    history_length: usize,
+    /// Decoded runs of instructions (if [InterpreterSettings::block_cache]
+    /// is enabled)
+    block_cache: Option<BlockCache<Idx, Space::Output>>,
+    /// Instructions executed in the current [Interpreter::tick]
+    tick_log: Option<Vec<TickStep<Idx, Space::Output>>>,
+    /// Instruction counters (if profiling is enabled)
+    profiler: Option<Profiler<Idx, Space::Output>>,
+    /// The rest of a tick that was interrupted by [InstructionResult::Yield]
+    suspended_tick: Option<SuspendedTick<Self>>,
+    /// Number of ticks completed
+    ticks: u64,
+    /// Number of instructions executed in the completed ticks
+    instructions: u64,
+    /// Index of the IP that made the last run panic
+    panicked_ip: Option<usize>,
+    /// ID of the IP moved by the last [RunMode::StepIp]
+    stepped_ip: Option<Space::Output>,
+}
+
+/// Progress through a tick, kept when an instruction yields so that the tick
+/// can be finished when the program is resumed
+struct SuspendedTick<F: Funge + 'static> {
+    schedule: Vec<usize>,
+    next: usize,
+    new_ips: Vec<(usize, InstructionPointer<F>)>,
+    stopped_ips: Vec<usize>,
+    n_instructions: u64,
 }
 
 impl<Idx, Space, Env> Funge for Interpreter<Idx, Space, Env>
@@ -110,6 +361,24 @@ where
     type Env = Env;
 }
 
+/// Information passed to [InterpreterEnv::trace] before an instruction is
+/// executed
+#[derive(Debug)]
+pub struct TraceEvent<'a, F: Funge> {
+    /// Number of the current tick (see [Interpreter::ticks])
+    pub tick: u64,
+    /// ID of the IP executing the instruction
+    pub ip_id: &'a F::Value,
+    /// Location of the instruction
+    pub location: F::Idx,
+    /// The instruction
+    pub instruction: char,
+    /// Size of the IP's stack (TOSS) before the instruction is executed
+    pub stack_depth: usize,
+    /// Top of the IP's stack before the instruction is executed
+    pub stack_top: Option<&'a F::Value>,
+}
+
 /// An interpreter environment provides things like IO and will be implemented
 /// differently depending on whether the interpreter is running from the command
 /// line, in a web browser, as part of the test suite, etc.
@@ -124,17 +393,40 @@ pub trait InterpreterEnv {
     fn input_reader(&mut self) -> &mut (dyn AsyncRead + Unpin);
     /// Method called on warnings like "unknown instruction"
     fn warn(&mut self, msg: &str);
+    /// Method called on warnings and errors about the program, with the
+    /// details a front end needs to show them next to the offending cell.
+    ///
+    /// The default implementation passes the formatted diagnostic on to
+    /// [InterpreterEnv::warn].
+    fn diagnostic<F: Funge>(&mut self, diagnostic: &Diagnostic<F>) {
+        self.warn(&diagnostic.to_string())
+    }
     /// What handprint should sysinfo (`y`) name? Default: 0x52464e47
     fn handprint(&self) -> i32 {
         0x52464e47 // RFNG
     }
+    /// What team number should sysinfo (`y`) report for a given IP? Default:
+    /// [InstructionPointer::team], which is 0 (no teams) unless set.
+    fn team_number<F: Funge>(&self, ip: &InstructionPointer<F>) -> i32 {
+        ip.team
+    }
+    /// Limits on the resources the program may use. These are read at the
+    /// start of every call to [Interpreter::run]. Default: no limits.
+    fn limits(&self) -> ResourceLimits {
+        ResourceLimits::default()
+    }
+    /// The files the program can access with `i` and `o`, if any. The
+    /// default implementations of the file methods below use this.
+    fn filesystem(&self) -> Option<&dyn FungeFilesystem> {
+        None
+    }
     /// Is `i` available? (see also: [InterpreterEnv::read_file])
     fn have_file_input(&self) -> bool {
-        false
+        self.filesystem().is_some()
     }
     /// Is `o` available? (see also: [InterpreterEnv::write_file])
     fn have_file_output(&self) -> bool {
-        false
+        self.filesystem().is_some_and(|fs| fs.is_writable())
     }
     /// Is `=` available, and how does [InterpreterEnv::execute_command] act
     /// (in the terms defined for sysinfo (`y`))?
@@ -142,17 +434,94 @@ pub trait InterpreterEnv {
         ExecMode::Disabled
     }
     /// Get the contents of a named file.
-    fn read_file(&mut self, _filename: &str) -> io::Result<Vec<u8>> {
-        Err(io::Error::from(io::ErrorKind::PermissionDenied))
+    fn read_file(&mut self, filename: &str) -> io::Result<Vec<u8>> {
+        match self.filesystem() {
+            Some(fs) => fs.read(filename),
+            None => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+        }
     }
     /// Write data to a named file.
-    fn write_file(&mut self, _filename: &str, _content: &[u8]) -> io::Result<()> {
-        Err(io::Error::from(io::ErrorKind::PermissionDenied))
+    fn write_file(&mut self, filename: &str, content: &[u8]) -> io::Result<()> {
+        match self.filesystem() {
+            Some(fs) => fs.write(filename, content),
+            None => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+        }
+    }
+    /// Write data to a named file, produced bit by bit by `write` (used by
+    /// `o`, so that large regions need not be held in memory). The default
+    /// implementation writes to the [InterpreterEnv::filesystem], or, if
+    /// there is none, collects the data and calls
+    /// [InterpreterEnv::write_file].
+    fn write_file_with(
+        &mut self,
+        filename: &str,
+        write: &mut dyn FnMut(&mut dyn io::Write) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if let Some(fs) = self.filesystem() {
+            let mut file = fs.create(filename)?;
+            write(&mut file)?;
+            return file.flush();
+        }
+        let mut content = Vec::new();
+        write(&mut content)?;
+        self.write_file(filename, &content)
     }
     /// Execute a command and return the exit status
     fn execute_command(&mut self, _command: &str) -> i32 {
         -1
     }
+    /// Execute a command, returning the exit status and everything the
+    /// command wrote to its standard output. Only used in
+    /// [ExecMode::Capture], where `=` pops a flags cell after the command:
+    /// with flag 1, it pops a vector and writes the output into funge-space
+    /// there, pushing its size and location (like `i`); otherwise it pushes
+    /// the first line of the output as a 0gnirts. Either way, the exit
+    /// status ends up on top.
+    fn execute_command_capture(&mut self, _command: &str) -> (i32, Vec<u8>) {
+        (-1, Vec::new())
+    }
+    /// Asynchronous version of [InterpreterEnv::read_file], which is what
+    /// `i` uses. Override this to read files without blocking the run loop;
+    /// the default implementation calls [InterpreterEnv::read_file].
+    fn read_file_async<'a>(&'a mut self, filename: &'a str) -> EnvFuture<'a, io::Result<Vec<u8>>> {
+        Box::pin(ready(self.read_file(filename)))
+    }
+    /// Asynchronous version of [InterpreterEnv::write_file]. The default
+    /// implementation calls [InterpreterEnv::write_file].
+    fn write_file_async<'a>(
+        &'a mut self,
+        filename: &'a str,
+        content: &'a [u8],
+    ) -> EnvFuture<'a, io::Result<()>> {
+        Box::pin(ready(self.write_file(filename, content)))
+    }
+    /// Asynchronous version of [InterpreterEnv::write_file_with], which is
+    /// what `o` uses. The default implementation calls
+    /// [InterpreterEnv::write_file_with]; an asynchronous implementation
+    /// will usually collect the data and call
+    /// [InterpreterEnv::write_file_async].
+    fn write_file_with_async<'a>(
+        &'a mut self,
+        filename: &'a str,
+        write: &'a mut dyn FnMut(&mut dyn io::Write) -> io::Result<()>,
+    ) -> EnvFuture<'a, io::Result<()>> {
+        Box::pin(ready(self.write_file_with(filename, write)))
+    }
+    /// Asynchronous version of [InterpreterEnv::execute_command], which is
+    /// what `=` uses. The default implementation calls
+    /// [InterpreterEnv::execute_command].
+    fn execute_command_async<'a>(&'a mut self, command: &'a str) -> EnvFuture<'a, i32> {
+        Box::pin(ready(self.execute_command(command)))
+    }
+    /// Asynchronous version of [InterpreterEnv::execute_command_capture].
+    /// The default implementation calls
+    /// [InterpreterEnv::execute_command_capture].
+    fn execute_command_capture_async<'a>(
+        &'a mut self,
+        command: &'a str,
+    ) -> EnvFuture<'a, (i32, Vec<u8>)> {
+        Box::pin(ready(self.execute_command_capture(command)))
+    }
     /// Get the environment variables to pass to the program
     fn env_vars(&mut self) -> Vec<(String, String)> {
         Vec::new()
@@ -167,11 +536,119 @@ pub trait InterpreterEnv {
     fn is_fingerprint_enabled(&self, _fpr: i32) -> bool {
         false
     }
+    /// Play a tone with the given frequency (in Hz) for `duration_ms`
+    /// milliseconds, or stay silent for that long if `frequency` is 0.
+    /// Tones must play one after the other. Implementations should queue
+    /// them rather than wait for each tone to finish: waiting here stalls
+    /// every IP, not just the one playing the tone.
+    ///
+    /// Returns `false` if sound is not supported. (Used by SQNT)
+    fn play_tone(&mut self, _frequency: f32, _duration_ms: u32) -> bool {
+        false
+    }
+    /// Get a random number. Used by `?`, which goes in the direction
+    /// `random_u32() % 4` (east, south, west, north) in Befunge, or
+    /// `random_u32() % 2` (east, west) in Unefunge.
+    ///
+    /// The default implementation takes the low bits of
+    /// [InterpreterEnv::random_u64].
+    fn random_u32(&mut self) -> u32 {
+        self.random_u64() as u32
+    }
+    /// Get a random number. All randomness in the interpreter (`?`, IP
+    /// shuffling, FIXP's `D`) comes from here, unless
+    /// [InterpreterEnv::random_u32] is overridden separately.
+    ///
+    /// The default implementation uses the thread-local generator of the
+    /// `rand` crate. Override it with a seeded generator to get reproducible
+    /// runs.
+    fn random_u64(&mut self) -> u64 {
+        rand::random()
+    }
+    /// Read a monotonic clock, returning the time elapsed since some
+    /// arbitrary, fixed point in time. (Used by HRTI and time limits)
+    ///
+    /// The default implementation converts [InterpreterEnv::monotonic_nanos].
+    fn monotonic_clock(&mut self) -> Duration {
+        Duration::from_nanos(self.monotonic_nanos())
+    }
+    /// Read a monotonic clock in nanoseconds, see
+    /// [InterpreterEnv::monotonic_clock]
+    ///
+    /// The default implementation uses a steady clock, counting from the
+    /// first time the clock is read. (On wasm32, which has no steady clock,
+    /// it uses the system clock instead, which may jump.) Override this to
+    /// use a fake clock for reproducible runs.
+    fn monotonic_nanos(&mut self) -> u64 {
+        steady_clock_nanos()
+    }
+    /// The current date and time, as reported by `y`
+    ///
+    /// Override this to freeze time for reproducible runs.
+    fn now_utc(&mut self) -> DateTime<Utc> {
+        Utc::now()
+    }
     /// Get the support library for a particular fingerprint that needs
     /// environment support, if available.
     fn fingerprint_support_library(&mut self, _fpr: i32) -> Option<&mut dyn Any> {
         None
     }
+    /// Called before every instruction the main loop executes (this does not
+    /// include the instructions executed by `k`), e.g. to implement a
+    /// debugger or a profiler.
+    ///
+    /// The default implementation does nothing, and compiles away entirely.
+    #[inline(always)]
+    fn trace<F: Funge>(&mut self, _event: &TraceEvent<F>) {}
+    /// Called when an IP is created by `t` (the new IP only starts moving in
+    /// the next tick)
+    fn on_ip_spawned<F: Funge>(&mut self, _id: &F::Value, _parent_id: &F::Value) {}
+    /// Called when an IP has stopped (with `@`; not when the whole program
+    /// ends with `q`)
+    fn on_ip_stopped<F: Funge>(&mut self, _id: &F::Value) {}
+    /// Decide what to do about an unknown instruction, if the interpreter's
+    /// [UnknownInstructionPolicy] is `Callback`. Returning `Callback` is
+    /// treated like `WarnAndReflect`, which is also the default.
+    fn unknown_instruction<F: Funge>(
+        &mut self,
+        _ip: &InstructionPointer<F>,
+        _instruction: &F::Value,
+    ) -> UnknownInstructionPolicy {
+        UnknownInstructionPolicy::WarnAndReflect
+    }
+}
+
+/// Nanoseconds elapsed since the first call
+#[cfg(not(target_arch = "wasm32"))]
+fn steady_clock_nanos() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+/// Nanoseconds since the UNIX epoch (`Instant` isn't available)
+#[cfg(target_arch = "wasm32")]
+fn steady_clock_nanos() -> u64 {
+    let now = Utc::now();
+    now.timestamp() as u64 * 1_000_000_000 + now.timestamp_subsec_nanos() as u64
+}
+
+/// Move by `delta` without skipping spaces (for string mode without
+/// [IpMode::SGML_SPACES]), unless that leaves the program's bounding box, in
+/// which case wrap around as usual
+fn step_without_skipping<Idx, Space>(space: &Space, start: Idx, delta: Idx) -> (Idx, Space::Output)
+where
+    Idx: FungeIndex + Add<Output = Idx>,
+    Space: FungeSpace<Idx>,
+    Space::Output: Clone,
+{
+    let next = start + delta;
+    if let (Some(least), Some(greatest)) = (space.min_idx(), space.max_idx()) {
+        if next.joint_max(&least) == next && next.joint_min(&greatest) == next {
+            return (next, space[next].clone());
+        }
+    }
+    let (next, value) = space.move_by(start, delta);
+    (next, value.clone())
 }
 
 impl<Idx, Space, Env> Interpreter<Idx, Space, Env>
@@ -186,27 +663,183 @@ where
         let mut new_ips = Vec::new();
         let mut location_log = Vec::new();
         let mut counter: u32 = 0;
+        let mut schedule = Vec::new();
+        let deadline = match mode {
+            RunMode::Timeout(limit) | RunMode::TimeSlice(limit) => {
+                Some(self.env.monotonic_clock() + limit)
+            }
+            _ => None,
+        };
+        let limits = self.env.limits();
+        if limits.max_space_bytes.is_some() {
+            self.space.set_memory_limit(limits.max_space_bytes);
+        }
+        self.panicked_ip = None;
+        if !self.debugger.is_empty() {
+            self.debugger.sync(&self.space);
+        }
+        for ip in self.ips.iter_mut() {
+            ip.instructions.unknown_policy = self.settings.unknown_instructions;
+            ip.instructions.division = self.settings.division;
+        }
+        // Is anything watching every single step?
+        let use_blocks = self.settings.block_cache
+            && !matches!(mode, RunMode::Step | RunMode::StepIp)
+            && self.debugger.is_empty()
+            && self.profiler.is_none()
+            && self.tick_log.is_none()
+            && self.history_length == 0
+            && limits.max_stack_depth.is_none();
+        if !self.settings.block_cache {
+            self.block_cache = None;
+        }
 
         loop {
-            for ip_idx in 0..self.ips.len() {
+            let mut first = 0;
+            let mut n_instructions: u64 = 0;
+            // Ticks beyond this one taken up by a block of instructions
+            let mut extra_ticks: u64 = 0;
+            if let Some(tick) = self.suspended_tick.take() {
+                schedule = tick.schedule;
+                first = tick.next;
+                new_ips = tick.new_ips;
+                stopped_ips = tick.stopped_ips;
+                n_instructions = tick.n_instructions;
+            } else {
+                self.schedule_tick(&mut schedule);
+            }
+            for pos in first..schedule.len() {
+                let ip_idx = schedule[pos];
                 let mut go_again = true;
                 location_log.truncate(0);
+                let mut moves: u32 = 0;
+                if use_blocks && self.ips.len() == 1 {
+                    let ip = &mut self.ips[ip_idx];
+                    if ip.private_data.is_empty()
+                        && ip.instructions.overrides().is_empty()
+                        && !ip.mode.contains(IpMode::STRING)
+                    {
+                        let max_len = match mode {
+                            RunMode::Limited(max_ticks) => (max_ticks - counter) as usize,
+                            _ => usize::MAX,
+                        };
+                        let n = self.block_cache.get_or_insert_with(BlockCache::new).run(
+                            ip,
+                            &self.space,
+                            &mut self.env,
+                            self.ticks,
+                            max_len,
+                        );
+                        if n > 0 {
+                            n_instructions += n as u64;
+                            extra_ticks = n as u64 - 1;
+                            if let RunMode::Limited(_) = mode {
+                                counter += n as u32 - 1;
+                            }
+                            continue;
+                        }
+                    }
+                }
+                {
+                    // Fingerprints keep their state in private_data, so only
+                    // IPs that have some need to be checked
+                    let ip = &mut self.ips[ip_idx];
+                    if !ip.private_data.is_empty() {
+                        fingerprints::before_move(ip);
+                    }
+                }
                 while go_again {
                     let ip = &mut self.ips[ip_idx];
-                    let (new_loc, new_val) = self.space.move_by(ip.location, ip.delta);
-                    let instruction = *new_val;
-                    // Check that this loop is not infinite
-                    if location_log.iter().any(|l| *l == new_loc) {
-                        return ProgramResult::Panic;
+                    moves += 1;
+                    let prev_loc = ip.location;
+                    let (new_loc, instruction) = if ip.mode.contains(IpMode::STRING)
+                        && !ip.mode.contains(IpMode::SGML_SPACES)
+                    {
+                        step_without_skipping(&self.space, ip.location, ip.delta)
                     } else {
-                        location_log.push(new_loc);
+                        let (new_loc, new_val) = self.space.move_by(ip.location, ip.delta);
+                        (new_loc, new_val.clone())
+                    };
+                    if self.history_length > 0 {
+                        if ip.history.len() >= self.history_length {
+                            ip.history.pop_front();
+                        }
+                        ip.history.push_back(HistoryEntry {
+                            location: new_loc,
+                            delta: ip.delta,
+                            instruction: instruction.clone(),
+                        });
+                    }
+                    // Check that this loop is not infinite
+                    let stuck = match self.settings.loop_detection {
+                        LoopDetection::Off => false,
+                        LoopDetection::CellLimit(limit) => moves > limit,
+                        LoopDetection::Heuristic => {
+                            if location_log.contains(&(new_loc, ip.delta)) {
+                                true
+                            } else {
+                                location_log.push((new_loc, ip.delta));
+                                false
+                            }
+                        }
+                    };
+                    if stuck {
+                        self.env.diagnostic(&Diagnostic::<Self> {
+                            severity: Severity::Error,
+                            code: DiagnosticCode::InfiniteLoop,
+                            location: Some(new_loc),
+                            ip_id: Some(&ip.id),
+                            message: "Infinite loop".to_owned(),
+                        });
+                        self.panicked_ip = Some(ip_idx);
+                        return ProgramResult::LoopDetected;
                     }
                     // Move everything to an instruction context
                     ip.location = new_loc;
                     go_again = false;
+                    n_instructions += 1;
+                    self.env.trace(&TraceEvent::<Self> {
+                        tick: self.ticks,
+                        ip_id: &ip.id,
+                        location: new_loc,
+                        instruction: instruction.to_char(),
+                        stack_depth: ip.stack().len(),
+                        stack_top: ip.stack().last(),
+                    });
+                    if let Some(profiler) = self.profiler.as_mut() {
+                        profiler.record(&ip.id, new_loc, instruction.to_char());
+                    }
+                    if let Some(tick_log) = self.tick_log.as_mut() {
+                        tick_log.push(TickStep {
+                            ip_id: ip.id.clone(),
+                            location: new_loc,
+                            delta: ip.delta,
+                            instruction: instruction.clone(),
+                        });
+                    }
                     // Hand context over to exec_instruction
                     let result =
                         exec_instruction(instruction, ip, &mut self.space, &mut self.env).await;
+                    if self.space.take_limit_exceeded() {
+                        self.env.diagnostic(&Diagnostic::at_ip(
+                            ip,
+                            Severity::Error,
+                            DiagnosticCode::SpaceFull,
+                            "Funge-space is full",
+                        ));
+                        self.panicked_ip = Some(ip_idx);
+                        return ProgramResult::Panic;
+                    }
+                    if !limits.allows_stack_depth(ip.stack().len()) {
+                        self.env.diagnostic(&Diagnostic::at_ip(
+                            ip,
+                            Severity::Error,
+                            DiagnosticCode::StackLimit,
+                            "Stack limit exceeded",
+                        ));
+                        self.panicked_ip = Some(ip_idx);
+                        return ProgramResult::Panic;
+                    }
                     // Continue
                     match result {
                         InstructionResult::Continue => {}
@@ -220,26 +853,55 @@ where
                             return ProgramResult::Done(returncode);
                         }
                         InstructionResult::Panic => {
+                            self.panicked_ip = Some(ip_idx);
                             return ProgramResult::Panic;
                         }
+                        InstructionResult::Yield => {
+                            // Execute the same instruction again on resume
+                            ip.location = prev_loc;
+                            self.suspended_tick = Some(SuspendedTick {
+                                schedule,
+                                next: pos,
+                                new_ips,
+                                stopped_ips,
+                                n_instructions: n_instructions - 1,
+                            });
+                            return ProgramResult::AwaitingInput;
+                        }
                         InstructionResult::Fork(n_forks) => {
-                            // Find an ID for the new IP
-                            let mut new_id =
-                                self.ips.iter().map(|ip| ip.id).max().unwrap() + 1.into();
-                            for _ in 0..n_forks {
-                                let ip = &mut self.ips[ip_idx]; // borrow
-                                let mut new_ip = ip.clone(); // Create the IP
-                                new_ip.id = new_id;
-                                new_id += 1.into();
-                                new_ip.delta = ip.delta * (-1).into();
-                                new_ips.push((ip_idx, new_ip));
-                            }
+                            self.fork_ip(ip_idx, n_forks, limits.max_ips, &mut new_ips);
+                        }
+                        InstructionResult::ForkAndStop(n_forks) => {
+                            self.fork_ip(ip_idx, n_forks, limits.max_ips, &mut new_ips);
+                            stopped_ips.push(ip_idx);
                         }
                     }
                 }
+
+                if mode == RunMode::StepIp {
+                    self.stepped_ip = Some(self.ips[ip_idx].id.clone());
+                }
+                if mode == RunMode::StepIp && pos + 1 < schedule.len() {
+                    // Finish the tick when we're resumed
+                    self.suspended_tick = Some(SuspendedTick {
+                        schedule,
+                        next: pos + 1,
+                        new_ips,
+                        stopped_ips,
+                        n_instructions,
+                    });
+                    return ProgramResult::Paused;
+                }
             }
 
-            // handle forks
+            self.ticks += 1 + extra_ticks;
+            self.instructions += n_instructions;
+
+            // handle forks (in list order, so that the indices stay valid;
+            // the sort is stable, so IPs spawned by the same parent keep
+            // their order)
+            new_ips.sort_by_key(|(ip_idx, _)| *ip_idx);
+            stopped_ips.sort_unstable();
             for (ip_idx, new_ip) in new_ips.drain(0..).rev() {
                 self.ips.insert(ip_idx, new_ip);
                 // Fix ip indices in stopped_ips
@@ -252,22 +914,72 @@ where
 
             // handle stops
             for idx in stopped_ips.drain(0..).rev() {
-                self.ips.remove(idx);
+                let ip = self.ips.remove(idx);
+                fingerprints::on_ip_removed(&ip);
+                self.env.on_ip_stopped::<Self>(&ip.id);
             }
 
             if self.ips.is_empty() {
                 return ProgramResult::Done(0);
             }
 
+            if let Some(budget) = self.settings.instruction_budget.as_mut() {
+                budget.used += n_instructions;
+                if budget.is_exhausted() {
+                    return ProgramResult::TimedOut;
+                }
+            }
+
+            if !self.debugger.is_empty() {
+                if let Some(id) = self.debugger.check(&self.ips, &self.space) {
+                    return ProgramResult::Hit(id);
+                }
+            }
+
             match mode {
                 RunMode::Run => (),
-                RunMode::Step => return ProgramResult::Paused,
+                RunMode::Step | RunMode::StepIp => return ProgramResult::Paused,
                 RunMode::Limited(max_instructions) => {
                     counter += 1;
                     if counter >= max_instructions {
                         return ProgramResult::Paused;
                     }
                 }
+                RunMode::Timeout(_) => {
+                    if let Some(deadline) = deadline {
+                        if self.env.monotonic_clock() >= deadline {
+                            return ProgramResult::TimedOut;
+                        }
+                    }
+                }
+                RunMode::TimeSlice(_) => {
+                    counter += 1;
+                    if counter.is_multiple_of(TIME_SLICE_TICKS) {
+                        if let Some(deadline) = deadline {
+                            if self.env.monotonic_clock() >= deadline {
+                                return ProgramResult::Paused;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Decide the order in which the IPs move in the next tick
+    fn schedule_tick(&mut self, schedule: &mut Vec<usize>) {
+        let n_ips = self.ips.len();
+        schedule.clear();
+        match self.settings.scheduling {
+            SchedulingPolicy::RoundRobin => schedule.extend(0..n_ips),
+            SchedulingPolicy::Reverse => schedule.extend((0..n_ips).rev()),
+            SchedulingPolicy::Random => {
+                // Fisher-Yates shuffle
+                schedule.extend(0..n_ips);
+                for i in (1..n_ips).rev() {
+                    let j = self.env.random_u32() as usize % (i + 1);
+                    schedule.swap(i, j);
+                }
             }
         }
     }
@@ -275,6 +987,92 @@ where
     pub fn run(&mut self, mode: RunMode) -> ProgramResult {
         block_on(self.run_async(mode))
     }
+
+    /// Number of ticks completed since the interpreter was created
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Number of instructions executed in the ticks completed so far (not
+    /// counting the instructions executed by `k`)
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The IP that was running when [Interpreter::run] last returned
+    /// [ProgramResult::Panic] or [ProgramResult::LoopDetected]. Its location is that of the last instruction
+    /// it executed.
+    pub fn panicked_ip(&self) -> Option<&InstructionPointer<Self>> {
+        self.panicked_ip.and_then(|idx| self.ips.get(idx))
+    }
+
+    /// The ID of the IP that the last run with [RunMode::StepIp] moved
+    pub fn stepped_ip(&self) -> Option<&Space::Output> {
+        self.stepped_ip.as_ref()
+    }
+
+    /// Start or stop counting the instructions executed (per instruction,
+    /// per cell, and per IP). Enabling profiling resets the counters.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiler = if enabled { Some(Profiler::new()) } else { None };
+    }
+
+    /// Get the instruction counts collected since profiling was enabled,
+    /// or `None` if profiling is not enabled
+    pub fn profile(&self) -> Option<ProfileReport<Idx, Space::Output>> {
+        self.profiler.as_ref().map(|p| p.report())
+    }
+
+    /// Keep the last `length` steps of each IP in
+    /// [InstructionPointer::history], to help diagnose IPs that go astray
+    /// (0, the default, turns the history off). Histories that are longer
+    /// are cut short.
+    pub fn set_history_length(&mut self, length: usize) {
+        self.history_length = length;
+        for ip in self.ips.iter_mut() {
+            let excess = ip.history.len().saturating_sub(length);
+            ip.history.drain(..excess);
+        }
+    }
+
+    /// Queue `n_forks` copies of the IP at `ip_idx` (with reversed deltas)
+    /// to be inserted before it at the end of the tick
+    fn fork_ip(
+        &mut self,
+        ip_idx: usize,
+        n_forks: i32,
+        max_ips: Option<usize>,
+        new_ips: &mut Vec<(usize, InstructionPointer<Self>)>,
+    ) {
+        // Beyond the IP limit, `t` reflects instead
+        let wanted = n_forks.max(0) as usize;
+        let n_forks = match max_ips {
+            Some(max_ips) => wanted.min(max_ips.saturating_sub(self.ips.len() + new_ips.len())),
+            None => wanted,
+        };
+        // Find an ID for the new IP (taking into account the IPs spawned
+        // earlier in this tick)
+        let mut new_id = self
+            .ips
+            .iter()
+            .chain(new_ips.iter().map(|(_, ip)| ip))
+            .map(|ip| ip.id.clone())
+            .max()
+            .unwrap()
+            + 1.into();
+        let ip = &self.ips[ip_idx];
+        for _ in 0..n_forks {
+            let mut new_ip = ip.clone(); // Create the IP
+            new_ip.id = new_id.clone();
+            new_id += 1.into();
+            new_ip.delta = ip.delta.scaled_by((-1).into());
+            self.env.on_ip_spawned::<Self>(&new_ip.id, &ip.id);
+            new_ips.push((ip_idx, new_ip));
+        }
+        if n_forks < wanted {
+            self.ips[ip_idx].reflect();
+        }
+    }
 }
 
 impl<Idx, Space, Env> Interpreter<Idx, Space, Env>
@@ -285,12 +1083,100 @@ where
     Env: InterpreterEnv + 'static,
 {
     pub fn new(space: Space, env: Env) -> Self {
+        let shared = SharedServices::new();
+        let mut ip = InstructionPointer::<Self>::new();
+        ip.shared = shared.clone();
         Self {
-            ips: vec![InstructionPointer::<Self>::new()],
+            ips: vec![ip],
             space,
             env,
+            settings: Default::default(),
+            debugger: DebugController::new(),
+            shared,
+            history_length: 0,
+            tick_log: None,
+            block_cache: None,
+            profiler: None,
+            suspended_tick: None,
+            ticks: 0,
+            instructions: 0,
+            panicked_ip: None,
+            stepped_ip: None,
+        }
+    }
+
+    /// Make a custom fingerprint available to all IPs (in addition to the
+    /// built-in fingerprints), replacing any fingerprint with the same ID.
+    /// The environment must still enable it (see
+    /// [InterpreterEnv::is_fingerprint_enabled]).
+    pub fn register_fingerprint(
+        &mut self,
+        name: &str,
+        safe: bool,
+        load: FingerprintFn<Self>,
+        unload: FingerprintFn<Self>,
+    ) {
+        self.update_registry(|registry| {
+            registry.register(string_to_fingerprint(name), safe, load, unload)
+        });
+    }
+
+    /// Modify the fingerprint registry shared by all IPs
+    pub fn update_registry(&mut self, f: impl FnOnce(&mut FingerprintRegistry<Self>)) {
+        let mut registry = match self.ips.first() {
+            Some(ip) => FingerprintRegistry::clone(ip.instructions.registry()),
+            None => FingerprintRegistry::new(),
+        };
+        f(&mut registry);
+        let registry = Rc::new(registry);
+        for ip in self.ips.iter_mut() {
+            ip.instructions.set_registry(registry.clone());
         }
     }
+
+    /// Bind `instruction` to the character `c` for all IPs, taking
+    /// precedence over the standard instruction and any fingerprint
+    /// semantics (but not over string mode)
+    pub fn override_instruction(&mut self, c: char, instruction: Instruction<Self>) {
+        self.update_overrides(|overrides| {
+            overrides.insert(c, instruction);
+        });
+    }
+
+    /// Remove an override added with [Interpreter::override_instruction].
+    /// Returns `false` if there was none.
+    pub fn remove_override(&mut self, c: char) -> bool {
+        let mut removed = false;
+        self.update_overrides(|overrides| removed = overrides.remove(&c).is_some());
+        removed
+    }
+
+    fn update_overrides(&mut self, f: impl FnOnce(&mut HashMap<char, Instruction<Self>>)) {
+        let mut overrides = match self.ips.first() {
+            Some(ip) => HashMap::clone(ip.instructions.overrides()),
+            None => HashMap::new(),
+        };
+        f(&mut overrides);
+        let overrides = Rc::new(overrides);
+        for ip in self.ips.iter_mut() {
+            ip.instructions.set_overrides(overrides.clone());
+        }
+    }
+
+    /// Read a unicode source file into funge-space, starting at `start`.
+    /// Returns the size of the region written to.
+    ///
+    /// This can be called several times to compose a program from multiple
+    /// files.
+    pub fn load_program_at(&mut self, start: Idx, src: &str) -> Idx {
+        Idx::read_str_at(&mut self.space, &start, src)
+    }
+
+    /// Read a binary/latin-1 source file into funge-space, starting at
+    /// `start`. Returns the size of the region written to.
+    pub fn load_program_bin_at(&mut self, start: Idx, src: &[u8]) -> Idx {
+        Idx::read_bin_at(&mut self.space, &start, src)
+    }
 }
 
 #[cfg(test)]
@@ -298,11 +1184,23 @@ mod tests {
     use async_std::io::{Empty, Sink};
 
     use super::*;
-    use crate::fungespace::{BefungeVec, PagedFungeSpace};
+    use crate::fungespace::{bfvec, BefungeVec, PagedFungeSpace};
+    use crate::interpreter::fingerprints::TURT::TurtleRobotBox;
 
     pub struct NoEnv {
         input: Empty,
         outout: Sink,
+        pub trace_log: Option<Vec<String>>,
+        pub team: i32,
+        pub fs: Option<MemoryFilesystem>,
+        pub now: Option<DateTime<Utc>>,
+        /// If set, `=` captures output, and every command prints this
+        pub exec_output: Option<Vec<u8>>,
+        pub limits: ResourceLimits,
+        /// Fingerprints available in addition to the safe ones
+        pub extra_fingerprints: Vec<i32>,
+        /// Support library for TURT (and TRTX)
+        pub turtle: Option<TurtleRobotBox>,
     }
 
     impl InterpreterEnv for NoEnv {
@@ -319,6 +1217,102 @@ mod tests {
             &mut self.input
         }
         fn warn(&mut self, _msg: &str) {}
+        fn filesystem(&self) -> Option<&dyn FungeFilesystem> {
+            self.fs.as_ref().map(|fs| fs as &dyn FungeFilesystem)
+        }
+        fn now_utc(&mut self) -> DateTime<Utc> {
+            self.now.unwrap_or_else(Utc::now)
+        }
+        fn limits(&self) -> ResourceLimits {
+            self.limits
+        }
+        fn have_execute(&self) -> ExecMode {
+            if self.exec_output.is_some() {
+                ExecMode::Capture
+            } else {
+                ExecMode::Disabled
+            }
+        }
+        fn execute_command_capture(&mut self, command: &str) -> (i32, Vec<u8>) {
+            (
+                command.len() as i32,
+                self.exec_output.clone().unwrap_or_default(),
+            )
+        }
+        fn team_number<F: Funge>(&self, _ip: &InstructionPointer<F>) -> i32 {
+            self.team
+        }
+        fn is_fingerprint_enabled(&self, fpr: i32) -> bool {
+            safe_fingerprints().contains(&fpr) || self.extra_fingerprints.contains(&fpr)
+        }
+        fn fingerprint_support_library(&mut self, fpr: i32) -> Option<&mut dyn Any> {
+            if fpr == string_to_fingerprint("TURT") {
+                self.turtle.as_mut().map(|t| t as &mut dyn Any)
+            } else {
+                None
+            }
+        }
+        fn trace<F: Funge>(&mut self, event: &TraceEvent<F>) {
+            if let Some(log) = self.trace_log.as_mut() {
+                log.push(format!(
+                    "{}: {} {:?} {} {} {:?}",
+                    event.tick,
+                    event.ip_id,
+                    event.location,
+                    event.instruction,
+                    event.stack_depth,
+                    event.stack_top
+                ));
+            }
+        }
+        fn diagnostic<F: Funge>(&mut self, diagnostic: &Diagnostic<F>) {
+            if let Some(log) = self.trace_log.as_mut() {
+                log.push(format!(
+                    "{} {}: {}",
+                    diagnostic.severity.as_str(),
+                    diagnostic.code.as_str(),
+                    diagnostic
+                ));
+            }
+        }
+        fn on_ip_spawned<F: Funge>(&mut self, id: &F::Value, parent_id: &F::Value) {
+            if let Some(log) = self.trace_log.as_mut() {
+                log.push(format!("spawned {} from {}", id, parent_id));
+            }
+        }
+        fn on_ip_stopped<F: Funge>(&mut self, id: &F::Value) {
+            if let Some(log) = self.trace_log.as_mut() {
+                log.push(format!("stopped {}", id));
+            }
+        }
+        fn unknown_instruction<F: Funge>(
+            &mut self,
+            _ip: &InstructionPointer<F>,
+            instruction: &F::Value,
+        ) -> UnknownInstructionPolicy {
+            if *instruction == ('Z' as i32).into() {
+                UnknownInstructionPolicy::Abort
+            } else {
+                UnknownInstructionPolicy::Reflect
+            }
+        }
+    }
+
+    impl NoEnv {
+        pub fn new() -> Self {
+            Self {
+                input: async_std::io::empty(),
+                outout: async_std::io::sink(),
+                trace_log: None,
+                team: 0,
+                fs: None,
+                now: None,
+                exec_output: None,
+                limits: Default::default(),
+                extra_fingerprints: Vec::new(),
+                turtle: None,
+            }
+        }
     }
 
     pub struct TestFunge {}
@@ -329,4 +1323,565 @@ mod tests {
         type Value = i64;
         type Env = NoEnv;
     }
+
+    /// The interpreter most tests run: Befunge with 64-bit cells
+    pub type TestInterpreter<Env = SilentEnv> =
+        Interpreter<BefungeVec<i64>, PagedFungeSpace<BefungeVec<i64>, i64>, Env>;
+
+    /// Funge-space holding `src` at the origin
+    pub fn befunge_space(src: &str) -> PagedFungeSpace<BefungeVec<i64>, i64> {
+        let mut space = PagedFungeSpace::new_with_page_size(bfvec(80, 25));
+        BefungeVec::read_str_at(&mut space, &bfvec(0, 0), src);
+        space
+    }
+
+    /// A Befunge interpreter running `src` in a [SilentEnv]
+    pub fn befunge(src: &str) -> TestInterpreter {
+        befunge_with(src, SilentEnv::new())
+    }
+
+    /// A Befunge interpreter running `src` in `env`
+    pub fn befunge_with<Env: InterpreterEnv + 'static>(
+        src: &str,
+        env: Env,
+    ) -> TestInterpreter<Env> {
+        Interpreter::new(befunge_space(src), env)
+    }
+
+    #[test]
+    fn test_monotonic_clock() {
+        let mut env = NoEnv::new();
+        let mut prev = env.monotonic_nanos();
+        for _ in 0..1000 {
+            let now = env.monotonic_nanos();
+            assert!(now >= prev);
+            prev = now;
+        }
+        // Counting from when the clock was first read, not from 1970
+        assert!(Duration::from_nanos(prev) < Duration::from_secs(365 * 24 * 3600));
+        // All environments share the clock
+        std::thread::sleep(Duration::from_millis(2));
+        let later = NoEnv::new().monotonic_clock();
+        assert!(later >= Duration::from_nanos(prev) + Duration::from_millis(2));
+    }
+
+    /// After a `t`, the parent writes 1 and the child writes 2 to (0, 1) in
+    /// the same tick. Returns the value that was written last.
+    fn last_writer(scheduling: SchedulingPolicy) -> i64 {
+        let mut interpreter = befunge("t101p@@p102");
+        interpreter.settings.scheduling = scheduling;
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        interpreter.space[bfvec(0, 1)]
+    }
+
+    #[test]
+    fn test_fork_order() {
+        let mut interpreter = befunge("t101p@@p102");
+        assert_eq!(interpreter.run(RunMode::Step), ProgramResult::Paused);
+        // The child comes first
+        assert_eq!(interpreter.ips.len(), 2);
+        assert_eq!(interpreter.ips[0].id, 1);
+        assert_eq!(interpreter.ips[0].delta, bfvec(-1, 0));
+        assert_eq!(interpreter.ips[1].id, 0);
+        assert_eq!(interpreter.ips[1].delta, bfvec(1, 0));
+        // Both IPs see the interpreter's shared services
+        interpreter.shared.insert(42_i32);
+        for ip in interpreter.ips.iter() {
+            assert!(ip.shared.ptr_eq(&interpreter.shared));
+            assert_eq!(*ip.shared.get::<i32>().unwrap().borrow(), 42);
+        }
+    }
+
+    #[test]
+    fn test_step_ip() {
+        let mut interpreter = befunge("t101p@@p102");
+        assert_eq!(interpreter.run(RunMode::StepIp), ProgramResult::Paused);
+        assert_eq!(interpreter.ips.len(), 2);
+        // The child moves first, then the parent
+        assert_eq!(interpreter.run(RunMode::StepIp), ProgramResult::Paused);
+        assert_eq!(interpreter.ips[0].stack()[..], [2]);
+        assert!(interpreter.ips[1].stack().is_empty());
+        assert_eq!(interpreter.stepped_ip(), Some(&interpreter.ips[0].id));
+        assert_eq!(interpreter.run(RunMode::StepIp), ProgramResult::Paused);
+        assert_eq!(interpreter.ips[1].stack()[..], [1]);
+        assert_eq!(interpreter.stepped_ip(), Some(&interpreter.ips[1].id));
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.space[bfvec(0, 1)], 1);
+    }
+
+    #[test]
+    fn test_scheduling_policy() {
+        assert_eq!(last_writer(SchedulingPolicy::RoundRobin), 1);
+        assert_eq!(last_writer(SchedulingPolicy::Reverse), 2);
+        assert!([1, 2].contains(&last_writer(SchedulingPolicy::Random)));
+    }
+
+    fn endless_loop() -> TestInterpreter {
+        befunge(">1$<")
+    }
+
+    #[test]
+    fn test_instructions_executed() {
+        // Two IPs execute @ in the second tick
+        let mut interpreter = befunge("t@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.ticks(), 2);
+        assert_eq!(interpreter.instructions_executed(), 3);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut interpreter = befunge_with("1 v\n@.<", NoEnv::new());
+        interpreter.env.trace_log = Some(Vec::new());
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.ticks(), 5);
+        assert_eq!(interpreter.instructions_executed(), 5);
+        assert_eq!(
+            interpreter.env.trace_log.unwrap(),
+            vec![
+                "0: 0 BefungeVec { x: 0, y: 0 } 1 0 None",
+                "1: 0 BefungeVec { x: 2, y: 0 } v 1 Some(1)",
+                "2: 0 BefungeVec { x: 2, y: 1 } < 1 Some(1)",
+                "3: 0 BefungeVec { x: 1, y: 1 } . 1 Some(1)",
+                "4: 0 BefungeVec { x: 0, y: 1 } @ 0 None",
+                "stopped 0",
+            ]
+        );
+    }
+
+    fn run_limited(src: &str, limits: ResourceLimits) -> (ProgramResult, NoEnv) {
+        let mut interpreter = befunge_with(src, NoEnv::new());
+        interpreter.env.limits = limits;
+        interpreter.env.trace_log = Some(Vec::new());
+        (interpreter.run(RunMode::Run), interpreter.env)
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let stack = ResourceLimits {
+            max_stack_depth: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(run_limited("12345@", stack).0, ProgramResult::Done(0));
+        assert_eq!(run_limited("123456@", stack).0, ProgramResult::Panic);
+        assert_eq!(run_limited("9k1@", stack).0, ProgramResult::Panic);
+        // `{` and `u` reflect rather than creating too many zeros
+        assert_eq!(run_limited("a{@", stack).0, ProgramResult::Done(0));
+        assert_eq!(run_limited("0{a-#@u1q", stack).0, ProgramResult::Done(0));
+        let unlimited = ResourceLimits::default();
+        assert_eq!(
+            run_limited("0{a-#@u1q", unlimited).0,
+            ProgramResult::Done(1)
+        );
+
+        // A third stack isn't allowed: `{` reflects, and the IP runs into the
+        // `@` it jumped over
+        let stacks = ResourceLimits {
+            max_stack_stack_height: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(run_limited("0{0#@{1q", stacks).0, ProgramResult::Done(0));
+        assert_eq!(run_limited("0{0#@{1q", unlimited).0, ProgramResult::Done(1));
+
+        // With one IP allowed, `t` reflects
+        let ips = ResourceLimits {
+            max_ips: Some(1),
+            ..Default::default()
+        };
+        let (result, env) = run_limited("t1q@", ips);
+        assert_eq!(result, ProgramResult::Done(0));
+        assert!(!env
+            .trace_log
+            .unwrap()
+            .iter()
+            .any(|l| l.starts_with("spawned")));
+        let (result, env) = run_limited("t1q@", unlimited);
+        assert_eq!(result, ProgramResult::Done(1));
+        assert!(env
+            .trace_log
+            .unwrap()
+            .contains(&"spawned 1 from 0".to_owned()));
+
+        // Room for a single 80x25 page of 64-bit cells
+        let space = ResourceLimits {
+            max_space_bytes: Some(80 * 25 * 8),
+            ..Default::default()
+        };
+        assert_eq!(run_limited("'xa7*0p@", space).0, ProgramResult::Done(0));
+        assert_eq!(run_limited("'xaa*0p@", space).0, ProgramResult::Panic);
+    }
+
+    #[test]
+    fn test_instruction_budget() {
+        let mut interpreter = endless_loop();
+        interpreter.settings.instruction_budget = Some(InstructionBudget::new(100));
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::TimedOut);
+        let budget = interpreter.settings.instruction_budget.unwrap();
+        assert!(budget.is_exhausted());
+        assert_eq!(budget.used, 100);
+        // The budget applies across calls to run
+        assert_eq!(interpreter.run(RunMode::Step), ProgramResult::TimedOut);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut interpreter = endless_loop();
+        assert_eq!(
+            interpreter.run(RunMode::Timeout(Duration::from_millis(20))),
+            ProgramResult::TimedOut
+        );
+    }
+
+    #[test]
+    fn test_time_slice() {
+        let mut interpreter = endless_loop();
+        let run_mode = RunMode::TimeSlice(Duration::from_millis(20));
+        assert_eq!(interpreter.run(run_mode), ProgramResult::Paused);
+        assert_eq!(interpreter.ticks() % TIME_SLICE_TICKS as u64, 0);
+        // and it can be resumed
+        assert_eq!(interpreter.run(run_mode), ProgramResult::Paused);
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn test_bigint_cells() {
+        use num::BigInt;
+
+        let mut interpreter = crate::new_befunge_interpreter_big(NoEnv::new());
+        // 2**64 - 1, written to (0, 1)
+        BefungeVec::read_str_at(&mut interpreter.space, &bfvec(0, 0), "2:*:*:*:*:*:*1-01p@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(
+            interpreter.space[bfvec(0, 1)],
+            (BigInt::from(1) << 64) - BigInt::from(1)
+        );
+    }
+
+    #[test]
+    fn test_sysinfo_cell_size() {
+        // `y` item 2, written to (0, 1)
+        macro_rules! cell_size {
+            ($interpreter:expr) => {{
+                let mut interpreter = $interpreter;
+                interpreter.load_program_at(bfvec(0, 0), "2y01p@");
+                assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+                interpreter.space[bfvec(0, 1)].clone()
+            }};
+        }
+
+        assert_eq!(
+            cell_size!(crate::new_befunge_interpreter::<i64, _>(NoEnv::new())),
+            8
+        );
+        assert_eq!(
+            cell_size!(crate::new_befunge_interpreter::<i32, _>(NoEnv::new())),
+            4
+        );
+        assert_eq!(
+            cell_size!(crate::new_befunge_interpreter_i16(NoEnv::new())),
+            2.into()
+        );
+        // Unbounded
+        #[cfg(feature = "bigint")]
+        assert_eq!(
+            cell_size!(crate::new_befunge_interpreter_big(NoEnv::new())),
+            0.into()
+        );
+    }
+
+    #[test]
+    fn test_load_program_at() {
+        let mut interpreter = befunge("");
+        assert_eq!(interpreter.load_program_at(bfvec(0, 0), "v"), bfvec(1, 1));
+        // writes 3 to (0, 0)
+        assert_eq!(
+            interpreter.load_program_bin_at(bfvec(0, 1), b">300p@"),
+            bfvec(6, 1)
+        );
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.space[bfvec(0, 0)], 3);
+    }
+
+    fn load_answer<F: Funge>(
+        ip: &mut InstructionPointer<F>,
+        _space: &mut F::Space,
+        _env: &mut F::Env,
+    ) -> bool {
+        ip.push(42.into());
+        true
+    }
+
+    fn unload_nothing<F: Funge>(
+        _ip: &mut InstructionPointer<F>,
+        _space: &mut F::Space,
+        _env: &mut F::Env,
+    ) -> bool {
+        true
+    }
+
+    #[test]
+    fn test_register_fingerprint() {
+        let mut interpreter = befunge("\"LLUN\"4($$01p@");
+        interpreter.register_fingerprint("NULL", true, load_answer, unload_nothing);
+        interpreter.update_registry(|r| {
+            r.unregister(string_to_fingerprint("ROMA"));
+        });
+        let registry = interpreter.ips[0].instructions.registry();
+        assert!(!registry.contains(string_to_fingerprint("ROMA")));
+        assert!(registry
+            .safe_fingerprints()
+            .contains(&string_to_fingerprint("NULL")));
+
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.space[bfvec(0, 1)], 42);
+    }
+
+    #[test]
+    fn test_fingerprint_names() {
+        assert_eq!(
+            try_string_to_fingerprint("ROMA"),
+            Some(string_to_fingerprint("ROMA"))
+        );
+        assert_eq!(try_string_to_fingerprint("3DSP"), Some(0x33445350));
+        // Longer names would overflow (or wrap around to another fingerprint)
+        assert_eq!(try_string_to_fingerprint("XROMA"), None);
+        assert_eq!(try_string_to_fingerprint(""), None);
+        assert_eq!(try_string_to_fingerprint("RÖMA"), None);
+    }
+
+    fn input_seven<F: Funge>(
+        ip: &mut InstructionPointer<F>,
+        _space: &mut F::Space,
+        _env: &mut F::Env,
+    ) -> InstructionResult {
+        ip.push(7.into());
+        InstructionResult::Continue
+    }
+
+    #[test]
+    fn test_override_instruction() {
+        let mut interpreter = befunge("~01p@");
+        interpreter.override_instruction('~', instruction_set::sync_instruction(input_seven));
+        assert!(interpreter.remove_override('~'));
+        assert!(!interpreter.remove_override('~'));
+        interpreter.override_instruction('~', instruction_set::sync_instruction(input_seven));
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.space[bfvec(0, 1)], 7);
+    }
+
+    /// `~` that waits until (0, 1) contains an `X`
+    fn wait_for_x<F: Funge>(
+        ip: &mut InstructionPointer<F>,
+        space: &mut F::Space,
+        _env: &mut F::Env,
+    ) -> InstructionResult {
+        let loc = MotionCmds::pop_vector(ip);
+        if space[loc] == ('X' as i32).into() {
+            ip.push(7.into());
+            InstructionResult::Continue
+        } else {
+            // Leave the IP as it was
+            MotionCmds::push_vector(ip, loc);
+            InstructionResult::Yield
+        }
+    }
+
+    #[test]
+    fn test_yield() {
+        let mut interpreter = befunge("01~02p@");
+        interpreter.override_instruction('~', instruction_set::sync_instruction(wait_for_x));
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::AwaitingInput);
+        assert_eq!(interpreter.ips[0].location, bfvec(1, 0));
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::AwaitingInput);
+        assert_eq!(interpreter.ips[0].stack()[..], [0, 1]);
+        interpreter.space[bfvec(0, 1)] = 'X' as i64;
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.space[bfvec(0, 2)], 7);
+    }
+
+    #[test]
+    fn test_unknown_instruction_policy() {
+        let run_with = |policy, src| {
+            let mut interpreter = befunge_with(src, NoEnv::new());
+            interpreter.settings.unknown_instructions = policy;
+            interpreter.run(RunMode::Run)
+        };
+        use UnknownInstructionPolicy::*;
+        assert_eq!(run_with(Reflect, "1Z@"), ProgramResult::Done(0));
+        assert_eq!(run_with(WarnAndReflect, "1Z@"), ProgramResult::Done(0));
+        assert_eq!(run_with(Abort, "1Z@"), ProgramResult::Panic);
+        assert_eq!(run_with(Callback, "1Z@"), ProgramResult::Panic);
+        assert_eq!(run_with(Callback, "1Y@"), ProgramResult::Done(0));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut interpreter = befunge_with("H@", NoEnv::new());
+        interpreter.settings.unknown_instructions = UnknownInstructionPolicy::WarnAndReflect;
+        interpreter.env.trace_log = Some(Vec::new());
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        let log = interpreter.env.trace_log.unwrap();
+        assert!(log.contains(
+            &"warning unknown-instruction: Unknown instruction: 'H' (IP 0 at (0, 0))".to_owned()
+        ));
+    }
+
+    #[test]
+    fn test_panicked_ip() {
+        let mut interpreter = befunge("1v\n Z@");
+        interpreter.settings.unknown_instructions = UnknownInstructionPolicy::Abort;
+        assert!(interpreter.panicked_ip().is_none());
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Panic);
+        assert_eq!(interpreter.panicked_ip().unwrap().location, bfvec(1, 1));
+    }
+
+    #[test]
+    fn test_loop_detection() {
+        let run_with = |loop_detection, src| {
+            let mut interpreter = befunge(src);
+            interpreter.settings.loop_detection = loop_detection;
+            interpreter.run(RunMode::Limited(100))
+        };
+        use LoopDetection::*;
+        assert_eq!(run_with(Heuristic, ";;"), ProgramResult::LoopDetected);
+        assert_eq!(run_with(CellLimit(10), ";;"), ProgramResult::LoopDetected);
+        assert_eq!(run_with(Heuristic, ";a;;b;@"), ProgramResult::Done(0));
+        assert_eq!(run_with(Off, ";a;;b;@"), ProgramResult::Done(0));
+        assert_eq!(run_with(CellLimit(3), ";a;;b;@"), ProgramResult::Done(0));
+        assert_eq!(
+            run_with(CellLimit(2), ";a;;b;@"),
+            ProgramResult::LoopDetected
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut interpreter = befunge_with(";;", NoEnv::new());
+        interpreter.env.trace_log = Some(Vec::new());
+        interpreter.set_history_length(3);
+        assert_eq!(
+            interpreter.run(RunMode::Limited(100)),
+            ProgramResult::LoopDetected
+        );
+        let log = interpreter.env.trace_log.as_ref().unwrap();
+        assert!(log.iter().any(|line| line.contains("infinite-loop")));
+        let ip = interpreter.panicked_ip().unwrap();
+        let steps: Vec<_> = ip
+            .history
+            .iter()
+            .map(|entry| (entry.location, entry.instruction))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![(bfvec(0, 0), ';' as i64), (bfvec(0, 0), ';' as i64),]
+        );
+        assert!(ip.history.iter().all(|entry| entry.delta == bfvec(1, 0)));
+
+        interpreter.set_history_length(1);
+        assert_eq!(interpreter.ips[0].history.len(), 1);
+        interpreter.ips[0].location = bfvec(-1, 0);
+        interpreter.run(RunMode::Limited(100));
+        assert_eq!(interpreter.ips[0].history.len(), 1);
+    }
+
+    #[test]
+    fn test_division_mode() {
+        let divide = |mode, src| {
+            let mut interpreter = befunge(src);
+            interpreter.settings.division = mode;
+            // quotient to (0, 1), remainder to (0, 2)
+            assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+            (
+                interpreter.space[bfvec(0, 1)],
+                interpreter.space[bfvec(0, 2)],
+            )
+        };
+        let pos = "07-2/01p07-2%02p@";
+        let neg = "07-02-/01p07-02-%02p@";
+        assert_eq!(divide(DivisionMode::Truncate, pos), (-3, -1));
+        assert_eq!(divide(DivisionMode::Floor, pos), (-4, 1));
+        assert_eq!(divide(DivisionMode::Euclid, pos), (-4, 1));
+        assert_eq!(divide(DivisionMode::Truncate, neg), (3, -1));
+        assert_eq!(divide(DivisionMode::Floor, neg), (3, -1));
+        assert_eq!(divide(DivisionMode::Euclid, neg), (4, 1));
+
+        // MIN / -1 wraps around in fixed-width cells rather than panicking
+        use crate::fungespace::Cell16;
+        for mode in [DivisionMode::Floor, DivisionMode::Euclid] {
+            let mut interpreter = crate::new_befunge_interpreter_i16(NoEnv::new());
+            interpreter.settings.division = mode;
+            interpreter.load_program_at(bfvec(0, 0), "/01p@");
+            interpreter.ips[0].push_cells([Cell16(i16::MIN), Cell16(-1)]);
+            assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+            assert_eq!(interpreter.space[bfvec(0, 1)], Cell16(i16::MIN));
+        }
+    }
+
+    #[test]
+    fn test_ip_modes() {
+        let interpreter_with_mode = |src: &str, mode: IpMode| {
+            let mut interpreter = befunge(src);
+            interpreter.ips[0].mode = mode;
+            interpreter
+        };
+
+        // A run of spaces in a string is pushed as one space, unless
+        // SGML_SPACES is switched off
+        let mut interpreter = interpreter_with_mode("\"a  b\"", IpMode::default());
+        assert_eq!(interpreter.run(RunMode::Limited(4)), ProgramResult::Paused);
+        assert_eq!(interpreter.ips[0].stack()[..], [97, 32, 98]);
+        assert!(!interpreter.ips[0].mode.contains(IpMode::STRING));
+        let mut interpreter = interpreter_with_mode("\"a  b\"", IpMode::empty());
+        assert_eq!(interpreter.run(RunMode::Limited(6)), ProgramResult::Paused);
+        assert_eq!(interpreter.ips[0].stack()[..], [97, 32, 32, 98]);
+        assert_eq!(interpreter.ips[0].mode, IpMode::empty());
+
+        // In hover mode, > speeds the IP up instead
+        let mut interpreter = interpreter_with_mode(">q7 q", IpMode::default());
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        let mut interpreter = interpreter_with_mode(">q7 q", IpMode::HOVER);
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(7));
+
+        // Vectors are popped and pushed one component at a time, so they
+        // come off the bottom in queue mode and go on the bottom in invert
+        // mode
+        let mut interpreter = interpreter_with_mode("01g", IpMode::QUEUE);
+        assert_eq!(interpreter.run(RunMode::Limited(3)), ProgramResult::Paused);
+        assert_eq!(interpreter.ips[0].stack()[..], ['1' as i64]);
+        let mut interpreter = interpreter_with_mode("@", IpMode::INVERT);
+        interpreter.ips[0].push(5);
+        MotionCmds::push_vector(&mut interpreter.ips[0], bfvec(1, 2));
+        assert_eq!(interpreter.ips[0].stack()[..], [2, 1, 5]);
+        interpreter.ips[0].mode = IpMode::QUEUE;
+        assert_eq!(MotionCmds::pop_vector(&mut interpreter.ips[0]), bfvec(1, 2));
+        assert_eq!(interpreter.ips[0].stack()[..], [5]);
+    }
+
+    #[test]
+    fn test_unsigned_cells() {
+        use crate::fungespace::CellU32;
+
+        let mut interpreter = crate::new_befunge_interpreter_u32(NoEnv::new());
+        // 0-1 is the largest value, and > 1; as a delta, it is -1
+        interpreter.load_program_at(bfvec(0, 0), "01-:01p1`02p001-x");
+        interpreter.load_program_at(bfvec(16, 3), "@");
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(interpreter.space[bfvec(0, 1)], CellU32(u32::MAX));
+        assert_eq!(interpreter.space[bfvec(0, 2)], CellU32(1));
+    }
+
+    #[test]
+    fn test_ip_lifecycle_events() {
+        let mut interpreter = befunge_with("t@", NoEnv::new());
+        interpreter.env.trace_log = Some(Vec::new());
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        let events: Vec<String> = interpreter
+            .env
+            .trace_log
+            .unwrap()
+            .into_iter()
+            .filter(|l| l.starts_with("spawned") || l.starts_with("stopped"))
+            .collect();
+        assert_eq!(events, vec!["spawned 1 from 0", "stopped 0", "stopped 1"]);
+    }
 }
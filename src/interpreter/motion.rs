@@ -17,19 +17,17 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
 use std::cmp::Ordering;
-use std::ops::{Add, Mul, Sub};
+use std::ops::{Add, Sub};
 
-use super::ip::InstructionPointer;
+use num::Zero;
+
+use super::ip::{InstructionPointer, IpMode, Stack};
 use super::{Funge, InterpreterEnv};
 use crate::fungespace::index::{bfvec, BefungeVec};
 use crate::fungespace::{FungeIndex, FungeSpace, FungeValue, SrcIO};
 
 pub trait MotionCmds<Space, Env>:
-    FungeIndex
-    + Add<Output = Self>
-    + Sub<Output = Self>
-    + Mul<Space::Output, Output = Self>
-    + SrcIO<Space>
+    FungeIndex + Add<Output = Self> + Sub<Output = Self> + SrcIO<Space>
 where
     Space: FungeSpace<Self>,
     Space::Output: FungeValue,
@@ -38,30 +36,55 @@ where
     fn apply_delta<F>(instruction: char, ip: &mut InstructionPointer<F>) -> bool
     where
         F: Funge<Idx = Self, Space = Space, Value = Space::Output, Env = Env> + 'static;
-    fn pop_vector_from(stack: &mut Vec<Space::Output>) -> Self;
-    fn push_vector_onto(stack: &mut Vec<Space::Output>, v: Self);
-
+    /// Pick a random cardinal direction for `?`, using
+    /// [InterpreterEnv::random_u32]
+    fn random_delta(env: &mut Env) -> Self;
+    fn pop_vector_from(stack: &mut Stack<Space::Output>) -> Self;
+    /// Push the components of `v` onto a stack, or any other collection of
+    /// cells
+    fn push_vector_onto<S: Extend<Space::Output>>(stack: &mut S, v: Self);
+
+    /// Pop a vector off the IP's stack, one component at a time as with
+    /// [InstructionPointer::pop] (so from the bottom in [IpMode::QUEUE])
     fn pop_vector<F>(ip: &mut InstructionPointer<F>) -> Self
     where
         F: Funge<Idx = Self, Space = Space, Value = Space::Output, Env = Env> + 'static,
     {
-        Self::pop_vector_from(ip.stack_mut())
+        if ip.mode.contains(IpMode::QUEUE) {
+            // The component popped first is the one on top of the stack
+            let mut components: Stack<_> = (0..Self::RANK).map(|_| ip.pop()).collect();
+            components.reverse();
+            Self::pop_vector_from(&mut components)
+        } else {
+            Self::pop_vector_from(ip.stack_mut())
+        }
     }
 
+    /// Push a vector onto the IP's stack as with [InstructionPointer::push]
+    /// (so onto the bottom in [IpMode::INVERT])
     fn push_vector<F>(ip: &mut InstructionPointer<F>, v: Self)
     where
         F: Funge<Idx = Self, Space = Space, Value = Space::Output, Env = Env> + 'static,
     {
-        Self::push_vector_onto(ip.stack_mut(), v)
+        if ip.mode.contains(IpMode::INVERT) {
+            let mut components = Stack::new();
+            Self::push_vector_onto(&mut components, v);
+            ip.push_cells(components);
+        } else {
+            Self::push_vector_onto(ip.stack_mut(), v)
+        }
     }
     fn one_further(&self) -> Self;
+    /// Multiply this vector by a cell value (e.g. a jump distance)
+    fn scaled_by(&self, n: Space::Output) -> Self;
 }
 
 // Unefunge implementation of MotionCmds
 impl<T, Space, Env> MotionCmds<Space, Env> for T
 where
-    T: FungeValue,
-    Space: FungeSpace<Self, Output = T>,
+    T: FungeValue + Copy,
+    Space: FungeSpace<Self>,
+    Space::Output: FungeValue,
     Env: InterpreterEnv,
 {
     fn apply_delta<F>(instruction: char, ip: &mut InstructionPointer<F>) -> bool
@@ -70,52 +93,52 @@ where
     {
         match instruction {
             '>' => {
-                ip.delta = T::from(1);
+                ip.steer(T::from(1));
                 true
             }
             '<' => {
-                ip.delta = T::from(-1);
+                ip.steer(T::from(-1));
                 true
             }
             '_' => {
                 let p = ip.pop();
-                ip.delta = if p == T::zero() {
-                    T::from(1)
-                } else {
-                    T::from(-1)
-                };
-                true
-            }
-            '?' => {
-                if rand::random::<bool>() {
-                    ip.delta = T::from(1);
-                } else {
-                    ip.delta = T::from(-1);
-                }
+                ip.steer(if p.is_zero() { T::from(1) } else { T::from(-1) });
                 true
             }
             _ => false,
         }
     }
 
-    fn pop_vector_from(stack: &mut Vec<Space::Output>) -> Self {
-        stack.pop().unwrap_or_else(|| 0.into())
+    fn random_delta(env: &mut Env) -> Self {
+        match env.random_u32() % 2 {
+            0 => T::from(1),
+            _ => T::from(-1),
+        }
     }
 
-    fn push_vector_onto(stack: &mut Vec<Space::Output>, v: Self) {
-        stack.push(v);
+    fn pop_vector_from(stack: &mut Stack<Space::Output>) -> Self {
+        stack.pop().map(|v| v.convert()).unwrap_or_else(|| 0.into())
+    }
+
+    fn push_vector_onto<S: Extend<Space::Output>>(stack: &mut S, v: Self) {
+        stack.extend([v.convert()]);
     }
 
     fn one_further(&self) -> Self {
         *self + 1.into()
     }
+
+    fn scaled_by(&self, n: Space::Output) -> Self {
+        *self * n.convert()
+    }
 }
 
 // Befunge implementation of MotionCmds
 impl<T, Space, Env> MotionCmds<Space, Env> for BefungeVec<T>
 where
-    Space: FungeSpace<Self, Output = T>,
-    T: FungeValue,
+    Space: FungeSpace<Self>,
+    Space::Output: FungeValue,
+    T: FungeValue + Copy,
     Env: InterpreterEnv,
 {
     fn apply_delta<F>(instruction: char, ip: &mut InstructionPointer<F>) -> bool
@@ -124,19 +147,19 @@ where
     {
         match instruction {
             '>' => {
-                ip.delta = bfvec(1, 0);
+                ip.steer(bfvec(1, 0));
                 true
             }
             '<' => {
-                ip.delta = bfvec(-1, 0);
+                ip.steer(bfvec(-1, 0));
                 true
             }
             '^' => {
-                ip.delta = bfvec(0, -1);
+                ip.steer(bfvec(0, -1));
                 true
             }
             'v' => {
-                ip.delta = bfvec(0, 1);
+                ip.steer(bfvec(0, 1));
                 true
             }
             ']' => {
@@ -149,20 +172,20 @@ where
             }
             '_' => {
                 let p = ip.pop();
-                ip.delta = if p == T::zero() {
+                ip.steer(if p.is_zero() {
                     bfvec(1, 0)
                 } else {
                     bfvec(-1, 0)
-                };
+                });
                 true
             }
             '|' => {
                 let p = ip.pop();
-                ip.delta = if p == T::zero() {
+                ip.steer(if p.is_zero() {
                     bfvec(0, 1)
                 } else {
                     bfvec(0, -1)
-                };
+                });
                 true
             }
             'w' => {
@@ -175,31 +198,34 @@ where
                 }
                 true
             }
-            '?' => {
-                ip.delta = match rand::random::<u8>() & 3 {
-                    0 => bfvec(1, 0),
-                    1 => bfvec(0, 1),
-                    2 => bfvec(-1, 0),
-                    _ => bfvec(0, -1),
-                };
-                true
-            }
             _ => false,
         }
     }
 
-    fn pop_vector_from(stack: &mut Vec<Space::Output>) -> Self {
-        let y = stack.pop().unwrap_or_else(|| 0.into());
-        let x = stack.pop().unwrap_or_else(|| 0.into());
+    fn random_delta(env: &mut Env) -> Self {
+        match env.random_u32() % 4 {
+            0 => bfvec(1, 0),
+            1 => bfvec(0, 1),
+            2 => bfvec(-1, 0),
+            _ => bfvec(0, -1),
+        }
+    }
+
+    fn pop_vector_from(stack: &mut Stack<Space::Output>) -> Self {
+        let y: T = stack.pop().map(|v| v.convert()).unwrap_or_else(|| 0.into());
+        let x: T = stack.pop().map(|v| v.convert()).unwrap_or_else(|| 0.into());
         bfvec(x, y)
     }
 
-    fn push_vector_onto(stack: &mut Vec<Space::Output>, v: Self) {
-        stack.push(v.x);
-        stack.push(v.y);
+    fn push_vector_onto<S: Extend<Space::Output>>(stack: &mut S, v: Self) {
+        stack.extend([v.x.convert(), v.y.convert()]);
     }
 
     fn one_further(&self) -> Self {
         bfvec(self.x + 1.into(), self.y)
     }
+
+    fn scaled_by(&self, n: Space::Output) -> Self {
+        *self * n.convert::<T>()
+    }
 }
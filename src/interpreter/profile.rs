@@ -0,0 +1,113 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use hashbrown::HashMap;
+
+use crate::fungespace::{FungeIndex, FungeValue};
+
+/// Instruction counters, see [Interpreter::set_profiling][super::Interpreter::set_profiling]
+#[derive(Debug, Clone)]
+pub struct Profiler<Idx, Value> {
+    by_instruction: HashMap<char, u64>,
+    by_cell: HashMap<Idx, u64>,
+    by_ip: HashMap<Value, u64>,
+}
+
+/// Summary of the instructions executed while profiling was enabled, as
+/// returned by [Interpreter::profile][super::Interpreter::profile]
+///
+/// All lists are sorted by count, highest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileReport<Idx, Value> {
+    /// Total number of instructions executed
+    pub total: u64,
+    /// Number of times each instruction was executed
+    pub by_instruction: Vec<(char, u64)>,
+    /// Number of instructions executed in each cell of funge-space
+    pub by_cell: Vec<(Idx, u64)>,
+    /// Number of instructions executed by each IP (by ID)
+    pub by_ip: Vec<(Value, u64)>,
+}
+
+impl<Idx, Value> Default for Profiler<Idx, Value> {
+    fn default() -> Self {
+        Self {
+            by_instruction: HashMap::new(),
+            by_cell: HashMap::new(),
+            by_ip: HashMap::new(),
+        }
+    }
+}
+
+impl<Idx, Value> Profiler<Idx, Value>
+where
+    Idx: FungeIndex,
+    Value: FungeValue,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn record(&mut self, ip_id: &Value, location: Idx, instruction: char) {
+        *self.by_instruction.entry(instruction).or_insert(0) += 1;
+        *self.by_cell.entry(location).or_insert(0) += 1;
+        if let Some(count) = self.by_ip.get_mut(ip_id) {
+            *count += 1;
+        } else {
+            self.by_ip.insert(ip_id.clone(), 1);
+        }
+    }
+
+    pub fn report(&self) -> ProfileReport<Idx, Value> {
+        ProfileReport {
+            total: self.by_instruction.values().sum(),
+            by_instruction: sorted_counts(self.by_instruction.iter().map(|(c, n)| (*c, *n))),
+            by_cell: sorted_counts(self.by_cell.iter().map(|(idx, n)| (*idx, *n))),
+            by_ip: sorted_counts(self.by_ip.iter().map(|(id, n)| (id.clone(), *n))),
+        }
+    }
+}
+
+fn sorted_counts<T>(counts: impl Iterator<Item = (T, u64)>) -> Vec<(T, u64)> {
+    let mut counts: Vec<(T, u64)> = counts.collect();
+    counts.sort_by(|(_, n1), (_, n2)| n2.cmp(n1));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::befunge;
+    use super::super::{ProgramResult, RunMode};
+    use crate::fungespace::bfvec;
+
+    #[test]
+    fn test_profile() {
+        // Loop three times around the bottom row before exiting
+        let mut interpreter = befunge("3>1-:v\n ^   _@");
+        assert!(interpreter.profile().is_none());
+        interpreter.set_profiling(true);
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        let report = interpreter.profile().unwrap();
+        assert_eq!(report.total, 7 * 3 + 1);
+        assert_eq!(report.by_instruction[0].1, 3);
+        assert_eq!(report.by_ip, vec![(0, report.total)]);
+        assert!(report.by_cell.contains(&(bfvec(4, 0), 3)));
+        assert!(report.by_cell.contains(&(bfvec(1, 1), 2)));
+    }
+}
@@ -0,0 +1,741 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Record and replay the interactions of an interpreter with its environment
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use chrono::prelude::{DateTime, TimeZone, Utc};
+use futures_lite::io::{AsyncRead, AsyncWrite, Cursor};
+
+use super::{
+    all_fingerprints, Diagnostic, EnvFuture, ExecMode, Funge, IOMode, InstructionPointer,
+    InterpreterEnv, ResourceLimits, TraceEvent, UnknownInstructionPolicy,
+};
+
+/// A single interaction between the interpreter and its environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayEvent {
+    /// Bytes read from the input
+    Input(Vec<u8>),
+    /// Result of [InterpreterEnv::read_file] (`None` on error)
+    ReadFile(Option<Vec<u8>>),
+    /// Result of [InterpreterEnv::write_file] (success or not)
+    WriteFile(bool),
+    /// Result of [InterpreterEnv::execute_command]
+    Execute(i32),
+    /// Result of [InterpreterEnv::execute_command_capture]
+    CaptureExecute(i32, Vec<u8>),
+    /// Result of [InterpreterEnv::env_vars]
+    EnvVars(Vec<(String, String)>),
+    /// Result of [InterpreterEnv::argv]
+    Argv(Vec<String>),
+    /// Result of [InterpreterEnv::play_tone]
+    PlayTone(bool),
+    /// Result of [InterpreterEnv::random_u64] or [InterpreterEnv::random_u32]
+    Random(u64),
+    /// Result of [InterpreterEnv::monotonic_clock] or
+    /// [InterpreterEnv::monotonic_nanos]
+    Clock(Duration),
+    /// Result of [InterpreterEnv::now_utc]
+    Now(DateTime<Utc>),
+}
+
+/// Everything needed to replay a program run with [ReplayEnv]: the
+/// configuration of the original environment, and all interactions with it
+/// in the order they happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayLog {
+    pub iomode: IOMode,
+    pub io_buffered: bool,
+    pub handprint: i32,
+    pub file_input: bool,
+    pub file_output: bool,
+    pub execute: ExecMode,
+    /// The enabled fingerprints
+    pub fingerprints: Vec<i32>,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl ReplayLog {
+    /// Create an empty log with the configuration of `env`
+    pub fn for_env<Env: InterpreterEnv>(env: &Env) -> Self {
+        Self {
+            iomode: env.get_iomode(),
+            io_buffered: env.is_io_buffered(),
+            handprint: env.handprint(),
+            file_input: env.have_file_input(),
+            file_output: env.have_file_output(),
+            execute: env.have_execute(),
+            fingerprints: all_fingerprints()
+                .into_iter()
+                .filter(|fpr| env.is_fingerprint_enabled(*fpr))
+                .collect(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Write the log in rfunge's line-based replay file format
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "rfunge-replay 1")?;
+        writeln!(
+            out,
+            "iomode {}",
+            match self.iomode {
+                IOMode::Text => "text",
+                IOMode::Binary => "binary",
+            }
+        )?;
+        writeln!(out, "buffered {}", self.io_buffered as i32)?;
+        writeln!(out, "handprint {}", self.handprint)?;
+        writeln!(out, "file_input {}", self.file_input as i32)?;
+        writeln!(out, "file_output {}", self.file_output as i32)?;
+        writeln!(
+            out,
+            "execute {}",
+            match self.execute {
+                ExecMode::Disabled => "disabled",
+                ExecMode::System => "system",
+                ExecMode::SpecificShell => "specific",
+                ExecMode::SameShell => "same",
+                ExecMode::Capture => "capture",
+            }
+        )?;
+        for fpr in self.fingerprints.iter() {
+            writeln!(out, "fingerprint {}", fpr)?;
+        }
+        for event in self.events.iter() {
+            match event {
+                ReplayEvent::Input(bytes) => writeln!(out, "input {}", to_hex(bytes))?,
+                ReplayEvent::ReadFile(Some(bytes)) => writeln!(out, "read {}", to_hex(bytes))?,
+                ReplayEvent::ReadFile(None) => writeln!(out, "read -")?,
+                ReplayEvent::WriteFile(ok) => writeln!(out, "write {}", *ok as i32)?,
+                ReplayEvent::Execute(status) => writeln!(out, "exec {}", status)?,
+                ReplayEvent::CaptureExecute(status, output) => {
+                    writeln!(out, "capture {} {}", status, to_hex(output))?
+                }
+                ReplayEvent::EnvVars(vars) => {
+                    write!(out, "env")?;
+                    for (k, v) in vars {
+                        write!(out, " {}={}", to_hex(k.as_bytes()), to_hex(v.as_bytes()))?;
+                    }
+                    writeln!(out)?;
+                }
+                ReplayEvent::Argv(args) => {
+                    write!(out, "argv")?;
+                    for arg in args {
+                        write!(out, " {}", to_hex(arg.as_bytes()))?;
+                    }
+                    writeln!(out)?;
+                }
+                ReplayEvent::PlayTone(ok) => writeln!(out, "tone {}", *ok as i32)?,
+                ReplayEvent::Random(n) => writeln!(out, "random {}", n)?,
+                ReplayEvent::Clock(t) => {
+                    writeln!(out, "clock {} {}", t.as_secs(), t.subsec_nanos())?
+                }
+                ReplayEvent::Now(t) => {
+                    writeln!(out, "now {} {}", t.timestamp(), t.timestamp_subsec_nanos())?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a log written by [ReplayLog::write_to]
+    pub fn read_from(input: &mut dyn BufRead) -> io::Result<Self> {
+        let mut log = Self {
+            iomode: IOMode::Binary,
+            io_buffered: true,
+            handprint: 0,
+            file_input: false,
+            file_output: false,
+            execute: ExecMode::Disabled,
+            fingerprints: Vec::new(),
+            events: Vec::new(),
+        };
+        let mut lines = input.lines();
+        if lines.next().transpose()?.as_deref() != Some("rfunge-replay 1") {
+            return Err(invalid_data());
+        }
+        for line in lines {
+            let line = line?;
+            let mut words = line.split(' ');
+            let key = words.next().unwrap_or_default();
+            let args: Vec<&str> = words.collect();
+            let arg = args.first().copied().unwrap_or_default();
+            match key {
+                "iomode" => {
+                    log.iomode = match arg {
+                        "text" => IOMode::Text,
+                        "binary" => IOMode::Binary,
+                        _ => return Err(invalid_data()),
+                    }
+                }
+                "buffered" => log.io_buffered = parse_bool(arg)?,
+                "handprint" => log.handprint = parse_num(arg)?,
+                "file_input" => log.file_input = parse_bool(arg)?,
+                "file_output" => log.file_output = parse_bool(arg)?,
+                "execute" => {
+                    log.execute = match arg {
+                        "disabled" => ExecMode::Disabled,
+                        "system" => ExecMode::System,
+                        "specific" => ExecMode::SpecificShell,
+                        "same" => ExecMode::SameShell,
+                        "capture" => ExecMode::Capture,
+                        _ => return Err(invalid_data()),
+                    }
+                }
+                "fingerprint" => log.fingerprints.push(parse_num(arg)?),
+                "input" => log.events.push(ReplayEvent::Input(from_hex(arg)?)),
+                "read" => log.events.push(ReplayEvent::ReadFile(if arg == "-" {
+                    None
+                } else {
+                    Some(from_hex(arg)?)
+                })),
+                "write" => log.events.push(ReplayEvent::WriteFile(parse_bool(arg)?)),
+                "exec" => log.events.push(ReplayEvent::Execute(parse_num(arg)?)),
+                "capture" => {
+                    let output = from_hex(args.get(1).copied().unwrap_or_default())?;
+                    log.events
+                        .push(ReplayEvent::CaptureExecute(parse_num(arg)?, output));
+                }
+                "env" => {
+                    let mut vars = Vec::new();
+                    for var in args.iter() {
+                        let (k, v) = var.split_once('=').ok_or_else(invalid_data)?;
+                        vars.push((from_hex_str(k)?, from_hex_str(v)?));
+                    }
+                    log.events.push(ReplayEvent::EnvVars(vars));
+                }
+                "argv" => {
+                    let args = args
+                        .iter()
+                        .map(|a| from_hex_str(a))
+                        .collect::<io::Result<_>>()?;
+                    log.events.push(ReplayEvent::Argv(args));
+                }
+                "tone" => log.events.push(ReplayEvent::PlayTone(parse_bool(arg)?)),
+                "random" => log.events.push(ReplayEvent::Random(parse_num(arg)?)),
+                "clock" => {
+                    let secs = parse_num(arg)?;
+                    let nanos = parse_num(args.get(1).copied().unwrap_or_default())?;
+                    log.events
+                        .push(ReplayEvent::Clock(Duration::new(secs, nanos)));
+                }
+                "now" => {
+                    let secs = parse_num(arg)?;
+                    let nanos = parse_num(args.get(1).copied().unwrap_or_default())?;
+                    let t = Utc.timestamp_opt(secs, nanos).single();
+                    log.events
+                        .push(ReplayEvent::Now(t.ok_or_else(invalid_data)?));
+                }
+                "" => {}
+                _ => return Err(invalid_data()),
+            }
+        }
+        Ok(log)
+    }
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::from(io::ErrorKind::InvalidData)
+}
+
+fn parse_num<T: std::str::FromStr>(s: &str) -> io::Result<T> {
+    s.parse().map_err(|_| invalid_data())
+}
+
+fn parse_bool(s: &str) -> io::Result<bool> {
+    Ok(parse_num::<i32>(s)? != 0)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> io::Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return Err(invalid_data());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(invalid_data)
+        })
+        .collect()
+}
+
+fn from_hex_str(s: &str) -> io::Result<String> {
+    String::from_utf8(from_hex(s)?).map_err(|_| invalid_data())
+}
+
+/// Input reader of a [RecordingEnv]. It owns the wrapped environment so that
+/// it can read from the environment's input reader.
+struct RecordingInput<Env> {
+    env: Env,
+    log: ReplayLog,
+}
+
+impl<Env> AsyncRead for RecordingInput<Env>
+where
+    Env: InterpreterEnv + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(this.env.input_reader()).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            if n > 0 {
+                this.log.events.push(ReplayEvent::Input(buf[..n].to_vec()));
+            }
+        }
+        result
+    }
+}
+
+/// An environment that wraps another environment, and records all
+/// interactions with it in a [ReplayLog], so that the run can be reproduced
+/// exactly using [ReplayEnv].
+pub struct RecordingEnv<Env> {
+    inner: RecordingInput<Env>,
+}
+
+impl<Env> RecordingEnv<Env>
+where
+    Env: InterpreterEnv + Unpin,
+{
+    pub fn new(env: Env) -> Self {
+        let log = ReplayLog::for_env(&env);
+        Self {
+            inner: RecordingInput { env, log },
+        }
+    }
+
+    /// The interactions recorded so far
+    pub fn log(&self) -> &ReplayLog {
+        &self.inner.log
+    }
+
+    /// The wrapped environment
+    pub fn env(&self) -> &Env {
+        &self.inner.env
+    }
+
+    /// Split into the wrapped environment and the log
+    pub fn into_parts(self) -> (Env, ReplayLog) {
+        (self.inner.env, self.inner.log)
+    }
+
+    fn record(&mut self, event: ReplayEvent) {
+        self.inner.log.events.push(event);
+    }
+}
+
+impl<Env> InterpreterEnv for RecordingEnv<Env>
+where
+    Env: InterpreterEnv + Unpin,
+{
+    fn get_iomode(&self) -> IOMode {
+        self.inner.env.get_iomode()
+    }
+    fn is_io_buffered(&self) -> bool {
+        self.inner.env.is_io_buffered()
+    }
+    fn output_writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
+        self.inner.env.output_writer()
+    }
+    fn input_reader(&mut self) -> &mut (dyn AsyncRead + Unpin) {
+        &mut self.inner
+    }
+    fn warn(&mut self, msg: &str) {
+        self.inner.env.warn(msg)
+    }
+    fn diagnostic<F: Funge>(&mut self, diagnostic: &Diagnostic<F>) {
+        self.inner.env.diagnostic(diagnostic)
+    }
+    fn handprint(&self) -> i32 {
+        self.inner.env.handprint()
+    }
+    fn team_number<F: Funge>(&self, ip: &InstructionPointer<F>) -> i32 {
+        self.inner.env.team_number(ip)
+    }
+    fn limits(&self) -> ResourceLimits {
+        self.inner.env.limits()
+    }
+    fn have_file_input(&self) -> bool {
+        self.inner.env.have_file_input()
+    }
+    fn have_file_output(&self) -> bool {
+        self.inner.env.have_file_output()
+    }
+    fn have_execute(&self) -> ExecMode {
+        self.inner.env.have_execute()
+    }
+    fn read_file(&mut self, filename: &str) -> io::Result<Vec<u8>> {
+        let result = self.inner.env.read_file(filename);
+        self.record(ReplayEvent::ReadFile(result.as_ref().ok().cloned()));
+        result
+    }
+    fn write_file(&mut self, filename: &str, content: &[u8]) -> io::Result<()> {
+        let result = self.inner.env.write_file(filename, content);
+        self.record(ReplayEvent::WriteFile(result.is_ok()));
+        result
+    }
+    fn write_file_with(
+        &mut self,
+        filename: &str,
+        write: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let result = self.inner.env.write_file_with(filename, write);
+        self.record(ReplayEvent::WriteFile(result.is_ok()));
+        result
+    }
+    fn execute_command(&mut self, command: &str) -> i32 {
+        let status = self.inner.env.execute_command(command);
+        self.record(ReplayEvent::Execute(status));
+        status
+    }
+    fn execute_command_capture(&mut self, command: &str) -> (i32, Vec<u8>) {
+        let (status, output) = self.inner.env.execute_command_capture(command);
+        self.record(ReplayEvent::CaptureExecute(status, output.clone()));
+        (status, output)
+    }
+    fn read_file_async<'a>(&'a mut self, filename: &'a str) -> EnvFuture<'a, io::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let result = self.inner.env.read_file_async(filename).await;
+            self.record(ReplayEvent::ReadFile(result.as_ref().ok().cloned()));
+            result
+        })
+    }
+    fn write_file_async<'a>(
+        &'a mut self,
+        filename: &'a str,
+        content: &'a [u8],
+    ) -> EnvFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let result = self.inner.env.write_file_async(filename, content).await;
+            self.record(ReplayEvent::WriteFile(result.is_ok()));
+            result
+        })
+    }
+    fn write_file_with_async<'a>(
+        &'a mut self,
+        filename: &'a str,
+        write: &'a mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> EnvFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let result = self.inner.env.write_file_with_async(filename, write).await;
+            self.record(ReplayEvent::WriteFile(result.is_ok()));
+            result
+        })
+    }
+    fn execute_command_async<'a>(&'a mut self, command: &'a str) -> EnvFuture<'a, i32> {
+        Box::pin(async move {
+            let status = self.inner.env.execute_command_async(command).await;
+            self.record(ReplayEvent::Execute(status));
+            status
+        })
+    }
+    fn execute_command_capture_async<'a>(
+        &'a mut self,
+        command: &'a str,
+    ) -> EnvFuture<'a, (i32, Vec<u8>)> {
+        Box::pin(async move {
+            let (status, output) = self.inner.env.execute_command_capture_async(command).await;
+            self.record(ReplayEvent::CaptureExecute(status, output.clone()));
+            (status, output)
+        })
+    }
+    fn env_vars(&mut self) -> Vec<(String, String)> {
+        let vars = self.inner.env.env_vars();
+        self.record(ReplayEvent::EnvVars(vars.clone()));
+        vars
+    }
+    fn argv(&mut self) -> Vec<String> {
+        let args = self.inner.env.argv();
+        self.record(ReplayEvent::Argv(args.clone()));
+        args
+    }
+    fn is_fingerprint_enabled(&self, fpr: i32) -> bool {
+        self.inner.env.is_fingerprint_enabled(fpr)
+    }
+    fn play_tone(&mut self, frequency: f32, duration_ms: u32) -> bool {
+        let ok = self.inner.env.play_tone(frequency, duration_ms);
+        self.record(ReplayEvent::PlayTone(ok));
+        ok
+    }
+    fn random_u32(&mut self) -> u32 {
+        let n = self.inner.env.random_u32();
+        self.record(ReplayEvent::Random(n as u64));
+        n
+    }
+    fn random_u64(&mut self) -> u64 {
+        let n = self.inner.env.random_u64();
+        self.record(ReplayEvent::Random(n));
+        n
+    }
+    fn monotonic_clock(&mut self) -> Duration {
+        let t = self.inner.env.monotonic_clock();
+        self.record(ReplayEvent::Clock(t));
+        t
+    }
+    fn monotonic_nanos(&mut self) -> u64 {
+        let t = self.inner.env.monotonic_nanos();
+        self.record(ReplayEvent::Clock(Duration::from_nanos(t)));
+        t
+    }
+    fn now_utc(&mut self) -> DateTime<Utc> {
+        let t = self.inner.env.now_utc();
+        self.record(ReplayEvent::Now(t));
+        t
+    }
+    fn fingerprint_support_library(&mut self, fpr: i32) -> Option<&mut dyn Any> {
+        self.inner.env.fingerprint_support_library(fpr)
+    }
+    #[inline(always)]
+    fn trace<F: Funge>(&mut self, event: &TraceEvent<F>) {
+        self.inner.env.trace(event)
+    }
+    fn on_ip_spawned<F: Funge>(&mut self, id: &F::Value, parent_id: &F::Value) {
+        self.inner.env.on_ip_spawned::<F>(id, parent_id)
+    }
+    fn on_ip_stopped<F: Funge>(&mut self, id: &F::Value) {
+        self.inner.env.on_ip_stopped::<F>(id)
+    }
+    fn unknown_instruction<F: Funge>(
+        &mut self,
+        ip: &InstructionPointer<F>,
+        instruction: &F::Value,
+    ) -> UnknownInstructionPolicy {
+        self.inner.env.unknown_instruction(ip, instruction)
+    }
+}
+
+/// An environment that plays back the interactions recorded by a
+/// [RecordingEnv]
+///
+/// Each kind of interaction is replayed in the recorded order; once the
+/// recorded events of a kind are used up, the environment acts as if the
+/// operation failed (or, for random numbers and the clocks, returns zero or
+/// the UNIX epoch).
+/// Output is collected in [ReplayEnv::output].
+pub struct ReplayEnv {
+    config: ReplayLog,
+    input: Cursor<Vec<u8>>,
+    /// Everything the program has written to its output
+    pub output: Vec<u8>,
+    read_file: VecDeque<Option<Vec<u8>>>,
+    write_file: VecDeque<bool>,
+    execute: VecDeque<i32>,
+    capture_execute: VecDeque<(i32, Vec<u8>)>,
+    env_vars: VecDeque<Vec<(String, String)>>,
+    argv: VecDeque<Vec<String>>,
+    play_tone: VecDeque<bool>,
+    random: VecDeque<u64>,
+    clock: VecDeque<Duration>,
+    now: VecDeque<DateTime<Utc>>,
+}
+
+impl ReplayEnv {
+    pub fn new(log: ReplayLog) -> Self {
+        let mut env = Self {
+            config: ReplayLog {
+                events: Vec::new(),
+                ..log
+            },
+            input: Cursor::new(Vec::new()),
+            output: Vec::new(),
+            read_file: VecDeque::new(),
+            write_file: VecDeque::new(),
+            execute: VecDeque::new(),
+            capture_execute: VecDeque::new(),
+            env_vars: VecDeque::new(),
+            argv: VecDeque::new(),
+            play_tone: VecDeque::new(),
+            random: VecDeque::new(),
+            clock: VecDeque::new(),
+            now: VecDeque::new(),
+        };
+        let mut input = Vec::new();
+        for event in log.events {
+            match event {
+                ReplayEvent::Input(bytes) => input.extend(bytes),
+                ReplayEvent::ReadFile(r) => env.read_file.push_back(r),
+                ReplayEvent::WriteFile(r) => env.write_file.push_back(r),
+                ReplayEvent::Execute(r) => env.execute.push_back(r),
+                ReplayEvent::CaptureExecute(status, output) => {
+                    env.capture_execute.push_back((status, output))
+                }
+                ReplayEvent::EnvVars(r) => env.env_vars.push_back(r),
+                ReplayEvent::Argv(r) => env.argv.push_back(r),
+                ReplayEvent::PlayTone(r) => env.play_tone.push_back(r),
+                ReplayEvent::Random(r) => env.random.push_back(r),
+                ReplayEvent::Clock(r) => env.clock.push_back(r),
+                ReplayEvent::Now(r) => env.now.push_back(r),
+            }
+        }
+        env.input = Cursor::new(input);
+        env
+    }
+}
+
+impl InterpreterEnv for ReplayEnv {
+    fn get_iomode(&self) -> IOMode {
+        self.config.iomode
+    }
+    fn is_io_buffered(&self) -> bool {
+        self.config.io_buffered
+    }
+    fn output_writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
+        &mut self.output
+    }
+    fn input_reader(&mut self) -> &mut (dyn AsyncRead + Unpin) {
+        &mut self.input
+    }
+    fn warn(&mut self, _msg: &str) {}
+    fn handprint(&self) -> i32 {
+        self.config.handprint
+    }
+    fn have_file_input(&self) -> bool {
+        self.config.file_input
+    }
+    fn have_file_output(&self) -> bool {
+        self.config.file_output
+    }
+    fn have_execute(&self) -> ExecMode {
+        self.config.execute
+    }
+    fn read_file(&mut self, _filename: &str) -> io::Result<Vec<u8>> {
+        self.read_file
+            .pop_front()
+            .flatten()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+    fn write_file(&mut self, _filename: &str, _content: &[u8]) -> io::Result<()> {
+        if self.write_file.pop_front().unwrap_or(false) {
+            Ok(())
+        } else {
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        }
+    }
+    fn execute_command(&mut self, _command: &str) -> i32 {
+        self.execute.pop_front().unwrap_or(-1)
+    }
+    fn execute_command_capture(&mut self, _command: &str) -> (i32, Vec<u8>) {
+        self.capture_execute.pop_front().unwrap_or((-1, Vec::new()))
+    }
+    fn env_vars(&mut self) -> Vec<(String, String)> {
+        self.env_vars.pop_front().unwrap_or_default()
+    }
+    fn argv(&mut self) -> Vec<String> {
+        self.argv.pop_front().unwrap_or_default()
+    }
+    fn is_fingerprint_enabled(&self, fpr: i32) -> bool {
+        self.config.fingerprints.contains(&fpr)
+    }
+    fn play_tone(&mut self, _frequency: f32, _duration_ms: u32) -> bool {
+        self.play_tone.pop_front().unwrap_or(false)
+    }
+    fn random_u32(&mut self) -> u32 {
+        self.random_u64() as u32
+    }
+    fn random_u64(&mut self) -> u64 {
+        self.random.pop_front().unwrap_or(0)
+    }
+    fn monotonic_clock(&mut self) -> Duration {
+        self.clock.pop_front().unwrap_or_default()
+    }
+    fn monotonic_nanos(&mut self) -> u64 {
+        self.monotonic_clock().as_nanos() as u64
+    }
+    fn now_utc(&mut self) -> DateTime<Utc> {
+        self.now.pop_front().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::befunge_with;
+    use super::super::{ProgramResult, RunMode};
+    use super::*;
+
+    fn run<Env: InterpreterEnv + 'static>(env: Env) -> Env {
+        let mut interpreter = befunge_with("&.?1.@", env);
+        assert_eq!(interpreter.run(RunMode::Run), ProgramResult::Done(0));
+        interpreter.env
+    }
+
+    fn example_log() -> ReplayLog {
+        ReplayLog {
+            iomode: IOMode::Binary,
+            io_buffered: true,
+            handprint: 0x52464e47,
+            file_input: false,
+            file_output: false,
+            execute: ExecMode::Disabled,
+            fingerprints: vec![0x4e554c4c],
+            events: vec![
+                ReplayEvent::Input(b"5".to_vec()),
+                ReplayEvent::Input(b"\n".to_vec()),
+                ReplayEvent::Random(1),
+                ReplayEvent::Random(0),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        let env = run(ReplayEnv::new(example_log()));
+        assert_eq!(env.output, b"5 1 ");
+
+        // Recording the replay gives back the same log
+        let (env, log) = run(RecordingEnv::new(ReplayEnv::new(example_log()))).into_parts();
+        assert_eq!(env.output, b"5 1 ");
+        assert_eq!(log, example_log());
+    }
+
+    #[test]
+    fn test_replay_file() {
+        let mut log = example_log();
+        log.events.extend([
+            ReplayEvent::ReadFile(Some(b"abc".to_vec())),
+            ReplayEvent::ReadFile(None),
+            ReplayEvent::EnvVars(vec![("HOME".to_owned(), "/home/me".to_owned())]),
+            ReplayEvent::Argv(vec!["rfunge".to_owned(), "a b.b98".to_owned()]),
+            ReplayEvent::Clock(Duration::new(3, 14159)),
+            ReplayEvent::Now(Utc.timestamp_opt(1633046400, 5).unwrap()),
+            ReplayEvent::CaptureExecute(0, b"hello\n".to_vec()),
+            ReplayEvent::CaptureExecute(1, Vec::new()),
+        ]);
+        let mut buf = Vec::new();
+        log.write_to(&mut buf).unwrap();
+        let log2 = ReplayLog::read_from(&mut &buf[..]).unwrap();
+        assert_eq!(log2, log);
+        assert!(ReplayLog::read_from(&mut &b"random 1\n"[..]).is_err());
+    }
+}
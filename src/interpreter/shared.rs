@@ -0,0 +1,122 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Resources shared by all IPs of an interpreter
+
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+/// A map of services shared by all IPs of an interpreter, at most one per
+/// type, for fingerprint state that must not be private to an IP (such as
+/// SOCK's socket table).
+///
+/// This is a handle: clones refer to the same services. The interpreter's
+/// handle is [Interpreter::shared](super::Interpreter::shared), and every
+/// IP carries a clone of it in
+/// [InstructionPointer::shared](super::InstructionPointer::shared). For
+/// state that belongs to one IP, use
+/// [InstructionPointer::private_data](super::InstructionPointer::private_data).
+#[derive(Clone, Default)]
+pub struct SharedServices {
+    services: Rc<RefCell<HashMap<TypeId, Rc<dyn Any>>>>,
+}
+
+impl SharedServices {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Get the service of type `T`, if there is one
+    pub fn get<T: Any>(&self) -> Option<Rc<RefCell<T>>> {
+        self.services
+            .borrow()
+            .get(&TypeId::of::<T>())
+            .cloned()
+            .and_then(|service| service.downcast::<RefCell<T>>().ok())
+    }
+
+    /// Get the service of type `T`, creating it with `f` if there is none
+    pub fn get_or_insert_with<T: Any>(&self, f: impl FnOnce() -> T) -> Rc<RefCell<T>> {
+        if let Some(service) = self.get::<T>() {
+            return service;
+        }
+        let service = Rc::new(RefCell::new(f()));
+        self.services
+            .borrow_mut()
+            .insert(TypeId::of::<T>(), service.clone());
+        service
+    }
+
+    /// Get the service of type `T`, creating a default one if there is none
+    pub fn get_or_default<T: Any + Default>(&self) -> Rc<RefCell<T>> {
+        self.get_or_insert_with(T::default)
+    }
+
+    /// Add or replace the service of type `T`
+    pub fn insert<T: Any>(&self, service: T) {
+        self.services
+            .borrow_mut()
+            .insert(TypeId::of::<T>(), Rc::new(RefCell::new(service)));
+    }
+
+    /// Remove the service of type `T`, returning it if there was one
+    pub fn remove<T: Any>(&self) -> Option<Rc<RefCell<T>>> {
+        self.services
+            .borrow_mut()
+            .remove(&TypeId::of::<T>())
+            .and_then(|service| service.downcast::<RefCell<T>>().ok())
+    }
+
+    /// Is this the same set of services as `other`?
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.services, &other.services)
+    }
+}
+
+impl fmt::Debug for SharedServices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedServices")
+            .field("len", &self.services.borrow().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_services() {
+        let services = SharedServices::new();
+        let handle = services.clone();
+        assert!(services.get::<Vec<i32>>().is_none());
+        handle.get_or_default::<Vec<i32>>().borrow_mut().push(1);
+        services.get_or_insert_with(|| vec![7]).borrow_mut().push(2);
+        assert_eq!(*services.get::<Vec<i32>>().unwrap().borrow(), vec![1, 2]);
+        services.insert(String::from("x"));
+        assert_eq!(*handle.get::<String>().unwrap().borrow(), "x");
+        assert!(handle.remove::<String>().is_some());
+        assert!(services.get::<String>().is_none());
+        assert!(handle.ptr_eq(&services));
+        assert!(!SharedServices::new().ptr_eq(&services));
+    }
+}
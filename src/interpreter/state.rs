@@ -0,0 +1,211 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use super::fingerprints::{fingerprint_to_string, string_to_fingerprint, FingerprintRegistry};
+use super::instruction_set::InstructionSet;
+use super::ip::IpMode;
+use super::{Interpreter, InterpreterEnv, InterpreterSettings, MotionCmds};
+use crate::fungespace::paged::PageSpaceVector;
+use crate::fungespace::{FungeValue, PagedFungeSpace, SrcIO};
+use crate::InstructionPointer;
+
+/// Snapshot of the state of an IP, as stored in an [InterpreterState]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpState<Idx, Value> {
+    pub id: Value,
+    pub location: Idx,
+    pub delta: Idx,
+    pub storage_offset: Idx,
+    /// The stacks, starting with the TOSS
+    pub stack_stack: Vec<Vec<Value>>,
+    pub mode: IpMode,
+    #[serde(default)]
+    pub team: i32,
+    /// Names of the loaded fingerprints, in the order they were loaded
+    pub fingerprints: Vec<String>,
+}
+
+/// Snapshot of the complete state of an [Interpreter] using a
+/// [PagedFungeSpace], as returned by [Interpreter::save_state]
+///
+/// Fingerprints are restored by loading them again, so any internal state
+/// they keep (e.g. REFC references, TRGR triggers) is not preserved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterpreterState<Idx, Value> {
+    pub settings: InterpreterSettings,
+    pub ips: Vec<IpState<Idx, Value>>,
+    pub page_size: Idx,
+    /// The populated pages of funge-space: (page index, contents)
+    pub pages: Vec<(Idx, Vec<Value>)>,
+}
+
+impl<Idx, Elem, Env> Interpreter<Idx, PagedFungeSpace<Idx, Elem>, Env>
+where
+    Idx: MotionCmds<PagedFungeSpace<Idx, Elem>, Env>
+        + SrcIO<PagedFungeSpace<Idx, Elem>>
+        + PageSpaceVector
+        + 'static,
+    Elem: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    /// Take a snapshot of the interpreter state, which can be serialized and
+    /// later restored with [Interpreter::load_state]
+    pub fn save_state(&self) -> InterpreterState<Idx, Elem> {
+        InterpreterState {
+            settings: self.settings,
+            ips: self
+                .ips
+                .iter()
+                .map(|ip| IpState {
+                    id: ip.id.clone(),
+                    location: ip.location,
+                    delta: ip.delta,
+                    storage_offset: ip.storage_offset,
+                    stack_stack: ip.stack_stack.iter().rev().map(|s| s.to_vec()).collect(),
+                    mode: ip.mode,
+                    team: ip.team,
+                    fingerprints: ip
+                        .instructions
+                        .loaded_fingerprints()
+                        .iter()
+                        .map(|fpr| fingerprint_to_string(*fpr))
+                        .collect(),
+                })
+                .collect(),
+            page_size: self.space.page_size(),
+            pages: self
+                .space
+                .pages()
+                .map(|(page_idx, page)| (*page_idx, page.to_vec()))
+                .collect(),
+        }
+    }
+
+    /// Replace the state of the interpreter (but not the environment) with
+    /// a snapshot taken by [Interpreter::save_state]
+    ///
+    /// Returns `false` if the snapshot doesn't fit this interpreter (wrong
+    /// page size, unavailable fingerprints), in which case the interpreter
+    /// is left in an undefined state.
+    pub fn load_state(&mut self, state: &InterpreterState<Idx, Elem>) -> bool {
+        if state.page_size != self.space.page_size() {
+            return false;
+        }
+        self.space.clear();
+        for (page_idx, page) in state.pages.iter() {
+            if !self.space.insert_page(*page_idx, page.clone()) {
+                return false;
+            }
+        }
+
+        self.settings = state.settings;
+        let (registry, overrides) = match self.ips.first() {
+            Some(ip) => (
+                ip.instructions.registry().clone(),
+                ip.instructions.overrides().clone(),
+            ),
+            None => (Rc::new(FingerprintRegistry::new()), Rc::new(HashMap::new())),
+        };
+        self.ips.clear();
+        self.suspended_tick = None;
+        for ip_state in state.ips.iter() {
+            let mut ip = InstructionPointer {
+                id: ip_state.id.clone(),
+                location: ip_state.location,
+                delta: ip_state.delta,
+                storage_offset: ip_state.storage_offset,
+                stack_stack: ip_state
+                    .stack_stack
+                    .iter()
+                    .rev()
+                    .map(|s| s.iter().cloned().collect())
+                    .collect(),
+                mode: ip_state.mode,
+                team: ip_state.team,
+                history: VecDeque::new(),
+                instructions: InstructionSet::new(),
+                private_data: HashMap::new(),
+                shared: self.shared.clone(),
+            };
+            ip.instructions.set_registry(registry.clone());
+            ip.instructions.set_overrides(overrides.clone());
+            for fpr_name in ip_state.fingerprints.iter() {
+                let fpr = string_to_fingerprint(fpr_name);
+                if !self.env.is_fingerprint_enabled(fpr)
+                    || !registry.load(&mut ip, &mut self.space, &mut self.env, fpr)
+                {
+                    return false;
+                }
+                ip.instructions.fingerprint_loaded(fpr);
+            }
+            self.ips.push(ip);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::befunge;
+    use super::super::{ProgramResult, RunMode};
+    use super::*;
+    use crate::fungespace::bfvec;
+
+    #[test]
+    fn test_save_and_load_state() {
+        // Load ROMA, push M (1000), then (after resuming) X (10), add,
+        // and write the result to (0, 1)
+        let src = "\"AMOR\"4(MX+01p@";
+        let mut interpreter = befunge(src);
+        assert_eq!(interpreter.run(RunMode::Limited(9)), ProgramResult::Paused);
+
+        let state = interpreter.save_state();
+        assert_eq!(state.ips.len(), 1);
+        assert_eq!(state.ips[0].fingerprints, vec!["ROMA".to_owned()]);
+        assert_eq!(state.ips[0].stack_stack, vec![vec![0x524f4d41, 1, 1000]]);
+
+        let json = serde_json::to_string(&state).unwrap();
+        let restored_state = serde_json::from_str(&json).unwrap();
+        assert_eq!(state, restored_state);
+
+        let mut restored = befunge("");
+        assert!(restored.load_state(&restored_state));
+        assert!(restored.ips[0].shared.ptr_eq(&restored.shared));
+        assert_eq!(restored.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(restored.space[bfvec(0, 1)], 1010);
+    }
+
+    #[test]
+    fn test_load_state_string_mode() {
+        let mut interpreter = befunge("\"ab\"01p@");
+        assert_eq!(interpreter.run(RunMode::Limited(2)), ProgramResult::Paused);
+        let state = interpreter.save_state();
+        assert!(state.ips[0].mode.contains(IpMode::STRING));
+
+        let mut restored = befunge("");
+        assert!(restored.load_state(&state));
+        assert_eq!(restored.run(RunMode::Run), ProgramResult::Done(0));
+        assert_eq!(restored.space[bfvec(0, 1)], 'b' as i64);
+    }
+}
@@ -0,0 +1,183 @@
+/*
+rfunge – a Funge-98 interpreter
+Copyright © 2021 Thomas Jollans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Running a program one tick at a time, with a report of what happened
+
+use super::{Interpreter, InterpreterEnv, MotionCmds, ProgramResult, RunMode};
+use crate::fungespace::paged::PageSpaceVector;
+use crate::fungespace::sparse::SparseSpaceVector;
+use crate::fungespace::{
+    FungeSpace, FungeSpaceDiff, FungeValue, PagedFungeSpace, SparseFungeSpace, SrcIO,
+};
+
+/// One instruction executed during a tick, as passed to
+/// [InterpreterEnv::trace]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TickStep<Idx, Value> {
+    /// ID of the IP that executed the instruction
+    pub ip_id: Value,
+    /// Location of the instruction
+    pub location: Idx,
+    /// The IP's delta when it reached the instruction
+    pub delta: Idx,
+    /// The instruction
+    pub instruction: Value,
+}
+
+/// What happened during a call to [Interpreter::tick]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TickReport<Idx, Value> {
+    /// The result of the tick: [ProgramResult::Paused] if the program can
+    /// go on
+    pub result: ProgramResult,
+    /// The instructions executed, in order. Instructions that do IO (`.`,
+    /// `,`, `&`, `~`, `i`, `o`, `=`, ...) are among them.
+    pub steps: Vec<TickStep<Idx, Value>>,
+    /// IDs of the IPs created with `t`
+    pub spawned: Vec<Value>,
+    /// IDs of the IPs that stopped
+    pub stopped: Vec<Value>,
+    /// The cells of funge-space that were changed
+    pub changes: FungeSpaceDiff<Idx, Value>,
+}
+
+impl<Idx, Space, Env> Interpreter<Idx, Space, Env>
+where
+    Idx: MotionCmds<Space, Env> + SrcIO<Space> + 'static,
+    Space: FungeSpace<Idx> + Clone + 'static,
+    Space::Output: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    fn tick_with(
+        &mut self,
+        diff: impl Fn(&Space, &Space) -> FungeSpaceDiff<Idx, Space::Output>,
+    ) -> TickReport<Idx, Space::Output> {
+        let before = self.space.clone();
+        let ids_before: Vec<_> = self.ips.iter().map(|ip| ip.id.clone()).collect();
+        self.tick_log = Some(Vec::new());
+        let result = self.run(RunMode::Step);
+        let steps = self.tick_log.take().unwrap_or_default();
+        let ids_after: Vec<_> = self.ips.iter().map(|ip| ip.id.clone()).collect();
+        TickReport {
+            result,
+            steps,
+            spawned: ids_after
+                .iter()
+                .filter(|id| !ids_before.contains(id))
+                .cloned()
+                .collect(),
+            stopped: ids_before
+                .iter()
+                .filter(|id| !ids_after.contains(id))
+                .cloned()
+                .collect(),
+            changes: diff(&before, &self.space),
+        }
+    }
+}
+
+impl<Idx, Elem, Env> Interpreter<Idx, PagedFungeSpace<Idx, Elem>, Env>
+where
+    Idx: MotionCmds<PagedFungeSpace<Idx, Elem>, Env>
+        + SrcIO<PagedFungeSpace<Idx, Elem>>
+        + PageSpaceVector
+        + 'static,
+    Elem: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    /// Execute exactly one tick (or the rest of a tick interrupted by
+    /// [RunMode::StepIp] or by waiting for input), and report what happened.
+    ///
+    /// This copies funge-space to find the changes, so it's meant for
+    /// stepping through a program, not for running it at full speed.
+    pub fn tick(&mut self) -> TickReport<Idx, Elem> {
+        self.tick_with(PagedFungeSpace::diff)
+    }
+}
+
+impl<Idx, Elem, Env> Interpreter<Idx, SparseFungeSpace<Idx, Elem>, Env>
+where
+    Idx: MotionCmds<SparseFungeSpace<Idx, Elem>, Env>
+        + SrcIO<SparseFungeSpace<Idx, Elem>>
+        + SparseSpaceVector
+        + 'static,
+    Elem: FungeValue + 'static,
+    Env: InterpreterEnv + 'static,
+{
+    /// Execute exactly one tick, and report what happened (see
+    /// [Interpreter::tick] for a [PagedFungeSpace])
+    pub fn tick(&mut self) -> TickReport<Idx, Elem> {
+        self.tick_with(SparseFungeSpace::diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{befunge, NoEnv};
+    use super::*;
+    use crate::fungespace::{bfvec, BefungeVec, CellChange};
+
+    #[test]
+    fn test_tick() {
+        let mut interpreter = befunge("701pt@");
+        for _ in 0..3 {
+            interpreter.tick();
+        }
+
+        let report = interpreter.tick();
+        assert_eq!(report.result, ProgramResult::Paused);
+        assert_eq!(
+            report.steps,
+            vec![TickStep {
+                ip_id: 0,
+                location: bfvec(3, 0),
+                delta: bfvec(1, 0),
+                instruction: 'p' as i64,
+            }]
+        );
+        assert_eq!(
+            report.changes.changes(),
+            &[CellChange {
+                idx: bfvec(0, 1),
+                old: ' ' as i64,
+                new: 7,
+            }]
+        );
+
+        let report = interpreter.tick();
+        assert_eq!(report.spawned, vec![1]);
+        assert!(report.stopped.is_empty());
+        assert!(report.changes.is_empty());
+
+        let report = interpreter.tick();
+        assert_eq!(report.steps.len(), 2);
+        assert_eq!(report.stopped, vec![0]);
+    }
+
+    #[test]
+    fn test_tick_sparse() {
+        let mut interpreter = Interpreter::new(
+            SparseFungeSpace::<BefungeVec<i64>, i64>::new(),
+            NoEnv::new(),
+        );
+        interpreter.load_program_at(bfvec(0, 0), "q");
+        let report = interpreter.tick();
+        assert_eq!(report.result, ProgramResult::Done(0));
+        assert_eq!(report.steps.len(), 1);
+    }
+}
@@ -16,6 +16,7 @@ You should have received a copy of the GNU Affero General Public License
 along with this program. If not, see <https://www.gnu.org/licenses/>.
 */
 
+mod builder;
 pub mod fungespace;
 pub mod interpreter;
 
@@ -24,15 +25,28 @@ mod wasm;
 
 use std::hash::Hash;
 
-use divrem::{DivEuclid, DivRemEuclid, RemEuclid};
+use divrem::{DivEuclid, DivRem, DivRemEuclid, RemEuclid};
+#[cfg(feature = "bigint")]
+use num::BigInt;
 
+pub use crate::builder::{FingerprintFilter, InterpreterBuilder};
 pub use crate::fungespace::{
-    bfvec, read_funge_src, read_funge_src_bin, BefungeVec, FungeSpace, FungeValue, PagedFungeSpace,
+    bfvec, read_funge_src, read_funge_src_bin, read_funge_src_bin_placed, read_funge_src_centered,
+    read_funge_src_placed, BefungeVec, Cell16, CellBytes, CellU32, FungeSpace, FungeSpaceDiff,
+    FungeValue, PagedFungeSpace, SparseFungeSpace, SrcPlacement,
 };
 pub use crate::interpreter::{
-    all_fingerprints, safe_fingerprints, string_to_fingerprint, ExecMode, Funge, IOMode,
-    InstructionPointer, InstructionResult, Interpreter, InterpreterEnv, ProgramResult, RunMode,
+    all_fingerprints, fingerprint_to_string, safe_fingerprints, string_to_fingerprint,
+    try_string_to_fingerprint, Breakpoint, BreakpointId, DebugController, Diagnostic,
+    DiagnosticCode, DirJail, DivisionMode, EnvFuture, ExecMode, FingerprintFn, FingerprintRegistry,
+    Funge, FungeFilesystem, HistoryEntry, IOMode, InstructionBudget, InstructionPointer,
+    InstructionResult, Interpreter, InterpreterEnv, InterpreterSettings, IpMode, LoopDetection,
+    MemoryFilesystem, PipeEnv, ProfileReport, ProgramResult, RealFilesystem, RecordingEnv,
+    ReplayEnv, ReplayEvent, ReplayLog, ResourceLimits, RunMode, SchedulingPolicy, Severity,
+    SharedServices, SilentEnv, Stack, StringEnv, TraceEvent, UnknownInstructionPolicy,
 };
+#[cfg(feature = "serde")]
+pub use crate::interpreter::{InterpreterState, IpState};
 
 /// Create a new Unefunge interpreter using the default implementation and
 /// parameters.
@@ -44,9 +58,11 @@ pub use crate::interpreter::{
 ///
 /// After creating the interpreter, you can fill fungespace with
 /// [read_funge_src] or [read_funge_src_bin].
+///
+/// [InterpreterBuilder] offers more options.
 pub fn new_unefunge_interpreter<T, Env>(env: Env) -> Interpreter<T, PagedFungeSpace<T, T>, Env>
 where
-    T: FungeValue + RemEuclid + Hash + DivEuclid + DivRemEuclid,
+    T: FungeValue + Copy + RemEuclid + Hash + DivEuclid + DivRem<Output = (T, T)> + DivRemEuclid,
     Env: InterpreterEnv,
 {
     Interpreter::new(PagedFungeSpace::new_with_page_size(1000.into()), env)
@@ -62,12 +78,93 @@ where
 ///
 /// After creating the interpreter, you can fill fungespace with
 /// [read_funge_src] or [read_funge_src_bin].
+///
+/// [InterpreterBuilder] offers more options.
 pub fn new_befunge_interpreter<T, Env>(
     env: Env,
 ) -> Interpreter<BefungeVec<T>, PagedFungeSpace<BefungeVec<T>, T>, Env>
 where
-    T: FungeValue + RemEuclid + Hash + DivEuclid + DivRemEuclid,
+    T: FungeValue + Copy + RemEuclid + Hash + DivEuclid + DivRem<Output = (T, T)> + DivRemEuclid,
     Env: InterpreterEnv,
 {
     Interpreter::new(PagedFungeSpace::new_with_page_size(bfvec(40, 20)), env)
 }
+
+/// Create a new Unefunge interpreter with arbitrary-precision cells.
+///
+/// Cell values (and the stack) are [num::BigInt]; coordinates are `i64`.
+/// Values used as coordinates saturate to the range of `i64`.
+#[cfg(feature = "bigint")]
+pub fn new_unefunge_interpreter_big<Env>(
+    env: Env,
+) -> Interpreter<i64, PagedFungeSpace<i64, BigInt>, Env>
+where
+    Env: InterpreterEnv,
+{
+    Interpreter::new(PagedFungeSpace::new_with_page_size(1000), env)
+}
+
+/// Create a new Befunge interpreter with arbitrary-precision cells.
+///
+/// Cell values (and the stack) are [num::BigInt]; coordinates are `i64`.
+/// Values used as coordinates saturate to the range of `i64`.
+#[cfg(feature = "bigint")]
+pub fn new_befunge_interpreter_big<Env>(
+    env: Env,
+) -> Interpreter<BefungeVec<i64>, PagedFungeSpace<BefungeVec<i64>, BigInt>, Env>
+where
+    Env: InterpreterEnv,
+{
+    Interpreter::new(PagedFungeSpace::new_with_page_size(bfvec(40, 20)), env)
+}
+
+/// Create a new Unefunge interpreter with 16-bit cells (see [Cell16]).
+pub fn new_unefunge_interpreter_i16<Env>(
+    env: Env,
+) -> Interpreter<i32, PagedFungeSpace<i32, Cell16>, Env>
+where
+    Env: InterpreterEnv,
+{
+    InterpreterBuilder::unefunge()
+        .cell_size_16()
+        .env(env)
+        .build()
+}
+
+/// Create a new Befunge interpreter with 16-bit cells (see [Cell16]).
+pub fn new_befunge_interpreter_i16<Env>(
+    env: Env,
+) -> Interpreter<BefungeVec<i32>, PagedFungeSpace<BefungeVec<i32>, Cell16>, Env>
+where
+    Env: InterpreterEnv,
+{
+    InterpreterBuilder::befunge()
+        .cell_size_16()
+        .env(env)
+        .build()
+}
+
+/// Create a new Unefunge interpreter with unsigned 32-bit cells (see
+/// [CellU32]).
+pub fn new_unefunge_interpreter_u32<Env>(
+    env: Env,
+) -> Interpreter<i32, PagedFungeSpace<i32, CellU32>, Env>
+where
+    Env: InterpreterEnv,
+{
+    InterpreterBuilder::unefunge()
+        .unsigned_32()
+        .env(env)
+        .build()
+}
+
+/// Create a new Befunge interpreter with unsigned 32-bit cells (see
+/// [CellU32]).
+pub fn new_befunge_interpreter_u32<Env>(
+    env: Env,
+) -> Interpreter<BefungeVec<i32>, PagedFungeSpace<BefungeVec<i32>, CellU32>, Env>
+where
+    Env: InterpreterEnv,
+{
+    InterpreterBuilder::befunge().unsigned_32().env(env).build()
+}
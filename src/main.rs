@@ -18,22 +18,44 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 mod app;
 
+use std::fmt::Display;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use futures_lite::io::Cursor;
 use regex::Regex;
 
 use rfunge::fungespace::SrcIO;
 use rfunge::interpreter::MotionCmds;
-#[cfg(not(feature = "turt-gui"))]
-use rfunge::RunMode;
 use rfunge::{
-    new_befunge_interpreter, new_unefunge_interpreter, read_funge_src, read_funge_src_bin, Funge,
-    FungeSpace, FungeValue, IOMode, Interpreter, ProgramResult,
+    all_fingerprints, fingerprint_to_string, read_funge_src, read_funge_src_bin, safe_fingerprints,
+    try_string_to_fingerprint, Funge, FungeSpace, FungeValue, IOMode, Interpreter,
+    InterpreterBuilder, ProgramResult, ResourceLimits,
 };
 
-use app::env::CmdLineEnv;
+use app::bench::{list_workloads, run_benchmarks};
+use app::check::{check_src, CheckOptions, Severity};
+use app::config::{choose_flag, Config};
+use app::debugger::{run_debugger, DebugIdx};
+#[cfg(not(feature = "turt-gui"))]
+use app::dump::run_with_dumps;
+use app::dump::StateDumper;
+use app::env::{CmdLineEnv, OutputBuffer};
+use app::expect::diff_output;
+use app::file_access::AllowedDirs;
+use app::format::{format_file, FormatOptions};
+use app::header::{parse_header, Header};
+use app::limits::{RunLimits, EXIT_LIMIT_REACHED};
+use app::panic::{print_panic, EXIT_PANIC};
+use app::profile::print_profile;
+use app::testsuite::{run_test_suite, ReportFormat, TestSuiteOptions};
+use app::trace::{TraceFilter, TraceFormat, Tracer};
+use app::turt_image::{TurtImageFormat, TurtOutput};
+#[cfg(feature = "visual")]
+use app::visual::run_visual;
 
 #[cfg(feature = "turt-gui")]
 use app::turt::run_with_turt;
@@ -42,6 +64,161 @@ fn main() {
     let arg_matches = App::new(env!("CARGO_BIN_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .about("Funge-98 interpreter")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("Run a directory of test programs (e.g. Mycology)")
+                .long_about(
+                    "Run all .b98 (Befunge) and .u98 (Unefunge) programs in a directory. \
+                     A program passes if it exits with code 0 and its output matches \
+                     NAME.b98.expected (or NAME.expected). Without an expected output file, \
+                     it fails if a line of its output starts with BAD. The input to the \
+                     program is taken from NAME.b98.input (or NAME.input), if it exists.",
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Report format")
+                        .possible_values(&["tap", "junit"])
+                        .default_value("tap"),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .help("Time limit for each program")
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("DIR")
+                        .help("Directory with the test programs")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Reformat a program")
+                .long_about(
+                    "Load a program and write it out again without trailing whitespace, \
+                     with consistent line endings and a final newline",
+                )
+                .arg(
+                    Arg::with_name("in-place")
+                        .short("i")
+                        .long("in-place")
+                        .help("Overwrite the file instead of writing to stdout"),
+                )
+                .arg(
+                    Arg::with_name("trim")
+                        .long("trim")
+                        .help("Also remove blank lines and columns before the program"),
+                )
+                .arg(
+                    Arg::with_name("crlf")
+                        .long("crlf")
+                        .help("Use CR LF line endings (default: LF)"),
+                )
+                .arg(
+                    Arg::with_name("unicode")
+                        .short("u")
+                        .long("unicode")
+                        .help("Unicode mode (default: binary)"),
+                )
+                .arg(
+                    Arg::with_name("unefunge")
+                        .short("1")
+                        .long("unefunge")
+                        .help("Unefunge mode"),
+                )
+                .arg(
+                    Arg::with_name("befunge")
+                        .short("2")
+                        .long("befunge")
+                        .help("Befunge mode")
+                        .conflicts_with("unefunge"),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Funge-98 source to format")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Look for problems in a program without running it")
+                .long_about(
+                    "Follow every path the IP could take from the origin and report unknown \
+                     instructions, strings and comments that never end, fingerprints that \
+                     are not available, and code that is never executed. Exits with code 1 \
+                     if there are any warnings.",
+                )
+                .arg(
+                    Arg::with_name("sandbox")
+                        .short("s")
+                        .long("sandbox")
+                        .help("Only allow fingerprints that are available in sandbox mode"),
+                )
+                .arg(
+                    Arg::with_name("unicode")
+                        .short("u")
+                        .long("unicode")
+                        .help("Unicode mode (default: binary)"),
+                )
+                .arg(
+                    Arg::with_name("unefunge")
+                        .short("1")
+                        .long("unefunge")
+                        .help("Unefunge mode"),
+                )
+                .arg(
+                    Arg::with_name("befunge")
+                        .short("2")
+                        .long("befunge")
+                        .help("Befunge mode")
+                        .conflicts_with("unefunge"),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Funge-98 source to check")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Measure the speed of the interpreter")
+                .long_about(
+                    "Run built-in workloads (tight loops, wrapping around funge-space, \
+                     stack manipulation, many concurrent IPs) and report how many \
+                     instructions per second each of them executes.",
+                )
+                .arg(
+                    Arg::with_name("instructions")
+                        .long("instructions")
+                        .value_name("N")
+                        .help("Number of instructions to run each workload for")
+                        .default_value("10000000"),
+                )
+                .arg(
+                    Arg::with_name("list")
+                        .long("list")
+                        .help("List the workloads and exit"),
+                )
+                .arg(
+                    Arg::with_name("WORKLOAD")
+                        .help("Workloads to run (default: all)")
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list-fingerprints")
+                .about("List the available fingerprints")
+                .long_about(
+                    "List the fingerprints this interpreter supports. Safe fingerprints \
+                     can't access files, the network or other programs, and are the only \
+                     ones available in sandbox mode.",
+                ),
+        )
         .arg(
             Arg::with_name("warn")
                 .short("w")
@@ -64,12 +241,86 @@ fn main() {
                 .conflicts_with("binary")
                 .display_order(3),
         )
+        .arg(
+            Arg::with_name("src-encoding")
+                .long("src-encoding")
+                .value_name("ENCODING")
+                .possible_values(&["utf-8", "latin-1", "raw"])
+                .help(
+                    "Encoding of the program source, overriding -u/-b \
+                     (latin-1 and raw both mean one byte per cell)",
+                )
+                .display_order(3),
+        )
+        .arg(
+            Arg::with_name("io-encoding")
+                .long("io-encoding")
+                .value_name("ENCODING")
+                .possible_values(&["utf-8", "latin-1", "raw"])
+                .help(
+                    "Encoding of the program's input and output and of the files it \
+                     reads and writes, overriding -u/-b",
+                )
+                .display_order(3),
+        )
         .arg(
             Arg::with_name("sandbox")
                 .short("s")
                 .long("sandbox")
                 .help("Run in sandbox / secure mode"),
         )
+        .arg(
+            Arg::with_name("fingerprints")
+                .long("fingerprints")
+                .value_name("NAMES")
+                .help("Only allow these fingerprints (comma-separated, e.g. ROMA,MODU)"),
+        )
+        .arg(
+            Arg::with_name("no-fingerprint")
+                .long("no-fingerprint")
+                .value_name("NAME")
+                .help("Don't allow this fingerprint (may be given more than once)")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("no-sandbox")
+                .long("no-sandbox")
+                .help("Don't run in sandbox mode (even if rfunge.toml says so)")
+                .conflicts_with("sandbox"),
+        )
+        .arg(
+            Arg::with_name("allow-read")
+                .long("allow-read")
+                .value_name("DIR")
+                .help(
+                    "Let i read files in this directory (may be given more than once; \
+                     only the directories given are then accessible, even outside \
+                     sandbox mode)",
+                )
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("allow-write")
+                .long("allow-write")
+                .value_name("DIR")
+                .help("Let i and o read and write files in this directory (like --allow-read)")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("capture-exec")
+                .long("capture-exec")
+                .help(
+                    "Let = capture the output of commands: after the command, = pops \
+                     flags; with flag 1 it pops a vector and puts the output there (like \
+                     i), otherwise it pushes the first line of the output as a string. \
+                     The exit status goes on top. y reports this as operating \
+                     paradigm 4.",
+                )
+                .conflicts_with("sandbox"),
+        )
         .arg(
             Arg::with_name("unefunge")
                 .short("1")
@@ -100,6 +351,186 @@ fn main() {
                 .conflicts_with("32bit")
                 .display_order(4),
         )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .help("Print a summary of the executed instructions when the program ends")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("history")
+                .long("history")
+                .value_name("STEPS")
+                .help("Keep the last steps of each IP, to show if the program panics")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .help("Run the program in an interactive step debugger")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("visual")
+                .long("visual")
+                .help("Show the program running in a terminal visualizer")
+                .conflicts_with("debug")
+                .hidden(!cfg!(feature = "visual"))
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("speed")
+                .long("speed")
+                .value_name("TICKS")
+                .help("Initial speed of the visualizer, in ticks per second (default: 10)")
+                .requires("visual")
+                .hidden(!cfg!(feature = "visual"))
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("Run the program again whenever the source file changes")
+                .conflicts_with_all(&["debug", "visual"])
+                .hidden(!cfg!(feature = "watch"))
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("time-limit")
+                .long("time-limit")
+                .value_name("SECONDS")
+                .help("Stop the program after this long (exit status 124)")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("max-ticks")
+                .long("max-ticks")
+                .value_name("TICKS")
+                .help("Stop the program after this many ticks (exit status 124)")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("max-stack")
+                .long("max-stack")
+                .value_name("CELLS")
+                .help("Limit the size of each stack (the program is aborted beyond it)")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("max-stacks")
+                .long("max-stacks")
+                .value_name("N")
+                .help("Limit the number of stacks per IP ({ reflects beyond it)")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("max-ips")
+                .long("max-ips")
+                .value_name("N")
+                .help("Limit the number of IPs (t reflects beyond it)")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("max-memory")
+                .long("max-memory")
+                .value_name("BYTES")
+                .help("Limit the memory used by funge-space (the program is aborted beyond it)")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("flush-every-write")
+                .long("flush-every-write")
+                .help("Flush the program's output after every write, for use in pipes")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("input")
+                .long("input")
+                .value_name("FILE")
+                .help("Read the program's input from FILE instead of stdin")
+                .conflicts_with("expect-io")
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("expect-io")
+                .long("expect-io")
+                .value_names(&["INPUT", "EXPECTED"])
+                .help(
+                    "Read the program's input from INPUT, and compare its output to \
+                     EXPECTED instead of printing it; show a diff and exit with status 1 \
+                     if they differ",
+                )
+                .conflicts_with_all(&["debug", "visual"])
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("dump-state")
+                .long("dump-state")
+                .value_name("FILE")
+                .help(
+                    "Write the IPs, their stacks and the bounds of funge-space to FILE (as \
+                     JSON) when the program ends, or when rfunge receives SIGUSR1",
+                )
+                .display_order(5),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .value_name("FORMAT")
+                .help("Log every instruction executed to stderr")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .possible_values(&["text", "json"])
+                .display_order(6),
+        )
+        .arg(
+            Arg::with_name("trace-ip")
+                .long("trace-ip")
+                .value_name("ID")
+                .help("Only trace the IP with this ID")
+                .requires("trace")
+                .display_order(6),
+        )
+        .arg(
+            Arg::with_name("trace-region")
+                .long("trace-region")
+                .value_name("X0,Y0:X1,Y1")
+                .help("Only trace instructions in this rectangle")
+                .requires("trace")
+                .display_order(6),
+        )
+        .arg(
+            Arg::with_name("turt-output-dir")
+                .long("turt-output-dir")
+                .value_name("DIR")
+                .help(
+                    "Where to save TURT drawings (default: $RFUNGE_TURT_OUTPUT_DIR, \
+                     or the current directory)",
+                ),
+        )
+        .arg(
+            Arg::with_name("turt-output")
+                .long("turt-output")
+                .value_name("FILE")
+                .help(
+                    "File name for TURT drawings (default: $RFUNGE_TURT_OUTPUT, or \
+                     rfunge_TURT_image); a number is added if the file exists",
+                ),
+        )
+        .arg(
+            Arg::with_name("turt-record")
+                .long("turt-record")
+                .value_name("FILE")
+                .help("Save an animated SVG of the TURT drawing as it progresses"),
+        )
+        .arg(
+            Arg::with_name("turt-format")
+                .long("turt-format")
+                .value_name("FORMAT")
+                .possible_values(&["svg", "png", "pdf"])
+                .help("Image format for TURT drawings (default: from the file name, or svg)"),
+        )
         .arg(
             Arg::with_name("PROGRAM")
                 .help("Funge-98 source to execute")
@@ -107,33 +538,163 @@ fn main() {
         )
         .arg(
             Arg::with_name("ARGS")
-                .help("Arguments to pass to program")
+                .help("Arguments to pass to program (put them after -- if they start with -)")
                 .required(false)
                 .multiple(true),
         )
         .get_matches();
 
-    let filename = arg_matches.value_of("PROGRAM").unwrap();
+    if let Some(fmt_matches) = arg_matches.subcommand_matches("fmt") {
+        let filename = fmt_matches.value_of("FILE").unwrap();
+        let src = std::fs::read(filename).unwrap_or_else(|e| {
+            eprintln!("ERROR: {}: {}", filename, e);
+            std::process::exit(2);
+        });
+        // Leave the shebang and directives alone
+        let (header, body) = read_header(filename, &src);
+        let options = FormatOptions {
+            dimensions: get_dimensions(fmt_matches, filename, header.dimensions) as u32,
+            unicode: fmt_matches.is_present("unicode") || header.unicode == Some(true),
+            trim: fmt_matches.is_present("trim"),
+            crlf: fmt_matches.is_present("crlf"),
+        };
+        let result =
+            format_file(&src[..src.len() - body.len()], body, &options).and_then(|formatted| {
+                if fmt_matches.is_present("in-place") {
+                    std::fs::write(filename, formatted)
+                } else {
+                    std::io::stdout().write_all(&formatted)
+                }
+            });
+        if let Err(e) = result {
+            eprintln!("ERROR: {}: {}", filename, e);
+            std::process::exit(2);
+        }
+        std::process::exit(0);
+    }
 
-    let unefunge_fn_re = Regex::new(r"(?i)\.u(f|98|nefunge)$").unwrap();
-    let befunge_fn_re = Regex::new(r"(?i)\.b(f|98|efunge)$").unwrap();
-    // Is this Unefunge or Befunge?
-    let dim = if arg_matches.is_present("unefunge") {
-        1
-    } else if arg_matches.is_present("befunge") {
-        2
-    } else if unefunge_fn_re.is_match(filename) {
-        1
-    } else if befunge_fn_re.is_match(filename) {
-        2
-    } else {
-        0
+    if let Some(check_matches) = arg_matches.subcommand_matches("check") {
+        let filename = check_matches.value_of("FILE").unwrap();
+        let src = std::fs::read(filename).unwrap_or_else(|e| {
+            eprintln!("ERROR: {}: {}", filename, e);
+            std::process::exit(2);
+        });
+        let (header, src) = read_header(filename, &src);
+        let options = CheckOptions {
+            dimensions: get_dimensions(check_matches, filename, header.dimensions) as u32,
+            unicode: check_matches.is_present("unicode") || header.unicode == Some(true),
+            sandbox: check_matches.is_present("sandbox"),
+        };
+        match check_src(src, &options) {
+            Ok(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    eprintln!("{}:{}", filename, diagnostic);
+                }
+                let failed = diagnostics.iter().any(|d| d.severity == Severity::Warning);
+                std::process::exit(if failed { 1 } else { 0 });
+            }
+            Err(e) => {
+                eprintln!("ERROR: {}: {}", filename, e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if let Some(bench_matches) = arg_matches.subcommand_matches("bench") {
+        if bench_matches.is_present("list") {
+            list_workloads();
+            std::process::exit(0);
+        }
+        let instructions = match bench_matches.value_of("instructions").unwrap().parse() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                eprintln!("ERROR: --instructions expects a positive number");
+                std::process::exit(2);
+            }
+        };
+        let names: Vec<_> = bench_matches
+            .values_of("WORKLOAD")
+            .map(|names| names.collect())
+            .unwrap_or_default();
+        if let Err(e) = run_benchmarks(&names, instructions) {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(2);
+        }
+        std::process::exit(0);
+    }
+
+    if arg_matches
+        .subcommand_matches("list-fingerprints")
+        .is_some()
+    {
+        let safe = safe_fingerprints();
+        let mut fingerprints = all_fingerprints();
+        fingerprints.sort_by_key(|fpr| fingerprint_to_string(*fpr));
+        for fpr in fingerprints {
+            println!(
+                "{:<4}  0x{:08x}  {}",
+                fingerprint_to_string(fpr),
+                fpr,
+                if safe.contains(&fpr) {
+                    "safe"
+                } else {
+                    "unsafe"
+                }
+            );
+        }
+        std::process::exit(0);
+    }
+
+    // Defaults from rfunge.toml
+    let config = match Config::load() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(2);
+        }
     };
-    if dim == 0 {
-        eprintln!(
-            "ERROR: Can't tell if this is unefunge or befunge. Try specifying the option -1 or -2!"
-        );
-        std::process::exit(2);
+    let sandbox = choose_flag(
+        arg_matches.is_present("sandbox"),
+        arg_matches.is_present("no-sandbox"),
+        config.sandbox,
+    );
+
+    if let Some(test_matches) = arg_matches.subcommand_matches("test") {
+        let timeout = match test_matches.value_of("timeout").unwrap().parse::<f64>() {
+            Ok(secs) if secs > 0. => Duration::from_secs_f64(secs),
+            _ => {
+                eprintln!("ERROR: --timeout expects a number of seconds");
+                std::process::exit(2);
+            }
+        };
+        let options = TestSuiteOptions {
+            format: match test_matches.value_of("format") {
+                Some("junit") => ReportFormat::JUnit,
+                _ => ReportFormat::Tap,
+            },
+            timeout,
+            is_32bit: choose_flag(
+                arg_matches.is_present("32bit"),
+                arg_matches.is_present("64bit"),
+                config.cell_size.map(|size| size == 32),
+            ),
+            sandbox,
+        };
+        let dir = Path::new(test_matches.value_of("DIR").unwrap());
+        match run_test_suite(dir, &options, &mut std::io::stdout()) {
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("ERROR: {}: {}", dir.display(), e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let filename = arg_matches.value_of("PROGRAM").unwrap();
+
+    if arg_matches.is_present("watch") {
+        run_watch(filename);
     }
 
     // Read the program source
@@ -144,18 +705,170 @@ fn main() {
         File::open(filename).and_then(|mut f| f.read_to_end(&mut src_bin))
     }
     .unwrap();
+    let (header, src) = read_header(filename, &src_bin);
+    let header_len = src_bin.len() - src.len();
+    src_bin.drain(..header_len);
+
+    let dim = get_dimensions(&arg_matches, filename, header.dimensions);
+    // The command line beats the program's own directives, which beat the
+    // config file
+    let is_32bit = choose_flag(
+        arg_matches.is_present("32bit"),
+        arg_matches.is_present("64bit"),
+        header.cell_size.or(config.cell_size).map(|size| size == 32),
+    );
+    let is_unicode = choose_flag(
+        arg_matches.is_present("unicode"),
+        arg_matches.is_present("binary"),
+        header.unicode.or(config.unicode),
+    );
+    // -u/-b set both encodings; these can pick them separately
+    let src_unicode = arg_matches
+        .value_of("src-encoding")
+        .map_or(is_unicode, |enc| enc == "utf-8");
+    if src_unicode && std::str::from_utf8(&src_bin).is_err() {
+        eprintln!(
+            "ERROR: {}: source is not valid UTF-8 (try --src-encoding latin-1)",
+            filename
+        );
+        std::process::exit(2);
+    }
+    let io_unicode = arg_matches
+        .value_of("io-encoding")
+        .map_or(is_unicode, |enc| enc == "utf-8");
+    let mut limits = RunLimits::default();
+    if let Some(secs) = arg_matches.value_of("time-limit") {
+        match secs.parse::<f64>() {
+            Ok(secs) if secs >= 0. && secs.is_finite() => {
+                limits.time_limit = Some(Duration::from_secs_f64(secs))
+            }
+            _ => {
+                eprintln!("ERROR: --time-limit expects a number of seconds");
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(ticks) = arg_matches.value_of("max-ticks") {
+        match ticks.parse() {
+            Ok(ticks) => limits.max_ticks = Some(ticks),
+            Err(_) => {
+                eprintln!("ERROR: --max-ticks expects a number of ticks");
+                std::process::exit(2);
+            }
+        }
+    }
+    let history = match arg_matches.value_of("history").map(str::parse) {
+        None => 0,
+        Some(Ok(steps)) => steps,
+        Some(Err(_)) => {
+            eprintln!("ERROR: --history expects a number of steps");
+            std::process::exit(2);
+        }
+    };
+    let options = RunOptions {
+        limits,
+        profile: arg_matches.is_present("profile"),
+        history,
+        debug: arg_matches.is_present("debug"),
+        visual: if arg_matches.is_present("visual") {
+            match arg_matches.value_of("speed").unwrap_or("10").parse() {
+                Ok(speed) => Some(speed),
+                Err(_) => {
+                    eprintln!("ERROR: --speed expects a number of ticks per second");
+                    std::process::exit(2);
+                }
+            }
+        } else {
+            None
+        },
+        dump_state: arg_matches.value_of("dump-state").map(|filename| {
+            StateDumper::new(PathBuf::from(filename)).unwrap_or_else(|e| {
+                eprintln!("ERROR: Can't set up --dump-state: {}", e);
+                std::process::exit(2);
+            })
+        }),
+    };
+    if options.visual.is_some() && !cfg!(feature = "visual") {
+        eprintln!(
+            "ERROR: This build of {} has no visualizer",
+            env!("CARGO_BIN_NAME")
+        );
+        std::process::exit(2);
+    }
 
-    let is_unicode = arg_matches.is_present("unicode");
+    let trace = if arg_matches.is_present("trace") {
+        let format = match arg_matches.value_of("trace") {
+            Some("json") => TraceFormat::Json,
+            _ => TraceFormat::Text,
+        };
+        let mut filter = TraceFilter::default();
+        if let Some(ip) = arg_matches.value_of("trace-ip") {
+            match TraceFilter::parse_ip(ip) {
+                Some(ip) => filter.ip = Some(ip),
+                None => {
+                    eprintln!("ERROR: --trace-ip expects an IP ID");
+                    std::process::exit(2);
+                }
+            }
+        }
+        if let Some(region) = arg_matches.value_of("trace-region") {
+            match TraceFilter::parse_region(region) {
+                Some(region) if region.0.len() == dim => filter.region = Some(region),
+                _ => {
+                    eprintln!("ERROR: --trace-region expects two corners like 0,0:79,24");
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some((format, filter))
+    } else {
+        None
+    };
+
+    let expect_io = arg_matches.values_of("expect-io").map(|mut files| {
+        let (input, expected) = (files.next().unwrap(), files.next().unwrap());
+        let read_file = |filename| {
+            std::fs::read(filename).unwrap_or_else(|e| {
+                eprintln!("ERROR: {}: {}", filename, e);
+                std::process::exit(2);
+            })
+        };
+        (read_file(input), read_file(expected))
+    });
+    let (expect_input, expected_output) = match expect_io {
+        Some((input, expected)) => (Some(input), Some(expected)),
+        None => (None, None),
+    };
+    let input_file = arg_matches.value_of("input").map(|filename| {
+        File::open(filename).unwrap_or_else(|e| {
+            eprintln!("ERROR: {}: {}", filename, e);
+            std::process::exit(2);
+        })
+    });
+    let captured_output = expected_output.as_ref().map(|_| OutputBuffer::new());
+    let env_output = captured_output.clone();
 
     // Set up the interpreter
     let mut argv = vec![filename.to_owned()];
     argv.append(&mut arg_matches.values_of_lossy("ARGS").unwrap_or_default());
-    let sandbox = arg_matches.is_present("sandbox");
     let show_warnings = arg_matches.is_present("warn");
+    let flush_every_write = arg_matches.is_present("flush-every-write");
+    let capture_exec = arg_matches.is_present("capture-exec");
+    let allowed_fingerprints =
+        get_allowed_fingerprints(&arg_matches, sandbox, config.fingerprints.as_deref());
+    let allowed_dirs = get_allowed_dirs(&arg_matches);
+    let resource_limits = ResourceLimits {
+        max_stack_depth: get_limit(&arg_matches, "max-stack"),
+        max_stack_stack_height: get_limit(&arg_matches, "max-stacks"),
+        max_ips: get_limit(&arg_matches, "max-ips"),
+        max_space_bytes: get_limit(&arg_matches, "max-memory"),
+    };
+    let turt_output = get_turt_output(&arg_matches, &config);
+    let turt_recording = arg_matches.value_of_os("turt-record").map(PathBuf::from);
 
     let make_env = move || {
-        CmdLineEnv::new(
-            if is_unicode {
+        let mut env = CmdLineEnv::new(
+            if io_unicode {
                 IOMode::Text
             } else {
                 IOMode::Binary
@@ -163,89 +876,407 @@ fn main() {
             show_warnings,
             sandbox,
             argv,
-        )
+        );
+        if let Some(allowed) = allowed_fingerprints {
+            env.set_allowed_fingerprints(allowed);
+        }
+        if let Some(dirs) = allowed_dirs {
+            env.set_allowed_dirs(dirs);
+        }
+        env.set_resource_limits(resource_limits);
+        env.set_turt_output(turt_output);
+        if let Some(path) = turt_recording {
+            env.set_turt_recording(path);
+        }
+        if flush_every_write {
+            env.set_flush_every_write();
+        }
+        if capture_exec {
+            env.set_capture_exec();
+        }
+        if let Some((format, filter)) = trace {
+            env.set_tracer(Tracer::new(format, filter));
+        }
+        if let Some(output) = env_output {
+            env.capture_output(output);
+        }
+        if let Some(input) = expect_input {
+            env.set_input(Box::new(Cursor::new(input)));
+        }
+        if let Some(file) = input_file {
+            env.set_input_file(file);
+        }
+        env
     };
 
-    let is_32bit = arg_matches.is_present("32bit");
-
     let result = if dim == 1 {
         // unefunge
         if is_32bit {
             read_and_run(
-                move || new_unefunge_interpreter::<i32, _>(make_env()),
+                move || {
+                    InterpreterBuilder::unefunge()
+                        .cell_size_32()
+                        .env(make_env())
+                        .build()
+                },
                 src_bin,
-                is_unicode,
+                src_unicode,
+                options,
             )
         } else {
             read_and_run(
-                move || new_unefunge_interpreter::<i64, _>(make_env()),
+                move || InterpreterBuilder::unefunge().env(make_env()).build(),
                 src_bin,
-                is_unicode,
+                src_unicode,
+                options,
             )
         }
     } else if dim == 2 {
         // befunge
         if is_32bit {
             read_and_run(
-                move || new_befunge_interpreter::<i32, _>(make_env()),
+                move || {
+                    InterpreterBuilder::befunge()
+                        .cell_size_32()
+                        .env(make_env())
+                        .build()
+                },
                 src_bin,
-                is_unicode,
+                src_unicode,
+                options,
             )
         } else {
             read_and_run(
-                move || new_befunge_interpreter::<i64, _>(make_env()),
+                move || InterpreterBuilder::befunge().env(make_env()).build(),
                 src_bin,
-                is_unicode,
+                src_unicode,
+                options,
             )
         }
     } else {
         ProgramResult::Panic
     };
 
+    if let (Some(expected), Some(output), ProgramResult::Done(_)) =
+        (expected_output, captured_output, result)
+    {
+        if let Some(diff) = diff_output(&expected, &output.contents()) {
+            eprintln!("Output differs from the expected output:");
+            eprint!("{}", diff);
+            std::process::exit(1);
+        }
+    }
+
     std::process::exit(match result {
         ProgramResult::Done(returncode) => returncode,
+        ProgramResult::TimedOut => {
+            eprintln!("Program stopped: limit reached");
+            EXIT_LIMIT_REACHED
+        }
+        ProgramResult::Panic | ProgramResult::LoopDetected => EXIT_PANIC,
         _ => 1,
     });
 }
 
+/// Options for how to run the program
+#[derive(Debug, Clone)]
+struct RunOptions {
+    /// `--time-limit` and `--max-ticks` (not applied in the debugger or the
+    /// visualizer)
+    limits: RunLimits,
+    /// Print a profile at the end (`--profile`)
+    profile: bool,
+    /// Number of steps to keep in each IP's history (`--history`)
+    history: usize,
+    /// Run under the interactive debugger (`--debug`)
+    debug: bool,
+    /// Run in the visualizer at this many ticks per second (`--visual`)
+    visual: Option<u32>,
+    /// Write the state to a file at the end, or on SIGUSR1 (`--dump-state`)
+    dump_state: Option<StateDumper>,
+}
+
+/// The fingerprints allowed by `--fingerprints` (or the config file) and
+/// `--no-fingerprint`, or None if the defaults apply
+fn get_allowed_fingerprints(
+    arg_matches: &ArgMatches,
+    sandbox: bool,
+    config_fingerprints: Option<&[String]>,
+) -> Option<Vec<i32>> {
+    let available = if sandbox {
+        safe_fingerprints()
+    } else {
+        all_fingerprints()
+    };
+    let parse = |name: &str| match try_string_to_fingerprint(name.trim()) {
+        Some(fpr) if all_fingerprints().contains(&fpr) => fpr,
+        Some(_) => {
+            eprintln!("ERROR: Unknown fingerprint {}", name);
+            std::process::exit(2);
+        }
+        None => {
+            eprintln!("ERROR: Invalid fingerprint name {}", name);
+            std::process::exit(2);
+        }
+    };
+    let names: Option<Vec<&str>> = match arg_matches.value_of("fingerprints") {
+        Some(names) => Some(names.split(',').collect()),
+        None => config_fingerprints.map(|names| names.iter().map(String::as_str).collect()),
+    };
+    let mut allowed = match names {
+        Some(names) => names
+            .into_iter()
+            .filter(|name| !name.trim().is_empty())
+            .map(|name| {
+                let fpr = parse(name);
+                if !available.contains(&fpr) {
+                    eprintln!("ERROR: {} is not available in sandbox mode", name);
+                    std::process::exit(2);
+                }
+                fpr
+            })
+            .collect(),
+        None if arg_matches.is_present("no-fingerprint") => available,
+        None => return None,
+    };
+    if let Some(names) = arg_matches.values_of("no-fingerprint") {
+        let denied: Vec<i32> = names
+            .flat_map(|names| names.split(','))
+            .map(parse)
+            .collect();
+        allowed.retain(|fpr| !denied.contains(fpr));
+    }
+    Some(allowed)
+}
+
+/// The value of one of the `--max-*` options. Exits if it isn't a number.
+fn get_limit(arg_matches: &ArgMatches, name: &str) -> Option<usize> {
+    let value = arg_matches.value_of(name)?;
+    match value.parse() {
+        Ok(n) => Some(n),
+        Err(_) => {
+            eprintln!("ERROR: --{} expects a number", name);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// The directories given with `--allow-read` and `--allow-write`, or None if
+/// there are none. Exits if one of them doesn't exist.
+fn get_allowed_dirs(arg_matches: &ArgMatches) -> Option<AllowedDirs> {
+    let mut dirs = AllowedDirs::new();
+    let read_dirs = arg_matches.values_of_os("allow-read").into_iter().flatten();
+    let write_dirs = arg_matches
+        .values_of_os("allow-write")
+        .into_iter()
+        .flatten();
+    for (dir, write) in read_dirs
+        .map(|d| (d, false))
+        .chain(write_dirs.map(|d| (d, true)))
+    {
+        let result = if write {
+            dirs.allow_write(dir)
+        } else {
+            dirs.allow_read(dir)
+        };
+        if let Err(e) = result {
+            eprintln!("ERROR: {}: {}", Path::new(dir).display(), e);
+            std::process::exit(2);
+        }
+    }
+    if dirs.is_empty() {
+        None
+    } else {
+        Some(dirs)
+    }
+}
+
+/// Where to save TURT drawings: the command line beats the environment
+/// variables, which beat the config file. Exits if the format is no good.
+fn get_turt_output(arg_matches: &ArgMatches, config: &Config) -> TurtOutput {
+    let dir = arg_matches
+        .value_of_os("turt-output-dir")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("RFUNGE_TURT_OUTPUT_DIR").map(PathBuf::from))
+        .or_else(|| config.turt_output_dir.clone());
+    let file_name = arg_matches
+        .value_of_os("turt-output")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("RFUNGE_TURT_OUTPUT").map(PathBuf::from));
+    let extension = file_name
+        .as_ref()
+        .and_then(|name| name.extension())
+        .map(|ext| ext.to_string_lossy().into_owned());
+    let format = match (arg_matches.value_of("turt-format"), &extension) {
+        (Some(name), _) => TurtImageFormat::from_name(name),
+        (None, Some(ext)) => TurtImageFormat::from_name(ext).or_else(|| {
+            eprintln!(
+                "ERROR: Can't tell the image format from {:?}. Try --turt-format!",
+                ext
+            );
+            std::process::exit(2);
+        }),
+        (None, None) => config.turt_format,
+    }
+    .unwrap_or_default();
+    if !format.is_supported() {
+        eprintln!(
+            "ERROR: This build of {} can't write {} images",
+            env!("CARGO_BIN_NAME"),
+            format.extension().to_uppercase()
+        );
+        std::process::exit(2);
+    }
+    TurtOutput {
+        dir,
+        file_name,
+        format,
+    }
+}
+
+/// Re-run ourselves (minus `--watch`) every time the program file changes.
+/// Never returns.
+fn run_watch(filename: &str) -> ! {
+    if filename == "-" {
+        eprintln!("ERROR: --watch needs a program file, not stdin");
+        std::process::exit(2);
+    }
+    #[cfg(feature = "watch")]
+    {
+        let mut past_options = false;
+        let args: Vec<_> = std::env::args_os()
+            .skip(1)
+            .filter(|arg| {
+                past_options |= arg == "--";
+                past_options || arg != "--watch"
+            })
+            .collect();
+        let e = app::watch::watch(std::path::Path::new(filename), &args).unwrap_err();
+        eprintln!("ERROR: {}: {}", filename, e);
+    }
+    #[cfg(not(feature = "watch"))]
+    eprintln!(
+        "ERROR: This build of {} has no watch mode",
+        env!("CARGO_BIN_NAME")
+    );
+    std::process::exit(2);
+}
+
+/// Split off the shebang and `#rfunge:` lines. Exits if a directive is bad.
+fn read_header<'a>(filename: &str, src: &'a [u8]) -> (Header, &'a [u8]) {
+    parse_header(src).unwrap_or_else(|e| {
+        eprintln!("ERROR: {}: {}", filename, e);
+        std::process::exit(2);
+    })
+}
+
+/// Is this Unefunge (1) or Befunge (2)? Exits if we can't tell.
+fn get_dimensions(
+    arg_matches: &ArgMatches,
+    filename: &str,
+    header_dimensions: Option<usize>,
+) -> usize {
+    let unefunge_fn_re = Regex::new(r"(?i)\.u(f|98|nefunge)$").unwrap();
+    let befunge_fn_re = Regex::new(r"(?i)\.b(f|98|efunge)$").unwrap();
+    if arg_matches.is_present("unefunge") {
+        1
+    } else if arg_matches.is_present("befunge") {
+        2
+    } else if let Some(dim) = header_dimensions {
+        dim
+    } else if unefunge_fn_re.is_match(filename) {
+        1
+    } else if befunge_fn_re.is_match(filename) {
+        2
+    } else {
+        eprintln!(
+            "ERROR: Can't tell if this is unefunge or befunge. Try specifying the option -1 or -2!"
+        );
+        std::process::exit(2);
+    }
+}
+
 fn read_and_run<Idx, Space, InitFn>(
     make_interpreter: InitFn,
     src_bin: Vec<u8>,
-    is_unicode: bool,
+    src_unicode: bool,
+    options: RunOptions,
 ) -> ProgramResult
 where
-    Idx: MotionCmds<Space, CmdLineEnv> + SrcIO<Space>,
+    Idx: MotionCmds<Space, CmdLineEnv> + SrcIO<Space> + DebugIdx + Display,
     Space: FungeSpace<Idx> + 'static,
     Space::Output: FungeValue,
     InitFn: FnOnce() -> Interpreter<Idx, Space, CmdLineEnv> + Send + 'static,
 {
-    run::<_, Interpreter<Idx, Space, CmdLineEnv>>(move || {
+    let init = move || {
         let mut interpreter = make_interpreter();
-        if is_unicode {
+        if src_unicode {
+            // Checked in main()
             let src_str = String::from_utf8(src_bin).unwrap();
             read_funge_src(&mut interpreter.space, &src_str);
         } else {
             read_funge_src_bin(&mut interpreter.space, &src_bin);
         }
+        interpreter.set_profiling(options.profile);
+        interpreter.set_history_length(options.history);
         interpreter
-    })
+    };
+    if options.debug || options.visual.is_some() {
+        // The debugger and the visualizer drive the interpreter themselves,
+        // on this thread
+        let mut interpreter = init();
+        let result = match options.visual {
+            #[cfg(feature = "visual")]
+            Some(speed) => run_visual(&mut interpreter, speed),
+            _ => run_debugger(&mut interpreter),
+        };
+        if matches!(result, ProgramResult::Panic | ProgramResult::LoopDetected) {
+            print_panic(&interpreter);
+        }
+        if let Some(report) = interpreter.profile() {
+            print_profile(&report);
+        }
+        if let Some(dumper) = &options.dump_state {
+            dumper.dump(&interpreter, "exit");
+        }
+        result
+    } else {
+        run::<_, Interpreter<Idx, Space, CmdLineEnv>>(init, options.limits, options.dump_state)
+    }
 }
 
 #[cfg(not(feature = "turt-gui"))]
-pub fn run<InitFn, Interp>(make_interpreter: InitFn) -> ProgramResult
+pub fn run<InitFn, Interp>(
+    make_interpreter: InitFn,
+    limits: RunLimits,
+    dump_state: Option<StateDumper>,
+) -> ProgramResult
 where
     InitFn: FnOnce() -> Interpreter<Interp::Idx, Interp::Space, Interp::Env> + Send + 'static,
     Interp: Funge<Env = CmdLineEnv> + 'static,
+    Interp::Idx: Display,
 {
     let mut interpreter = make_interpreter();
-    interpreter.run(RunMode::Run)
+    let result = run_with_dumps(&mut interpreter, limits, dump_state.as_ref());
+    if matches!(result, ProgramResult::Panic | ProgramResult::LoopDetected) {
+        print_panic(&interpreter);
+    }
+    if let Some(report) = interpreter.profile() {
+        print_profile(&report);
+    }
+    result
 }
 
 #[cfg(feature = "turt-gui")]
-pub fn run<InitFn, Interp>(make_interpreter: InitFn) -> ProgramResult
+pub fn run<InitFn, Interp>(
+    make_interpreter: InitFn,
+    limits: RunLimits,
+    dump_state: Option<StateDumper>,
+) -> ProgramResult
 where
     InitFn: FnOnce() -> Interpreter<Interp::Idx, Interp::Space, Interp::Env> + Send + 'static,
     Interp: Funge<Env = CmdLineEnv> + 'static,
+    Interp::Idx: Display,
 {
-    run_with_turt::<InitFn, Interp>(make_interpreter)
+    run_with_turt::<InitFn, Interp>(make_interpreter, limits, dump_state)
 }
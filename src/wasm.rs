@@ -19,32 +19,47 @@ along with this program. If not, see <https://www.gnu.org/licenses/>.
 #![cfg(target_family = "wasm")]
 
 use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
 use std::cmp::min;
 use std::future::Future;
+use std::io;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use futures_lite::io as f_io;
 use futures_lite::io::{AsyncRead, AsyncWrite};
+use num::ToPrimitive;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
 use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 
-use crate::fungespace::SrcIO;
-use crate::interpreter::fingerprints::string_to_fingerprint;
+use crate::fungespace::paged::PageSpaceVector;
+use crate::fungespace::{CellBytes, SrcIO};
 use crate::interpreter::fingerprints::TURT::{
-    Colour, Dot, Line, SimpleRobot, TurtleDisplay, TurtleRobotBox,
+    Colour, Dot, Fill, Line, SimpleRobot, TurtleDisplay, TurtleRobotBox,
+};
+use crate::interpreter::fingerprints::{
+    fingerprint_to_string, string_to_fingerprint, try_string_to_fingerprint,
+};
+use crate::interpreter::{
+    Breakpoint, BreakpointId, Diagnostic, FungeFilesystem, IpMode, MotionCmds,
 };
 use crate::{
-    bfvec, new_befunge_interpreter, read_funge_src, safe_fingerprints, BefungeVec, ExecMode,
-    FungeSpace, IOMode, Interpreter, InterpreterEnv, PagedFungeSpace, ProgramResult, RunMode,
+    bfvec, read_funge_src, safe_fingerprints, BefungeVec, EnvFuture, ExecMode, Funge, FungeSpace,
+    FungeValue, IOMode, Interpreter, InterpreterBuilder, InterpreterEnv, InterpreterState,
+    PagedFungeSpace, ProgramResult, RunMode,
 };
 
 #[wasm_bindgen]
 extern "C" {
     pub type JSEnvInterface;
     pub type JSTurtleDisplay;
+    pub type JSFileSystem;
 
     #[wasm_bindgen(method, js_name = "writeOutput")]
     fn write_output(this: &JSEnvInterface, s: &str);
@@ -54,17 +69,164 @@ extern "C" {
     fn env_vars(this: &JSEnvInterface) -> js_sys::Object;
     #[wasm_bindgen(method, js_name = "readInput")]
     fn read_input(this: &JSEnvInterface) -> js_sys::Promise;
+    // Optional hooks, so they are looked up as properties
+    #[wasm_bindgen(method, getter, js_name = "readInputBytes")]
+    fn read_input_bytes_hook(this: &JSEnvInterface) -> JsValue;
+    #[wasm_bindgen(method, getter, js_name = "writeOutputBytes")]
+    fn write_output_bytes_hook(this: &JSEnvInterface) -> JsValue;
+    #[wasm_bindgen(method, getter, js_name = "diagnostic")]
+    fn diagnostic_hook(this: &JSEnvInterface) -> JsValue;
+    #[wasm_bindgen(method, getter, js_name = "ioMode")]
+    fn io_mode(this: &JSEnvInterface) -> Option<String>;
+    #[wasm_bindgen(method, getter)]
+    fn fingerprints(this: &JSEnvInterface) -> JsValue;
+    #[wasm_bindgen(method, getter, js_name = "fileSystem")]
+    fn file_system(this: &JSEnvInterface) -> Option<JSFileSystem>;
     #[wasm_bindgen(method, getter, js_name = "turtleDisplay")]
     fn turtle_display(this: &JSEnvInterface) -> JSTurtleDisplay;
+    #[wasm_bindgen(method, js_name = "playTone")]
+    fn play_tone(this: &JSEnvInterface, frequency: f32, duration_ms: u32) -> bool;
 
     #[wasm_bindgen(method)]
     fn display(this: &JSTurtleDisplay, show: bool);
     #[wasm_bindgen(method, js_name = "isDisplayVisible")]
     fn display_visible(this: &JSTurtleDisplay) -> bool;
     #[wasm_bindgen(method)]
-    fn draw(this: &JSTurtleDisplay, background: JsValue, lines: Vec<JsValue>, dots: Vec<JsValue>);
+    fn draw(
+        this: &JSTurtleDisplay,
+        turtle: i32,
+        background: JsValue,
+        fills: Vec<JsValue>,
+        lines: Vec<JsValue>,
+        dots: Vec<JsValue>,
+    );
     #[wasm_bindgen(method)]
-    fn print(this: &JSTurtleDisplay, background: JsValue, lines: Vec<JsValue>, dots: Vec<JsValue>);
+    fn print(
+        this: &JSTurtleDisplay,
+        background: JsValue,
+        fills: Vec<JsValue>,
+        lines: Vec<JsValue>,
+        dots: Vec<JsValue>,
+    );
+
+    #[wasm_bindgen(method, catch, js_name = "readFile")]
+    fn read_file(this: &JSFileSystem, filename: &str) -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(method, catch, js_name = "writeFile")]
+    fn write_file(
+        this: &JSFileSystem,
+        filename: &str,
+        content: &js_sys::Uint8Array,
+    ) -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(method, catch, js_name = "listDir")]
+    fn list_dir(this: &JSFileSystem, path: &str) -> Result<JsValue, JsValue>;
+}
+
+fn js_io_error(e: JsValue) -> io::Error {
+    let msg = e
+        .dyn_into::<js_sys::Error>()
+        .map(|e| String::from(e.message()))
+        .unwrap_or_else(|e| {
+            e.as_string()
+                .unwrap_or_else(|| "JavaScript Error".to_owned())
+        });
+    io::Error::other(msg)
+}
+
+/// Wait for `value` if it is a promise
+async fn resolve_js(value: JsValue) -> Result<JsValue, JsValue> {
+    match value.dyn_into::<js_sys::Promise>() {
+        Ok(promise) => JsFuture::from(promise).await,
+        Err(value) => Ok(value),
+    }
+}
+
+/// The contents of a file as returned by `readFile()`: a string, a
+/// Uint8Array, or null/undefined if there is no such file
+fn js_file_content(content: JsValue) -> io::Result<Vec<u8>> {
+    if content.is_null() || content.is_undefined() {
+        Err(io::Error::from(io::ErrorKind::NotFound))
+    } else if let Some(s) = content.as_string() {
+        Ok(s.into_bytes())
+    } else if let Ok(bytes) = content.dyn_into::<js_sys::Uint8Array>() {
+        Ok(bytes.to_vec())
+    } else {
+        Err(io::Error::from(io::ErrorKind::InvalidData))
+    }
+}
+
+/// The exit status of a command run by `=`, from the value it evaluated to
+fn js_exit_status(val: &JsValue) -> i32 {
+    if val.is_null() || val.is_undefined() {
+        0
+    } else if let Some(n) = val.as_f64() {
+        n as i32
+    } else if val.is_truthy() {
+        0
+    } else {
+        1
+    }
+}
+
+/// A file being written to a [JSFileSystem]; `writeFile()` is called when
+/// it is flushed or dropped
+struct JSFile<'a> {
+    fs: &'a JSFileSystem,
+    path: String,
+    buf: Vec<u8>,
+    dirty: bool,
+}
+
+impl io::Write for JSFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        self.dirty = true;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.dirty = false;
+            self.fs
+                .write_file(&self.path, &js_sys::Uint8Array::from(&self.buf[..]))
+                .map_err(js_io_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for JSFile<'_> {
+    fn drop(&mut self) {
+        io::Write::flush(self).ok();
+    }
+}
+
+impl FungeFilesystem for JSFileSystem {
+    fn open(&self, path: &str) -> io::Result<Box<dyn io::Read + '_>> {
+        Ok(Box::new(io::Cursor::new(self.read(path)?)))
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        js_file_content(self.read_file(path).map_err(js_io_error)?)
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn io::Write + '_>> {
+        Ok(Box::new(JSFile {
+            fs: self,
+            path: path.to_owned(),
+            buf: Vec::new(),
+            // even an empty file has to be written
+            dirty: true,
+        }))
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        let names = self
+            .list_dir(path)
+            .map_err(js_io_error)?
+            .dyn_into::<js_sys::Array>()
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+        Ok(names.iter().filter_map(|name| name.as_string()).collect())
+    }
 }
 
 struct TurtleDisplayWrapper {
@@ -78,33 +240,49 @@ impl TurtleDisplay for TurtleDisplayWrapper {
     fn display_visible(&self) -> bool {
         self.display.display_visible()
     }
-    fn draw(&mut self, background: Option<Colour>, lines: &[Line], dots: &[Dot]) {
+    fn draw(
+        &mut self,
+        turtle: i32,
+        background: Option<Colour>,
+        fills: &[Fill],
+        lines: &[Line],
+        dots: &[Dot],
+    ) {
         self.display.draw(
+            turtle,
             background
                 .as_ref()
-                .and_then(|c| JsValue::from_serde(c).ok())
+                .and_then(|c| serde_wasm_bindgen::to_value(c).ok())
                 .unwrap_or(JsValue::NULL),
+            fills
+                .iter()
+                .filter_map(|f| serde_wasm_bindgen::to_value(f).ok())
+                .collect(),
             lines
                 .iter()
-                .filter_map(|l| JsValue::from_serde(l).ok())
+                .filter_map(|l| serde_wasm_bindgen::to_value(l).ok())
                 .collect(),
             dots.iter()
-                .filter_map(|d| JsValue::from_serde(d).ok())
+                .filter_map(|d| serde_wasm_bindgen::to_value(d).ok())
                 .collect(),
         )
     }
-    fn print(&mut self, background: Option<Colour>, lines: &[Line], dots: &[Dot]) {
+    fn print(&mut self, background: Option<Colour>, fills: &[Fill], lines: &[Line], dots: &[Dot]) {
         self.display.print(
             background
                 .as_ref()
-                .and_then(|c| JsValue::from_serde(c).ok())
+                .and_then(|c| serde_wasm_bindgen::to_value(c).ok())
                 .unwrap_or(JsValue::NULL),
+            fills
+                .iter()
+                .filter_map(|f| serde_wasm_bindgen::to_value(f).ok())
+                .collect(),
             lines
                 .iter()
-                .filter_map(|l| JsValue::from_serde(l).ok())
+                .filter_map(|l| serde_wasm_bindgen::to_value(l).ok())
                 .collect(),
             dots.iter()
-                .filter_map(|d| JsValue::from_serde(d).ok())
+                .filter_map(|d| serde_wasm_bindgen::to_value(d).ok())
                 .collect(),
         )
     }
@@ -112,22 +290,93 @@ impl TurtleDisplay for TurtleDisplayWrapper {
 
 pub struct JSEnv {
     inner: JSEnvInterface,
+    io_mode: IOMode,
+    /// Set by the host with `env.fingerprints`; if `None`, the safe
+    /// fingerprints plus TURT and TRTX are available
+    fingerprints: Option<Vec<i32>>,
+    file_system: Option<JSFileSystem>,
     input_promise: Option<JsFuture>,
     input_buf: Vec<u8>,
     turt_helper: Option<TurtleRobotBox>,
 }
 
+impl JSEnv {
+    fn new(inner: JSEnvInterface) -> Self {
+        let io_mode = match inner.io_mode().as_deref() {
+            Some("binary") => IOMode::Binary,
+            _ => IOMode::Text,
+        };
+        let fingerprints = inner
+            .fingerprints()
+            .dyn_into::<js_sys::Array>()
+            .ok()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.as_string())
+                    .filter_map(|name| {
+                        let fpr = try_string_to_fingerprint(&name);
+                        if fpr.is_none() {
+                            inner.warn(&format!("Invalid fingerprint name {}", name));
+                        }
+                        fpr
+                    })
+                    .collect()
+            });
+        let file_system = inner.file_system();
+        Self {
+            inner,
+            io_mode,
+            fingerprints,
+            file_system,
+            input_promise: None,
+            input_buf: vec![],
+            turt_helper: None,
+        }
+    }
+
+    /// Ask JS for more input, preferring `readInputBytes()` if there is one
+    fn request_input(&self) -> js_sys::Promise {
+        match self
+            .inner
+            .read_input_bytes_hook()
+            .dyn_into::<js_sys::Function>()
+        {
+            Ok(read_bytes) => match read_bytes.call0(&self.inner) {
+                Ok(promise) => js_sys::Promise::resolve(&promise),
+                Err(e) => js_sys::Promise::reject(&e),
+            },
+            Err(_) => self.inner.read_input(),
+        }
+    }
+}
+
 impl AsyncWrite for JSEnv {
     fn poll_write(
         self: Pin<&mut Self>,
         _cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<f_io::Result<usize>> {
-        if let Ok(s) = std::str::from_utf8(buf) {
+        if self.io_mode == IOMode::Binary {
+            // Bytes need not be valid UTF-8 in binary mode
+            if let Ok(write_bytes) = self
+                .inner
+                .write_output_bytes_hook()
+                .dyn_into::<js_sys::Function>()
+            {
+                let bytes = js_sys::Uint8Array::from(buf);
+                return match write_bytes.call1(&self.inner, &bytes) {
+                    Ok(_) => Poll::Ready(Ok(buf.len())),
+                    Err(_) => Poll::Ready(Err(f_io::Error::other("JavaScript Error"))),
+                };
+            }
+            self.inner.write_output(&String::from_utf8_lossy(buf));
+            Poll::Ready(Ok(buf.len()))
+        } else if let Ok(s) = std::str::from_utf8(buf) {
             self.inner.write_output(s);
             Poll::Ready(Ok(s.len()))
         } else {
-            Poll::Ready(Err(f_io::Error::new(f_io::ErrorKind::Other, "UTF-8 error")))
+            Poll::Ready(Err(f_io::Error::other("UTF-8 error")))
         }
     }
 
@@ -149,7 +398,7 @@ impl AsyncRead for JSEnv {
         while self.input_buf.len() < buf.len() {
             if self.input_promise.is_none() {
                 // Call into JS
-                let raw_promise = self.inner.read_input();
+                let raw_promise = self.request_input();
                 self.input_promise = Some(JsFuture::from(raw_promise));
             }
             let fut = self.input_promise.as_mut().unwrap();
@@ -159,30 +408,21 @@ impl AsyncRead for JSEnv {
                 }
                 Poll::Ready(Err(_)) => {
                     self.input_promise = None;
-                    return Poll::Ready(Err(f_io::Error::new(
-                        f_io::ErrorKind::Other,
-                        "JavaScript Error",
-                    )));
+                    return Poll::Ready(Err(f_io::Error::other("JavaScript Error")));
                 }
-                Poll::Ready(Ok(js_str)) => {
+                Poll::Ready(Ok(input)) => {
                     self.input_promise = None;
-                    match js_str.as_string() {
-                        None => {
-                            return Poll::Ready(Err(f_io::Error::new(
-                                f_io::ErrorKind::Other,
-                                "JavaScript type Error",
-                            )))
-                        }
-                        Some(s) => {
-                            // got a string from JS
-                            if s.len() == 0 {
-                                // EOF
-                                break;
-                            } else {
-                                self.input_buf.extend_from_slice(s.as_ref());
-                                // carry on with the while loop
-                            }
-                        }
+                    // null or undefined signal EOF; strings and byte arrays
+                    // (even empty ones) are input, and the while loop
+                    // carries on
+                    if input.is_null() || input.is_undefined() {
+                        break;
+                    } else if let Some(s) = input.as_string() {
+                        self.input_buf.extend_from_slice(s.as_ref());
+                    } else if let Ok(bytes) = input.dyn_into::<js_sys::Uint8Array>() {
+                        self.input_buf.extend(bytes.to_vec());
+                    } else {
+                        return Poll::Ready(Err(f_io::Error::other("JavaScript type Error")));
                     }
                 }
             }
@@ -210,7 +450,7 @@ impl InterpreterEnv for JSEnv {
         0x52464e57 // RFNW
     }
     fn get_iomode(&self) -> IOMode {
-        IOMode::Text
+        self.io_mode
     }
     fn output_writer(&mut self) -> &mut (dyn AsyncWrite + Unpin) {
         self
@@ -224,12 +464,53 @@ impl InterpreterEnv for JSEnv {
         self.inner.warn(msg);
     }
 
+    fn diagnostic<F: Funge>(&mut self, diagnostic: &Diagnostic<F>) {
+        // Without a diagnostic() hook, this falls back to warn()
+        match self.inner.diagnostic_hook().dyn_into::<js_sys::Function>() {
+            Ok(hook) => {
+                let info = JsDiagnostic {
+                    severity: diagnostic.severity.as_str(),
+                    code: diagnostic.code.as_str(),
+                    location: diagnostic
+                        .coordinates()
+                        .map(|coords| coords.iter().filter_map(|c| c.to_f64()).collect()),
+                    ip_id: match diagnostic.ip_id {
+                        // Same types as getIp(): numbers for 32-bit cells,
+                        // BigInts for 64-bit cells
+                        Some(id) if F::Value::CELL_BYTES == 4 => id.to_i32().map(JsValue::from),
+                        Some(id) => id.to_i64().map(JsValue::from),
+                        None => None,
+                    }
+                    .unwrap_or(JsValue::UNDEFINED),
+                    message: &diagnostic.message,
+                };
+                if let Ok(info) = to_js_value(&info) {
+                    hook.call1(&self.inner, &info).ok();
+                }
+            }
+            Err(_) => self.warn(&diagnostic.to_string()),
+        }
+    }
+
     fn is_io_buffered(&self) -> bool {
         true
     }
 
     fn is_fingerprint_enabled(&self, fpr: i32) -> bool {
-        safe_fingerprints().into_iter().any(|f| f == fpr) || fpr == string_to_fingerprint("TURT")
+        match &self.fingerprints {
+            Some(fingerprints) => fingerprints.contains(&fpr),
+            None => {
+                safe_fingerprints().into_iter().any(|f| f == fpr)
+                    || fpr == string_to_fingerprint("TURT")
+                    || fpr == string_to_fingerprint("TRTX")
+            }
+        }
+    }
+
+    fn filesystem(&self) -> Option<&dyn FungeFilesystem> {
+        self.file_system
+            .as_ref()
+            .map(|fs| fs as &dyn FungeFilesystem)
     }
 
     fn env_vars(&mut self) -> Vec<(String, String)> {
@@ -248,21 +529,72 @@ impl InterpreterEnv for JSEnv {
 
     fn execute_command(&mut self, command: &str) -> i32 {
         match js_sys::eval(command) {
-            Ok(val) => {
-                if val.is_null() || val.is_undefined() {
-                    0
-                } else if let Some(n) = val.as_f64() {
-                    n as i32
-                } else if val.is_truthy() {
-                    0
-                } else {
-                    1
-                }
-            }
+            Ok(val) => js_exit_status(&val),
             Err(_) => 1,
         }
     }
 
+    // The async versions wait for promises returned by the file system or
+    // by commands, without blocking the page
+
+    fn read_file_async<'a>(&'a mut self, filename: &'a str) -> EnvFuture<'a, io::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let fs = self
+                .file_system
+                .as_ref()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::PermissionDenied))?;
+            let content = fs.read_file(filename).map_err(js_io_error)?;
+            js_file_content(resolve_js(content).await.map_err(js_io_error)?)
+        })
+    }
+
+    fn write_file_async<'a>(
+        &'a mut self,
+        filename: &'a str,
+        content: &'a [u8],
+    ) -> EnvFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let fs = self
+                .file_system
+                .as_ref()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::PermissionDenied))?;
+            let result = fs
+                .write_file(filename, &js_sys::Uint8Array::from(content))
+                .map_err(js_io_error)?;
+            resolve_js(result).await.map_err(js_io_error)?;
+            Ok(())
+        })
+    }
+
+    fn write_file_with_async<'a>(
+        &'a mut self,
+        filename: &'a str,
+        write: &'a mut dyn FnMut(&mut dyn io::Write) -> io::Result<()>,
+    ) -> EnvFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let mut content = Vec::new();
+            write(&mut content)?;
+            self.write_file_async(filename, &content).await
+        })
+    }
+
+    fn execute_command_async<'a>(&'a mut self, command: &'a str) -> EnvFuture<'a, i32> {
+        Box::pin(async move {
+            match js_sys::eval(command) {
+                Ok(val) => match resolve_js(val).await {
+                    Ok(val) => js_exit_status(&val),
+                    Err(_) => 1,
+                },
+                Err(_) => 1,
+            }
+        })
+    }
+
+    fn play_tone(&mut self, frequency: f32, duration_ms: u32) -> bool {
+        // The JS side schedules the tones with WebAudio
+        self.inner.play_tone(frequency, duration_ms)
+    }
+
     fn fingerprint_support_library(&mut self, fpr: i32) -> Option<&mut dyn Any> {
         if fpr == string_to_fingerprint("TURT") {
             if self.turt_helper.is_none() {
@@ -277,50 +609,539 @@ impl InterpreterEnv for JSEnv {
     }
 }
 
-type WebBefungeInterp = Interpreter<BefungeVec<i32>, PagedFungeSpace<BefungeVec<i32>, i32>, JSEnv>;
+/// Coordinates as seen from JS: always `(x, y)`, where Unefunge ignores `y`.
+/// Coordinates beyond the range of `i32` are clamped.
+trait WebIdx: Sized {
+    const DIMENSIONS: u32;
+    fn from_xy(x: i32, y: i32) -> Self;
+    fn to_xy(&self) -> (i32, i32);
+    /// Page size for a fresh funge-space
+    fn page_size() -> Self;
+}
+
+fn clamp_i32(n: i64) -> i32 {
+    n.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+impl WebIdx for i32 {
+    const DIMENSIONS: u32 = 1;
+    fn from_xy(x: i32, _y: i32) -> Self {
+        x
+    }
+    fn to_xy(&self) -> (i32, i32) {
+        (*self, 0)
+    }
+    fn page_size() -> Self {
+        1000
+    }
+}
+
+impl WebIdx for i64 {
+    const DIMENSIONS: u32 = 1;
+    fn from_xy(x: i32, _y: i32) -> Self {
+        x as i64
+    }
+    fn to_xy(&self) -> (i32, i32) {
+        (clamp_i32(*self), 0)
+    }
+    fn page_size() -> Self {
+        1000
+    }
+}
+
+impl WebIdx for BefungeVec<i32> {
+    const DIMENSIONS: u32 = 2;
+    fn from_xy(x: i32, y: i32) -> Self {
+        bfvec(x, y)
+    }
+    fn to_xy(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+    fn page_size() -> Self {
+        bfvec(80, 25)
+    }
+}
+
+impl WebIdx for BefungeVec<i64> {
+    const DIMENSIONS: u32 = 2;
+    fn from_xy(x: i32, y: i32) -> Self {
+        bfvec(x as i64, y as i64)
+    }
+    fn to_xy(&self) -> (i32, i32) {
+        (clamp_i32(self.x), clamp_i32(self.y))
+    }
+    fn page_size() -> Self {
+        bfvec(80, 25)
+    }
+}
+
+/// Cell values as seen from JS: numbers for 32-bit cells, and BigInts for
+/// 64-bit cells
+trait WebValue: Sized {
+    const CELL_SIZE: u32;
+    fn to_js(&self) -> JsValue;
+    fn from_js(value: &JsValue) -> Option<Self>;
+}
+
+impl WebValue for i32 {
+    const CELL_SIZE: u32 = 32;
+    fn to_js(&self) -> JsValue {
+        JsValue::from(*self)
+    }
+    fn from_js(value: &JsValue) -> Option<Self> {
+        value.as_f64().map(|n| n as i32)
+    }
+}
+
+impl WebValue for i64 {
+    const CELL_SIZE: u32 = 64;
+    fn to_js(&self) -> JsValue {
+        JsValue::from(*self)
+    }
+    fn from_js(value: &JsValue) -> Option<Self> {
+        // Accept plain numbers, too
+        value
+            .as_f64()
+            .map(|n| n as i64)
+            .or_else(|| i64::try_from(value.clone()).ok())
+    }
+}
+
+/// What `saveState()` produces: an [InterpreterState], tagged with the
+/// dialect it belongs to, as JSON
+#[derive(Serialize, Deserialize)]
+struct WebState<S> {
+    dimensions: u32,
+    cell_size: u32,
+    state: S,
+}
+
+/// Convert a serializable value to JS, passing 64-bit integers as BigInts
+/// so that they match the cell values from `getStacks()`
+fn to_js_value<T: Serialize + ?Sized>(value: &T) -> Result<JsValue, serde_wasm_bindgen::Error> {
+    value.serialize(
+        &serde_wasm_bindgen::Serializer::new().serialize_large_number_types_as_bigints(true),
+    )
+}
+
+/// Everything about an IP that the front end might want to show, as
+/// returned by `getIp()`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IpInfo<Value> {
+    id: Value,
+    location: (i32, i32),
+    delta: (i32, i32),
+    projected_location: (i32, i32),
+    storage_offset: (i32, i32),
+    string_mode: bool,
+    /// Names of the loaded fingerprints, in the order they were loaded
+    fingerprints: Vec<String>,
+    /// Sizes of the stacks on the stack stack, TOSS first
+    stack_sizes: Vec<u32>,
+}
+
+/// A diagnostic as passed to the `diagnostic()` hook of the environment
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsDiagnostic<'a> {
+    severity: &'static str,
+    code: &'static str,
+    location: Option<Vec<f64>>,
+    #[serde(with = "serde_wasm_bindgen::preserve")]
+    ip_id: JsValue,
+    message: &'a str,
+}
+
+/// Execution statistics, as returned by `getStats()`. The counts are u32
+/// since `to_js_value` turns anything 64-bit (including usize) into a BigInt.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Stats {
+    ticks: u64,
+    instructions: u64,
+    ip_count: u32,
+    page_count: u32,
+}
+
+type WebInterp<Idx, Value> = Interpreter<Idx, PagedFungeSpace<Idx, Value>, JSEnv>;
+
+/// What the bindings need from an interpreter, whatever its dimensions and
+/// cell size
+trait WebFunge {
+    fn into_env(self: Box<Self>) -> JSEnv;
+    fn load_src(&mut self, src: &str);
+    fn replace_src(&mut self, src: &str);
+    fn run(&mut self, mode: RunMode) -> Pin<Box<dyn Future<Output = ProgramResult> + '_>>;
+    fn ip_count(&self) -> usize;
+    fn stats(&self) -> Stats;
+    fn ip_info(&self, ip_idx: usize) -> Option<JsValue>;
+    /// The stack stack of an IP, TOSS first
+    fn get_stacks(&self, ip_idx: usize) -> Option<Vec<Vec<JsValue>>>;
+    fn get_cell(&self, x: i32, y: i32) -> JsValue;
+    /// Returns false if `value` is not a valid cell value
+    fn set_cell(&mut self, x: i32, y: i32, value: &JsValue) -> bool;
+    /// The area of funge-space holding the program: `(x0, y0, x1, y1)`
+    /// inclusive, and including the origin
+    fn src_bounds(&self) -> (i32, i32, i32, i32);
+    fn get_src_str(&self, x: i32, y: i32, width: i32, height: i32, strip: bool) -> String;
+    fn take_dirty_regions(&mut self) -> Vec<((i32, i32), (i32, i32))>;
+    fn add_breakpoint(&mut self, x: i32, y: i32) -> u32;
+    fn remove_breakpoint(&mut self, id: u32) -> bool;
+    /// Run until the IP `ip_idx` has executed one instruction. Returns
+    /// `None` if there is no such IP.
+    fn step_instruction(
+        &mut self,
+        ip_idx: usize,
+    ) -> Pin<Box<dyn Future<Output = Option<ProgramResult>> + '_>>;
+    fn save_state(&self) -> Result<Vec<u8>, String>;
+    fn restore_state(&mut self, bytes: &[u8]) -> Result<(), String>;
+}
+
+impl<Idx, Value> WebFunge for WebInterp<Idx, Value>
+where
+    Idx: WebIdx
+        + PageSpaceVector
+        + MotionCmds<PagedFungeSpace<Idx, Value>, JSEnv>
+        + SrcIO<PagedFungeSpace<Idx, Value>>
+        + Serialize
+        + DeserializeOwned
+        + 'static,
+    Value: WebValue + FungeValue + Serialize + DeserializeOwned + 'static,
+{
+    fn into_env(self: Box<Self>) -> JSEnv {
+        self.env
+    }
+
+    fn load_src(&mut self, src: &str) {
+        read_funge_src::<Idx, _>(&mut self.space, src);
+    }
+
+    fn replace_src(&mut self, src: &str) {
+        self.space = PagedFungeSpace::new_with_page_size(Idx::page_size());
+        self.space.set_dirty_tracking(true);
+        read_funge_src::<Idx, _>(&mut self.space, src);
+    }
+
+    fn run(&mut self, mode: RunMode) -> Pin<Box<dyn Future<Output = ProgramResult> + '_>> {
+        Box::pin(self.run_async(mode))
+    }
+
+    fn ip_count(&self) -> usize {
+        self.ips.len()
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            ticks: self.ticks(),
+            instructions: self.instructions_executed(),
+            ip_count: self.ips.len() as u32,
+            page_count: self.space.page_count() as u32,
+        }
+    }
+
+    fn ip_info(&self, ip_idx: usize) -> Option<JsValue> {
+        let ip = self.ips.get(ip_idx)?;
+        let (next_loc, _) = self.space.move_by(ip.location, ip.delta);
+        let info = IpInfo {
+            id: ip.id.clone(),
+            location: ip.location.to_xy(),
+            delta: ip.delta.to_xy(),
+            projected_location: next_loc.to_xy(),
+            storage_offset: ip.storage_offset.to_xy(),
+            string_mode: ip.mode.contains(IpMode::STRING),
+            fingerprints: ip
+                .instructions
+                .loaded_fingerprints()
+                .iter()
+                .map(|fpr| fingerprint_to_string(*fpr))
+                .collect(),
+            stack_sizes: ip
+                .stack_stack
+                .iter()
+                .rev()
+                .map(|s| s.len() as u32)
+                .collect(),
+        };
+        to_js_value(&info).ok()
+    }
+
+    fn get_stacks(&self, ip_idx: usize) -> Option<Vec<Vec<JsValue>>> {
+        Some(
+            self.ips
+                .get(ip_idx)?
+                .stack_stack
+                .iter()
+                .rev()
+                .map(|stack| stack.iter().map(WebValue::to_js).collect())
+                .collect(),
+        )
+    }
+
+    fn get_cell(&self, x: i32, y: i32) -> JsValue {
+        self.space[Idx::from_xy(x, y)].to_js()
+    }
+
+    fn set_cell(&mut self, x: i32, y: i32, value: &JsValue) -> bool {
+        match Value::from_js(value) {
+            Some(value) => {
+                self.space[Idx::from_xy(x, y)] = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn src_bounds(&self) -> (i32, i32, i32, i32) {
+        let (x0, y0) = self.space.min_idx().map(|i| i.to_xy()).unwrap_or((0, 0));
+        let (x1, y1) = self.space.max_idx().map(|i| i.to_xy()).unwrap_or((0, 0));
+        (min(0, x0), min(0, y0), x1, y1)
+    }
+
+    fn get_src_str(&self, x: i32, y: i32, width: i32, height: i32, strip: bool) -> String {
+        Idx::get_src_str(
+            &self.space,
+            &Idx::from_xy(x, y),
+            &Idx::from_xy(width, height),
+            strip,
+        )
+    }
+
+    fn take_dirty_regions(&mut self) -> Vec<((i32, i32), (i32, i32))> {
+        self.space
+            .take_dirty_regions()
+            .into_iter()
+            .map(|(least, greatest)| (least.to_xy(), greatest.to_xy()))
+            .collect()
+    }
+
+    fn add_breakpoint(&mut self, x: i32, y: i32) -> u32 {
+        self.debugger
+            .add(Breakpoint::Location(Idx::from_xy(x, y)))
+            .0
+    }
+
+    fn remove_breakpoint(&mut self, id: u32) -> bool {
+        self.debugger.remove(BreakpointId(id))
+    }
+
+    fn step_instruction(
+        &mut self,
+        ip_idx: usize,
+    ) -> Pin<Box<dyn Future<Output = Option<ProgramResult>> + '_>> {
+        Box::pin(async move {
+            let target = self.ips.get(ip_idx)?.id.clone();
+            // IPs move in order, so the ones before this one in the tick have
+            // to move first
+            loop {
+                let result = self.run_async(RunMode::StepIp).await;
+                if result != ProgramResult::Paused || self.stepped_ip() == Some(&target) {
+                    return Some(result);
+                }
+            }
+        })
+    }
+
+    fn save_state(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(&WebState {
+            dimensions: Idx::DIMENSIONS,
+            cell_size: Value::CELL_SIZE,
+            state: Interpreter::save_state(self),
+        })
+        .map_err(|e| e.to_string())
+    }
+
+    fn restore_state(&mut self, bytes: &[u8]) -> Result<(), String> {
+        // Check the dialect first to give a sensible error
+        let header: WebState<serde::de::IgnoredAny> =
+            serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if (header.dimensions, header.cell_size) != (Idx::DIMENSIONS, Value::CELL_SIZE) {
+            return Err(format!(
+                "The state is for a {}-dimensional interpreter with {}-bit cells",
+                header.dimensions, header.cell_size
+            ));
+        }
+        let web_state: WebState<InterpreterState<Idx, Value>> =
+            serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        let state = web_state.state;
+        // replaceSrc() may have changed the page size
+        if state.page_size != self.space.page_size() {
+            self.space = PagedFungeSpace::new_with_page_size(state.page_size);
+            self.space.set_dirty_tracking(true);
+        }
+        if self.load_state(&state) {
+            Ok(())
+        } else {
+            Err("The state could not be restored".to_owned())
+        }
+    }
+}
+
+/// Describe the result of a run for JS: an object with a `reason` (one of
+/// `"paused"`, `"breakpoint"`, `"input"`, `"done"`, `"timeout"`, `"panic"`
+/// and `"loop"`), and the `breakpoint` ID or the `exitCode` where applicable
+fn program_result_to_js(result: ProgramResult) -> JsValue {
+    let obj = js_sys::Object::new();
+    let set = |key: &str, value: JsValue| {
+        js_sys::Reflect::set(&obj, &JsValue::from_str(key), &value).ok();
+    };
+    let reason = match result {
+        ProgramResult::Paused => "paused",
+        ProgramResult::Hit(id) => {
+            set("breakpoint", JsValue::from(id.0));
+            "breakpoint"
+        }
+        ProgramResult::AwaitingInput => "input",
+        ProgramResult::Done(returncode) => {
+            set("exitCode", JsValue::from(returncode));
+            "done"
+        }
+        ProgramResult::TimedOut => "timeout",
+        ProgramResult::Panic => "panic",
+        ProgramResult::LoopDetected => "loop",
+    };
+    set("reason", JsValue::from_str(reason));
+    obj.into()
+}
+
+fn boxed_interpreter<Idx, Value>(mut interpreter: WebInterp<Idx, Value>) -> Box<dyn WebFunge>
+where
+    WebInterp<Idx, Value>: WebFunge,
+    Idx: PageSpaceVector
+        + MotionCmds<PagedFungeSpace<Idx, Value>, JSEnv>
+        + SrcIO<PagedFungeSpace<Idx, Value>>
+        + 'static,
+    Value: FungeValue + 'static,
+{
+    interpreter.space.set_dirty_tracking(true);
+    Box::new(interpreter)
+}
+
+/// The interpreter is shared with the futures of the async methods, which
+/// hold it mutably borrowed while they run, and keep it alive if JS drops
+/// the [BefungeInterpreter] in the meantime
+type SharedInterpreter = Rc<RefCell<Box<dyn WebFunge>>>;
+
+fn busy_error() -> JsValue {
+    JsValue::from_str("The interpreter is running")
+}
 
 #[wasm_bindgen]
 pub struct BefungeInterpreter {
-    interpreter: WebBefungeInterp,
+    interpreter: SharedInterpreter,
+    dimensions: u32,
+    cell_size: u32,
 }
 
+// The async methods hold the interpreter borrowed across await points on
+// purpose: that is what makes the other methods throw while a run is going on
+#[allow(clippy::await_holding_refcell_ref)]
 #[wasm_bindgen]
 impl BefungeInterpreter {
+    /// Create an interpreter. `dimensions` is 1 (Unefunge) or 2 (Befunge,
+    /// the default); `cellSize` is 32 (the default) or 64. With 64-bit
+    /// cells, cell values are passed to and from JS as BigInts. The
+    /// interpreter has no Trefunge support, so `dimensions` 3 is an error.
+    ///
+    /// `env.readInput()` resolves to a string, a `Uint8Array`, or `null`
+    /// for EOF. If `env.ioMode` is `"binary"`, `~` and `,` work on bytes,
+    /// and output goes to `env.writeOutputBytes(bytes)` if it exists.
+    /// `env.readInputBytes()`, if it exists, is used instead of
+    /// `readInput()`.
+    ///
+    /// `env.fingerprints` may list the names of the fingerprints to make
+    /// available, and `env.fileSystem` may provide `readFile(name)`
+    /// (returning a string, a `Uint8Array`, or `null` if there is no such
+    /// file), `writeFile(name, bytes)` and `listDir(path)` for `i` and `o`.
+    /// Errors thrown by these make the instruction reflect.
     #[wasm_bindgen(constructor)]
-    pub fn new(env: JSEnvInterface) -> Self {
+    pub fn new(
+        env: JSEnvInterface,
+        dimensions: Option<u32>,
+        cell_size: Option<u32>,
+    ) -> Result<BefungeInterpreter, JsValue> {
         // console_error_panic_hook::set_once();
-        let real_env = JSEnv {
-            inner: env,
-            input_promise: None,
-            input_buf: vec![],
-            turt_helper: None,
+        let real_env = JSEnv::new(env);
+        let dimensions = dimensions.unwrap_or(2);
+        let cell_size = cell_size.unwrap_or(32);
+        let interpreter = match (dimensions, cell_size) {
+            (1, 32) => boxed_interpreter(
+                InterpreterBuilder::unefunge()
+                    .cell_size_32()
+                    .env(real_env)
+                    .build(),
+            ),
+            (1, 64) => boxed_interpreter(
+                InterpreterBuilder::unefunge()
+                    .cell_size_64()
+                    .env(real_env)
+                    .build(),
+            ),
+            (2, 32) => boxed_interpreter(
+                InterpreterBuilder::befunge()
+                    .cell_size_32()
+                    .env(real_env)
+                    .build(),
+            ),
+            (2, 64) => boxed_interpreter(
+                InterpreterBuilder::befunge()
+                    .cell_size_64()
+                    .env(real_env)
+                    .build(),
+            ),
+            (3, _) => {
+                return Err(JsValue::from_str("Trefunge is not supported"));
+            }
+            (1 | 2, _) => {
+                return Err(JsValue::from_str("The cell size must be 32 or 64"));
+            }
+            _ => {
+                return Err(JsValue::from_str("The number of dimensions must be 1 or 2"));
+            }
         };
-        Self {
-            interpreter: new_befunge_interpreter::<i32, _>(real_env),
-        }
+        Ok(Self {
+            interpreter: Rc::new(RefCell::new(interpreter)),
+            dimensions,
+            cell_size,
+        })
+    }
+
+    /// Destroy the interpreter and return the environment. If the
+    /// interpreter is still running, it is destroyed when the run finishes,
+    /// and this returns `undefined`.
+    pub fn close(self) -> Option<JSEnvInterface> {
+        let interpreter = Rc::try_unwrap(self.interpreter).ok()?.into_inner();
+        Some(interpreter.into_env().inner)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn dimensions(&self) -> u32 {
+        self.dimensions
     }
 
-    pub fn close(self) -> JSEnvInterface {
-        self.interpreter.env.inner
+    #[wasm_bindgen(getter, js_name = "cellSize")]
+    pub fn cell_size(&self) -> u32 {
+        self.cell_size
     }
 
     #[wasm_bindgen(js_name = "loadSrc")]
-    pub fn load_src(&mut self, src: &str) {
-        read_funge_src(&mut self.interpreter.space, src);
+    pub fn load_src(&self, src: &str) -> Result<(), JsValue> {
+        self.borrow_mut()?.load_src(src);
+        Ok(())
     }
 
     #[wasm_bindgen(js_name = "replaceSrc")]
-    pub fn replace_src(&mut self, src: &str) {
-        self.interpreter.space = PagedFungeSpace::new_with_page_size(bfvec(80, 25));
-        read_funge_src(&mut self.interpreter.space, src);
+    pub fn replace_src(&self, src: &str) -> Result<(), JsValue> {
+        self.borrow_mut()?.replace_src(src);
+        Ok(())
     }
 
     #[wasm_bindgen(js_name = "runAsync")]
-    pub fn run_async(&mut self) -> js_sys::Promise {
-        let self_ptr: *mut Self = self;
+    pub fn run_async(&self) -> js_sys::Promise {
+        let interpreter = self.interpreter.clone();
         wasm_bindgen_futures::future_to_promise(async move {
-            let this: &mut Self = unsafe { &mut *self_ptr };
-            let result = match this.interpreter.run_async(RunMode::Run).await {
+            let mut interpreter = interpreter.try_borrow_mut().map_err(|_| busy_error())?;
+            let result = match interpreter.run(RunMode::Run).await {
                 ProgramResult::Done(returncode) => returncode,
                 _ => -1,
             };
@@ -329,21 +1150,20 @@ impl BefungeInterpreter {
     }
 
     #[wasm_bindgen(js_name = "runLimitedAsync")]
-    pub fn run_limited_async(&mut self, loop_limit: u32) -> js_sys::Promise {
-        let self_ptr: *mut Self = self;
+    pub fn run_limited_async(&self, loop_limit: u32) -> js_sys::Promise {
+        let interpreter = self.interpreter.clone();
         wasm_bindgen_futures::future_to_promise(async move {
-            // Circumventing the borrow checker: the JS code promises not to
-            // destroy the interpreter while it is running, and self lives at
-            // a fixed place in memory. The compiler can't know these things.
-            let this: &mut Self = unsafe { &mut *self_ptr };
-            let result = match this
-                .interpreter
-                .run_async(RunMode::Limited(loop_limit))
-                .await
-            {
+            // The interpreter stays borrowed until the run is over; in the
+            // meantime, the other methods throw
+            let mut interpreter = interpreter.try_borrow_mut().map_err(|_| busy_error())?;
+            let result = match interpreter.run(RunMode::Limited(loop_limit)).await {
                 ProgramResult::Done(returncode) => Some(returncode),
-                ProgramResult::Panic => Some(-1),
-                ProgramResult::Paused => None,
+                ProgramResult::Panic | ProgramResult::TimedOut | ProgramResult::LoopDetected => {
+                    Some(-1)
+                }
+                ProgramResult::Paused | ProgramResult::Hit(_) | ProgramResult::AwaitingInput => {
+                    None
+                }
             };
             Ok(result
                 .map(|n| JsValue::from_f64(n as f64))
@@ -351,16 +1171,34 @@ impl BefungeInterpreter {
         })
     }
 
+    /// Run for (roughly) `milliseconds` of wall clock time, so that the
+    /// caller can keep the UI responsive. Resolves to `{reason, breakpoint,
+    /// exitCode}` like `stepInstruction()`; `reason` is `"paused"` if the
+    /// time ran out.
+    #[wasm_bindgen(js_name = "runFor")]
+    pub fn run_for(&self, milliseconds: u32) -> js_sys::Promise {
+        let interpreter = self.interpreter.clone();
+        wasm_bindgen_futures::future_to_promise(async move {
+            let mut interpreter = interpreter.try_borrow_mut().map_err(|_| busy_error())?;
+            let limit = Duration::from_millis(milliseconds as u64);
+            let result = interpreter.run(RunMode::TimeSlice(limit)).await;
+            Ok(program_result_to_js(result))
+        })
+    }
+
     #[wasm_bindgen(js_name = "stepAsync")]
-    pub fn step_async(&mut self) -> js_sys::Promise {
-        let self_ptr: *mut Self = self;
+    pub fn step_async(&self) -> js_sys::Promise {
+        let interpreter = self.interpreter.clone();
         wasm_bindgen_futures::future_to_promise(async move {
-            // see comment in run_limited_async
-            let this: &mut Self = unsafe { &mut *self_ptr };
-            let result = match this.interpreter.run_async(RunMode::Step).await {
+            let mut interpreter = interpreter.try_borrow_mut().map_err(|_| busy_error())?;
+            let result = match interpreter.run(RunMode::Step).await {
                 ProgramResult::Done(returncode) => Some(returncode),
-                ProgramResult::Panic => Some(-1),
-                ProgramResult::Paused => None,
+                ProgramResult::Panic | ProgramResult::TimedOut | ProgramResult::LoopDetected => {
+                    Some(-1)
+                }
+                ProgramResult::Paused | ProgramResult::Hit(_) | ProgramResult::AwaitingInput => {
+                    None
+                }
             };
             Ok(result
                 .map(|n| JsValue::from_f64(n as f64))
@@ -368,70 +1206,419 @@ impl BefungeInterpreter {
         })
     }
 
-    #[wasm_bindgen(getter, js_name = "ipCount")]
-    pub fn ip_count(&self) -> usize {
-        self.interpreter.ips.len()
+    /// Step one IP by a single instruction (other IPs move too if they
+    /// come before it in the tick). Resolves to `{reason, breakpoint,
+    /// exitCode}`, where `reason` is `"paused"`, `"breakpoint"`, `"input"`,
+    /// `"done"`, `"timeout"` or `"panic"`.
+    #[wasm_bindgen(js_name = "stepInstruction")]
+    pub fn step_instruction(&self, ip_idx: usize) -> js_sys::Promise {
+        let interpreter = self.interpreter.clone();
+        wasm_bindgen_futures::future_to_promise(async move {
+            let mut interpreter = interpreter.try_borrow_mut().map_err(|_| busy_error())?;
+            match interpreter.step_instruction(ip_idx).await {
+                Some(result) => Ok(program_result_to_js(result)),
+                None => Err(JsValue::from_str("No such IP")),
+            }
+        })
     }
 
-    #[wasm_bindgen(js_name = "ipLocation")]
-    pub fn ip_location(&self, ip_idx: usize) -> Option<Vec<i32>> {
-        let loc = self.interpreter.ips.get(ip_idx)?.location;
-        Some(vec![loc.x, loc.y])
+    /// Pause when an IP is about to execute the instruction at `(x, y)`.
+    /// Returns the breakpoint ID.
+    #[wasm_bindgen(js_name = "addBreakpoint")]
+    pub fn add_breakpoint(&self, x: i32, y: i32) -> Result<u32, JsValue> {
+        Ok(self.borrow_mut()?.add_breakpoint(x, y))
     }
 
-    #[wasm_bindgen(js_name = "ipDelta")]
-    pub fn ip_delta(&self, ip_idx: usize) -> Option<Vec<i32>> {
-        let d = self.interpreter.ips.get(ip_idx)?.delta;
-        Some(vec![d.x, d.y])
+    #[wasm_bindgen(js_name = "removeBreakpoint")]
+    pub fn remove_breakpoint(&self, id: u32) -> Result<bool, JsValue> {
+        Ok(self.borrow_mut()?.remove_breakpoint(id))
     }
 
-    #[wasm_bindgen(js_name = "projectedIpLocation")]
-    pub fn projected_ip_location(&self, ip_idx: usize) -> Option<Vec<i32>> {
-        let ip = self.interpreter.ips.get(ip_idx)?;
-        let (next_loc, _) = self.interpreter.space.move_by(ip.location, ip.delta);
-        Some(vec![next_loc.x, next_loc.y])
+    /// Snapshot the interpreter (funge-space, IPs, stacks and loaded
+    /// fingerprints, but not the internal state of fingerprints or the
+    /// environment) for undo, saving or sharing
+    #[wasm_bindgen(js_name = "saveState")]
+    pub fn save_state(&self) -> Result<Vec<u8>, JsValue> {
+        self.borrow()?
+            .save_state()
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Restore a snapshot taken by `saveState()` by an interpreter of the
+    /// same dialect. The whole of funge-space should be redrawn afterwards.
+    #[wasm_bindgen(js_name = "restoreState")]
+    pub fn restore_state(&self, bytes: &[u8]) -> Result<(), JsValue> {
+        self.borrow_mut()?
+            .restore_state(bytes)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(getter, js_name = "ipCount")]
+    pub fn ip_count(&self) -> Result<usize, JsValue> {
+        Ok(self.borrow()?.ip_count())
     }
 
-    #[wasm_bindgen(js_name = "stackCount")]
-    pub fn stack_count(&self, ip_idx: usize) -> usize {
-        self.interpreter
-            .ips
-            .get(ip_idx)
-            .map(|ip| ip.stack_stack.len())
-            .unwrap_or(0)
+    /// Execution statistics: `{ticks, instructions, ipCount, pageCount}`,
+    /// where `pageCount` is the number of funge-space pages allocated.
+    /// `ticks` and `instructions` are BigInts.
+    #[wasm_bindgen(js_name = "getStats")]
+    pub fn get_stats(&self) -> Result<JsValue, JsValue> {
+        to_js_value(&self.borrow()?.stats()).map_err(JsValue::from)
     }
 
-    /// Get a stack; TOSS is the stack_idx = 0
-    #[wasm_bindgen(js_name = "getStack")]
-    pub fn get_stack(&self, ip_idx: usize, stack_idx: usize) -> Option<Vec<i32>> {
-        self.interpreter
-            .ips
-            .get(ip_idx)
-            .and_then(|ip| ip.stack_stack.get(stack_idx))
-            .map(|v| v.clone())
+    /// Describe an IP: `{id, location, delta, projectedLocation,
+    /// storageOffset, stringMode, fingerprints, stackSizes}`, where the
+    /// vectors are `[x, y]` and `stackSizes` starts with the TOSS. Like the
+    /// cells from `getStacks()`, `id` is a BigInt for 64-bit cells.
+    #[wasm_bindgen(js_name = "getIp")]
+    pub fn get_ip(&self, ip_idx: usize) -> Result<JsValue, JsValue> {
+        self.borrow()?
+            .ip_info(ip_idx)
+            .ok_or_else(|| JsValue::from_str("No such IP"))
+    }
+
+    /// The stack stack of an IP as an array of arrays, TOSS first, with the
+    /// top of each stack last
+    #[wasm_bindgen(js_name = "getStacks")]
+    pub fn get_stacks(&self, ip_idx: usize) -> Result<Vec<JsValue>, JsValue> {
+        let stacks = self
+            .borrow()?
+            .get_stacks(ip_idx)
+            .ok_or_else(|| JsValue::from_str("No such IP"))?;
+        Ok(stacks
+            .into_iter()
+            .map(|stack| stack.into_iter().collect::<js_sys::Array>().into())
+            .collect())
     }
 
     #[wasm_bindgen(js_name = "getSrc")]
-    pub fn get_src(&self) -> String {
-        let space = &self.interpreter.space;
-        let mut start = space.min_idx().unwrap_or(bfvec(0, 0));
-        start = bfvec(min(0, start.x), min(0, start.y));
-        let end_incl = space.max_idx().unwrap_or(bfvec(0, 0));
-        let size = bfvec(end_incl.x - start.x + 1, end_incl.y - start.y + 1);
-        SrcIO::get_src_str(space, &start, &size, true)
+    pub fn get_src(&self) -> Result<String, JsValue> {
+        let interpreter = self.borrow()?;
+        let (x0, y0, x1, y1) = interpreter.src_bounds();
+        Ok(interpreter.get_src_str(x0, y0, x1 - x0 + 1, y1 - y0 + 1, true))
+    }
+
+    #[wasm_bindgen(js_name = "getCell")]
+    pub fn get_cell(&self, x: i32, y: i32) -> Result<JsValue, JsValue> {
+        Ok(self.borrow()?.get_cell(x, y))
+    }
+
+    /// Write to funge-space (e.g. from an editor). `value` is a number, or
+    /// a BigInt for 64-bit cells.
+    #[wasm_bindgen(js_name = "setCell")]
+    pub fn set_cell(&self, x: i32, y: i32, value: JsValue) -> Result<(), JsValue> {
+        if self.borrow_mut()?.set_cell(x, y, &value) {
+            Ok(())
+        } else {
+            Err(JsValue::from_str("Invalid cell value"))
+        }
+    }
+
+    /// The `width` × `height` cells starting at `(x, y)`, row by row
+    #[wasm_bindgen(js_name = "getRegion")]
+    pub fn get_region(
+        &self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Result<Vec<JsValue>, JsValue> {
+        let interpreter = self.borrow()?;
+        let interpreter = &interpreter;
+        Ok((y..y.saturating_add(height.max(0)))
+            .flat_map(move |row| {
+                (x..x.saturating_add(width.max(0))).map(move |col| interpreter.get_cell(col, row))
+            })
+            .collect())
+    }
+
+    /// Regions of funge-space written to since the last call, as a flat
+    /// array of `[x0, y0, x1, y1, ...]` where each region runs from
+    /// `(x0, y0)` to `(x1, y1)` inclusive
+    #[wasm_bindgen(js_name = "takeDirtyRegions")]
+    pub fn take_dirty_regions(&self) -> Result<Vec<i32>, JsValue> {
+        Ok(self
+            .borrow_mut()?
+            .take_dirty_regions()
+            .into_iter()
+            .flat_map(|((x0, y0), (x1, y1))| [x0, y0, x1, y1])
+            .collect())
+    }
+
+    /// Cells in the regions written to since the last call, as a flat array
+    /// of `[x, y, value, ...]`. This uses the same tracking as
+    /// `takeDirtyRegions()`, so only call one of the two. The regions are
+    /// bounding boxes, so this is a superset of the cells that actually
+    /// changed: it may include cells that were never written to, or were
+    /// set to the value they already had.
+    #[wasm_bindgen(js_name = "takeChangedCells")]
+    pub fn take_changed_cells(&self) -> Result<Vec<JsValue>, JsValue> {
+        let mut interpreter = self.borrow_mut()?;
+        let regions = interpreter.take_dirty_regions();
+        let interpreter = &interpreter;
+        Ok(regions
+            .into_iter()
+            .flat_map(move |((x0, y0), (x1, y1))| {
+                (y0..=y1).flat_map(move |y| {
+                    (x0..=x1).flat_map(move |x| {
+                        [
+                            JsValue::from(x),
+                            JsValue::from(y),
+                            interpreter.get_cell(x, y),
+                        ]
+                    })
+                })
+            })
+            .collect())
     }
 
     #[wasm_bindgen(js_name = "getSrcLines")]
-    pub fn get_src_lines(&self) -> Vec<JsValue> {
-        let space = &self.interpreter.space;
-        let mut start = space.min_idx().unwrap_or(bfvec(0, 0));
-        start = bfvec(min(0, start.x), min(0, start.y));
-        let end_incl = space.max_idx().unwrap_or(bfvec(0, 0));
-        let line_len = end_incl.x - start.x + 1;
-
-        (start.y..(end_incl.y + 1))
-            .map(|y| SrcIO::get_src_str(space, &bfvec(start.x, y), &bfvec(line_len, 1), false))
+    pub fn get_src_lines(&self) -> Result<Vec<JsValue>, JsValue> {
+        let interpreter = self.borrow()?;
+        let (x0, y0, x1, y1) = interpreter.src_bounds();
+        Ok((y0..(y1 + 1))
+            .map(|y| interpreter.get_src_str(x0, y, x1 - x0 + 1, 1, false))
             .map(|s| JsValue::from_str(&s))
-            .collect()
+            .collect())
+    }
+}
+
+impl BefungeInterpreter {
+    fn borrow(&self) -> Result<Ref<'_, Box<dyn WebFunge>>, JsValue> {
+        self.interpreter.try_borrow().map_err(|_| busy_error())
+    }
+
+    fn borrow_mut(&self) -> Result<RefMut<'_, Box<dyn WebFunge>>, JsValue> {
+        self.interpreter.try_borrow_mut().map_err(|_| busy_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use js_sys::{Array, Function, Reflect};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    /// An environment that collects text output in `output` and binary
+    /// output in `bytes`, with some extra properties (e.g. `ioMode`)
+    fn test_env(extra: &str) -> JsValue {
+        Function::new_no_args(&format!(
+            "return {{
+                output: '',
+                bytes: [],
+                envVars: {{}},
+                writeOutput(s) {{ this.output += s; }},
+                warnings: [],
+                warn(msg) {{ this.warnings.push(msg); }},
+                readInput() {{ return Promise.resolve(null); }},
+                playTone() {{ return false; }},
+                {}
+            }};",
+            extra
+        ))
+        .call0(&JsValue::NULL)
+        .unwrap()
+    }
+
+    fn get(obj: &JsValue, key: &str) -> JsValue {
+        Reflect::get(obj, &JsValue::from_str(key)).unwrap()
+    }
+
+    fn interpreter(env: &JsValue, src: &str) -> BefungeInterpreter {
+        let interpreter =
+            BefungeInterpreter::new(env.clone().unchecked_into(), None, None).unwrap();
+        interpreter.load_src(src).unwrap();
+        interpreter
+    }
+
+    async fn run_for(interpreter: &BefungeInterpreter, milliseconds: u32) -> JsValue {
+        JsFuture::from(interpreter.run_for(milliseconds))
+            .await
+            .unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_cells() {
+        let interpreter = interpreter(&test_env(""), "12\n34");
+        assert_eq!(interpreter.get_cell(1, 1).unwrap(), '4' as i32);
+        interpreter.set_cell(0, 0, JsValue::from(65)).unwrap();
+        assert!(interpreter.set_cell(0, 0, JsValue::from_str("x")).is_err());
+        let region = interpreter.get_region(0, 0, 2, 2).unwrap();
+        assert_eq!(region, [65, '2' as i32, '3' as i32, '4' as i32]);
+        assert_eq!(interpreter.get_src().unwrap(), "A2\n34");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_dialects() {
+        let interpreter =
+            BefungeInterpreter::new(test_env("").unchecked_into(), Some(1), Some(64)).unwrap();
+        assert_eq!(interpreter.dimensions(), 1);
+        assert_eq!(interpreter.cell_size(), 64);
+        interpreter.load_src("1\n2").unwrap();
+        // Unefunge ignores y, and 64-bit cells are BigInts
+        assert_eq!(
+            interpreter.get_cell(0, 5).unwrap(),
+            JsValue::from('1' as i64)
+        );
+        interpreter
+            .set_cell(1, 0, JsValue::from(1_i64 << 40))
+            .unwrap();
+        assert_eq!(
+            interpreter.get_cell(1, 0).unwrap(),
+            JsValue::from(1_i64 << 40)
+        );
+
+        assert!(BefungeInterpreter::new(test_env("").unchecked_into(), Some(3), None).is_err());
+        assert!(BefungeInterpreter::new(test_env("").unchecked_into(), Some(2), Some(16)).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_changed_cells() {
+        let interpreter = interpreter(&test_env(""), "55+20p@");
+        interpreter.take_changed_cells().unwrap();
+        assert_eq!(
+            get(&run_for(&interpreter, 1000).await, "reason"),
+            JsValue::from_str("done")
+        );
+        let changed = interpreter.take_changed_cells().unwrap();
+        assert_eq!(changed, [2, 0, 10]);
+        assert!(interpreter.take_dirty_regions().unwrap().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_breakpoints() {
+        let interpreter = interpreter(&test_env(""), "123@");
+        let id = interpreter.add_breakpoint(2, 0).unwrap();
+
+        let result = JsFuture::from(interpreter.step_instruction(0))
+            .await
+            .unwrap();
+        assert_eq!(get(&result, "reason"), JsValue::from_str("paused"));
+
+        let result = run_for(&interpreter, 1000).await;
+        assert_eq!(get(&result, "reason"), JsValue::from_str("breakpoint"));
+        assert_eq!(get(&result, "breakpoint"), JsValue::from(id));
+        let stacks = interpreter.get_stacks(0).unwrap();
+        assert_eq!(Array::from(&stacks[0]).to_vec(), [1, 2]);
+
+        assert!(interpreter.remove_breakpoint(id).unwrap());
+        let result = run_for(&interpreter, 1000).await;
+        assert_eq!(get(&result, "reason"), JsValue::from_str("done"));
+        assert_eq!(get(&result, "exitCode"), JsValue::from(0));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_run_for_pauses() {
+        let interpreter = interpreter(&test_env(""), ">");
+        let result = run_for(&interpreter, 10).await;
+        assert_eq!(get(&result, "reason"), JsValue::from_str("paused"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_save_state() {
+        let interpreter = interpreter(&test_env(""), "12p@");
+        let state = interpreter.save_state().unwrap();
+        run_for(&interpreter, 1000).await;
+        assert_eq!(interpreter.get_cell(1, 2).unwrap(), 0);
+        interpreter.restore_state(&state).unwrap();
+        assert_eq!(interpreter.get_cell(1, 2).unwrap(), ' ' as i32);
+        assert_eq!(interpreter.ip_count().unwrap(), 1);
+
+        // A snapshot only fits the same dialect
+        let other =
+            BefungeInterpreter::new(test_env("").unchecked_into(), Some(2), Some(64)).unwrap();
+        assert!(other.restore_state(&state).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_ip_and_stats() {
+        for (cell_size, id) in [(32, JsValue::from(0)), (64, JsValue::from(0_i64))] {
+            let interpreter =
+                BefungeInterpreter::new(test_env("").unchecked_into(), None, Some(cell_size))
+                    .unwrap();
+            interpreter.load_src("v\n>12@").unwrap();
+            interpreter.add_breakpoint(3, 1).unwrap();
+            run_for(&interpreter, 1000).await;
+
+            let ip = interpreter.get_ip(0).unwrap();
+            assert_eq!(get(&ip, "id"), id);
+            // location is the last instruction executed, not the breakpoint
+            assert_eq!(Array::from(&get(&ip, "location")).to_vec(), [2, 1]);
+            assert_eq!(Array::from(&get(&ip, "projectedLocation")).to_vec(), [3, 1]);
+            assert_eq!(Array::from(&get(&ip, "delta")).to_vec(), [1, 0]);
+            assert!(interpreter.get_ip(1).is_err());
+
+            let stats = interpreter.get_stats().unwrap();
+            assert!(get(&stats, "ticks").is_bigint());
+            assert_eq!(get(&stats, "instructions"), JsValue::from(4_u64));
+            assert_eq!(get(&stats, "ipCount"), JsValue::from(1));
+            assert_eq!(Array::from(&get(&ip, "stackSizes")).to_vec(), [2]);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_text_output() {
+        let env = test_env("");
+        let interpreter = interpreter(&env, "\"ih\",,1.@");
+        run_for(&interpreter, 1000).await;
+        assert_eq!(get(&env, "output"), JsValue::from_str("hi1 "));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_binary_io() {
+        let env = test_env(
+            "ioMode: 'binary',
+             writeOutputBytes(b) { this.bytes.push(...b); },
+             readInputBytes() { const i = this.input; this.input = null; return i; },
+             input: new Uint8Array([200, 7]),",
+        );
+        let interpreter = interpreter(&env, "~~,,@");
+        run_for(&interpreter, 1000).await;
+        assert_eq!(Array::from(&get(&env, "bytes")).to_vec(), [7, 200]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_fingerprints() {
+        // ( reflects onto the @ it jumped over if ROMA isn't available
+        for (fingerprints, output) in [("['ROMA']", "1 "), ("[]", "")] {
+            let env = test_env(&format!("fingerprints: {},", fingerprints));
+            let interpreter = interpreter(&env, "\"AMOR\"4#@(I.@");
+            run_for(&interpreter, 1000).await;
+            assert_eq!(get(&env, "output"), JsValue::from_str(output));
+        }
+        // A name that's too long is skipped with a warning, rather than
+        // wrapping around to another fingerprint
+        let env = test_env("fingerprints: ['XROMA'],");
+        let interpreter = interpreter(&env, "\"AMOR\"4#@(I.@");
+        run_for(&interpreter, 1000).await;
+        assert_eq!(get(&env, "output"), JsValue::from_str(""));
+        let warnings = Array::from(&get(&env, "warnings")).to_vec();
+        assert_eq!(
+            warnings,
+            [JsValue::from_str("Invalid fingerprint name XROMA")]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_file_system() {
+        let env = test_env(
+            "fileSystem: {
+                files: { in: 'xy' },
+                readFile(name) { return this.files[name] ?? null; },
+                writeFile(name, bytes) { this.files[name] = [...bytes]; },
+                listDir(path) { return Object.keys(this.files); },
+            },",
+        );
+        // Read "in" to (0, 1), and write it back out to "out"
+        let interpreter = interpreter(&env, "0100\"ni\"i00\"tuo\"o@");
+        let result = run_for(&interpreter, 1000).await;
+        assert_eq!(get(&result, "reason"), JsValue::from_str("done"));
+        assert_eq!(interpreter.get_cell(1, 1).unwrap(), 'y' as i32);
+        let files = get(&get(&env, "fileSystem"), "files");
+        assert_eq!(
+            Array::from(&get(&files, "out")).to_vec(),
+            ['x' as i32, 'y' as i32]
+        );
     }
 }
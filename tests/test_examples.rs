@@ -35,6 +35,7 @@ struct TestEnv {
     output: Vec<u8>,
     input: Empty,
     working_dir: PathBuf,
+    random_counter: u32,
 }
 
 impl InterpreterEnv for TestEnv {
@@ -66,6 +67,12 @@ impl InterpreterEnv for TestEnv {
     fn is_fingerprint_enabled(&self, _fpr: i32) -> bool {
         true
     }
+    fn random_u32(&mut self) -> u32 {
+        // Not random at all: `?` goes east, south, west, north, east, ...
+        let r = self.random_counter;
+        self.random_counter = r.wrapping_add(1);
+        r
+    }
 }
 
 const TEST_ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests");
@@ -99,7 +106,7 @@ fn get_b98_tests() -> io::Result<Vec<(PathBuf, PathBuf)>> {
 
     result.sort();
 
-    return Ok(result);
+    Ok(result)
 }
 
 fn run_b98_test(program_path: &Path, output_path: &Path) {
@@ -114,6 +121,7 @@ fn run_b98_test(program_path: &Path, output_path: &Path) {
             output: Vec::new(),
             input: async_std::io::empty(),
             working_dir: dir_name.to_owned(),
+            random_counter: 0,
         });
 
         {
@@ -292,7 +292,10 @@ export class RFungeGui extends LitElement {
   }
 
   _syncDebuggerState () {
-    this.editorRef.value.srcLines = this._controller.getSrcLines()
+    // only re-read the source if funge-space has been written to
+    if (this._controller.takeDirtyRegions().length !== 0) {
+      this.editorRef.value.srcLines = this._controller.getSrcLines()
+    }
     this.editorRef.value.cursors = this._controller.getCursors()
     this.stackWindowRef.value.stacks = this._controller.getStacks()
   }
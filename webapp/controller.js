@@ -21,15 +21,23 @@ import { TurtleDisplay } from './turtle.js'
 
 let wasmInitialized = false
 
-let ticksPerCall = 1000
+// How long to run before returning control to the UI
+const msPerCall = 100
 
 export class RFungeController {
   constructor (host) {
     this._host = host
     this._stopRequest = null
     this._inputBuffer = ''
+    this._inputClosed = false
     this._onInput = []
     this._turtle = null
+    this._audioCtx = null
+    this._nextToneTime = 0
+    this._dimensions = 2
+    this._cellSize = 32
+    this._fingerprints = undefined
+    this._fileSystem = undefined
   }
 
   async init () {
@@ -37,7 +45,11 @@ export class RFungeController {
       await initRFunge()
       wasmInitialized = true
     }
-    this._interpreter = new BefungeInterpreter(this)
+    this._interpreter = new BefungeInterpreter(
+      this,
+      this._dimensions,
+      this._cellSize
+    )
   }
 
   /******************************************************
@@ -52,6 +64,15 @@ export class RFungeController {
     console.warn('RFunge warning: %s', msg)
   }
 
+  // d is {severity, code, location: [x, y], ipId, message}
+  diagnostic (d) {
+    if (typeof this._host.showDiagnostic === 'function') {
+      this._host.showDiagnostic(d)
+    } else {
+      console.warn('RFunge %s (%s): %s', d.severity, d.code, d.message)
+    }
+  }
+
   get envVars () {
     return {
       USER_AGENT: navigator.userAgent,
@@ -74,20 +95,55 @@ export class RFungeController {
         const result = this._inputBuffer
         this._inputBuffer = ''
         resolve(result)
+      } else if (this._inputClosed) {
+        resolve(null) // EOF
       } else {
         const idx = this._onInput.length
         const inputCallback = () => {
           const result = this._inputBuffer
           this._inputBuffer = ''
           this._onInput.splice(idx, 1) // remove callback from array
-          resolve(result)
+          resolve(result === '' && this._inputClosed ? null : result)
         }
         this._onInput.push(inputCallback)
-        this._inputStamp = true
       }
     })
   }
 
+  playTone (frequency, durationMs) {
+    if (this._audioCtx == null) {
+      const AudioContext = window.AudioContext || window.webkitAudioContext
+      if (AudioContext == null) return false
+      this._audioCtx = new AudioContext()
+    }
+    const ctx = this._audioCtx
+    // Queue the tone after the previous one, as the interpreter doesn't wait
+    const start = Math.max(ctx.currentTime, this._nextToneTime)
+    const end = start + durationMs / 1000
+    if (frequency > 0) {
+      const osc = ctx.createOscillator()
+      const gain = ctx.createGain()
+      osc.frequency.value = frequency
+      gain.gain.value = 0.2
+      osc.connect(gain).connect(ctx.destination)
+      osc.start(start)
+      osc.stop(end)
+    }
+    this._nextToneTime = end
+    return true
+  }
+
+  // The fingerprints to offer (undefined: the interpreter's default)
+  get fingerprints () {
+    return this._fingerprints
+  }
+
+  // Object with readFile(name) and writeFile(name, bytes) for i and o;
+  // both may return promises
+  get fileSystem () {
+    return this._fileSystem
+  }
+
   get turtleDisplay () {
     if (this._turtle == null) {
       this._turtle = new TurtleDisplay(this._host)
@@ -109,37 +165,14 @@ export class RFungeController {
           callback()
           return
         }
-        this._inputStamp = false
-        // Execute tickPerCall instructions and meausure how long it took
-        const t0 = performance.now()
-        const result = await this._interpreter.runLimitedAsync(ticksPerCall)
-        const t1 = performance.now()
-        const dt = t1 - t0
-
-        if (result != null) {
+        const result = await this._interpreter.runFor(msPerCall)
+
+        if (result.reason === 'done') {
           // We're done!
-          resolve(result)
-          return
+          resolve(result.exitCode)
+        } else if (result.reason === 'timeout' || result.reason === 'panic') {
+          resolve(-1)
         } else {
-          // Continue running
-          if (!this._inputStamp) {
-            // only time iterations that did NOT wait for input
-            // target 100ms per step
-            const timeFactor = (dt + 1) / 100
-            if (timeFactor > 2) {
-              // too slow
-              ticksPerCall = Math.floor(ticksPerCall / timeFactor)
-              console.log(`adjusting to ${ticksPerCall} ticks per iteration`)
-            } else if (timeFactor < 0.5) {
-              // too fast
-              ticksPerCall = Math.floor(ticksPerCall / timeFactor)
-
-              // prevent overflow on supercomputers (or with buggy WASM...)
-              if (ticksPerCall > 1 << 30) ticksPerCall = 1 << 30
-
-              console.log(`adjusting to ${ticksPerCall} ticks per iteration`)
-            }
-          }
           // go again - after returning control to the UI
           setTimeout(continueRunning, 0)
         }
@@ -156,15 +189,37 @@ export class RFungeController {
 
   stop () {
     return new Promise(resolve => {
-      this.writeInput('') // EOF
       this._stopRequest = resolve
+      this.closeInput()
     })
   }
 
   reset () {
     this._interpreter.close()
-    this._interpreter = new BefungeInterpreter(this)
+    this._interpreter = new BefungeInterpreter(
+      this,
+      this._dimensions,
+      this._cellSize
+    )
     this._turtle = null
+    this._nextToneTime = 0
+    this._inputClosed = false
+  }
+
+  // Switch between Unefunge (1) and Befunge (2), and 32- or 64-bit cells.
+  // This resets the interpreter.
+  setDialect (dimensions, cellSize) {
+    this._dimensions = dimensions
+    this._cellSize = cellSize
+    this.reset()
+  }
+
+  // Choose the fingerprints (a list of names) and the virtual file system
+  // the program can use; pass undefined for the defaults
+  setSandbox (fingerprints, fileSystem) {
+    this._fingerprints = fingerprints
+    this._fileSystem = fileSystem
+    this.reset()
   }
 
   setSrc (src) {
@@ -179,15 +234,31 @@ export class RFungeController {
     return this._interpreter.getSrcLines()
   }
 
+  takeDirtyRegions () {
+    return this._interpreter.takeDirtyRegions()
+  }
+
+  takeChangedCells () {
+    return this._interpreter.takeChangedCells()
+  }
+
+  getStats () {
+    return this._interpreter.getStats()
+  }
+
+  saveState () {
+    return this._interpreter.saveState()
+  }
+
+  restoreState (bytes) {
+    this._interpreter.restoreState(bytes)
+  }
+
   getCursors () {
     const ipCount = this._interpreter.ipCount
     let cursors = []
     for (let i = 0; i < ipCount; ++i) {
-      cursors.push({
-        location: this._interpreter.ipLocation(i),
-        delta: this._interpreter.ipDelta(i),
-        projectedLocation: this._interpreter.projectedIpLocation(i)
-      })
+      cursors.push(this._interpreter.getIp(i))
     }
     return cursors
   }
@@ -196,16 +267,19 @@ export class RFungeController {
     const ipCount = this._interpreter.ipCount
     let stackStackStack = []
     for (let i = 0; i < ipCount; ++i) {
-      const stackCount = this._interpreter.stackCount(i)
-      let stackStack = []
-      for (let j = 0; j < stackCount; ++j) {
-        stackStack.push(this._interpreter.getStack(i, j))
-      }
-      stackStackStack.push(stackStack)
+      stackStackStack.push(this._interpreter.getStacks(i))
     }
     return stackStackStack
   }
 
+  // Signal EOF to the program once the input buffer is used up
+  closeInput () {
+    this._inputClosed = true
+    for (const callback of this._onInput) {
+      callback()
+    }
+  }
+
   writeInput (s) {
     this._inputBuffer += s
     for (const callback of this._onInput) {
@@ -62,8 +62,8 @@ export class TurtleDisplay {
     return this._app.turtActive
   }
 
-  _bounds (lines, dots) {
-    if (lines.length === 0 && dots.length === 0) {
+  _bounds (fills, lines, dots) {
+    if (fills.length === 0 && lines.length === 0 && dots.length === 0) {
       return [0, 0, 0, 0]
     }
     let top = null
@@ -80,6 +80,14 @@ export class TurtleDisplay {
       bottom = bottom == null ? maxY : Math.max(maxY, bottom)
       right = right == null ? maxX : Math.max(maxX, right)
     }
+    for (const f of fills) {
+      for (const p of f.points) {
+        top = top == null ? p.y : Math.min(p.y, top)
+        left = left == null ? p.x : Math.min(p.x, left)
+        bottom = bottom == null ? p.y : Math.max(p.y, bottom)
+        right = right == null ? p.x : Math.max(p.x, right)
+      }
+    }
     for (const d of dots) {
       top = top == null ? d.pos.y : Math.min(d.pos.y, top)
       left = left == null ? d.pos.x : Math.min(d.pos.x, left)
@@ -89,14 +97,14 @@ export class TurtleDisplay {
     return [left, top, right, bottom]
   }
 
-  print (background, lines, dots) {
+  print (background, fills, lines, dots) {
     // create a new canvas
     const canvas = document.createElement('canvas')
     // minimum size
     canvas.width = 10
     canvas.height = 10
     // Draw, expanding the canvas
-    this._drawToCanvas(canvas, false, background, lines, dots)
+    this._drawToCanvas(canvas, false, background, fills, lines, dots)
     // Get the image
     const url = canvas.toDataURL()
     const linkElem = document.createElement('a')
@@ -107,8 +115,8 @@ export class TurtleDisplay {
     document.body.removeChild(linkElem)
   }
 
-  _drawToCanvas (canvas, rescale = true, background, lines, dots) {
-    const [left, top, right, bottom] = this._bounds(lines, dots)
+  _drawToCanvas (canvas, rescale = true, background, fills, lines, dots) {
+    const [left, top, right, bottom] = this._bounds(fills, lines, dots)
     let { width, height } = canvas
     // Do have have to scale to fit?
     const imageWidth = right - left + 8
@@ -142,30 +150,47 @@ export class TurtleDisplay {
       ctx.fillStyle = cssColour(background)
       ctx.fillRect(0, 0, width, height)
     }
-    // Step 2: draw all the lines
+    // Step 2: fill in the polygons
+    for (const fill of fills) {
+      ctx.beginPath()
+      ctx.fillStyle = cssColour(fill.colour)
+      fill.points.forEach((p, i) => {
+        const x = offsetX + scale * p.x
+        const y = offsetY + scale * p.y
+        if (i === 0) {
+          ctx.moveTo(x, y)
+        } else {
+          ctx.lineTo(x, y)
+        }
+      })
+      ctx.closePath()
+      ctx.fill()
+    }
+    // Step 3: draw all the lines
     for (const line of lines) {
       ctx.beginPath()
       ctx.strokeStyle = cssColour(line.colour)
       ctx.moveTo(offsetX + scale * line.from.x, offsetY + scale * line.from.y)
       ctx.lineTo(offsetX + scale * line.to.x, offsetY + scale * line.to.y)
-      ctx.lineWidth = scale
+      ctx.lineWidth = scale * line.width
       ctx.lineCap = 'round'
       ctx.stroke()
     }
-    // Step 3: draw all the dots
+    // Step 4: draw all the dots
     for (const dot of dots) {
       ctx.fillStyle = cssColour(dot.colour)
       ctx.fillRect(
-        offsetX + scale * (dot.pos.x - 0.5),
-        offsetY + scale * (dot.pos.y - 0.5),
-        scale,
-        scale
+        offsetX + scale * (dot.pos.x - dot.width / 2),
+        offsetY + scale * (dot.pos.y - dot.width / 2),
+        scale * dot.width,
+        scale * dot.width
       )
     }
   }
 
-  draw (background, lines, dots) {
+  draw (turtle, background, fills, lines, dots) {
     this._background = background
+    this._fills = fills
     this._lines = lines
     this._dots = dots
     this._redraw = true
@@ -185,6 +210,7 @@ export class TurtleDisplay {
             canvas,
             true,
             this._background,
+            this._fills,
             this._lines,
             this._dots
           )